@@ -0,0 +1,490 @@
+use crate::candidate::{Candidate, CandidateType, TcpType, Transport};
+use bifrost_stun::message::TransactionId;
+use std::cmp::{self, Reverse};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// The state of a candidate pair, defined in
+/// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-6.1.2.6).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum PairState {
+    Frozen,
+    Waiting,
+    InProgress,
+    Succeeded,
+    Failed,
+}
+
+/// The state of a checklist, defined in
+/// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-6.1.2.1).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ChecklistState {
+    Running,
+    Completed,
+    Failed,
+}
+
+/// A pair of a local and a remote candidate, defined in
+/// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-6.1.2).
+///
+/// The local candidate is a host or relayed candidate, whose base checks
+/// are sent from. When a check discovers a peer reflexive local candidate,
+/// the checked pair itself becomes valid rather than a new pair with the
+/// same base and remote candidate.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CandidatePair {
+    pub local: Candidate,
+    pub remote: Candidate,
+    pub priority: u64,
+    pub state: PairState,
+    /// Whether a check of the pair succeeded, which makes it usable.
+    pub valid: bool,
+    /// Whether the pair has been nominated for its component.
+    pub nominated: bool,
+    /// Whether a successful check of the pair nominates it: a check the
+    /// controlling agent sends with USE-CANDIDATE, or one the controlled
+    /// agent sends after receiving one.
+    pub(crate) use_candidate: bool,
+    pub(crate) check: Option<Check>,
+}
+
+impl CandidatePair {
+    pub(crate) fn new(local: Candidate, remote: Candidate, controlling: bool) -> Self {
+        let mut pair = Self {
+            local,
+            remote,
+            priority: 0,
+            state: PairState::Frozen,
+            valid: false,
+            nominated: false,
+            use_candidate: false,
+            check: None,
+        };
+        pair.update_priority(controlling);
+        pair
+    }
+
+    /// Returns the address checks and data are sent from.
+    pub fn base(&self) -> SocketAddr {
+        self.local.base.unwrap_or(self.local.addr)
+    }
+
+    pub fn component(&self) -> u16 {
+        self.local.component
+    }
+
+    /// Returns the foundation of the pair, made of those of its candidates.
+    pub fn foundation(&self) -> (&str, &str) {
+        (&self.local.foundation, &self.remote.foundation)
+    }
+
+    /// Returns whether the pair is yet to be checked or being checked.
+    pub fn is_pending(&self) -> bool {
+        match self.state {
+            PairState::Frozen | PairState::Waiting | PairState::InProgress => true,
+            PairState::Succeeded | PairState::Failed => false,
+        }
+    }
+
+    pub(crate) fn key(&self) -> Key {
+        (self.local.transport, self.base(), self.remote.addr)
+    }
+
+    pub(crate) fn update_priority(&mut self, controlling: bool) {
+        let (g, d) = if controlling {
+            (self.local.priority, self.remote.priority)
+        } else {
+            (self.remote.priority, self.local.priority)
+        };
+        self.priority = pair_priority(g, d);
+    }
+}
+
+/// Identifies a pair by its transport, base and remote address, which
+/// checks and data of the pair are sent with.
+pub(crate) type Key = (Transport, SocketAddr, SocketAddr);
+
+/// Computes the priority of a pair from the priorities of the controlling
+/// agent's candidate `g` and the controlled agent's `d`, defined in
+/// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-6.1.2.3).
+pub fn pair_priority(g: u32, d: u32) -> u64 {
+    let (g, d) = (u64::from(g), u64::from(d));
+    (cmp::min(g, d) << 32) + 2 * cmp::max(g, d) + if g > d { 1 } else { 0 }
+}
+
+/// An outstanding connectivity check.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Check {
+    pub transaction_id: TransactionId,
+    pub request: Vec<u8>,
+    /// The PRIORITY sent in the request, which a discovered peer reflexive
+    /// candidate takes.
+    pub priority: u32,
+    /// Whether the check was sent as the controlling agent.
+    pub controlling: bool,
+    pub requests: u32,
+    /// When the last request was sent.
+    pub sent: Instant,
+    pub deadline: Instant,
+    /// Whether the check is no longer retransmitted, as a triggered check
+    /// of the pair is queued. A response is processed until the triggered
+    /// check is sent.
+    pub cancelled: bool,
+    pub rto: Duration,
+}
+
+/// The candidate pairs of a data stream and the state of checking them.
+#[derive(Clone, Debug)]
+pub(crate) struct Checklist {
+    pub state: ChecklistState,
+    /// Ordered by decreasing priority.
+    pub pairs: Vec<CandidatePair>,
+    /// Keys of pairs waiting for a triggered check.
+    pub triggered: VecDeque<Key>,
+}
+
+impl Checklist {
+    pub fn new() -> Self {
+        Self {
+            state: ChecklistState::Running,
+            pairs: Vec::new(),
+            triggered: VecDeque::new(),
+        }
+    }
+
+    pub fn find(&self, key: Key) -> Option<usize> {
+        self.pairs.iter().position(|pair| pair.key() == key)
+    }
+
+    /// Adds `pair` in order of priority, unless a pair with the same base
+    /// and remote candidate exists or the checklist is full. Returns the
+    /// index of the pair with that key, if any.
+    pub fn add(&mut self, pair: CandidatePair, max_pairs: usize) -> Option<usize> {
+        if let Some(i) = self.find(pair.key()) {
+            return Some(i);
+        }
+        if self.pairs.len() >= max_pairs {
+            return None;
+        }
+
+        let i = self
+            .pairs
+            .iter()
+            .position(|other| other.priority < pair.priority)
+            .unwrap_or(self.pairs.len());
+        self.pairs.insert(i, pair);
+        Some(i)
+    }
+
+    /// Adds a pair formed from a trickled candidate after checks started,
+    /// defined in [RFC 8838](https://tools.ietf.org/html/rfc8838). The pair
+    /// is Waiting if a pair with its foundation already succeeded, and
+    /// Frozen until the foundation is unfrozen otherwise.
+    pub fn add_trickled(&mut self, pair: CandidatePair, max_pairs: usize) {
+        if self.find(pair.key()).is_some() {
+            return;
+        }
+        let succeeded = self.pairs.iter().any(|other| {
+            other.state == PairState::Succeeded && other.foundation() == pair.foundation()
+        });
+
+        if let Some(i) = self.add(pair, max_pairs) {
+            if succeeded {
+                self.pairs[i].state = PairState::Waiting;
+            }
+            if self.state == ChecklistState::Failed {
+                self.state = ChecklistState::Running;
+            }
+        }
+    }
+
+    /// Queues a triggered check of the pair at `i`, defined in
+    /// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-7.3.1.4).
+    pub fn trigger(&mut self, i: usize) {
+        let pair = &mut self.pairs[i];
+        match pair.state {
+            PairState::Succeeded => return,
+            // The check in progress is no longer retransmitted. A response
+            // to it is processed only until the triggered check replaces it.
+            PairState::InProgress => {
+                if let Some(check) = &mut pair.check {
+                    check.cancelled = true;
+                }
+            }
+            PairState::Frozen | PairState::Waiting | PairState::Failed => (),
+        }
+        pair.state = PairState::Waiting;
+
+        let key = pair.key();
+        if !self.triggered.contains(&key) {
+            self.triggered.push_back(key);
+        }
+        if self.state == ChecklistState::Failed {
+            self.state = ChecklistState::Running;
+        }
+    }
+
+    /// Queues a check of the valid pair at `i` that nominates it, defined in
+    /// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-8.1.1).
+    pub fn nominate(&mut self, i: usize) {
+        let pair = &mut self.pairs[i];
+        pair.use_candidate = true;
+        pair.state = PairState::Waiting;
+        self.triggered.push_back(pair.key());
+    }
+
+    /// Completes the checklist once every component has a nominated pair,
+    /// defined in
+    /// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-8.1.2), and
+    /// stops checking the other pairs.
+    pub fn complete(&mut self) {
+        self.state = ChecklistState::Completed;
+        self.triggered.clear();
+        for pair in &mut self.pairs {
+            if pair.is_pending() {
+                pair.state = PairState::Failed;
+                pair.check = None;
+            }
+        }
+    }
+
+    /// Fails the checklist once every pair has been checked, if some of its
+    /// components have no valid pair. With trickle, no more pairs may be
+    /// added only once `ended`, at the end of candidates of both agents.
+    pub fn update_state(&mut self, components: u16, ended: bool) {
+        if self.state != ChecklistState::Running
+            || !ended
+            || !self.triggered.is_empty()
+            || self.pairs.iter().any(CandidatePair::is_pending)
+        {
+            return;
+        }
+
+        let connected = (1..=components).all(|component| {
+            self.pairs
+                .iter()
+                .any(|pair| pair.valid && pair.component() == component)
+        });
+        if !connected {
+            self.state = ChecklistState::Failed;
+        }
+    }
+
+    /// Returns whether any pair with `foundation` is Waiting or In-Progress.
+    pub fn is_foundation_active(&self, foundation: (&str, &str)) -> bool {
+        self.pairs.iter().any(|pair| {
+            pair.foundation() == foundation
+                && (pair.state == PairState::Waiting || pair.state == PairState::InProgress)
+        })
+    }
+}
+
+/// Forms the candidate pairs of a data stream, defined in
+/// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-6.1.2.2).
+///
+/// Server reflexive local candidates are replaced by their bases, and pairs
+/// with the same base and remote candidate as a higher priority one are
+/// pruned, per
+/// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-6.1.2.4).
+pub(crate) fn form_pairs(
+    locals: &[Candidate],
+    remotes: &[Candidate],
+    controlling: bool,
+) -> Vec<CandidatePair> {
+    let mut pairs = Vec::new();
+    for local in locals
+        .iter()
+        .filter_map(|local| pairing_base(locals, local))
+    {
+        for remote in remotes {
+            if let Some(pair) = new_pair(local, remote, controlling) {
+                pairs.push(pair);
+            }
+        }
+    }
+
+    pairs.sort_by_key(|pair| Reverse(pair.priority));
+    let mut seen = Vec::new();
+    pairs.retain(|pair| {
+        let key = pair.key();
+        if seen.contains(&key) {
+            false
+        } else {
+            seen.push(key);
+            true
+        }
+    });
+    pairs
+}
+
+/// Returns the candidate to pair in place of `local`: itself for host and
+/// relayed candidates, its base for server reflexive candidates, and none for
+/// peer reflexive candidates, whose base is already paired.
+///
+/// Passive TCP candidates are not paired either, since they only accept
+/// connections, and are only checked when checks arrive on them, per
+/// [RFC 6544](https://tools.ietf.org/html/rfc6544#section-6.2).
+pub(crate) fn pairing_base<'a>(
+    locals: &'a [Candidate],
+    local: &'a Candidate,
+) -> Option<&'a Candidate> {
+    if local.tcp_type == Some(TcpType::Passive) {
+        return None;
+    }
+    match local.kind {
+        CandidateType::Host | CandidateType::Relayed => Some(local),
+        CandidateType::ServerReflexive => locals.iter().find(|base| {
+            base.kind == CandidateType::Host
+                && Some(base.addr) == local.base
+                && base.component == local.component
+        }),
+        CandidateType::PeerReflexive => None,
+    }
+}
+
+/// Pairs `local` with `remote` if they can reach each other.
+pub(crate) fn new_pair(
+    local: &Candidate,
+    remote: &Candidate,
+    controlling: bool,
+) -> Option<CandidatePair> {
+    if local.component != remote.component
+        || local.transport != remote.transport
+        || local.addr.is_ipv4() != remote.addr.is_ipv4()
+        || remote.addr.ip().is_unspecified()
+        || remote.addr.port() == 0
+    {
+        return None;
+    }
+    // TCP candidates pair with those that can connect to or accept from
+    // them, defined in
+    // [RFC 6544](https://tools.ietf.org/html/rfc6544#section-6.2).
+    if local.transport == Transport::Tcp
+        && (local.tcp_type.is_none() || remote.tcp_type != local.tcp_type.map(TcpType::peer))
+    {
+        return None;
+    }
+    Some(CandidatePair::new(
+        local.clone(),
+        remote.clone(),
+        controlling,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn priority() {
+        assert_eq!(pair_priority(1, 2), (1 << 32) + 4);
+        assert_eq!(pair_priority(2, 1), (1 << 32) + 5);
+        assert!(pair_priority(2_130_706_431, 1) > pair_priority(1, 2_130_706_431));
+    }
+
+    #[test]
+    fn form() {
+        let host = Candidate::host(1, Transport::Udp, "10.0.0.1:1000".parse().unwrap(), 65535);
+        let host6 = Candidate::host(1, Transport::Udp, "[fd00::1]:1000".parse().unwrap(), 65534);
+        let srflx = Candidate::server_reflexive(
+            1,
+            Transport::Udp,
+            "203.0.113.1:1000".parse().unwrap(),
+            host.addr,
+            "198.51.100.1".parse().unwrap(),
+            65535,
+        );
+        let rtp2 = Candidate::host(2, Transport::Udp, "10.0.0.1:1001".parse().unwrap(), 65535);
+        let locals = vec![srflx, host.clone(), host6, rtp2];
+
+        let remote = Candidate::host(1, Transport::Udp, "10.0.0.2:2000".parse().unwrap(), 65535);
+        let remote_srflx = Candidate::server_reflexive(
+            1,
+            Transport::Udp,
+            "203.0.113.2:2000".parse().unwrap(),
+            remote.addr,
+            "198.51.100.1".parse().unwrap(),
+            65535,
+        );
+        let remote_tcp = Candidate::host(1, Transport::Tcp, "10.0.0.2:9".parse().unwrap(), 65535);
+        let remotes = vec![remote_srflx.clone(), remote.clone(), remote_tcp];
+
+        let pairs = form_pairs(&locals, &remotes, true);
+        // The server reflexive candidate is replaced by its base, which is
+        // already paired, and IPv6, TCP and component 2 pair with nothing.
+        assert_eq!(pairs.len(), 2);
+        assert!(pairs.iter().all(|pair| pair.local == host));
+        assert_eq!(pairs[0].remote, remote);
+        assert_eq!(pairs[1].remote, remote_srflx);
+        assert!(pairs[0].priority > pairs[1].priority);
+        assert!(pairs.iter().all(|pair| pair.state == PairState::Frozen));
+    }
+
+    #[test]
+    fn form_tcp() {
+        let addr = "10.0.0.1:1000".parse().unwrap();
+        let tcp = |tcp_type, addr| Candidate::tcp_host(1, tcp_type, addr, 65535);
+        let active = tcp(TcpType::Active, addr);
+        let so = tcp(TcpType::SimultaneousOpen, addr);
+        let locals = vec![active.clone(), tcp(TcpType::Passive, addr), so.clone()];
+
+        let addr = "10.0.0.2:2000".parse().unwrap();
+        let remote_passive = tcp(TcpType::Passive, addr);
+        let remote_so = tcp(TcpType::SimultaneousOpen, addr);
+        let remotes = vec![
+            tcp(TcpType::Active, addr),
+            remote_passive.clone(),
+            remote_so.clone(),
+            Candidate::host(1, Transport::Udp, addr, 65535),
+        ];
+
+        // The passive candidate is only paired when checked.
+        let pairs = form_pairs(&locals, &remotes, true);
+        let pairs: Vec<_> = pairs
+            .iter()
+            .map(|pair| (&pair.local, &pair.remote))
+            .collect();
+        assert_eq!(pairs, [(&active, &remote_passive), (&so, &remote_so)]);
+        assert!(new_pair(&locals[1], &remotes[0], true).is_some());
+    }
+
+    #[test]
+    fn trickled() {
+        let local = Candidate::host(1, Transport::Udp, "10.0.0.1:1000".parse().unwrap(), 65535);
+        let pair = |remote: &str| {
+            let remote = Candidate::host(1, Transport::Udp, remote.parse().unwrap(), 65535);
+            CandidatePair::new(local.clone(), remote, true)
+        };
+
+        let mut checklist = Checklist::new();
+        checklist.add_trickled(pair("10.0.0.2:2000"), 100);
+        assert_eq!(checklist.pairs[0].state, PairState::Frozen);
+        checklist.pairs[0].state = PairState::Succeeded;
+
+        // A pair whose foundation succeeded is checked right away.
+        checklist.add_trickled(pair("10.0.0.2:2002"), 100);
+        checklist.add_trickled(pair("10.0.0.3:3000"), 100);
+        checklist.add_trickled(pair("10.0.0.2:2000"), 100);
+        let states: Vec<_> = checklist
+            .pairs
+            .iter()
+            .map(|pair| (pair.remote.addr.to_string(), pair.state))
+            .collect();
+        assert_eq!(
+            states,
+            [
+                ("10.0.0.2:2000".to_owned(), PairState::Succeeded),
+                ("10.0.0.2:2002".to_owned(), PairState::Waiting),
+                ("10.0.0.3:3000".to_owned(), PairState::Frozen),
+            ]
+        );
+
+        // New pairs revive a failed checklist, within the limit.
+        checklist.state = ChecklistState::Failed;
+        checklist.add_trickled(pair("10.0.0.4:4000"), 3);
+        assert_eq!(checklist.state, ChecklistState::Failed);
+        checklist.add_trickled(pair("10.0.0.4:4000"), 4);
+        assert_eq!(checklist.state, ChecklistState::Running);
+    }
+}
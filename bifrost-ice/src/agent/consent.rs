@@ -0,0 +1,138 @@
+use crate::agent::checklist::Key;
+use bifrost_stun::message::TransactionId;
+use rand::{Rng, RngCore};
+use std::time::{Duration, Instant};
+
+/// The default interval between consent checks, defined in
+/// [RFC 7675](https://tools.ietf.org/html/rfc7675#section-5.1).
+pub const DEFAULT_CONSENT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The default time consent lasts without a successful consent check,
+/// defined in [RFC 7675](https://tools.ietf.org/html/rfc7675#section-5.1).
+pub const DEFAULT_CONSENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The number of consent checks whose responses are still accepted.
+const MAX_PENDING: usize = 8;
+
+/// The consent of the peer to receive data on a selected pair, defined in
+/// [RFC 7675](https://tools.ietf.org/html/rfc7675).
+#[derive(Clone, Debug)]
+pub(crate) struct Consent {
+    /// The key of the selected pair.
+    pub key: Key,
+    /// When consent expires, unless refreshed.
+    pub expiry: Instant,
+    /// When the next consent check is sent.
+    pub next_check: Instant,
+    /// The transaction ids of the checks sent and when, oldest first.
+    pending: Vec<(TransactionId, Instant)>,
+    pub expired: bool,
+}
+
+impl Consent {
+    /// Starts tracking consent for the pair `key`, which was just found to
+    /// be valid. The first check is scheduled with `rng`.
+    pub fn new<R: RngCore + ?Sized>(
+        key: Key,
+        now: Instant,
+        interval: Duration,
+        timeout: Duration,
+        rng: &mut R,
+    ) -> Self {
+        Self {
+            key,
+            expiry: now + timeout,
+            next_check: now + randomize(interval, rng),
+            pending: Vec::new(),
+            expired: false,
+        }
+    }
+
+    /// Records a consent check sent at `now`, and schedules the next one
+    /// with `rng`.
+    pub fn sent<R: RngCore + ?Sized>(
+        &mut self,
+        transaction_id: TransactionId,
+        now: Instant,
+        interval: Duration,
+        rng: &mut R,
+    ) {
+        if self.pending.len() == MAX_PENDING {
+            self.pending.remove(0);
+        }
+        self.pending.push((transaction_id, now));
+        self.next_check = now + randomize(interval, rng);
+    }
+
+    /// Returns when the consent check with `transaction_id` was sent, if
+    /// it is one, forgetting it.
+    pub fn take(&mut self, transaction_id: TransactionId) -> Option<Instant> {
+        let i = self
+            .pending
+            .iter()
+            .position(|&(id, _)| id == transaction_id)?;
+        Some(self.pending.remove(i).1)
+    }
+
+    /// Returns when the next consent check is sent or consent expires.
+    pub fn timeout(&self) -> Option<Instant> {
+        if self.expired {
+            None
+        } else {
+            Some(self.next_check.min(self.expiry))
+        }
+    }
+}
+
+/// Randomizes `interval` uniformly to between 0.8 and 1.2 times its value,
+/// so that consent checks are not synchronized, defined in
+/// [RFC 7675](https://tools.ietf.org/html/rfc7675#section-5.1).
+fn randomize<R: RngCore + ?Sized>(interval: Duration, rng: &mut R) -> Duration {
+    interval * rng.gen_range(800, 1200) / 1000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::candidate::Transport;
+
+    #[test]
+    fn interval() {
+        for _ in 0..100 {
+            let interval = randomize(DEFAULT_CONSENT_INTERVAL, &mut rand::thread_rng());
+            assert!(interval >= Duration::from_secs(4));
+            assert!(interval < Duration::from_secs(6));
+        }
+    }
+
+    #[test]
+    fn pending() {
+        let key = (
+            Transport::Udp,
+            "192.0.2.1:1000".parse().unwrap(),
+            "192.0.2.2:2000".parse().unwrap(),
+        );
+        let now = Instant::now();
+        let mut rng = rand::thread_rng();
+        let mut consent = Consent::new(
+            key,
+            now,
+            DEFAULT_CONSENT_INTERVAL,
+            DEFAULT_CONSENT_TIMEOUT,
+            &mut rng,
+        );
+        assert_eq!(consent.expiry, now + DEFAULT_CONSENT_TIMEOUT);
+        assert_eq!(consent.timeout(), Some(consent.next_check));
+
+        let ids: Vec<_> = (0..=MAX_PENDING).map(|_| TransactionId::random()).collect();
+        for &id in &ids {
+            consent.sent(id, now, DEFAULT_CONSENT_INTERVAL, &mut rng);
+        }
+        assert_eq!(consent.take(ids[0]), None);
+        assert_eq!(consent.take(ids[1]), Some(now));
+        assert_eq!(consent.take(ids[1]), None);
+
+        consent.expired = true;
+        assert_eq!(consent.timeout(), None);
+    }
+}
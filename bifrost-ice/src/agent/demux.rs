@@ -0,0 +1,99 @@
+use crate::stun;
+use bifrost_stun::message::attribute::Username;
+use bifrost_stun::message::{Class, Method};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::SocketAddr;
+
+/// Routes the datagrams received on a socket shared by many agents, such as
+/// that of an ICE-lite server, to the agent identified by `K` they are for.
+///
+/// Connectivity checks are routed by the local username fragment in their
+/// USERNAME, and other datagrams by their source address, to the agent the
+/// last check from that address was routed to.
+#[derive(Clone, Debug)]
+pub struct Demux<K> {
+    ufrags: HashMap<String, K>,
+    addrs: HashMap<SocketAddr, K>,
+}
+
+impl<K: Clone + Eq + Hash> Demux<K> {
+    pub fn new() -> Self {
+        Self {
+            ufrags: HashMap::new(),
+            addrs: HashMap::new(),
+        }
+    }
+
+    /// Routes checks for the local username fragment `ufrag` to `key`.
+    pub fn insert(&mut self, ufrag: &str, key: K) {
+        self.ufrags.insert(ufrag.to_owned(), key);
+    }
+
+    /// Stops routing anything to `key`.
+    pub fn remove(&mut self, key: &K) {
+        self.ufrags.retain(|_, other| other != key);
+        self.addrs.retain(|_, other| other != key);
+    }
+
+    /// Returns the agent a datagram received from `from` is for, if any.
+    pub fn route(&mut self, from: SocketAddr, data: &[u8]) -> Option<K> {
+        match stun::decode(data) {
+            Some(ref msg) if msg.class == Class::Request && msg.method == Method::BINDING => {
+                let username = msg.attr::<Username>()?;
+                let ufrag = username.0.split(':').next().unwrap_or_default();
+                let key = self.ufrags.get(ufrag)?.clone();
+                self.addrs.insert(from, key.clone());
+                Some(key)
+            }
+            _ => self.addrs.get(&from).cloned(),
+        }
+    }
+}
+
+impl<K: Clone + Eq + Hash> Default for Demux<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::{Agent, AgentConfig, Credentials, Role};
+    use crate::test_util::host;
+    use std::time::Instant;
+
+    #[test]
+    fn route() {
+        let server = "198.51.100.1:3478";
+        let mut demux = Demux::new();
+
+        let mut checks = Vec::new();
+        for session in 0..3 {
+            let ufrag = format!("ufrag{}", session);
+            demux.insert(&ufrag, session);
+
+            let mut agent = Agent::new(AgentConfig::new(Role::Controlling));
+            agent.add_stream(1, Credentials::random());
+            agent.set_remote_credentials(0, Credentials::new(&ufrag, "pwd"));
+            let addr = format!("192.0.2.{}:1000", session + 1);
+            agent.add_local_candidate(0, host(&addr));
+            agent.add_remote_candidate(0, host(server));
+            agent.start(Instant::now());
+            agent.handle_timeout(Instant::now());
+            checks.push(agent.poll_transmit().unwrap());
+        }
+
+        for (session, check) in checks.iter().enumerate().rev() {
+            assert_eq!(demux.route(check.source, &check.data), Some(session));
+            assert_eq!(demux.route(check.source, b"\x80\x00rtp"), Some(session));
+        }
+        assert_eq!(demux.route(host(server).addr, b"\x80\x00rtp"), None);
+
+        demux.remove(&1);
+        assert_eq!(demux.route(checks[1].source, &checks[1].data), None);
+        assert_eq!(demux.route(checks[1].source, b"\x80\x00rtp"), None);
+        assert_eq!(demux.route(checks[2].source, &checks[2].data), Some(2));
+    }
+}
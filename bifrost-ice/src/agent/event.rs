@@ -0,0 +1,65 @@
+use crate::agent::{CandidatePair, Role};
+use crate::candidate::Candidate;
+
+/// The overall connectivity state of an agent, across all its data streams,
+/// modeled after
+/// [`RTCIceConnectionState`](https://www.w3.org/TR/webrtc/#rtciceconnectionstate-enum).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ConnectionState {
+    /// Checks have not started.
+    New,
+    /// Some component has no valid pair yet.
+    Checking,
+    /// Every component has a valid pair, but some are not nominated yet.
+    Connected,
+    /// Every checklist has completed.
+    Completed,
+    /// Some checklist has failed.
+    Failed,
+    /// Consent to send on a selected pair has been lost.
+    Disconnected,
+}
+
+/// Something that happened to an agent, which the application should act on.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+    StateChanged(ConnectionState),
+    /// Data for `component` of `stream` should now be sent on `pair`.
+    SelectedPair {
+        stream: usize,
+        component: u16,
+        pair: Box<CandidatePair>,
+    },
+    /// The role of the agent switched, to resolve a role conflict.
+    RoleChanged(Role),
+    /// With trickle, a local candidate of `stream` was added, which should
+    /// be sent to the peer.
+    LocalCandidate {
+        stream: usize,
+        candidate: Box<Candidate>,
+    },
+    /// With trickle, all local candidates of `stream` have been added, which
+    /// should be signaled to the peer.
+    EndOfLocalCandidates {
+        stream: usize,
+    },
+    /// A remote candidate with a multicast DNS hostname was added, which
+    /// should be resolved for it to be paired.
+    ResolveHostname {
+        hostname: String,
+    },
+    /// Consent to send on the selected pair of `component` of `stream` has
+    /// expired, so data must no longer be sent on it until ICE is
+    /// restarted, defined in
+    /// [RFC 7675](https://tools.ietf.org/html/rfc7675#section-5.1).
+    ConsentLost {
+        stream: usize,
+        component: u16,
+    },
+    /// ICE was restarted for `stream` after a network change. Its new local
+    /// credentials and candidates must be signaled to the peer, and `start`
+    /// called once the remote ones have been added.
+    Restarted {
+        stream: usize,
+    },
+}
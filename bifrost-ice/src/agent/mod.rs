@@ -0,0 +1,2479 @@
+mod checklist;
+mod consent;
+mod demux;
+mod event;
+mod network;
+mod stats;
+
+pub use self::checklist::{pair_priority, CandidatePair, ChecklistState, PairState};
+pub use self::consent::{DEFAULT_CONSENT_INTERVAL, DEFAULT_CONSENT_TIMEOUT};
+pub use self::demux::Demux;
+pub use self::event::{ConnectionState, Event};
+pub use self::network::{default_network_policy, NetworkAction, NetworkChange};
+pub use self::stats::{CheckError, PairStats};
+
+use self::checklist::{Check, Checklist, Key};
+use self::consent::Consent;
+use crate::candidate::{self, Candidate, CandidateType, TcpType, Transport, RTP_COMPONENT};
+use crate::gather::{InterfaceChange, DEFAULT_TA};
+use crate::stun;
+use bifrost_stun::message::attribute::{
+    ErrorCode, IceControlled, IceControlling, MessageIntegrity, Priority, UseCandidate, Username,
+    XorMappedAddress,
+};
+use bifrost_stun::message::{Class, Message, Method, TransactionId};
+use rand::distributions::Alphanumeric;
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+use serde_json::json;
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
+use std::mem;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+/// The default maximum number of candidate pairs in a checklist,
+/// recommended by
+/// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-6.1.2.5).
+pub const DEFAULT_MAX_PAIRS: usize = 100;
+
+/// The role of an agent, defined in
+/// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-6.1.1).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Role {
+    Controlling,
+    Controlled,
+}
+
+/// The username fragment and password of one side of a data stream, defined
+/// in [RFC 8445](https://tools.ietf.org/html/rfc8445#section-5.3).
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Credentials {
+    pub ufrag: String,
+    pub pwd: String,
+}
+
+impl Credentials {
+    pub fn new(ufrag: &str, pwd: &str) -> Self {
+        Self {
+            ufrag: ufrag.to_owned(),
+            pwd: pwd.to_owned(),
+        }
+    }
+
+    /// Generates random credentials, with more than the 24 and 128 bits of
+    /// randomness RFC 8445 requires of the username fragment and password.
+    pub fn random() -> Self {
+        Self::from_rng(&mut rand::thread_rng())
+    }
+
+    /// Generates random credentials like `random`, with `rng`.
+    pub fn from_rng<R: RngCore + ?Sized>(rng: &mut R) -> Self {
+        let mut random = |len| (&mut *rng).sample_iter(&Alphanumeric).take(len).collect();
+        Self {
+            ufrag: random(8),
+            pwd: random(24),
+        }
+    }
+}
+
+/// The parameters of an agent.
+#[derive(Clone, Debug)]
+pub struct AgentConfig {
+    pub role: Role,
+    /// The pacing of connectivity checks.
+    pub ta: Duration,
+    /// The initial retransmission timeout of a check, doubled after each
+    /// retransmission.
+    pub rto: Duration,
+    /// The number of requests sent for a check before it fails.
+    pub max_requests: u32,
+    /// How long a check over TCP, which is not retransmitted, waits for a
+    /// response before it fails, defined in
+    /// [RFC 5389](https://tools.ietf.org/html/rfc5389#section-7.2.2).
+    pub tcp_timeout: Duration,
+    /// The maximum number of candidate pairs in a checklist.
+    pub max_pairs: usize,
+    /// How long the controlling agent waits for higher priority pairs to be
+    /// checked, once each component has a valid pair, before nominating.
+    pub nomination_timeout: Duration,
+    /// Whether candidates are trickled, defined in
+    /// [RFC 8838](https://tools.ietf.org/html/rfc8838). If so, local
+    /// candidates are reported as they are added, and checklists only fail
+    /// after the end of candidates of both agents.
+    pub trickle: bool,
+    /// Whether the agent is a lite agent, defined in
+    /// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-2.5), which
+    /// only has host candidates, is always controlled, and never sends
+    /// checks but only responds to them.
+    pub lite: bool,
+    /// Whether all data streams share the transport of the first, negotiated
+    /// with [BUNDLE](https://tools.ietf.org/html/rfc8843). If so, only the
+    /// first data stream runs ICE, and the others are aliases for it.
+    pub bundle: bool,
+    /// The mean interval between consent checks on the selected pairs.
+    pub consent_interval: Duration,
+    /// How long consent to send on a selected pair lasts without a
+    /// successful consent check.
+    pub consent_timeout: Duration,
+    /// Decides what the agent does about a change of the local network
+    /// interfaces.
+    pub network_policy: fn(NetworkChange) -> NetworkAction,
+    /// The seed of the random number generator the agent draws its
+    /// tie-breaker, transaction IDs, consent check intervals and credentials
+    /// from, or `None` to seed it from the operating system. A fixed seed
+    /// makes the agent reproducible, e.g. in a simulation.
+    pub seed: Option<u64>,
+}
+
+impl AgentConfig {
+    pub fn new(role: Role) -> Self {
+        Self {
+            role,
+            ta: DEFAULT_TA,
+            rto: Duration::from_millis(500),
+            max_requests: 7,
+            tcp_timeout: Duration::from_millis(39_500),
+            max_pairs: DEFAULT_MAX_PAIRS,
+            nomination_timeout: Duration::from_secs(1),
+            trickle: false,
+            lite: false,
+            bundle: false,
+            consent_interval: DEFAULT_CONSENT_INTERVAL,
+            consent_timeout: DEFAULT_CONSENT_TIMEOUT,
+            network_policy: default_network_policy,
+            seed: None,
+        }
+    }
+
+    /// Returns the parameters of a lite agent.
+    pub fn lite() -> Self {
+        Self {
+            lite: true,
+            ..Self::new(Role::Controlled)
+        }
+    }
+}
+
+/// A datagram the agent wants sent from the local `source` address.
+///
+/// Over TCP, it is sent framed per
+/// [RFC 4571](https://tools.ietf.org/html/rfc4571) on the connection
+/// between the addresses, which is first opened from an active or
+/// simultaneous-open candidate if there is none, defined in
+/// [RFC 6544](https://tools.ietf.org/html/rfc6544#section-7.1). The local
+/// address of connections from active candidates is taken to be their base.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Transmit {
+    pub transport: Transport,
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug)]
+struct DataStream {
+    components: u16,
+    local_credentials: Credentials,
+    remote_credentials: Option<Credentials>,
+    local_candidates: Vec<Candidate>,
+    remote_candidates: Vec<Candidate>,
+    checklist: Checklist,
+    /// Whether the checklist has been formed, since the last restart.
+    started: bool,
+    /// The selected pairs, by component, which are kept through a restart
+    /// until new ones are selected.
+    selected: HashMap<u16, CandidatePair>,
+    /// The consent to send on the selected pairs, by component.
+    consent: HashMap<u16, Consent>,
+    /// When every component first had a valid pair.
+    valid_since: Option<Instant>,
+    /// Whether all local and remote candidates have been added.
+    local_ended: bool,
+    remote_ended: bool,
+    /// The statistics of the pairs and selected pairs.
+    stats: HashMap<Key, PairStats>,
+}
+
+impl DataStream {
+    fn new(components: u16, local_credentials: Credentials, trickle: bool) -> Self {
+        Self {
+            components,
+            local_credentials,
+            remote_credentials: None,
+            local_candidates: Vec::new(),
+            remote_candidates: Vec::new(),
+            checklist: Checklist::new(),
+            started: false,
+            selected: HashMap::new(),
+            consent: HashMap::new(),
+            valid_since: None,
+            local_ended: !trickle,
+            remote_ended: !trickle,
+            stats: HashMap::new(),
+        }
+    }
+
+    fn update_checklist(&mut self) {
+        let ended = self.local_ended && self.remote_ended;
+        self.checklist.update_state(self.components, ended);
+    }
+
+    /// Records the states of the pairs at `now`, and forgets the statistics
+    /// of pairs neither in the checklist nor selected.
+    fn update_stats(&mut self, now: Instant) {
+        let DataStream {
+            checklist,
+            selected,
+            stats,
+            ..
+        } = self;
+        stats.retain(|&key, _| {
+            checklist.find(key).is_some() || selected.values().any(|pair| pair.key() == key)
+        });
+        for pair in &checklist.pairs {
+            stats
+                .entry(pair.key())
+                .or_default()
+                .record_state(now, pair.state);
+        }
+    }
+
+    /// Returns the statistics of the selected pair of `component`, if any.
+    fn selected_stats(&mut self, component: u16) -> Option<&mut PairStats> {
+        let key = self.selected.get(&component)?.key();
+        Some(self.stats.entry(key).or_default())
+    }
+}
+
+/// A full ICE agent, defined in
+/// [RFC 8445](https://tools.ietf.org/html/rfc8445).
+///
+/// The agent performs no I/O. The application passes it received datagrams
+/// and the current time, and sends the datagrams it asks for from the
+/// sockets bound to the bases of its local candidates.
+#[derive(Clone, Debug)]
+pub struct Agent {
+    config: AgentConfig,
+    rng: StdRng,
+    tie_breaker: u64,
+    streams: Vec<DataStream>,
+    state: ConnectionState,
+    transmits: VecDeque<Transmit>,
+    events: VecDeque<Event>,
+    /// The data stream whose checklist is looked at first for the next
+    /// ordinary or triggered check.
+    next_stream: usize,
+    /// When the next check may be sent, or `None` before checks start.
+    next_check: Option<Instant>,
+    /// The time last passed to the agent.
+    now: Option<Instant>,
+    /// The time first passed to the agent, which diagnostics are relative
+    /// to.
+    epoch: Option<Instant>,
+    /// With BUNDLE, the number of data streams sharing the transport of the
+    /// first.
+    bundled: usize,
+}
+
+impl Agent {
+    pub fn new(mut config: AgentConfig) -> Self {
+        if config.lite {
+            config.role = Role::Controlled;
+        }
+        let mut rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Self {
+            config,
+            tie_breaker: rng.gen(),
+            rng,
+            streams: Vec::new(),
+            state: ConnectionState::New,
+            transmits: VecDeque::new(),
+            events: VecDeque::new(),
+            next_stream: 0,
+            next_check: None,
+            now: None,
+            epoch: None,
+            bundled: 0,
+        }
+    }
+
+    pub fn role(&self) -> Role {
+        self.config.role
+    }
+
+    pub fn tie_breaker(&self) -> u64 {
+        self.tie_breaker
+    }
+
+    /// Generates random credentials for a data stream, like
+    /// `Credentials::random` but with the agent's random number generator.
+    pub fn random_credentials(&mut self) -> Credentials {
+        Credentials::from_rng(&mut self.rng)
+    }
+
+    /// Signals that the peer is a lite agent, which makes this agent
+    /// controlling, defined in
+    /// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-6.1.1).
+    pub fn set_remote_lite(&mut self) {
+        self.switch_role(Role::Controlling);
+    }
+
+    /// Adds a data stream with components 1 to `components`, returning its
+    /// index.
+    ///
+    /// With BUNDLE, every data stream after the first shares its transport,
+    /// including its components, candidates and credentials, and is passed
+    /// to the agent as an alias for it. Events only name the first.
+    pub fn add_stream(&mut self, components: u16, local_credentials: Credentials) -> usize {
+        if self.config.bundle && !self.streams.is_empty() {
+            self.bundled += 1;
+            return self.bundled;
+        }
+        let trickle = self.config.trickle;
+        self.streams
+            .push(DataStream::new(components, local_credentials, trickle));
+        self.streams.len() - 1
+    }
+
+    /// Restarts ICE for `stream` with new local credentials, defined in
+    /// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-9).
+    ///
+    /// The candidates and pairs of the stream are discarded. The new
+    /// candidates and remote credentials are then added as at first, and
+    /// checks restart with `start`. Data is sent on the pairs selected
+    /// before the restart until new ones are selected.
+    pub fn restart(&mut self, stream: usize, local_credentials: Credentials) {
+        let stream = self.transport(stream);
+        let components = self.streams[stream].components;
+        let new = DataStream::new(components, local_credentials, self.config.trickle);
+        let old = mem::replace(&mut self.streams[stream], new);
+        self.streams[stream].selected = old.selected;
+        self.streams[stream].consent = old.consent;
+        self.streams[stream].stats = old.stats;
+    }
+
+    /// Handles a change of the local network interfaces.
+    ///
+    /// The candidates and pairs on a removed interface are discarded, and
+    /// consent to send on a selected pair among them is lost. What happens
+    /// next is up to `config.network_policy`: if it restarts ICE, the local
+    /// candidates left are kept with new credentials.
+    pub fn handle_network_change(&mut self, change: InterfaceChange) {
+        let lite = self.config.lite;
+        let mut selected_lost = false;
+        if let InterfaceChange::Removed(interface) = &change {
+            let ip = interface.ip;
+            for (index, stream) in self.streams.iter_mut().enumerate() {
+                stream
+                    .local_candidates
+                    .retain(|candidate| candidate.base.map(|base| base.ip()) != Some(ip));
+                stream.checklist.pairs.retain(|pair| pair.base().ip() != ip);
+
+                let mut lost: Vec<_> = stream
+                    .selected
+                    .iter()
+                    .filter(|(_, pair)| pair.base().ip() == ip)
+                    .map(|(&component, _)| component)
+                    .collect();
+                lost.sort();
+                for component in lost {
+                    selected_lost = true;
+                    match stream.consent.get_mut(&component) {
+                        Some(consent) => {
+                            if !consent.expired {
+                                consent.expired = true;
+                                self.events.push_back(Event::ConsentLost {
+                                    stream: index,
+                                    component,
+                                });
+                            }
+                        }
+                        None => {
+                            stream.selected.remove(&component);
+                        }
+                    }
+                }
+
+                if stream.started && !lite {
+                    stream.update_checklist();
+                }
+            }
+        }
+
+        let change = NetworkChange {
+            change,
+            selected_lost,
+        };
+        if (self.config.network_policy)(change) == NetworkAction::Restart {
+            for stream in 0..self.streams.len() {
+                let candidates: Vec<_> = self.streams[stream]
+                    .local_candidates
+                    .iter()
+                    .filter(|candidate| candidate.kind != CandidateType::PeerReflexive)
+                    .cloned()
+                    .collect();
+                let credentials = self.random_credentials();
+                self.restart(stream, credentials);
+                for candidate in candidates {
+                    self.add_local_candidate(stream, candidate);
+                }
+                self.events.push_back(Event::Restarted { stream });
+            }
+        }
+        self.update();
+    }
+
+    /// Collapses the RTP and RTCP components of `stream` into the RTP one,
+    /// once the peer has agreed to multiplex them, defined in
+    /// [RFC 5761](https://tools.ietf.org/html/rfc5761#section-5.1.3). The
+    /// candidates and pairs of the RTCP component are discarded.
+    pub fn rtcp_mux(&mut self, stream: usize) {
+        let stream = self.transport(stream);
+        let DataStream {
+            components,
+            local_candidates,
+            remote_candidates,
+            checklist,
+            selected,
+            consent,
+            started,
+            ..
+        } = &mut self.streams[stream];
+        *components = RTP_COMPONENT;
+        local_candidates.retain(|candidate| candidate.component == RTP_COMPONENT);
+        remote_candidates.retain(|candidate| candidate.component == RTP_COMPONENT);
+        checklist
+            .pairs
+            .retain(|pair| pair.component() == RTP_COMPONENT);
+        selected.retain(|&component, _| component == RTP_COMPONENT);
+        consent.retain(|&component, _| component == RTP_COMPONENT);
+
+        if *started && !self.config.lite {
+            self.streams[stream].update_checklist();
+            self.update();
+        }
+    }
+
+    pub fn local_credentials(&self, stream: usize) -> &Credentials {
+        &self.streams[self.transport(stream)].local_credentials
+    }
+
+    pub fn set_remote_credentials(&mut self, stream: usize, credentials: Credentials) {
+        let stream = self.transport(stream);
+        self.streams[stream].remote_credentials = Some(credentials);
+    }
+
+    /// Adds a candidate gathered for `stream`. After checks have started,
+    /// it is paired with the remote candidates right away. A lite agent
+    /// ignores all but host candidates.
+    pub fn add_local_candidate(&mut self, stream: usize, candidate: Candidate) {
+        let stream = self.transport(stream);
+        if self.config.lite && candidate.kind != CandidateType::Host {
+            return;
+        }
+        let controlling = self.is_controlling();
+        let max_pairs = self.config.max_pairs;
+        let index = stream;
+        let stream = &mut self.streams[stream];
+        if stream.local_candidates.contains(&candidate) {
+            return;
+        }
+        if self.config.trickle {
+            self.events.push_back(Event::LocalCandidate {
+                stream: index,
+                candidate: Box::new(candidate.clone()),
+            });
+        }
+        stream.local_candidates.push(candidate);
+
+        if stream.started {
+            let local = stream.local_candidates.last().unwrap();
+            if let Some(local) = checklist::pairing_base(&stream.local_candidates, local) {
+                for remote in &stream.remote_candidates {
+                    if let Some(pair) = checklist::new_pair(local, remote, controlling) {
+                        stream.checklist.add_trickled(pair, max_pairs);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Adds a candidate received from the peer for `stream`. After checks
+    /// have started, it is paired with the local candidates right away.
+    ///
+    /// A candidate with a multicast DNS hostname is only paired once the
+    /// hostname is resolved.
+    pub fn add_remote_candidate(&mut self, stream: usize, candidate: Candidate) {
+        let stream = self.transport(stream);
+        let controlling = self.is_controlling();
+        let max_pairs = self.config.max_pairs;
+        let stream = &mut self.streams[stream];
+        if stream.remote_candidates.contains(&candidate) {
+            return;
+        }
+        match &candidate.hostname {
+            Some(hostname) if candidate.addr.ip().is_unspecified() => {
+                self.events.push_back(Event::ResolveHostname {
+                    hostname: hostname.clone(),
+                });
+                stream.remote_candidates.push(candidate);
+                return;
+            }
+            _ => (),
+        }
+
+        if stream.started {
+            let locals = &stream.local_candidates;
+            for local in locals
+                .iter()
+                .filter_map(|local| checklist::pairing_base(locals, local))
+            {
+                if let Some(pair) = checklist::new_pair(local, &candidate, controlling) {
+                    stream.checklist.add_trickled(pair, max_pairs);
+                }
+            }
+        }
+        stream.remote_candidates.push(candidate);
+    }
+
+    /// Sets the IP address of the remote candidates with a multicast DNS
+    /// `hostname`, which pairs them.
+    pub fn resolve_hostname(&mut self, hostname: &str, ip: IpAddr) {
+        for stream in 0..self.streams.len() {
+            let remotes = &mut self.streams[stream].remote_candidates;
+            let mut resolved = Vec::new();
+            remotes.retain(|candidate| match &candidate.hostname {
+                Some(other)
+                    if other.eq_ignore_ascii_case(hostname)
+                        && candidate.addr.ip().is_unspecified() =>
+                {
+                    resolved.push(candidate.clone());
+                    false
+                }
+                _ => true,
+            });
+
+            for mut candidate in resolved {
+                candidate.addr.set_ip(ip);
+                self.add_remote_candidate(stream, candidate);
+            }
+        }
+    }
+
+    /// Signals that all local candidates of `stream` have been gathered and
+    /// added, so that the end of candidates can be sent to the peer.
+    pub fn end_local_candidates(&mut self, stream: usize) {
+        let stream = self.transport(stream);
+        if !self.streams[stream].local_ended {
+            self.streams[stream].local_ended = true;
+            self.events
+                .push_back(Event::EndOfLocalCandidates { stream });
+            self.end_candidates(stream);
+        }
+    }
+
+    /// Signals that the peer has sent all its candidates of `stream`.
+    pub fn end_remote_candidates(&mut self, stream: usize) {
+        let stream = self.transport(stream);
+        self.streams[stream].remote_ended = true;
+        self.end_candidates(stream);
+    }
+
+    fn end_candidates(&mut self, stream: usize) {
+        if self.streams[stream].started && !self.config.lite {
+            self.streams[stream].update_checklist();
+            self.update();
+        }
+    }
+
+    pub fn local_candidates(&self, stream: usize) -> &[Candidate] {
+        &self.streams[self.transport(stream)].local_candidates
+    }
+
+    pub fn remote_candidates(&self, stream: usize) -> &[Candidate] {
+        &self.streams[self.transport(stream)].remote_candidates
+    }
+
+    /// Returns the candidate pairs of `stream`, in decreasing priority.
+    pub fn pairs(&self, stream: usize) -> &[CandidatePair] {
+        &self.streams[self.transport(stream)].checklist.pairs
+    }
+
+    /// Returns the valid pairs of `stream`, in decreasing priority.
+    pub fn valid_pairs(&self, stream: usize) -> impl Iterator<Item = &CandidatePair> {
+        self.pairs(stream).iter().filter(|pair| pair.valid)
+    }
+
+    pub fn checklist_state(&self, stream: usize) -> ChecklistState {
+        self.streams[self.transport(stream)].checklist.state
+    }
+
+    /// Returns the pair data for `component` of `stream` is sent on: the
+    /// highest priority nominated pair, as it was when selected.
+    pub fn selected_pair(&self, stream: usize, component: u16) -> Option<&CandidatePair> {
+        self.streams[self.transport(stream)]
+            .selected
+            .get(&component)
+    }
+
+    /// Returns whether data for `component` of `stream` may be sent on its
+    /// selected pair: whether the peer consents to receive it, defined in
+    /// [RFC 7675](https://tools.ietf.org/html/rfc7675#section-5.1).
+    pub fn can_send(&self, stream: usize, component: u16) -> bool {
+        let stream = self.transport(stream);
+        let stream = &self.streams[stream];
+        stream.selected.contains_key(&component)
+            && stream
+                .consent
+                .get(&component)
+                .map(|consent| consent.expired)
+                != Some(true)
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    /// Returns the statistics of `pair`, one of the pairs or selected pairs
+    /// of `stream`.
+    pub fn pair_stats(&self, stream: usize, pair: &CandidatePair) -> Option<&PairStats> {
+        self.streams[self.transport(stream)].stats.get(&pair.key())
+    }
+
+    /// Records a packet of `len` bytes sent at `now` for `component` of
+    /// `stream`, on its selected pair.
+    pub fn record_sent(&mut self, now: Instant, stream: usize, component: u16, len: usize) {
+        let stream = self.transport(stream);
+        if let Some(stats) = self.streams[stream].selected_stats(component) {
+            stats.packets_sent += 1;
+            stats.bytes_sent += len as u64;
+            stats.last_packet_sent = Some(now);
+        }
+    }
+
+    /// Records a packet of `len` bytes received at `now` for `component` of
+    /// `stream`, on its selected pair.
+    pub fn record_received(&mut self, now: Instant, stream: usize, component: u16, len: usize) {
+        let stream = self.transport(stream);
+        if let Some(stats) = self.streams[stream].selected_stats(component) {
+            stats.packets_received += 1;
+            stats.bytes_received += len as u64;
+            stats.last_packet_received = Some(now);
+        }
+    }
+
+    /// Dumps the state of the agent as JSON, for diagnosing failed
+    /// connections: the candidates, pairs and selected pairs of every data
+    /// stream, with the statistics of the pairs. Timestamps are in
+    /// milliseconds since the first time passed to the agent. Passwords are
+    /// left out.
+    pub fn diagnostics(&self) -> serde_json::Value {
+        let epoch = self.epoch.unwrap_or_else(Instant::now);
+        let default_stats = PairStats::default();
+        let pair_json = |stream: &DataStream, pair: &CandidatePair| {
+            let stats = stream.stats.get(&pair.key()).unwrap_or(&default_stats);
+            stats::pair_json(pair, stats, epoch)
+        };
+
+        let streams: Vec<_> = self
+            .streams
+            .iter()
+            .map(|stream| {
+                let mut selected: Vec<_> = stream.selected.iter().collect();
+                selected.sort_by_key(|&(&component, _)| component);
+                let remote_ufrag = stream
+                    .remote_credentials
+                    .as_ref()
+                    .map(|credentials| &credentials.ufrag);
+                json!({
+                    "components": stream.components,
+                    "localUfrag": stream.local_credentials.ufrag,
+                    "remoteUfrag": remote_ufrag,
+                    "state": stats::checklist_state_name(stream.checklist.state),
+                    "localCandidates": stream
+                        .local_candidates
+                        .iter()
+                        .map(stats::candidate_json)
+                        .collect::<Vec<_>>(),
+                    "remoteCandidates": stream
+                        .remote_candidates
+                        .iter()
+                        .map(stats::candidate_json)
+                        .collect::<Vec<_>>(),
+                    "pairs": stream
+                        .checklist
+                        .pairs
+                        .iter()
+                        .map(|pair| pair_json(stream, pair))
+                        .collect::<Vec<_>>(),
+                    "selectedPairs": selected
+                        .into_iter()
+                        .map(|(&component, pair)| {
+                            let can_send = stream
+                                .consent
+                                .get(&component)
+                                .map(|consent| consent.expired)
+                                != Some(true);
+                            json!({
+                                "component": component,
+                                "consent": can_send,
+                                "pair": pair_json(stream, pair),
+                            })
+                        })
+                        .collect::<Vec<_>>(),
+                })
+            })
+            .collect();
+        json!({
+            "role": stats::role_name(self.config.role),
+            "lite": self.config.lite,
+            "state": stats::connection_state_name(self.state),
+            "streams": streams,
+        })
+    }
+
+    /// Forms the checklists and starts connectivity checks, defined in
+    /// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-6.1.2), for
+    /// the data streams not started yet or restarted. A lite agent only
+    /// starts responding to checks.
+    pub fn start(&mut self, now: Instant) {
+        self.set_now(now);
+        if self.config.lite {
+            for stream in &mut self.streams {
+                stream.started = true;
+            }
+            self.next_check = Some(now);
+            return self.update();
+        }
+
+        let controlling = self.is_controlling();
+        let started: Vec<_> = (0..self.streams.len())
+            .filter(|&i| !self.streams[i].started)
+            .collect();
+        for &i in &started {
+            let stream = &mut self.streams[i];
+            let mut pairs = checklist::form_pairs(
+                &stream.local_candidates,
+                &stream.remote_candidates,
+                controlling,
+            );
+            pairs.truncate(self.config.max_pairs);
+            stream.checklist.pairs = pairs;
+        }
+
+        // One pair per foundation across all checklists is set Waiting,
+        // preferring the lowest component and then the highest priority.
+        let mut foundations: Vec<(String, String)> = Vec::new();
+        for &i in &started {
+            let pairs = &mut self.streams[i].checklist.pairs;
+            let mut order: Vec<_> = (0..pairs.len()).collect();
+            order.sort_by_key(|&i| (pairs[i].component(), Reverse(pairs[i].priority)));
+
+            for i in order {
+                let (local, remote) = pairs[i].foundation();
+                let foundation = (local.to_owned(), remote.to_owned());
+                if !foundations.contains(&foundation) {
+                    foundations.push(foundation);
+                    pairs[i].state = PairState::Waiting;
+                }
+            }
+        }
+
+        self.next_check.get_or_insert(now);
+        for &i in &started {
+            let stream = &mut self.streams[i];
+            stream.started = true;
+            stream.update_checklist();
+        }
+        self.update();
+    }
+
+    /// Returns when `handle_timeout` should next be called.
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        if self.config.lite {
+            return None;
+        }
+
+        let running = self
+            .streams
+            .iter()
+            .any(|stream| stream.checklist.state == ChecklistState::Running);
+        let next_check = if running { self.next_check } else { None };
+
+        self.streams
+            .iter()
+            .flat_map(|stream| &stream.checklist.pairs)
+            .filter_map(|pair| pair.check.as_ref().map(|check| check.deadline))
+            .chain(next_check)
+            .chain(
+                self.streams
+                    .iter()
+                    .flat_map(|stream| stream.consent.values())
+                    .filter_map(Consent::timeout),
+            )
+            .min()
+    }
+
+    pub fn handle_timeout(&mut self, now: Instant) {
+        self.set_now(now);
+        if self.config.lite {
+            return;
+        }
+
+        self.retransmit(now);
+        if self.is_controlling() {
+            self.nominate(now);
+        }
+
+        match self.next_check {
+            Some(next_check) if now >= next_check => {
+                self.next_check = Some(now + self.config.ta);
+                self.check_next(now);
+            }
+            _ => (),
+        }
+        self.check_consent(now);
+        self.update();
+    }
+
+    /// Returns the next datagram to send.
+    pub fn poll_transmit(&mut self) -> Option<Transmit> {
+        self.transmits.pop_front()
+    }
+
+    /// Returns the next event.
+    pub fn poll_event(&mut self) -> Option<Event> {
+        self.events.pop_front()
+    }
+
+    /// Handles a datagram received over `transport` on the local base
+    /// address `local` from `from`, or a frame received on a TCP connection
+    /// between them. Returns `false` if it is not an ICE connectivity check
+    /// message, in which case it is left to the application.
+    pub fn handle_receive(
+        &mut self,
+        now: Instant,
+        transport: Transport,
+        local: SocketAddr,
+        from: SocketAddr,
+        data: &[u8],
+    ) -> bool {
+        let msg = match stun::decode(data) {
+            Some(msg) => msg,
+            None => return false,
+        };
+        if msg.method != Method::BINDING || !msg.verify_fingerprint() {
+            return false;
+        }
+
+        self.set_now(now);
+        match msg.class {
+            Class::Request => self.handle_request((transport, local, from), &msg),
+            Class::SuccessResponse | Class::FailureResponse => {
+                self.handle_response(now, (transport, local, from), &msg)
+            }
+            Class::Indication => (),
+        }
+        self.update();
+        true
+    }
+
+    /// Returns the data stream that runs ICE for `stream`, which is the
+    /// first one with BUNDLE.
+    fn transport(&self, stream: usize) -> usize {
+        if self.config.bundle {
+            assert!(stream <= self.bundled, "no data stream {}", stream);
+            0
+        } else {
+            stream
+        }
+    }
+
+    fn set_now(&mut self, now: Instant) {
+        self.now = Some(now);
+        self.epoch.get_or_insert(now);
+    }
+
+    fn is_controlling(&self) -> bool {
+        self.config.role == Role::Controlling
+    }
+
+    /// Switches the role of the agent, which changes the priorities of all
+    /// pairs.
+    fn switch_role(&mut self, role: Role) {
+        if self.config.role == role {
+            return;
+        }
+        self.config.role = role;
+
+        let controlling = self.is_controlling();
+        for stream in &mut self.streams {
+            let pairs = &mut stream.checklist.pairs;
+            for pair in pairs.iter_mut() {
+                pair.update_priority(controlling);
+            }
+            pairs.sort_by_key(|pair| Reverse(pair.priority));
+        }
+        self.events.push_back(Event::RoleChanged(role));
+    }
+
+    /// Reports newly selected pairs and completes checklists, then reports
+    /// any change of the connection state.
+    fn update(&mut self) {
+        let consent = if self.config.lite { None } else { self.now };
+        for (index, stream) in self.streams.iter_mut().enumerate() {
+            let mut nominated = 0;
+            for component in 1..=stream.components {
+                let pair = match stream
+                    .checklist
+                    .pairs
+                    .iter()
+                    .find(|pair| pair.nominated && pair.component() == component)
+                {
+                    Some(pair) => pair,
+                    None => continue,
+                };
+                nominated += 1;
+                if stream.selected.get(&component).map(CandidatePair::key) != Some(pair.key()) {
+                    stream.selected.insert(component, pair.clone());
+                    self.events.push_back(Event::SelectedPair {
+                        stream: index,
+                        component,
+                        pair: Box::new(pair.clone()),
+                    });
+                    // The check that nominated the pair is the first
+                    // consent to send on it.
+                    if let Some(now) = consent {
+                        let interval = self.config.consent_interval;
+                        let timeout = self.config.consent_timeout;
+                        let consent =
+                            Consent::new(pair.key(), now, interval, timeout, &mut self.rng);
+                        stream.consent.insert(component, consent);
+                    }
+                }
+            }
+
+            if stream.checklist.state == ChecklistState::Running && nominated == stream.components {
+                stream.checklist.complete();
+            }
+            if let Some(now) = self.now {
+                stream.update_stats(now);
+            }
+        }
+
+        if self.next_check.is_none() {
+            return;
+        }
+        let state = self.connection_state();
+        if state != self.state {
+            self.state = state;
+            self.events.push_back(Event::StateChanged(state));
+        }
+    }
+
+    fn connection_state(&self) -> ConnectionState {
+        let states = || self.streams.iter().map(|stream| stream.checklist.state);
+        if states().any(|state| state == ChecklistState::Failed) {
+            ConnectionState::Failed
+        } else if self
+            .streams
+            .iter()
+            .flat_map(|stream| stream.consent.values())
+            .any(|consent| consent.expired)
+        {
+            ConnectionState::Disconnected
+        } else if states().all(|state| state == ChecklistState::Completed) {
+            ConnectionState::Completed
+        } else if self.streams.iter().all(|stream| {
+            (1..=stream.components).all(|component| {
+                stream.selected.contains_key(&component)
+                    || stream
+                        .checklist
+                        .pairs
+                        .iter()
+                        .any(|pair| pair.valid && pair.component() == component)
+            })
+        }) {
+            ConnectionState::Connected
+        } else {
+            ConnectionState::Checking
+        }
+    }
+
+    /// Nominates the highest priority valid pair of each component of the
+    /// running checklists, defined in
+    /// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-8.1.1).
+    fn nominate(&mut self, now: Instant) {
+        let timeout = self.config.nomination_timeout;
+        for stream in &mut self.streams {
+            let checklist = &mut stream.checklist;
+            if checklist.state != ChecklistState::Running
+                || checklist
+                    .pairs
+                    .iter()
+                    .any(|pair| pair.use_candidate || pair.nominated)
+            {
+                continue;
+            }
+
+            let best: Vec<_> = (1..=stream.components)
+                .filter_map(|component| {
+                    checklist
+                        .pairs
+                        .iter()
+                        .position(|pair| pair.valid && pair.component() == component)
+                })
+                .collect();
+            if best.len() < usize::from(stream.components) {
+                continue;
+            }
+
+            // Higher priority pairs still being checked may yet succeed, but
+            // are only waited for so long.
+            let valid_since = *stream.valid_since.get_or_insert(now);
+            let pending = best.iter().any(|&i| {
+                let component = checklist.pairs[i].component();
+                checklist.pairs[..i]
+                    .iter()
+                    .any(|pair| pair.component() == component && pair.is_pending())
+            });
+            if pending && now < valid_since + timeout {
+                continue;
+            }
+
+            for i in best {
+                checklist.nominate(i);
+            }
+        }
+    }
+
+    /// Sends the next ordinary or triggered check, from the checklists in
+    /// turn.
+    fn check_next(&mut self, now: Instant) {
+        let count = self.streams.len();
+        for k in 0..count {
+            let stream = (self.next_stream + k) % count;
+            if let Some(i) = self.next_pair(stream) {
+                self.send_check(now, stream, i);
+                self.next_stream = stream + 1;
+                return;
+            }
+        }
+    }
+
+    /// Picks the pair of `stream` to check next, defined in
+    /// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-6.1.4.2).
+    fn next_pair(&mut self, stream: usize) -> Option<usize> {
+        {
+            let stream = &mut self.streams[stream];
+            if stream.remote_credentials.is_none()
+                || stream.checklist.state != ChecklistState::Running
+            {
+                return None;
+            }
+
+            let checklist = &mut stream.checklist;
+            while let Some(key) = checklist.triggered.pop_front() {
+                match checklist.find(key) {
+                    Some(i) if checklist.pairs[i].state == PairState::Waiting => return Some(i),
+                    _ => (),
+                }
+            }
+        }
+
+        let waiting = |streams: &[DataStream]| {
+            streams[stream]
+                .checklist
+                .pairs
+                .iter()
+                .position(|pair| pair.state == PairState::Waiting)
+        };
+        if let Some(i) = waiting(&self.streams) {
+            return Some(i);
+        }
+
+        // A Frozen pair is unfrozen if no pair with its foundation is
+        // Waiting or In-Progress in any checklist.
+        for i in 0..self.streams[stream].checklist.pairs.len() {
+            let pair = &self.streams[stream].checklist.pairs[i];
+            if pair.state == PairState::Frozen
+                && !self
+                    .streams
+                    .iter()
+                    .any(|other| other.checklist.is_foundation_active(pair.foundation()))
+            {
+                self.streams[stream].checklist.pairs[i].state = PairState::Waiting;
+            }
+        }
+        waiting(&self.streams)
+    }
+
+    /// Sends a connectivity check for the pair at `i`, defined in
+    /// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-7.2.4).
+    fn send_check(&mut self, now: Instant, stream: usize, i: usize) {
+        let role = self.config.role;
+        let pair = &self.streams[stream].checklist.pairs[i];
+        // Checks over TCP are not retransmitted, defined in
+        // [RFC 6544](https://tools.ietf.org/html/rfc6544#section-7.1).
+        let (requests, rto) = match pair.local.transport {
+            Transport::Udp => (1, self.config.rto),
+            Transport::Tcp => (self.config.max_requests, self.config.tcp_timeout),
+        };
+        let use_candidate = pair.use_candidate && role == Role::Controlling;
+        let transaction_id = TransactionId::from_rng(&mut self.rng);
+        let (priority, request) = self.request(transaction_id, stream, pair, use_candidate);
+
+        let stream = &mut self.streams[stream];
+        let pair = &mut stream.checklist.pairs[i];
+        pair.state = PairState::InProgress;
+        pair.check = Some(Check {
+            transaction_id,
+            request: request.clone(),
+            priority,
+            controlling: role == Role::Controlling,
+            requests,
+            sent: now,
+            deadline: now + rto,
+            rto,
+            cancelled: false,
+        });
+        stream.stats.entry(pair.key()).or_default().requests_sent += 1;
+        self.transmits.push_back(Transmit {
+            transport: pair.local.transport,
+            source: pair.base(),
+            destination: pair.remote.addr,
+            data: request,
+        });
+    }
+
+    /// Builds a connectivity check of `pair` of `stream` with
+    /// `transaction_id`, returning the PRIORITY it carries and its encoding.
+    fn request(
+        &self,
+        transaction_id: TransactionId,
+        stream: usize,
+        pair: &CandidatePair,
+        use_candidate: bool,
+    ) -> (u32, Vec<u8>) {
+        let stream = &self.streams[stream];
+        let remote_credentials = stream.remote_credentials.as_ref().unwrap();
+
+        // The priority a peer reflexive candidate learned from the check
+        // would have.
+        let priority = candidate::compute_priority(
+            CandidateType::PeerReflexive,
+            (pair.local.priority >> 8) as u16,
+            pair.component(),
+        );
+
+        let mut msg = Message::new(Class::Request, Method::BINDING, transaction_id);
+        msg.add_attr(&Username(format!(
+            "{}:{}",
+            remote_credentials.ufrag, stream.local_credentials.ufrag
+        )));
+        msg.add_attr(&Priority(priority));
+        match self.config.role {
+            Role::Controlling => msg.add_attr(&IceControlling(self.tie_breaker)),
+            Role::Controlled => msg.add_attr(&IceControlled(self.tie_breaker)),
+        }
+        if use_candidate {
+            msg.add_attr(&UseCandidate);
+        }
+        msg.add_message_integrity(&MessageIntegrity::short_term_key(&remote_credentials.pwd));
+        msg.add_fingerprint();
+        (priority, stun::encode(msg))
+    }
+
+    /// Sends the consent checks due on the selected pairs, and expires the
+    /// consent not refreshed in time, defined in
+    /// [RFC 7675](https://tools.ietf.org/html/rfc7675#section-5.1).
+    fn check_consent(&mut self, now: Instant) {
+        let interval = self.config.consent_interval;
+        for index in 0..self.streams.len() {
+            for component in 1..=self.streams[index].components {
+                let stream = &self.streams[index];
+                let (consent, pair) = match (
+                    stream.consent.get(&component),
+                    stream.selected.get(&component),
+                ) {
+                    (Some(consent), Some(pair)) if !consent.expired => (consent, pair),
+                    _ => continue,
+                };
+
+                if now >= consent.expiry {
+                    let consent = self.streams[index].consent.get_mut(&component).unwrap();
+                    consent.expired = true;
+                    self.events.push_back(Event::ConsentLost {
+                        stream: index,
+                        component,
+                    });
+                    continue;
+                }
+                // Checks wait for the remote credentials after a restart.
+                if now < consent.next_check || stream.remote_credentials.is_none() {
+                    continue;
+                }
+
+                let transaction_id = TransactionId::from_rng(&mut self.rng);
+                let (_, request) = self.request(transaction_id, index, pair, false);
+                self.transmits.push_back(Transmit {
+                    transport: pair.local.transport,
+                    source: pair.base(),
+                    destination: pair.remote.addr,
+                    data: request,
+                });
+                let stream = &mut self.streams[index];
+                if let Some(stats) = stream.selected_stats(component) {
+                    stats.consent_requests_sent += 1;
+                }
+                let consent = stream.consent.get_mut(&component).unwrap();
+                consent.sent(transaction_id, now, interval, &mut self.rng);
+            }
+        }
+    }
+
+    /// Retransmits the checks whose responses are overdue, and fails those
+    /// that have run out of requests.
+    fn retransmit(&mut self, now: Instant) {
+        let max_requests = self.config.max_requests;
+        for stream in &mut self.streams {
+            for pair in &mut stream.checklist.pairs {
+                let (transport, source, destination) = pair.key();
+                let check = match &mut pair.check {
+                    Some(check) if check.deadline <= now => check,
+                    _ => continue,
+                };
+
+                if check.cancelled {
+                    pair.check = None;
+                    continue;
+                }
+                let stats = stream
+                    .stats
+                    .entry((transport, source, destination))
+                    .or_default();
+                if check.requests >= max_requests {
+                    pair.check = None;
+                    pair.state = PairState::Failed;
+                    pair.use_candidate = false;
+                    stats.last_error = Some(CheckError::Timeout);
+                    continue;
+                }
+                check.requests += 1;
+                check.rto *= 2;
+                check.sent = now;
+                check.deadline = now + check.rto;
+                stats.retransmissions_sent += 1;
+                self.transmits.push_back(Transmit {
+                    transport,
+                    source,
+                    destination,
+                    data: check.request.clone(),
+                });
+            }
+            stream.update_checklist();
+        }
+    }
+
+    /// Responds to a connectivity check, defined in
+    /// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-7.3.1).
+    fn handle_request(&mut self, path: Key, msg: &Message) {
+        let (transport, local, from) = path;
+        let (username, priority) = match (
+            msg.attr::<Username>(),
+            msg.attr::<Priority>(),
+            msg.attr::<MessageIntegrity>(),
+        ) {
+            (Some(username), Some(priority), Some(_)) => (username.0, priority.0),
+            _ => return self.respond_error(path, msg, ErrorCode::BAD_REQUEST, None),
+        };
+
+        let local_ufrag = username.split(':').next().unwrap_or_default();
+        let stream = match self.streams.iter().position(|stream| {
+            stream.local_credentials.ufrag == local_ufrag
+                && msg.verify_message_integrity(&MessageIntegrity::short_term_key(
+                    &stream.local_credentials.pwd,
+                ))
+        }) {
+            Some(stream) => stream,
+            None => return self.respond_error(path, msg, ErrorCode::UNAUTHORIZED, None),
+        };
+        let key = MessageIntegrity::short_term_key(&self.streams[stream].local_credentials.pwd);
+
+        // Role conflicts are resolved by comparing tie-breakers, defined in
+        // [RFC 8445](https://tools.ietf.org/html/rfc8445#section-7.3.1.1).
+        let conflict = match self.config.role {
+            Role::Controlling => msg.attr::<IceControlling>().map(|attr| attr.0),
+            Role::Controlled => msg.attr::<IceControlled>().map(|attr| attr.0),
+        };
+        if let Some(tie_breaker) = conflict {
+            // A lite agent cannot switch to controlling.
+            match (self.config.role, self.tie_breaker >= tie_breaker) {
+                (Role::Controlling, false) => self.switch_role(Role::Controlled),
+                (Role::Controlled, true) if !self.config.lite => {
+                    self.switch_role(Role::Controlling)
+                }
+                _ => {
+                    let code = ErrorCode::ROLE_CONFLICT;
+                    return self.respond_error(path, msg, code, Some(&key));
+                }
+            }
+        }
+
+        let controlling = self.is_controlling();
+        let lite = self.config.lite;
+        let max_pairs = self.config.max_pairs;
+        let DataStream {
+            local_candidates,
+            remote_candidates,
+            checklist,
+            started,
+            stats,
+            ..
+        } = &mut self.streams[stream];
+
+        let base = match local_candidates.iter().find(|candidate| {
+            candidate.base == Some(local)
+                && candidate.transport == transport
+                && (candidate.kind == CandidateType::Host
+                    || candidate.kind == CandidateType::Relayed)
+        }) {
+            Some(base) => base,
+            None => return,
+        };
+
+        let mut response =
+            Message::new(Class::SuccessResponse, Method::BINDING, msg.transaction_id);
+        response.add_attr(&XorMappedAddress(from));
+        response.add_message_integrity(&key);
+        response.add_fingerprint();
+        self.transmits.push_back(Transmit {
+            transport,
+            source: local,
+            destination: from,
+            data: stun::encode(response),
+        });
+
+        // A request from an unknown address reveals a peer reflexive
+        // candidate, defined in
+        // [RFC 8445](https://tools.ietf.org/html/rfc8445#section-7.3.1.3).
+        // Over TCP, it connected to or accepted from the base, per
+        // [RFC 6544](https://tools.ietf.org/html/rfc6544#section-7.2).
+        let remote = match remote_candidates
+            .iter()
+            .position(|candidate| candidate.addr == from && candidate.transport == transport)
+        {
+            Some(i) => i,
+            None => {
+                remote_candidates.push(Candidate {
+                    base: None,
+                    related_addr: None,
+                    tcp_type: base.tcp_type.map(TcpType::peer),
+                    ..Candidate::peer_reflexive(base.component, transport, from, from, priority)
+                });
+                remote_candidates.len() - 1
+            }
+        };
+
+        if !*started {
+            return;
+        }
+        let i = match checklist.find(path) {
+            Some(i) => i,
+            None => match checklist::new_pair(base, &remote_candidates[remote], controlling)
+                .and_then(|pair| checklist.add(pair, max_pairs))
+            {
+                Some(i) => i,
+                None => return,
+            },
+        };
+        let stats = stats.entry(path).or_default();
+        stats.requests_received += 1;
+        stats.responses_sent += 1;
+
+        if lite {
+            // A lite agent takes the pairs the controlling agent nominates
+            // as valid, without checking them, defined in
+            // [RFC 8445](https://tools.ietf.org/html/rfc8445#section-7.3.1.5).
+            if msg.attr::<UseCandidate>().is_some() {
+                let pair = &mut checklist.pairs[i];
+                pair.state = PairState::Succeeded;
+                pair.valid = true;
+                pair.nominated = true;
+            }
+            return;
+        }
+        checklist.trigger(i);
+
+        // The controlled agent nominates a pair once a check of it with
+        // USE-CANDIDATE succeeds both ways, defined in
+        // [RFC 8445](https://tools.ietf.org/html/rfc8445#section-7.3.1.5).
+        if !controlling && msg.attr::<UseCandidate>().is_some() {
+            let pair = &mut checklist.pairs[i];
+            if pair.state == PairState::Succeeded {
+                pair.nominated = true;
+            } else {
+                pair.use_candidate = true;
+            }
+        }
+    }
+
+    /// Sends an error response, authenticated with `key` if the request
+    /// was.
+    fn respond_error(
+        &mut self,
+        (transport, local, from): Key,
+        msg: &Message,
+        code: u16,
+        key: Option<&[u8]>,
+    ) {
+        let reason = match code {
+            ErrorCode::BAD_REQUEST => "Bad Request",
+            ErrorCode::UNAUTHORIZED => "Unauthorized",
+            ErrorCode::ROLE_CONFLICT => "Role Conflict",
+            _ => "",
+        };
+        let mut response =
+            Message::new(Class::FailureResponse, Method::BINDING, msg.transaction_id);
+        response.add_attr(&ErrorCode::new(code, reason));
+        if let Some(key) = key {
+            response.add_message_integrity(key);
+        }
+        response.add_fingerprint();
+        self.transmits.push_back(Transmit {
+            transport,
+            source: local,
+            destination: from,
+            data: stun::encode(response),
+        });
+    }
+
+    /// Processes the response to a connectivity check, defined in
+    /// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-7.2.5).
+    fn handle_response(&mut self, now: Instant, path: Key, msg: &Message) {
+        let found = self.streams.iter().enumerate().find_map(|(s, stream)| {
+            stream
+                .checklist
+                .pairs
+                .iter()
+                .position(|pair| {
+                    pair.check.as_ref().map(|check| check.transaction_id)
+                        == Some(msg.transaction_id)
+                })
+                .map(|i| (s, i))
+        });
+        let (index, i) = match found {
+            Some(found) => found,
+            None => return self.handle_consent_response(now, path, msg),
+        };
+
+        let stream = &mut self.streams[index];
+        let key = match &stream.remote_credentials {
+            Some(credentials) => MessageIntegrity::short_term_key(&credentials.pwd),
+            None => return,
+        };
+        if !msg.verify_message_integrity(&key) {
+            return;
+        }
+
+        let pair = &mut stream.checklist.pairs[i];
+        let check = pair.check.take().unwrap();
+        let stats = stream.stats.entry(pair.key()).or_default();
+
+        // The agent switches to the role opposite to the one it sent the
+        // check as and retries, defined in
+        // [RFC 8445](https://tools.ietf.org/html/rfc8445#section-7.2.5.1).
+        if msg.attr::<ErrorCode>().map(|error| error.code) == Some(ErrorCode::ROLE_CONFLICT) {
+            stats.responses_received += 1;
+            stats.last_error = Some(CheckError::ErrorResponse(ErrorCode::ROLE_CONFLICT));
+            let key = pair.key();
+            self.switch_role(if check.controlling {
+                Role::Controlled
+            } else {
+                Role::Controlling
+            });
+            let checklist = &mut self.streams[index].checklist;
+            if let Some(i) = checklist.find(key) {
+                checklist.trigger(i);
+            }
+            return;
+        }
+
+        let mapped = match msg.attr::<XorMappedAddress>() {
+            // Responses must come from where the request was sent, to
+            // where it was sent from.
+            Some(mapped) if msg.class == Class::SuccessResponse && pair.key() == path => mapped.0,
+            _ => {
+                stats.responses_received += 1;
+                stats.last_error = Some(match msg.attr::<ErrorCode>() {
+                    Some(error) if msg.class == Class::FailureResponse => {
+                        CheckError::ErrorResponse(error.code)
+                    }
+                    _ => CheckError::InvalidResponse,
+                });
+                pair.state = PairState::Failed;
+                pair.use_candidate = false;
+                stream.update_checklist();
+                return;
+            }
+        };
+        stats.response(now - check.sent);
+        pair.state = PairState::Succeeded;
+        pair.valid = true;
+        if pair.use_candidate {
+            pair.nominated = true;
+        }
+
+        // A mapped address matching no local candidate reveals a peer
+        // reflexive one, defined in
+        // [RFC 8445](https://tools.ietf.org/html/rfc8445#section-7.2.5.3.1).
+        let (transport, local, _) = path;
+        if !stream
+            .local_candidates
+            .iter()
+            .any(|candidate| candidate.addr == mapped && candidate.transport == transport)
+        {
+            stream.local_candidates.push(Candidate {
+                tcp_type: pair.local.tcp_type,
+                ..Candidate::peer_reflexive(
+                    pair.component(),
+                    transport,
+                    mapped,
+                    local,
+                    check.priority,
+                )
+            });
+        }
+
+        stream.update_checklist();
+
+        // Pairs with the same foundation are likely to succeed too, in every
+        // checklist, defined in
+        // [RFC 8445](https://tools.ietf.org/html/rfc8445#section-7.2.5.3.3).
+        let pair = &self.streams[index].checklist.pairs[i];
+        let (local_foundation, remote_foundation) = (
+            pair.local.foundation.clone(),
+            pair.remote.foundation.clone(),
+        );
+        for stream in &mut self.streams {
+            if stream.checklist.state != ChecklistState::Running {
+                continue;
+            }
+            for pair in &mut stream.checklist.pairs {
+                if pair.state == PairState::Frozen
+                    && pair.foundation() == (&local_foundation, &remote_foundation)
+                {
+                    pair.state = PairState::Waiting;
+                }
+            }
+        }
+    }
+
+    /// Refreshes consent on a successful response to a consent check,
+    /// defined in [RFC 7675](https://tools.ietf.org/html/rfc7675#section-5.1).
+    fn handle_consent_response(&mut self, now: Instant, path: Key, msg: &Message) {
+        let timeout = self.config.consent_timeout;
+        for stream in &mut self.streams {
+            let key = match &stream.remote_credentials {
+                Some(credentials) => MessageIntegrity::short_term_key(&credentials.pwd),
+                None => continue,
+            };
+            for consent in stream.consent.values_mut() {
+                let sent = match consent.take(msg.transaction_id) {
+                    Some(sent) => sent,
+                    None => continue,
+                };
+                if msg.class == Class::SuccessResponse
+                    && consent.key == path
+                    && !consent.expired
+                    && msg.verify_message_integrity(&key)
+                {
+                    consent.expiry = now + timeout;
+                    stream
+                        .stats
+                        .entry(consent.key)
+                        .or_default()
+                        .response(now - sent);
+                }
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::candidate::RTCP_COMPONENT;
+    use crate::gather::Interface;
+    use crate::mdns::{Mdns, MdnsEvent};
+    use crate::test_util::{component_host, exchange_credentials, host, Network};
+    use std::iter;
+
+    /// Returns a controlling and a controlled agent with `streams` data
+    /// streams of one component each.
+    fn agents(streams: usize) -> Vec<Agent> {
+        new_agents(streams, false)
+    }
+
+    fn new_agents(streams: usize, trickle: bool) -> Vec<Agent> {
+        let config = |role| AgentConfig {
+            trickle,
+            ..AgentConfig::new(role)
+        };
+        let mut agents = vec![
+            Agent::new(config(Role::Controlling)),
+            Agent::new(config(Role::Controlled)),
+        ];
+        for stream in 0..streams {
+            for agent in &mut agents {
+                agent.add_stream(1, Credentials::random());
+            }
+            exchange_credentials(&mut agents, stream);
+        }
+        agents
+    }
+
+    /// Passes the trickled candidates of each agent to the other.
+    fn signal(agents: &mut [Agent]) {
+        for i in 0..2 {
+            while let Some(event) = agents[i].poll_event() {
+                match event {
+                    Event::LocalCandidate { stream, candidate } => {
+                        agents[1 - i].add_remote_candidate(stream, *candidate)
+                    }
+                    Event::EndOfLocalCandidates { stream } => {
+                        agents[1 - i].end_remote_candidates(stream)
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    fn start(net: &Network, agents: &mut [Agent]) {
+        for agent in agents {
+            agent.start(net.now);
+        }
+    }
+
+    #[test]
+    fn connect() {
+        let mut net = Network::new();
+        let mut agents = agents(1);
+        net.add_host(&mut agents, 0, 0, "192.0.2.1:1000");
+        net.add_host(&mut agents, 1, 0, "192.0.2.2:2000");
+        agents[0].add_remote_candidate(0, host("192.0.2.2:2000"));
+        agents[1].add_remote_candidate(0, host("192.0.2.1:1000"));
+        start(&net, &mut agents);
+        assert_eq!(agents[0].pairs(0)[0].state, PairState::Waiting);
+
+        net.run(&mut agents, Duration::from_secs(1));
+        for agent in &agents {
+            let pairs = agent.pairs(0);
+            assert_eq!(pairs.len(), 1);
+            assert_eq!(pairs[0].state, PairState::Succeeded);
+            assert!(pairs[0].valid);
+            assert_eq!(agent.local_candidates(0).len(), 1);
+            assert_eq!(agent.remote_candidates(0).len(), 1);
+            assert_eq!(agent.checklist_state(0), ChecklistState::Completed);
+            assert!(agent.selected_pair(0, 1).unwrap().nominated);
+        }
+        assert_eq!(
+            agents[0].pairs(0)[0].priority,
+            agents[1].pairs(0)[0].priority
+        );
+
+        for agent in &mut agents {
+            let events: Vec<_> = iter::from_fn(|| agent.poll_event()).collect();
+            match &events[..] {
+                [Event::StateChanged(ConnectionState::Checking), Event::StateChanged(ConnectionState::Connected), Event::SelectedPair {
+                    stream: 0,
+                    component: 1,
+                    pair,
+                }, Event::StateChanged(ConnectionState::Completed)] => {
+                    assert_eq!(pair.remote.addr, agent.remote_candidates(0)[0].addr)
+                }
+                events => panic!("unexpected events: {:?}", events),
+            }
+        }
+    }
+
+    #[test]
+    fn stats() {
+        let mut net = Network::new();
+        let mut agents = agents(1);
+        net.add_host(&mut agents, 0, 0, "192.0.2.1:1000");
+        net.add_host(&mut agents, 1, 0, "192.0.2.2:2000");
+        agents[0].add_remote_candidate(0, host("192.0.2.2:2000"));
+        agents[1].add_remote_candidate(0, host("192.0.2.1:1000"));
+        let start_time = net.now;
+        start(&net, &mut agents);
+        net.run(&mut agents, Duration::from_secs(1));
+
+        let pair = &agents[0].pairs(0)[0];
+        let stats = agents[0].pair_stats(0, pair).unwrap().clone();
+        // One check is ordinary and the other nominates the pair.
+        assert_eq!(stats.requests_sent, 2);
+        assert_eq!(stats.responses_received, 2);
+        assert_eq!(stats.requests_received, 1);
+        assert_eq!(stats.responses_sent, 1);
+        assert_eq!(stats.current_round_trip_time, Some(Duration::from_secs(0)));
+        assert_eq!(stats.last_error, None);
+        let states: Vec<_> = stats
+            .state_history
+            .iter()
+            .map(|&(_, state)| state)
+            .collect();
+        // The check of the peer triggers one of the pair while the first is
+        // in progress, and the response to the first arrives before the
+        // triggered check is sent.
+        assert_eq!(
+            states,
+            [
+                PairState::Waiting,
+                PairState::InProgress,
+                PairState::Waiting,
+                PairState::Succeeded,
+                PairState::InProgress,
+                PairState::Succeeded,
+            ]
+        );
+        assert_eq!(stats.state_history[0].0, start_time);
+
+        // Consent checks and data count toward the selected pair.
+        net.run(&mut agents, Duration::from_secs(10));
+        agents[0].record_sent(net.now, 0, 1, 100);
+        agents[0].record_received(net.now, 0, 1, 50);
+        let pair = agents[0].selected_pair(0, 1).unwrap();
+        let stats = agents[0].pair_stats(0, pair).unwrap();
+        assert!(stats.consent_requests_sent >= 1);
+        assert_eq!(stats.responses_received, 2 + stats.consent_requests_sent);
+        assert_eq!((stats.packets_sent, stats.bytes_sent), (1, 100));
+        assert_eq!((stats.packets_received, stats.bytes_received), (1, 50));
+        assert_eq!(stats.last_packet_sent, Some(net.now));
+
+        let json = agents[0].diagnostics();
+        assert_eq!(json["role"], "controlling");
+        assert_eq!(json["state"], "completed");
+        let stream = &json["streams"][0];
+        assert_eq!(stream["localCandidates"][0]["address"], "192.0.2.1");
+        assert_eq!(stream["remoteCandidates"][0]["port"], 2000);
+        assert_eq!(stream["pairs"][0]["state"], "succeeded");
+        assert_eq!(stream["pairs"][0]["bytesSent"], 100);
+        assert_eq!(stream["pairs"][0]["stateHistory"][0]["timestamp"], 0);
+        assert_eq!(stream["pairs"][0]["stateHistory"][0]["state"], "waiting");
+        assert_eq!(stream["selectedPairs"][0]["component"], 1);
+        assert_eq!(stream["selectedPairs"][0]["consent"], true);
+        assert!(!json
+            .to_string()
+            .contains(&agents[0].local_credentials(0).pwd));
+    }
+
+    #[test]
+    fn peer_reflexive() {
+        let mut net = Network::new();
+        let mut agents = agents(1);
+        net.add_host(&mut agents, 0, 0, "192.0.2.1:1000");
+        net.add_host(&mut agents, 1, 0, "10.0.0.2:2000");
+        net.add_mapping("10.0.0.2:2000", "203.0.113.2:2000");
+        // The controlled agent behind the NAT only signals its host
+        // candidate, which cannot be reached.
+        agents[0].add_remote_candidate(0, host("10.0.0.2:2000"));
+        agents[1].add_remote_candidate(0, host("192.0.2.1:1000"));
+        start(&net, &mut agents);
+
+        net.run(&mut agents, Duration::from_secs(70));
+        let public: SocketAddr = "203.0.113.2:2000".parse().unwrap();
+
+        let remote = &agents[0].remote_candidates(0)[1];
+        assert_eq!(remote.kind, CandidateType::PeerReflexive);
+        assert_eq!(remote.addr, public);
+        let pairs = agents[0].pairs(0);
+        assert_eq!(pairs.len(), 2);
+        for pair in pairs {
+            let reachable = pair.remote.addr == public;
+            assert_eq!(pair.valid, reachable);
+            assert_eq!(
+                pair.state,
+                if reachable {
+                    PairState::Succeeded
+                } else {
+                    PairState::Failed
+                }
+            );
+        }
+        assert_eq!(agents[0].checklist_state(0), ChecklistState::Completed);
+        assert_eq!(agents[0].selected_pair(0, 1).unwrap().remote.addr, public);
+
+        let local = &agents[1].local_candidates(0)[1];
+        assert_eq!(local.kind, CandidateType::PeerReflexive);
+        assert_eq!(local.addr, public);
+        assert_eq!(local.base, Some("10.0.0.2:2000".parse().unwrap()));
+        assert_eq!(agents[1].valid_pairs(0).count(), 1);
+    }
+
+    #[test]
+    fn nomination_timeout() {
+        let mut net = Network::new();
+        let mut agents = agents(1);
+        net.add_host(&mut agents, 0, 0, "192.0.2.1:1000");
+        net.add_host(&mut agents, 1, 0, "10.0.0.2:2000");
+        net.add_mapping("10.0.0.2:2000", "203.0.113.2:2000");
+        agents[0].add_remote_candidate(0, host("10.0.0.2:2000"));
+        agents[1].add_remote_candidate(0, host("192.0.2.1:1000"));
+        start(&net, &mut agents);
+
+        // The higher priority pair with the unreachable host candidate is
+        // only waited for until the nomination timeout.
+        net.run(&mut agents, Duration::from_millis(900));
+        assert_eq!(agents[0].state(), ConnectionState::Connected);
+        assert!(agents[0].selected_pair(0, 1).is_none());
+
+        net.run(&mut agents, Duration::from_millis(300));
+        for agent in &agents {
+            assert_eq!(agent.state(), ConnectionState::Completed);
+            let selected = agent.selected_pair(0, 1).unwrap();
+            assert!(selected.nominated);
+        }
+        assert_eq!(agents[0].pairs(0)[0].state, PairState::Failed);
+    }
+
+    #[test]
+    fn role_conflict() {
+        for &roles in [Role::Controlling, Role::Controlled].iter() {
+            for &(first, second) in [(1, 2), (2, 1)].iter() {
+                let mut net = Network::new();
+                let mut agents = agents(1);
+                for (agent, tie_breaker) in agents.iter_mut().zip(&[first, second]) {
+                    agent.config.role = roles;
+                    agent.tie_breaker = *tie_breaker;
+                }
+                net.add_host(&mut agents, 0, 0, "192.0.2.1:1000");
+                net.add_host(&mut agents, 1, 0, "192.0.2.2:2000");
+                agents[0].add_remote_candidate(0, host("192.0.2.2:2000"));
+                agents[1].add_remote_candidate(0, host("192.0.2.1:1000"));
+                start(&net, &mut agents);
+
+                net.run(&mut agents, Duration::from_secs(1));
+                // The agent with the larger tie-breaker ends up controlling.
+                let controlling = if first > second { 0 } else { 1 };
+                assert_eq!(agents[controlling].role(), Role::Controlling);
+                assert_eq!(agents[1 - controlling].role(), Role::Controlled);
+
+                let switched = if roles == Role::Controlling {
+                    1 - controlling
+                } else {
+                    controlling
+                };
+                let events: Vec<_> = iter::from_fn(|| agents[switched].poll_event()).collect();
+                assert!(events.iter().any(|event| match event {
+                    Event::RoleChanged(role) => *role != roles,
+                    _ => false,
+                }));
+
+                for agent in &agents {
+                    assert_eq!(agent.state(), ConnectionState::Completed);
+                }
+                assert_eq!(
+                    agents[0].pairs(0)[0].priority,
+                    agents[1].pairs(0)[0].priority
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn unfreeze() {
+        let mut net = Network::new();
+        let mut agents = agents(2);
+        for (stream, port) in [(0, 1000), (1, 1002)].iter() {
+            net.add_host(&mut agents, 0, *stream, &format!("192.0.2.1:{}", port));
+            net.add_host(
+                &mut agents,
+                1,
+                *stream,
+                &format!("192.0.2.2:{}", port + 1000),
+            );
+            agents[0].add_remote_candidate(*stream, host(&format!("192.0.2.2:{}", port + 1000)));
+            agents[1].add_remote_candidate(*stream, host(&format!("192.0.2.1:{}", port)));
+        }
+        start(&net, &mut agents);
+
+        // The pairs of both streams share a foundation, so only the first
+        // one is checked at first.
+        assert_eq!(agents[0].pairs(0)[0].state, PairState::Waiting);
+        assert_eq!(agents[0].pairs(1)[0].state, PairState::Frozen);
+
+        // Once the first one succeeds, the second one is unfrozen.
+        net.run(&mut agents, Duration::from_secs(0));
+        assert_eq!(agents[0].pairs(0)[0].state, PairState::Succeeded);
+        assert_eq!(agents[0].pairs(1)[0].state, PairState::Waiting);
+
+        net.run(&mut agents, Duration::from_secs(1));
+        for agent in &agents {
+            for stream in 0..2 {
+                assert_eq!(agent.pairs(stream)[0].state, PairState::Succeeded);
+            }
+        }
+    }
+
+    /// Returns a controlling and a controlled agent with a data stream with
+    /// RTP and RTCP components, each with a host candidate.
+    fn rtp_rtcp_agents(net: &mut Network) -> Vec<Agent> {
+        let mut agents = vec![
+            Agent::new(AgentConfig::new(Role::Controlling)),
+            Agent::new(AgentConfig::new(Role::Controlled)),
+        ];
+        for agent in &mut agents {
+            agent.add_stream(2, Credentials::random());
+        }
+        exchange_credentials(&mut agents, 0);
+
+        for component in 1..=2 {
+            let addrs = [
+                format!("192.0.2.1:{}", 999 + component),
+                format!("192.0.2.2:{}", 1999 + component),
+            ];
+            for i in 0..2 {
+                net.add_component_host(&mut agents, i, 0, component, &addrs[i]);
+                agents[1 - i].add_remote_candidate(0, component_host(component, &addrs[i]));
+            }
+        }
+        agents
+    }
+
+    #[test]
+    fn components() {
+        let mut net = Network::new();
+        let mut agents = rtp_rtcp_agents(&mut net);
+        start(&net, &mut agents);
+
+        // Only candidates of the same component are paired, and the RTCP
+        // pair shares the foundation of the RTP one.
+        let pairs = agents[0].pairs(0);
+        assert_eq!(pairs.len(), 2);
+        assert_eq!(pairs[0].component(), RTP_COMPONENT);
+        assert_eq!(pairs[0].state, PairState::Waiting);
+        assert_eq!(pairs[1].component(), RTCP_COMPONENT);
+        assert_eq!(pairs[1].state, PairState::Frozen);
+
+        net.run(&mut agents, Duration::from_secs(2));
+        for agent in &agents {
+            assert_eq!(agent.state(), ConnectionState::Completed);
+        }
+        let rtcp = agents[0].selected_pair(0, RTCP_COMPONENT).unwrap();
+        assert_eq!(rtcp.local.addr, "192.0.2.1:1001".parse().unwrap());
+        assert_eq!(rtcp.remote.addr, "192.0.2.2:2001".parse().unwrap());
+        assert!(agents[0].can_send(0, RTP_COMPONENT));
+    }
+
+    #[test]
+    fn rtcp_mux() {
+        let mut net = Network::new();
+        let mut agents = rtp_rtcp_agents(&mut net);
+        for agent in &mut agents {
+            agent.rtcp_mux(0);
+            assert_eq!(agent.local_candidates(0).len(), 1);
+            assert_eq!(agent.remote_candidates(0).len(), 1);
+        }
+        start(&net, &mut agents);
+        assert_eq!(agents[0].pairs(0).len(), 1);
+
+        net.run(&mut agents, Duration::from_secs(2));
+        for agent in &agents {
+            assert_eq!(agent.state(), ConnectionState::Completed);
+            assert!(agent.selected_pair(0, RTP_COMPONENT).is_some());
+            assert!(agent.selected_pair(0, RTCP_COMPONENT).is_none());
+        }
+    }
+
+    #[test]
+    fn bundle() {
+        let mut net = Network::new();
+        let config = |role| AgentConfig {
+            bundle: true,
+            ..AgentConfig::new(role)
+        };
+        let mut agents = vec![
+            Agent::new(config(Role::Controlling)),
+            Agent::new(config(Role::Controlled)),
+        ];
+        for stream in 0..3 {
+            for agent in &mut agents {
+                assert_eq!(agent.add_stream(2, Credentials::random()), stream);
+            }
+        }
+        exchange_credentials(&mut agents, 0);
+        assert_eq!(
+            agents[0].local_credentials(2),
+            agents[0].local_credentials(0)
+        );
+
+        // Candidates signaled for any bundled data stream go to the first.
+        net.add_host(&mut agents, 0, 0, "192.0.2.1:1000");
+        net.add_host(&mut agents, 1, 1, "192.0.2.2:2000");
+        agents[0].add_remote_candidate(2, host("192.0.2.2:2000"));
+        agents[1].add_remote_candidate(0, host("192.0.2.1:1000"));
+        for agent in &mut agents {
+            agent.rtcp_mux(1);
+        }
+        start(&net, &mut agents);
+        assert_eq!(agents[0].pairs(1).len(), 1);
+
+        net.run(&mut agents, Duration::from_secs(1));
+        for agent in &mut agents {
+            assert_eq!(agent.state(), ConnectionState::Completed);
+            let selected = agent.selected_pair(0, RTP_COMPONENT).unwrap().key();
+            for stream in 1..3 {
+                let pair = agent.selected_pair(stream, RTP_COMPONENT).unwrap();
+                assert_eq!(pair.key(), selected);
+            }
+            while let Some(event) = agent.poll_event() {
+                if let Event::SelectedPair { stream, .. } = event {
+                    assert_eq!(stream, 0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unreachable() {
+        let mut net = Network::new();
+        let mut agents = agents(1);
+        net.add_host(&mut agents, 0, 0, "192.0.2.1:1000");
+        agents[0].add_remote_candidate(0, host("192.0.2.9:9000"));
+        start(&net, &mut agents);
+
+        // Seven requests are sent, 0.5 s after each other at first with the
+        // interval doubling after each, and the check fails 63.5 s in.
+        net.run(&mut agents, Duration::from_secs(63));
+        assert_eq!(agents[0].pairs(0)[0].state, PairState::InProgress);
+        assert_eq!(agents[0].checklist_state(0), ChecklistState::Running);
+
+        net.run(&mut agents, Duration::from_secs(1));
+        assert_eq!(agents[0].pairs(0)[0].state, PairState::Failed);
+        assert_eq!(agents[0].checklist_state(0), ChecklistState::Failed);
+        assert_eq!(agents[0].poll_timeout(), None);
+
+        let stats = agents[0].pair_stats(0, &agents[0].pairs(0)[0]).unwrap();
+        assert_eq!(stats.requests_sent, 1);
+        assert_eq!(stats.retransmissions_sent, 6);
+        assert_eq!(stats.responses_received, 0);
+        assert_eq!(stats.last_error, Some(CheckError::Timeout));
+    }
+
+    #[test]
+    fn mdns() {
+        let mut net = Network::new();
+        let mut agents = new_agents(1, true);
+        net.add_host(&mut agents, 0, 0, "192.0.2.1:1000");
+        net.add_host(&mut agents, 1, 0, "192.0.2.2:2000");
+        agents[1].add_remote_candidate(0, host("192.0.2.1:1000"));
+
+        // The controlled agent hides its address behind a hostname.
+        let mut responder = Mdns::new();
+        let mut candidate = agents[1].local_candidates(0)[0].clone();
+        candidate.hostname = Some(responder.register(candidate.addr.ip()));
+        agents[0].add_remote_candidate(0, candidate.to_string().parse().unwrap());
+        start(&net, &mut agents);
+        assert!(agents[0].pairs(0).is_empty());
+
+        let hostname = iter::from_fn(|| agents[0].poll_event())
+            .find_map(|event| match event {
+                Event::ResolveHostname { hostname } => Some(hostname),
+                _ => None,
+            })
+            .unwrap();
+        let mut resolver = Mdns::new();
+        resolver.resolve(net.now, &hostname);
+        responder.handle_receive(&resolver.poll_transmit().unwrap());
+        resolver.handle_receive(&responder.poll_transmit().unwrap());
+        match resolver.poll_event() {
+            Some(MdnsEvent::Resolved { hostname, ip }) => agents[0].resolve_hostname(&hostname, ip),
+            event => panic!("unexpected event: {:?}", event),
+        }
+        assert_eq!(agents[0].pairs(0).len(), 1);
+
+        net.run(&mut agents, Duration::from_secs(1));
+        for agent in &agents {
+            assert_eq!(agent.state(), ConnectionState::Completed);
+        }
+        let remote = &agents[0].selected_pair(0, 1).unwrap().remote;
+        assert_eq!(remote.addr, candidate.addr);
+        assert_eq!(remote.hostname, candidate.hostname);
+    }
+
+    #[test]
+    fn tcp() {
+        let mut net = Network::new();
+        let mut agents = agents(1);
+        let active = net.add_tcp_host(&mut agents, 0, 0, TcpType::Active, "192.0.2.1:1000");
+        let passive = net.add_tcp_host(&mut agents, 1, 0, TcpType::Passive, "192.0.2.2:2000");
+        agents[0].add_remote_candidate(0, passive.clone());
+        agents[1].add_remote_candidate(0, active.clone());
+        start(&net, &mut agents);
+        // The passive candidate only waits for connections.
+        assert_eq!(agents[0].pairs(0).len(), 1);
+        assert!(agents[1].pairs(0).is_empty());
+
+        net.run(&mut agents, Duration::from_secs(1));
+        for agent in &agents {
+            assert_eq!(agent.state(), ConnectionState::Completed);
+        }
+        let selected = agents[0].selected_pair(0, 1).unwrap();
+        assert_eq!((&selected.local, &selected.remote), (&active, &passive));
+
+        // The passive side learns the ephemeral address the connection came
+        // from.
+        let selected = agents[1].selected_pair(0, 1).unwrap();
+        assert_eq!(selected.local, passive);
+        assert_eq!(selected.remote.kind, CandidateType::PeerReflexive);
+        assert_eq!(selected.remote.transport, Transport::Tcp);
+        assert_eq!(selected.remote.tcp_type, Some(TcpType::Active));
+        assert_eq!(selected.remote.addr.ip(), active.addr.ip());
+        assert_ne!(selected.remote.addr, active.addr);
+    }
+
+    #[test]
+    fn tcp_unreachable() {
+        let mut net = Network::new();
+        let mut agents = agents(1);
+        net.add_tcp_host(
+            &mut agents,
+            0,
+            0,
+            TcpType::SimultaneousOpen,
+            "192.0.2.1:1000",
+        );
+        let remote = Candidate::tcp_host(
+            1,
+            TcpType::SimultaneousOpen,
+            "192.0.2.9:9000".parse().unwrap(),
+            65535,
+        );
+        agents[0].add_remote_candidate(0, remote);
+        start(&net, &mut agents);
+
+        // A single request is sent, and the check fails 39.5 s in.
+        net.run(&mut agents, Duration::from_secs(39));
+        assert_eq!(agents[0].pairs(0)[0].state, PairState::InProgress);
+        assert_eq!(iter::from_fn(|| agents[0].poll_transmit()).count(), 0);
+
+        net.run(&mut agents, Duration::from_secs(1));
+        assert_eq!(agents[0].pairs(0)[0].state, PairState::Failed);
+        assert_eq!(agents[0].checklist_state(0), ChecklistState::Failed);
+    }
+
+    #[test]
+    fn wrong_credentials() {
+        let mut net = Network::new();
+        let mut agents = agents(1);
+        let mut credentials = agents[0].local_credentials(0).clone();
+        credentials.pwd.push('x');
+        agents[1].set_remote_credentials(0, credentials);
+        // Consent checks would trigger checks by the controlled agent.
+        agents[0].config.consent_interval = Duration::from_secs(3600);
+
+        net.add_host(&mut agents, 0, 0, "192.0.2.1:1000");
+        net.add_host(&mut agents, 1, 0, "192.0.2.2:2000");
+        agents[0].add_remote_candidate(0, host("192.0.2.2:2000"));
+        agents[1].add_remote_candidate(0, host("192.0.2.1:1000"));
+        start(&net, &mut agents);
+
+        // Checks the controlling agent cannot authenticate are rejected, and
+        // the unauthenticated responses ignored.
+        net.run(&mut agents, Duration::from_secs(70));
+        assert_eq!(agents[0].pairs(0)[0].state, PairState::Succeeded);
+        assert_eq!(agents[1].pairs(0)[0].state, PairState::Failed);
+        assert_eq!(agents[1].checklist_state(0), ChecklistState::Failed);
+    }
+
+    #[test]
+    fn trickle() {
+        let mut net = Network::new();
+        let mut agents = new_agents(1, true);
+        start(&net, &mut agents);
+
+        // Without candidates, checks wait for them rather than fail.
+        net.run(&mut agents, Duration::from_secs(5));
+        for agent in &agents {
+            assert_eq!(agent.state(), ConnectionState::Checking);
+            assert_eq!(agent.checklist_state(0), ChecklistState::Running);
+        }
+
+        net.add_host(&mut agents, 0, 0, "192.0.2.1:1000");
+        net.add_host(&mut agents, 1, 0, "192.0.2.2:2000");
+        signal(&mut agents);
+        for agent in &agents {
+            assert_eq!(agent.pairs(0).len(), 1);
+        }
+
+        net.run(&mut agents, Duration::from_secs(1));
+        for agent in &agents {
+            assert_eq!(agent.state(), ConnectionState::Completed);
+        }
+    }
+
+    #[test]
+    fn end_of_candidates() {
+        let mut net = Network::new();
+        let mut agents = new_agents(1, true);
+        net.add_host(&mut agents, 0, 0, "192.0.2.1:1000");
+        agents[0].add_remote_candidate(0, host("192.0.2.9:9000"));
+        start(&net, &mut agents);
+
+        net.run(&mut agents, Duration::from_secs(70));
+        assert_eq!(agents[0].pairs(0)[0].state, PairState::Failed);
+        assert_eq!(agents[0].checklist_state(0), ChecklistState::Running);
+
+        agents[0].end_local_candidates(0);
+        assert_eq!(agents[0].checklist_state(0), ChecklistState::Running);
+        let events: Vec<_> = iter::from_fn(|| agents[0].poll_event()).collect();
+        assert!(events.contains(&Event::EndOfLocalCandidates { stream: 0 }));
+
+        agents[0].end_remote_candidates(0);
+        assert_eq!(agents[0].checklist_state(0), ChecklistState::Failed);
+        assert_eq!(agents[0].state(), ConnectionState::Failed);
+        let events: Vec<_> = iter::from_fn(|| agents[0].poll_event()).collect();
+        assert!(events.contains(&Event::StateChanged(ConnectionState::Failed)));
+    }
+
+    #[test]
+    fn lite() {
+        for &signaled in [true, false].iter() {
+            let mut net = Network::new();
+            let mut agents = vec![
+                Agent::new(AgentConfig::new(Role::Controlled)),
+                Agent::new(AgentConfig::lite()),
+            ];
+            for agent in &mut agents {
+                agent.add_stream(1, Credentials::random());
+            }
+            exchange_credentials(&mut agents, 0);
+            // Without the hint, the role conflict is resolved in favor of
+            // the lite agent staying controlled.
+            if signaled {
+                agents[0].set_remote_lite();
+            }
+
+            net.add_host(&mut agents, 0, 0, "192.0.2.1:1000");
+            net.add_host(&mut agents, 1, 0, "198.51.100.1:3478");
+            agents[1].add_local_candidate(
+                0,
+                Candidate::relayed(
+                    1,
+                    Transport::Udp,
+                    "198.51.100.2:4000".parse().unwrap(),
+                    "198.51.100.1:3478".parse().unwrap(),
+                    "198.51.100.2".parse().unwrap(),
+                    65535,
+                ),
+            );
+            assert_eq!(agents[1].local_candidates(0).len(), 1);
+            agents[0].add_remote_candidate(0, host("198.51.100.1:3478"));
+            start(&net, &mut agents);
+            assert_eq!(agents[1].poll_timeout(), None);
+
+            net.run(&mut agents, Duration::from_secs(1));
+            assert_eq!(agents[0].role(), Role::Controlling);
+            assert_eq!(agents[1].role(), Role::Controlled);
+            for agent in &agents {
+                assert_eq!(agent.state(), ConnectionState::Completed);
+            }
+            let selected = agents[1].selected_pair(0, 1).unwrap();
+            assert_eq!(selected.remote.addr, host("192.0.2.1:1000").addr);
+            assert_eq!(selected.remote.kind, CandidateType::PeerReflexive);
+        }
+    }
+
+    #[test]
+    fn consent() {
+        let mut net = Network::new();
+        let mut agents = agents(1);
+        net.add_host(&mut agents, 0, 0, "192.0.2.1:1000");
+        net.add_host(&mut agents, 1, 0, "192.0.2.2:2000");
+        agents[0].add_remote_candidate(0, host("192.0.2.2:2000"));
+        agents[1].add_remote_candidate(0, host("192.0.2.1:1000"));
+        start(&net, &mut agents);
+
+        // Consent checks keep refreshing consent past its timeout.
+        net.run(&mut agents, Duration::from_secs(60));
+        let lost = Event::ConsentLost {
+            stream: 0,
+            component: 1,
+        };
+        for agent in &mut agents {
+            assert_eq!(agent.state(), ConnectionState::Completed);
+            assert!(agent.can_send(0, 1));
+            let events: Vec<_> = iter::from_fn(|| agent.poll_event()).collect();
+            assert!(!events.contains(&lost));
+        }
+
+        // The last refresh was at most 1.2 consent intervals ago.
+        net.block("192.0.2.2:2000");
+        net.run(&mut agents, Duration::from_secs(23));
+        assert!(agents.iter().all(|agent| agent.can_send(0, 1)));
+
+        net.run(&mut agents, Duration::from_secs(7));
+        for agent in &mut agents {
+            assert!(!agent.can_send(0, 1));
+            assert_eq!(agent.state(), ConnectionState::Disconnected);
+            let events: Vec<_> = iter::from_fn(|| agent.poll_event()).collect();
+            assert_eq!(
+                events,
+                [
+                    lost.clone(),
+                    Event::StateChanged(ConnectionState::Disconnected)
+                ]
+            );
+        }
+        assert_eq!(agents[0].poll_timeout(), None);
+    }
+
+    #[test]
+    fn restart() {
+        let mut net = Network::new();
+        let mut agents = agents(1);
+        net.add_host(&mut agents, 0, 0, "192.0.2.1:1000");
+        net.add_host(&mut agents, 1, 0, "192.0.2.2:2000");
+        agents[0].add_remote_candidate(0, host("192.0.2.2:2000"));
+        agents[1].add_remote_candidate(0, host("192.0.2.1:1000"));
+        start(&net, &mut agents);
+        net.run(&mut agents, Duration::from_secs(1));
+        for agent in &mut agents {
+            while agent.poll_event().is_some() {}
+        }
+
+        // The controlling agent moves to another network, and restarts.
+        let old = agents[0].selected_pair(0, 1).unwrap().key();
+        for agent in &mut agents {
+            agent.restart(0, Credentials::random());
+        }
+        exchange_credentials(&mut agents, 0);
+        net.add_host(&mut agents, 0, 0, "198.51.100.1:1000");
+        net.add_host(&mut agents, 1, 0, "192.0.2.2:2000");
+        agents[0].add_remote_candidate(0, host("192.0.2.2:2000"));
+        agents[1].add_remote_candidate(0, host("198.51.100.1:1000"));
+        for agent in &agents {
+            assert_eq!(agent.local_candidates(0).len(), 1);
+            assert_eq!(agent.remote_candidates(0).len(), 1);
+            assert!(agent.pairs(0).is_empty());
+        }
+
+        // Data is sent on the old pair until the new one is selected.
+        start(&net, &mut agents);
+        assert_eq!(agents[0].selected_pair(0, 1).unwrap().key(), old);
+        assert!(agents[0].can_send(0, 1));
+
+        net.run(&mut agents, Duration::from_secs(1));
+        let new: SocketAddr = "198.51.100.1:1000".parse().unwrap();
+        assert_eq!(agents[0].selected_pair(0, 1).unwrap().base(), new);
+        assert_eq!(agents[1].selected_pair(0, 1).unwrap().remote.addr, new);
+        for agent in &mut agents {
+            let events: Vec<_> = iter::from_fn(|| agent.poll_event()).collect();
+            match &events[..] {
+                [Event::StateChanged(ConnectionState::Connected), Event::SelectedPair {
+                    stream: 0,
+                    component: 1,
+                    ..
+                }, Event::StateChanged(ConnectionState::Completed)] => (),
+                events => panic!("unexpected events: {:?}", events),
+            }
+        }
+    }
+
+    fn interface(ip: &str) -> Interface {
+        Interface {
+            name: "eth0".to_owned(),
+            ip: ip.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn network_change() {
+        let mut net = Network::new();
+        let mut agents = agents(1);
+        net.add_host(&mut agents, 0, 0, "192.0.2.1:1000");
+        net.add_host(&mut agents, 1, 0, "192.0.2.2:2000");
+        agents[0].add_remote_candidate(0, host("192.0.2.2:2000"));
+        agents[1].add_remote_candidate(0, host("192.0.2.1:1000"));
+        start(&net, &mut agents);
+        net.run(&mut agents, Duration::from_secs(1));
+        assert_eq!(agents[0].state(), ConnectionState::Completed);
+        while agents[0].poll_event().is_some() {}
+
+        // Losing the interface of the selected pair restarts ICE.
+        let credentials = agents[0].local_credentials(0).clone();
+        let removed = InterfaceChange::Removed(interface("192.0.2.1"));
+        agents[0].handle_network_change(removed);
+        let events: Vec<_> = iter::from_fn(|| agents[0].poll_event()).collect();
+        assert_eq!(
+            events,
+            [
+                Event::ConsentLost {
+                    stream: 0,
+                    component: 1
+                },
+                Event::Restarted { stream: 0 },
+                Event::StateChanged(ConnectionState::Disconnected),
+            ]
+        );
+        assert_ne!(agents[0].local_credentials(0), &credentials);
+        assert!(agents[0].local_candidates(0).is_empty());
+        assert!(!agents[0].can_send(0, 1));
+
+        // Checks start over on the new interface once the peer restarts
+        // too.
+        net.add_host(&mut agents, 0, 0, "198.51.100.1:1000");
+        agents[1].restart(0, Credentials::random());
+        net.add_host(&mut agents, 1, 0, "192.0.2.2:2000");
+        exchange_credentials(&mut agents, 0);
+        agents[0].add_remote_candidate(0, host("192.0.2.2:2000"));
+        agents[1].add_remote_candidate(0, host("198.51.100.1:1000"));
+        start(&net, &mut agents);
+
+        net.run(&mut agents, Duration::from_secs(1));
+        assert_eq!(agents[0].state(), ConnectionState::Completed);
+        let pair = agents[0].selected_pair(0, 1).unwrap();
+        assert_eq!(pair.local.addr, "198.51.100.1:1000".parse().unwrap());
+        assert!(agents[0].can_send(0, 1));
+    }
+
+    #[test]
+    fn network_policy() {
+        let mut net = Network::new();
+        let mut agents = agents(1);
+        agents[0].config.network_policy = |_| NetworkAction::Continue;
+        net.add_host(&mut agents, 0, 0, "192.0.2.1:1000");
+        net.add_host(&mut agents, 0, 0, "198.51.100.1:1000");
+        net.add_host(&mut agents, 1, 0, "192.0.2.2:2000");
+        agents[0].add_remote_candidate(0, host("192.0.2.2:2000"));
+        start(&net, &mut agents);
+        net.run(&mut agents, Duration::from_secs(1));
+        assert_eq!(agents[0].pairs(0).len(), 2);
+
+        let credentials = agents[0].local_credentials(0).clone();
+        agents[0].handle_network_change(InterfaceChange::Removed(interface("198.51.100.1")));
+        agents[0].handle_network_change(InterfaceChange::Added(interface("203.0.113.1")));
+        assert_eq!(agents[0].local_credentials(0), &credentials);
+        assert_eq!(agents[0].local_candidates(0).len(), 1);
+        assert_eq!(agents[0].pairs(0).len(), 1);
+        assert_eq!(agents[0].state(), ConnectionState::Completed);
+        assert!(iter::from_fn(|| agents[0].poll_event())
+            .all(|event| event != Event::Restarted { stream: 0 }));
+    }
+
+    #[test]
+    fn not_stun() {
+        let mut agent = Agent::new(AgentConfig::new(Role::Controlled));
+        let addr = "192.0.2.1:1000".parse().unwrap();
+        let now = Instant::now();
+        assert!(!agent.handle_receive(now, Transport::Udp, addr, addr, b"\x80\x00rtp"));
+        assert!(agent.poll_transmit().is_none());
+    }
+}
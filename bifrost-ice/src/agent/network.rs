@@ -0,0 +1,30 @@
+use crate::gather::InterfaceChange;
+
+/// A change of the local network interfaces, as the agent saw it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NetworkChange {
+    pub change: InterfaceChange,
+    /// Whether a selected pair was on a removed interface, so that data can
+    /// no longer be sent on it.
+    pub selected_lost: bool,
+}
+
+/// What an agent does about a network change.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum NetworkAction {
+    /// Carry on. Candidates gathered on new interfaces are paired and
+    /// checked while checklists are still running.
+    Continue,
+    /// Restart ICE on every data stream, defined in
+    /// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-9).
+    Restart,
+}
+
+/// Restarts ICE when a selected pair is lost, and carries on otherwise.
+pub fn default_network_policy(change: NetworkChange) -> NetworkAction {
+    if change.selected_lost {
+        NetworkAction::Restart
+    } else {
+        NetworkAction::Continue
+    }
+}
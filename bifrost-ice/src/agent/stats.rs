@@ -0,0 +1,186 @@
+use crate::agent::{CandidatePair, ChecklistState, ConnectionState, PairState, Role};
+use crate::candidate::{Candidate, CandidateType, TcpType, Transport};
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+
+/// Why the last check of a candidate pair failed.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum CheckError {
+    /// No response arrived before the requests ran out.
+    Timeout,
+    /// An error response arrived, with this error code.
+    ErrorResponse(u16),
+    /// A success response arrived from or to an address other than the one
+    /// the request was sent to or from, or without a mapped address.
+    InvalidResponse,
+}
+
+/// Statistics of a candidate pair, modeled after
+/// [`RTCIceCandidatePairStats`](https://www.w3.org/TR/webrtc-stats/#candidatepair-dict*).
+///
+/// Data is not handled by the agent, so the packets and bytes are only
+/// those the application records with `Agent::record_sent` and
+/// `Agent::record_received`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PairStats {
+    /// Connectivity checks sent, not counting retransmissions.
+    pub requests_sent: u64,
+    pub retransmissions_sent: u64,
+    pub requests_received: u64,
+    pub responses_sent: u64,
+    /// Responses received to connectivity and consent checks.
+    pub responses_received: u64,
+    pub consent_requests_sent: u64,
+    /// The round-trip time measured by the last successful response.
+    pub current_round_trip_time: Option<Duration>,
+    /// The sum of the round-trip times measured by successful responses.
+    pub total_round_trip_time: Duration,
+    pub packets_sent: u64,
+    pub bytes_sent: u64,
+    pub packets_received: u64,
+    pub bytes_received: u64,
+    pub last_packet_sent: Option<Instant>,
+    pub last_packet_received: Option<Instant>,
+    /// The states the pair went through, and when, as seen at the end of
+    /// each call into the agent.
+    pub state_history: Vec<(Instant, PairState)>,
+    pub last_error: Option<CheckError>,
+}
+
+impl PairStats {
+    /// Records a successful response, received `rtt` after its request was
+    /// sent.
+    pub(crate) fn response(&mut self, rtt: Duration) {
+        self.responses_received += 1;
+        self.current_round_trip_time = Some(rtt);
+        self.total_round_trip_time += rtt;
+    }
+
+    /// Records the state of the pair at `now`, if it changed.
+    pub(crate) fn record_state(&mut self, now: Instant, state: PairState) {
+        if self.state_history.last().map(|&(_, last)| last) != Some(state) {
+            self.state_history.push((now, state));
+        }
+    }
+}
+
+pub(crate) fn role_name(role: Role) -> &'static str {
+    match role {
+        Role::Controlling => "controlling",
+        Role::Controlled => "controlled",
+    }
+}
+
+pub(crate) fn connection_state_name(state: ConnectionState) -> &'static str {
+    match state {
+        ConnectionState::New => "new",
+        ConnectionState::Checking => "checking",
+        ConnectionState::Connected => "connected",
+        ConnectionState::Completed => "completed",
+        ConnectionState::Failed => "failed",
+        ConnectionState::Disconnected => "disconnected",
+    }
+}
+
+pub(crate) fn checklist_state_name(state: ChecklistState) -> &'static str {
+    match state {
+        ChecklistState::Running => "running",
+        ChecklistState::Completed => "completed",
+        ChecklistState::Failed => "failed",
+    }
+}
+
+/// Returns the name of a pair state, as in
+/// [`RTCStatsIceCandidatePairState`](https://www.w3.org/TR/webrtc-stats/#rtcstatsicecandidatepairstate-enum).
+fn pair_state_name(state: PairState) -> &'static str {
+    match state {
+        PairState::Frozen => "frozen",
+        PairState::Waiting => "waiting",
+        PairState::InProgress => "in-progress",
+        PairState::Succeeded => "succeeded",
+        PairState::Failed => "failed",
+    }
+}
+
+/// Returns milliseconds since `epoch`.
+fn timestamp(epoch: Instant, time: Instant) -> u64 {
+    (time - epoch).as_millis() as u64
+}
+
+/// Dumps a candidate with the fields of
+/// [`RTCIceCandidateStats`](https://www.w3.org/TR/webrtc-stats/#icecandidate-dict*).
+pub(crate) fn candidate_json(candidate: &Candidate) -> Value {
+    let candidate_type = match candidate.kind {
+        CandidateType::Host => "host",
+        CandidateType::ServerReflexive => "srflx",
+        CandidateType::PeerReflexive => "prflx",
+        CandidateType::Relayed => "relay",
+    };
+    let protocol = match candidate.transport {
+        Transport::Udp => "udp",
+        Transport::Tcp => "tcp",
+    };
+    let tcp_type = candidate.tcp_type.map(|tcp_type| match tcp_type {
+        TcpType::Active => "active",
+        TcpType::Passive => "passive",
+        TcpType::SimultaneousOpen => "so",
+    });
+    json!({
+        "foundation": candidate.foundation,
+        "component": candidate.component,
+        "protocol": protocol,
+        "priority": candidate.priority,
+        "address": candidate.addr.ip().to_string(),
+        "port": candidate.addr.port(),
+        "candidateType": candidate_type,
+        "relatedAddress": candidate.related_addr.map(|addr| addr.ip().to_string()),
+        "relatedPort": candidate.related_addr.map(|addr| addr.port()),
+        "tcpType": tcp_type,
+    })
+}
+
+/// Dumps a pair and its statistics with the fields of
+/// [`RTCIceCandidatePairStats`](https://www.w3.org/TR/webrtc-stats/#candidatepair-dict*),
+/// with timestamps in milliseconds since `epoch` and round-trip times in
+/// seconds.
+pub(crate) fn pair_json(pair: &CandidatePair, stats: &PairStats, epoch: Instant) -> Value {
+    let last_error = stats.last_error.map(|error| match error {
+        CheckError::Timeout => json!("timeout"),
+        CheckError::ErrorResponse(code) => json!({ "errorCode": code }),
+        CheckError::InvalidResponse => json!("invalid-response"),
+    });
+    let history: Vec<_> = stats
+        .state_history
+        .iter()
+        .map(|&(time, state)| {
+            json!({
+                "timestamp": timestamp(epoch, time),
+                "state": pair_state_name(state),
+            })
+        })
+        .collect();
+    json!({
+        "local": candidate_json(&pair.local),
+        "remote": candidate_json(&pair.remote),
+        "priority": pair.priority,
+        "state": pair_state_name(pair.state),
+        "valid": pair.valid,
+        "nominated": pair.nominated,
+        "requestsSent": stats.requests_sent,
+        "retransmissionsSent": stats.retransmissions_sent,
+        "requestsReceived": stats.requests_received,
+        "responsesSent": stats.responses_sent,
+        "responsesReceived": stats.responses_received,
+        "consentRequestsSent": stats.consent_requests_sent,
+        "currentRoundTripTime": stats.current_round_trip_time.map(|rtt| rtt.as_secs_f64()),
+        "totalRoundTripTime": stats.total_round_trip_time.as_secs_f64(),
+        "packetsSent": stats.packets_sent,
+        "bytesSent": stats.bytes_sent,
+        "packetsReceived": stats.packets_received,
+        "bytesReceived": stats.bytes_received,
+        "lastPacketSentTimestamp": stats.last_packet_sent.map(|time| timestamp(epoch, time)),
+        "lastPacketReceivedTimestamp": stats.last_packet_received.map(|time| timestamp(epoch, time)),
+        "stateHistory": history,
+        "lastError": last_error,
+    })
+}
@@ -0,0 +1,455 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, SocketAddr};
+
+mod sdp;
+
+pub use self::sdp::ParseCandidateError;
+
+/// The local preference of a candidate on a host with a single IP address,
+/// defined in [RFC 8445](https://tools.ietf.org/html/rfc8445#section-5.1.2.1).
+pub const DEFAULT_LOCAL_PREFERENCE: u16 = 65535;
+
+/// The component ids of RTP and RTCP, defined in
+/// [RFC 5245](https://tools.ietf.org/html/rfc5245#section-4.1.1.1).
+pub const RTP_COMPONENT: u16 = 1;
+pub const RTCP_COMPONENT: u16 = 2;
+
+/// The port of active TCP candidates, which connect from ephemeral ports
+/// instead, defined in
+/// [RFC 6544](https://tools.ietf.org/html/rfc6544#section-4.5).
+pub const ACTIVE_TCP_PORT: u16 = 9;
+
+/// The type of a candidate, defined in
+/// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-5.1.1).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum CandidateType {
+    Host,
+    ServerReflexive,
+    PeerReflexive,
+    Relayed,
+}
+
+impl CandidateType {
+    /// Returns the recommended type preference, defined in
+    /// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-5.1.2.2).
+    pub fn preference(self) -> u8 {
+        match self {
+            CandidateType::Host => 126,
+            CandidateType::PeerReflexive => 110,
+            CandidateType::ServerReflexive => 100,
+            CandidateType::Relayed => 0,
+        }
+    }
+}
+
+/// The transport protocol of a candidate.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Transport {
+    Udp,
+    Tcp,
+}
+
+/// The role of a TCP candidate in establishing connections, defined in
+/// [RFC 6544](https://tools.ietf.org/html/rfc6544#section-4.5).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum TcpType {
+    Active,
+    Passive,
+    SimultaneousOpen,
+}
+
+impl TcpType {
+    /// Returns the type of the remote candidates a candidate of this type
+    /// pairs with, defined in
+    /// [RFC 6544](https://tools.ietf.org/html/rfc6544#section-6.2).
+    pub fn peer(self) -> Self {
+        match self {
+            TcpType::Active => TcpType::Passive,
+            TcpType::Passive => TcpType::Active,
+            TcpType::SimultaneousOpen => TcpType::SimultaneousOpen,
+        }
+    }
+}
+
+/// An ICE candidate, defined in
+/// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-5.1).
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Candidate {
+    /// Identifies candidates of the same type, base IP address, STUN or TURN
+    /// server and transport, which are likely to fare the same in checks.
+    pub foundation: String,
+    /// The component ID, between 1 and 256.
+    pub component: u16,
+    pub transport: Transport,
+    pub priority: u32,
+    /// The transport address of the candidate.
+    pub addr: SocketAddr,
+    /// The multicast DNS hostname sent instead of the IP address of a host
+    /// candidate, to hide it, defined in
+    /// [draft-ietf-mmusic-mdns-ice-candidates](https://tools.ietf.org/html/draft-ietf-mmusic-mdns-ice-candidates-02).
+    /// The IP address of a remote candidate is unspecified until the
+    /// hostname is resolved.
+    pub hostname: Option<String>,
+    pub kind: CandidateType,
+    /// The address the agent sends from to use the candidate, which is
+    /// `addr` itself for host and relayed candidates. Only known for local
+    /// candidates.
+    pub base: Option<SocketAddr>,
+    /// The base of a reflexive candidate, or the mapped address of a relayed
+    /// one, defined in
+    /// [RFC 8839](https://tools.ietf.org/html/rfc8839#section-5.1).
+    pub related_addr: Option<SocketAddr>,
+    /// The role of a TCP candidate.
+    pub tcp_type: Option<TcpType>,
+    /// The `generation` extension of browsers, which counts ICE restarts.
+    pub generation: Option<u32>,
+    /// The `ufrag` extension of browsers, the username fragment the
+    /// candidate was gathered for.
+    pub ufrag: Option<String>,
+    /// Other extension attributes, in the order they appeared.
+    pub extensions: Vec<(String, String)>,
+}
+
+impl Candidate {
+    /// Creates a host candidate on a local address.
+    pub fn host(
+        component: u16,
+        transport: Transport,
+        addr: SocketAddr,
+        local_preference: u16,
+    ) -> Self {
+        Self::local(
+            CandidateType::Host,
+            component,
+            transport,
+            addr,
+            addr,
+            None,
+            None,
+            local_preference,
+        )
+    }
+
+    /// Creates a TCP host candidate of type `tcp_type` on a local address,
+    /// whose port is ignored for active candidates. `local_preference` is
+    /// that of a UDP candidate on the address, made into that of a TCP one by
+    /// [`tcp_local_preference`](fn.tcp_local_preference.html).
+    pub fn tcp_host(
+        component: u16,
+        tcp_type: TcpType,
+        mut addr: SocketAddr,
+        local_preference: u16,
+    ) -> Self {
+        if tcp_type == TcpType::Active {
+            addr.set_port(ACTIVE_TCP_PORT);
+        }
+        let local_preference =
+            tcp_local_preference(CandidateType::Host, tcp_type, local_preference);
+        Self {
+            tcp_type: Some(tcp_type),
+            ..Self::host(component, Transport::Tcp, addr, local_preference)
+        }
+    }
+
+    /// Creates a server reflexive candidate on `addr`, the address `server`
+    /// saw requests from `base` come from.
+    pub fn server_reflexive(
+        component: u16,
+        transport: Transport,
+        addr: SocketAddr,
+        base: SocketAddr,
+        server: IpAddr,
+        local_preference: u16,
+    ) -> Self {
+        Self::local(
+            CandidateType::ServerReflexive,
+            component,
+            transport,
+            addr,
+            base,
+            Some(base),
+            Some(server),
+            local_preference,
+        )
+    }
+
+    /// Creates a peer reflexive candidate on `addr`, the address the peer
+    /// saw connectivity checks from `base` come from, defined in
+    /// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-7.2.5.3.1).
+    /// Its priority is the one sent in the check.
+    pub fn peer_reflexive(
+        component: u16,
+        transport: Transport,
+        addr: SocketAddr,
+        base: SocketAddr,
+        priority: u32,
+    ) -> Self {
+        Self {
+            foundation: compute_foundation(
+                CandidateType::PeerReflexive,
+                base.ip(),
+                None,
+                transport,
+            ),
+            component,
+            transport,
+            priority,
+            addr,
+            hostname: None,
+            kind: CandidateType::PeerReflexive,
+            base: Some(base),
+            related_addr: Some(base),
+            tcp_type: None,
+            generation: None,
+            ufrag: None,
+            extensions: Vec::new(),
+        }
+    }
+
+    /// Creates a relayed candidate on `addr`, a relayed transport address
+    /// allocated on the TURN server at `server`, which saw the allocation
+    /// request come from `mapped_addr`.
+    pub fn relayed(
+        component: u16,
+        transport: Transport,
+        addr: SocketAddr,
+        mapped_addr: SocketAddr,
+        server: IpAddr,
+        local_preference: u16,
+    ) -> Self {
+        Self::local(
+            CandidateType::Relayed,
+            component,
+            transport,
+            addr,
+            addr,
+            Some(mapped_addr),
+            Some(server),
+            local_preference,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn local(
+        kind: CandidateType,
+        component: u16,
+        transport: Transport,
+        addr: SocketAddr,
+        base: SocketAddr,
+        related_addr: Option<SocketAddr>,
+        server: Option<IpAddr>,
+        local_preference: u16,
+    ) -> Self {
+        Self {
+            foundation: compute_foundation(kind, base.ip(), server, transport),
+            component,
+            transport,
+            priority: compute_priority(kind, local_preference, component),
+            addr,
+            hostname: None,
+            kind,
+            base: Some(base),
+            related_addr,
+            tcp_type: None,
+            generation: None,
+            ufrag: None,
+            extensions: Vec::new(),
+        }
+    }
+}
+
+/// Computes the priority of a candidate, defined in
+/// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-5.1.2.1).
+///
+/// `component` must be between 1 and 256, as component IDs parsed from SDP
+/// are.
+pub fn compute_priority(kind: CandidateType, local_preference: u16, component: u16) -> u32 {
+    debug_assert!((1..=256).contains(&component), "component ID out of range");
+    (u32::from(kind.preference()) << 24)
+        + (u32::from(local_preference) << 8)
+        + (256 - u32::from(component))
+}
+
+/// Returns the local preference of a TCP candidate, defined in
+/// [RFC 6544](https://tools.ietf.org/html/rfc6544#section-4.2): the
+/// preference for its direction in the top 3 bits, and the lower 13 bits of
+/// `other_preference`, which ranks its address as for UDP candidates.
+pub fn tcp_local_preference(kind: CandidateType, tcp_type: TcpType, other_preference: u16) -> u16 {
+    // Simultaneous-open is preferred for reflexive candidates, which may
+    // only get through NATs that way, and least preferred otherwise.
+    let direction = match (kind, tcp_type) {
+        (CandidateType::Host, TcpType::Active) | (CandidateType::Relayed, TcpType::Active) => 6,
+        (CandidateType::Host, TcpType::Passive) | (CandidateType::Relayed, TcpType::Passive) => 4,
+        (CandidateType::Host, TcpType::SimultaneousOpen)
+        | (CandidateType::Relayed, TcpType::SimultaneousOpen) => 2,
+        (_, TcpType::SimultaneousOpen) => 6,
+        (_, TcpType::Active) => 4,
+        (_, TcpType::Passive) => 2,
+    };
+    (direction << 13) | (other_preference & 0x1fff)
+}
+
+/// Returns the local preference of an address of a multihomed host, defined
+/// in [RFC 8445](https://tools.ietf.org/html/rfc8445#section-5.1.2.2).
+///
+/// Addresses are ranked by their precedence in the default policy table of
+/// [RFC 6724](https://tools.ietf.org/html/rfc6724#section-2.1), which puts
+/// IPv6 ahead of IPv4, and then by `index`, the order of the interface they
+/// are on, so that no two addresses share a preference.
+pub fn local_preference(ip: IpAddr, index: u8) -> u16 {
+    (u16::from(precedence(ip)) << 8) | u16::from(255 - index)
+}
+
+fn precedence(ip: IpAddr) -> u8 {
+    let ip = match ip {
+        IpAddr::V4(_) => return 35,
+        IpAddr::V6(ip) => ip,
+    };
+    let segments = ip.segments();
+
+    if ip.is_loopback() {
+        50
+    } else if ip.to_ipv4().is_some() && segments[5] == 0xffff {
+        // IPv4-mapped.
+        35
+    } else if segments[0] == 0x2002 {
+        // 6to4.
+        30
+    } else if segments[0] == 0x2001 && segments[1] == 0 {
+        // Teredo.
+        5
+    } else if segments[0] & 0xfe00 == 0xfc00 {
+        // Unique local.
+        3
+    } else if segments[..6] == [0; 6] || segments[0] & 0xffc0 == 0xfec0 || segments[0] == 0x3ffe {
+        // IPv4-compatible, site-local and 6bone.
+        1
+    } else {
+        40
+    }
+}
+
+/// Computes a foundation, defined in
+/// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-5.1.1.3), as a
+/// hash of everything that makes two candidates share one.
+pub fn compute_foundation(
+    kind: CandidateType,
+    base_ip: IpAddr,
+    server: Option<IpAddr>,
+    transport: Transport,
+) -> String {
+    let mut hasher = DefaultHasher::new();
+    (kind, base_ip, server, transport).hash(&mut hasher);
+    (hasher.finish() as u32).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn priority() {
+        let addr = "192.0.2.1:5000".parse().unwrap();
+        let candidate = Candidate::host(1, Transport::Udp, addr, DEFAULT_LOCAL_PREFERENCE);
+        assert_eq!(candidate.priority, 2_130_706_431);
+        assert_eq!(candidate.base, Some(addr));
+        assert_eq!(candidate.related_addr, None);
+
+        let candidate = Candidate::host(2, Transport::Udp, addr, DEFAULT_LOCAL_PREFERENCE);
+        assert_eq!(candidate.priority, 2_130_706_430);
+
+        let priorities: Vec<_> = [
+            CandidateType::Host,
+            CandidateType::PeerReflexive,
+            CandidateType::ServerReflexive,
+            CandidateType::Relayed,
+        ]
+        .iter()
+        .map(|&kind| compute_priority(kind, DEFAULT_LOCAL_PREFERENCE, 1))
+        .collect();
+        assert!(priorities.windows(2).all(|pair| pair[0] > pair[1]));
+        assert_eq!(compute_priority(CandidateType::Relayed, 0, 256), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "component ID out of range")]
+    #[cfg(debug_assertions)]
+    fn priority_component_zero() {
+        compute_priority(CandidateType::Host, DEFAULT_LOCAL_PREFERENCE, 0);
+    }
+
+    #[test]
+    fn local_preferences() {
+        let ipv6 = "2001:db8::1".parse().unwrap();
+        let ipv4 = "192.0.2.1".parse().unwrap();
+        let ula = "fd00::1".parse().unwrap();
+
+        assert!(local_preference(ipv6, 0) > local_preference(ipv4, 0));
+        assert!(local_preference(ipv4, 0) > local_preference(ula, 0));
+        assert!(local_preference(ipv4, 0) > local_preference(ipv4, 1));
+        assert!(local_preference(ipv6, 255) > local_preference(ipv4, 0));
+    }
+
+    #[test]
+    fn foundation() {
+        let base = "192.0.2.1:5000".parse().unwrap();
+        let other_base = "192.0.2.1:5001".parse().unwrap();
+        let server = "198.51.100.1".parse().unwrap();
+        let mapped = "203.0.113.1:6000".parse().unwrap();
+
+        let srflx = |base, server| {
+            Candidate::server_reflexive(1, Transport::Udp, mapped, base, server, 65535)
+        };
+        let candidate = srflx(base, server);
+        assert_eq!(candidate.related_addr, Some(base));
+
+        // Only the base IP address matters, not the port.
+        assert_eq!(candidate.foundation, srflx(other_base, server).foundation);
+        assert_ne!(
+            candidate.foundation,
+            srflx(base, "198.51.100.2".parse().unwrap()).foundation
+        );
+
+        let host = Candidate::host(1, Transport::Udp, base, 65535);
+        let tcp_host = Candidate::host(1, Transport::Tcp, base, 65535);
+        assert_ne!(host.foundation, candidate.foundation);
+        assert_ne!(host.foundation, tcp_host.foundation);
+
+        // Components share foundations.
+        assert_eq!(
+            host.foundation,
+            Candidate::host(2, Transport::Udp, other_base, 1).foundation
+        );
+
+        let relayed = Candidate::relayed(1, Transport::Udp, mapped, base, server, 65535);
+        assert_eq!(relayed.base, Some(mapped));
+        assert_eq!(relayed.related_addr, Some(base));
+        assert_ne!(relayed.foundation, candidate.foundation);
+    }
+
+    #[test]
+    fn tcp() {
+        let addr = "192.0.2.1:5000".parse().unwrap();
+        let active = Candidate::tcp_host(1, TcpType::Active, addr, DEFAULT_LOCAL_PREFERENCE);
+        let passive = Candidate::tcp_host(1, TcpType::Passive, addr, DEFAULT_LOCAL_PREFERENCE);
+        let so = Candidate::tcp_host(1, TcpType::SimultaneousOpen, addr, 0);
+        assert_eq!(active.addr.port(), ACTIVE_TCP_PORT);
+        assert_eq!(active.base, Some(active.addr));
+        assert_eq!(passive.addr, addr);
+        assert_eq!(passive.tcp_type, Some(TcpType::Passive));
+        assert_eq!(so.transport, Transport::Tcp);
+
+        // Host candidates prefer active, then passive, then simultaneous-open.
+        assert_eq!(
+            active.priority,
+            compute_priority(CandidateType::Host, 0xdfff, 1)
+        );
+        assert!(active.priority > passive.priority);
+        assert!(passive.priority > so.priority);
+
+        let srflx = |tcp_type| tcp_local_preference(CandidateType::ServerReflexive, tcp_type, 0);
+        assert!(srflx(TcpType::SimultaneousOpen) > srflx(TcpType::Active));
+        assert!(srflx(TcpType::Active) > srflx(TcpType::Passive));
+    }
+}
@@ -0,0 +1,398 @@
+use crate::candidate::{Candidate, CandidateType, TcpType, Transport};
+use crate::mdns;
+use bifrost_sdp::Attribute;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+
+/// The name of the attribute carrying a candidate.
+const NAME: &str = "candidate";
+
+impl Candidate {
+    /// Parses the value of an `a=candidate` attribute, defined in
+    /// [RFC 8839](https://tools.ietf.org/html/rfc8839#section-5.1), which is
+    /// everything after `candidate:`.
+    pub fn from_sdp_value(value: &str) -> Result<Self, ParseCandidateError> {
+        // foundation SP component-id SP transport SP priority SP
+        // connection-address SP port SP "typ" SP cand-type
+        // [SP "raddr" SP connection-address SP "rport" SP port]
+        // *(SP extension-att-name SP extension-att-value)
+        let mut fields = value.split_ascii_whitespace();
+        let mut next = |field| fields.next().ok_or(ParseCandidateError(field));
+
+        let foundation = next("missing foundation")?;
+        if foundation.is_empty() || foundation.len() > 32 || !foundation.chars().all(is_ice_char) {
+            return Err(ParseCandidateError("invalid foundation"));
+        }
+
+        let component = next("missing component ID")?
+            .parse()
+            .ok()
+            .filter(|component| (1..=256).contains(component))
+            .ok_or(ParseCandidateError("invalid component ID"))?;
+
+        let transport = match next("missing transport")?.to_ascii_lowercase().as_str() {
+            "udp" => Transport::Udp,
+            "tcp" => Transport::Tcp,
+            _ => return Err(ParseCandidateError("unknown transport")),
+        };
+
+        let priority = next("missing priority")?
+            .parse()
+            .map_err(|_| ParseCandidateError("invalid priority"))?;
+
+        // The address may be a multicast DNS hostname, whose IP address is
+        // unknown until resolved.
+        let (ip, hostname) = match next("missing address")? {
+            hostname if mdns::is_mdns_hostname(hostname) => {
+                (IpAddr::V4(Ipv4Addr::UNSPECIFIED), Some(hostname.to_owned()))
+            }
+            ip => (parse_ip(ip)?, None),
+        };
+        let port = parse_port(next("missing port")?)?;
+
+        if next("missing type")? != "typ" {
+            return Err(ParseCandidateError("missing type"));
+        }
+        let kind = match next("missing type")? {
+            "host" => CandidateType::Host,
+            "srflx" => CandidateType::ServerReflexive,
+            "prflx" => CandidateType::PeerReflexive,
+            "relay" => CandidateType::Relayed,
+            _ => return Err(ParseCandidateError("unknown type")),
+        };
+
+        let mut candidate = Self {
+            foundation: foundation.to_owned(),
+            component,
+            transport,
+            priority,
+            addr: SocketAddr::new(ip, port),
+            hostname,
+            kind,
+            base: None,
+            related_addr: None,
+            tcp_type: None,
+            generation: None,
+            ufrag: None,
+            extensions: Vec::new(),
+        };
+
+        let mut related_ip = None;
+        while let Some(name) = fields.next() {
+            let value = fields
+                .next()
+                .ok_or(ParseCandidateError("missing extension value"))?;
+            match name {
+                "raddr" if related_ip.is_none() && candidate.related_addr.is_none() => {
+                    related_ip = Some(parse_ip(value)?);
+                }
+                "rport" => {
+                    let ip = related_ip
+                        .take()
+                        .ok_or(ParseCandidateError("rport without raddr"))?;
+                    candidate.related_addr = Some(SocketAddr::new(ip, parse_port(value)?));
+                }
+                "tcptype" if candidate.tcp_type.is_none() => {
+                    candidate.tcp_type = Some(match value {
+                        "active" => TcpType::Active,
+                        "passive" => TcpType::Passive,
+                        "so" => TcpType::SimultaneousOpen,
+                        _ => return Err(ParseCandidateError("unknown TCP type")),
+                    });
+                }
+                "generation" if candidate.generation.is_none() => {
+                    candidate.generation = Some(
+                        value
+                            .parse()
+                            .map_err(|_| ParseCandidateError("invalid generation"))?,
+                    );
+                }
+                "ufrag" if candidate.ufrag.is_none() => candidate.ufrag = Some(value.to_owned()),
+                "raddr" | "tcptype" | "generation" | "ufrag" => {
+                    return Err(ParseCandidateError("duplicate extension"));
+                }
+                _ => candidate
+                    .extensions
+                    .push((name.to_owned(), value.to_owned())),
+            }
+        }
+        if related_ip.is_some() {
+            return Err(ParseCandidateError("raddr without rport"));
+        }
+
+        Ok(candidate)
+    }
+
+    /// Returns the value of the `a=candidate` attribute for the candidate.
+    pub fn to_sdp_value(&self) -> String {
+        let transport = match self.transport {
+            Transport::Udp => "udp",
+            Transport::Tcp => "tcp",
+        };
+        let kind = match self.kind {
+            CandidateType::Host => "host",
+            CandidateType::ServerReflexive => "srflx",
+            CandidateType::PeerReflexive => "prflx",
+            CandidateType::Relayed => "relay",
+        };
+        let address = match &self.hostname {
+            Some(hostname) => hostname.clone(),
+            None => self.addr.ip().to_string(),
+        };
+        let mut value = format!(
+            "{} {} {} {} {} {} typ {}",
+            self.foundation,
+            self.component,
+            transport,
+            self.priority,
+            address,
+            self.addr.port(),
+            kind
+        );
+
+        if let Some(addr) = self.related_addr {
+            value += &format!(" raddr {} rport {}", addr.ip(), addr.port());
+        }
+        if let Some(tcp_type) = self.tcp_type {
+            let tcp_type = match tcp_type {
+                TcpType::Active => "active",
+                TcpType::Passive => "passive",
+                TcpType::SimultaneousOpen => "so",
+            };
+            value += &format!(" tcptype {}", tcp_type);
+        }
+        if let Some(generation) = self.generation {
+            value += &format!(" generation {}", generation);
+        }
+        if let Some(ufrag) = &self.ufrag {
+            value += &format!(" ufrag {}", ufrag);
+        }
+        for (name, value_) in &self.extensions {
+            value += &format!(" {} {}", name, value_);
+        }
+
+        value
+    }
+
+    /// Returns the `a=candidate` attribute for the candidate.
+    pub fn to_attribute(&self) -> Attribute {
+        Attribute {
+            name: NAME.to_owned(),
+            value: Some(self.to_sdp_value()),
+        }
+    }
+}
+
+/// Formats the candidate as `candidate:<value>`, the form browsers use in
+/// `RTCIceCandidate.candidate`.
+impl fmt::Display for Candidate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", NAME, self.to_sdp_value())
+    }
+}
+
+/// Parses a candidate in the form of `candidate:<value>`, optionally
+/// preceded by `a=`.
+impl FromStr for Candidate {
+    type Err = ParseCandidateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim_end_matches(&['\r', '\n'][..]);
+        let s = s.trim_start_matches("a=");
+        match s.find(':') {
+            Some(i) if &s[..i] == NAME => Self::from_sdp_value(&s[i + 1..]),
+            _ => Err(ParseCandidateError("not a candidate attribute")),
+        }
+    }
+}
+
+impl TryFrom<&Attribute> for Candidate {
+    type Error = ParseCandidateError;
+
+    fn try_from(attribute: &Attribute) -> Result<Self, Self::Error> {
+        match &attribute.value {
+            Some(value) if attribute.name == NAME => Self::from_sdp_value(value),
+            _ => Err(ParseCandidateError("not a candidate attribute")),
+        }
+    }
+}
+
+fn is_ice_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '+' || c == '/'
+}
+
+fn parse_ip(s: &str) -> Result<IpAddr, ParseCandidateError> {
+    s.parse()
+        .map_err(|_| ParseCandidateError("invalid address"))
+}
+
+fn parse_port(s: &str) -> Result<u16, ParseCandidateError> {
+    s.parse().map_err(|_| ParseCandidateError("invalid port"))
+}
+
+/// An error parsing a `Candidate`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ParseCandidateError(&'static str);
+
+impl fmt::Display for ParseCandidateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid candidate: {}", self.0)
+    }
+}
+
+impl Error for ParseCandidateError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_parse_display(s: &str) -> Candidate {
+        let candidate: Candidate = s.parse().unwrap();
+        assert_eq!(candidate.to_string(), s);
+        candidate
+    }
+
+    #[test]
+    fn chrome() {
+        let candidate = assert_parse_display(
+            "candidate:1467250027 1 udp 2122260223 192.168.0.196 46243 typ host \
+             generation 0 ufrag 9sXw network-id 1 network-cost 10",
+        );
+        assert_eq!(
+            candidate,
+            Candidate {
+                foundation: "1467250027".to_owned(),
+                component: 1,
+                transport: Transport::Udp,
+                priority: 2_122_260_223,
+                addr: "192.168.0.196:46243".parse().unwrap(),
+                hostname: None,
+                kind: CandidateType::Host,
+                base: None,
+                related_addr: None,
+                tcp_type: None,
+                generation: Some(0),
+                ufrag: Some("9sXw".to_owned()),
+                extensions: vec![
+                    ("network-id".to_owned(), "1".to_owned()),
+                    ("network-cost".to_owned(), "10".to_owned()),
+                ],
+            }
+        );
+
+        let candidate = assert_parse_display(
+            "candidate:842163049 1 udp 1677729535 203.0.113.7 54400 typ srflx \
+             raddr 192.168.0.196 rport 46243 generation 0 ufrag 9sXw network-id 1 \
+             network-cost 10",
+        );
+        assert_eq!(candidate.kind, CandidateType::ServerReflexive);
+        assert_eq!(
+            candidate.related_addr,
+            Some("192.168.0.196:46243".parse().unwrap())
+        );
+
+        let candidate = assert_parse_display(
+            "candidate:435653019 1 tcp 1518280447 192.168.0.196 9 typ host \
+             tcptype active generation 0 ufrag 9sXw network-id 1 network-cost 10",
+        );
+        assert_eq!(candidate.transport, Transport::Tcp);
+        assert_eq!(candidate.tcp_type, Some(TcpType::Active));
+    }
+
+    #[test]
+    fn mdns() {
+        let candidate = assert_parse_display(
+            "candidate:1 1 udp 2122262783 9b36eaac-bb2e-49bb-bb78-21c41c499900.local 56143 \
+             typ host generation 0 ufrag 9sXw network-cost 999",
+        );
+        assert_eq!(
+            candidate.hostname.as_ref().unwrap(),
+            "9b36eaac-bb2e-49bb-bb78-21c41c499900.local"
+        );
+        assert!(candidate.addr.ip().is_unspecified());
+        assert_eq!(candidate.addr.port(), 56143);
+
+        // A local candidate shows its hostname instead of its address.
+        let mut candidate =
+            Candidate::host(1, Transport::Udp, "192.0.2.1:5000".parse().unwrap(), 65535);
+        candidate.hostname = Some("host.local".to_owned());
+        assert!(candidate.to_sdp_value().contains(" host.local 5000 "));
+    }
+
+    #[test]
+    fn firefox() {
+        let candidate: Candidate = "candidate:0 1 UDP 2122252543 2001:db8::7 56143 typ host"
+            .parse()
+            .unwrap();
+        assert_eq!(candidate.transport, Transport::Udp);
+        assert_eq!(candidate.addr, "[2001:db8::7]:56143".parse().unwrap());
+        assert_eq!(
+            candidate.to_string(),
+            "candidate:0 1 udp 2122252543 2001:db8::7 56143 typ host"
+        );
+
+        let candidate: Candidate =
+            "a=candidate:5 2 UDP 92217086 198.51.100.4 61520 typ relay raddr 203.0.113.7 rport 54400\r\n"
+                .parse()
+                .unwrap();
+        assert_eq!(candidate.component, 2);
+        assert_eq!(candidate.kind, CandidateType::Relayed);
+        assert_eq!(
+            candidate.related_addr,
+            Some("203.0.113.7:54400".parse().unwrap())
+        );
+        assert_eq!(candidate.generation, None);
+        assert!(candidate.extensions.is_empty());
+    }
+
+    #[test]
+    fn attribute() {
+        let candidate =
+            Candidate::host(1, Transport::Udp, "192.0.2.1:5000".parse().unwrap(), 65535);
+        let attribute = candidate.to_attribute();
+        assert_eq!(attribute.name, "candidate");
+
+        let mut parsed = Candidate::try_from(&attribute).unwrap();
+        assert_eq!(parsed.base, None);
+        parsed.base = candidate.base;
+        assert_eq!(parsed, candidate);
+
+        let attribute = Attribute {
+            name: "ice-ufrag".to_owned(),
+            value: Some("9sXw".to_owned()),
+        };
+        assert!(Candidate::try_from(&attribute).is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        for s in &[
+            "",
+            "candidate:",
+            "candidate 1 1 udp 1 192.0.2.1 1 typ host",
+            "candidate:1 1 udp 1 192.0.2.1 1",
+            "candidate:1 1 udp 1 192.0.2.1 1 typ",
+            "candidate:1 1 udp 1 192.0.2.1 1 type host",
+            "candidate:1 1 udp 1 192.0.2.1 1 typ foo",
+            "candidate:1 0 udp 1 192.0.2.1 1 typ host",
+            "candidate:1 257 udp 1 192.0.2.1 1 typ host",
+            "candidate:1 1 sctp 1 192.0.2.1 1 typ host",
+            "candidate:1 1 udp -1 192.0.2.1 1 typ host",
+            "candidate:1 1 udp 1 192.0.2.256 1 typ host",
+            "candidate:1 1 udp 1 host.example 1 typ host",
+            "candidate:1 1 udp 1 192.0.2.1 65536 typ host",
+            "candidate:f-o 1 udp 1 192.0.2.1 1 typ host",
+            "candidate:1 1 udp 1 192.0.2.1 1 typ srflx raddr 192.0.2.2",
+            "candidate:1 1 udp 1 192.0.2.1 1 typ srflx rport 1",
+            "candidate:1 1 tcp 1 192.0.2.1 1 typ host tcptype foo",
+            "candidate:1 1 udp 1 192.0.2.1 1 typ host generation x",
+            "candidate:1 1 udp 1 192.0.2.1 1 typ host ufrag a ufrag b",
+            "candidate:1 1 udp 1 192.0.2.1 1 typ host network-id",
+        ] {
+            assert!(s.parse::<Candidate>().is_err(), "parsed {:?}", s);
+        }
+    }
+}
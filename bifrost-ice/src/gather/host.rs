@@ -0,0 +1,324 @@
+use crate::candidate::{self, Candidate, Transport, DEFAULT_LOCAL_PREFERENCE};
+use futures_util::stream::{self, Stream};
+use std::io;
+use std::net::{IpAddr, SocketAddr, SocketAddrV6, UdpSocket as StdUdpSocket};
+use tokio_net::driver::Handle;
+use tokio_net::udp::UdpSocket;
+
+/// The prefixes of the names of common virtual interfaces, such as those of
+/// containers, virtual machines and VPNs.
+const VIRTUAL_PREFIXES: &[&str] = &[
+    "docker", "veth", "br-", "virbr", "vmnet", "vboxnet", "tun", "tap", "utun", "zt",
+];
+
+/// An IP address of a local network interface.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Interface {
+    pub name: String,
+    pub ip: IpAddr,
+}
+
+impl Interface {
+    /// Lists the addresses of all local network interfaces.
+    pub fn list() -> io::Result<Vec<Self>> {
+        Ok(get_if_addrs::get_if_addrs()?
+            .into_iter()
+            .map(|interface| Self {
+                ip: interface.ip(),
+                name: interface.name,
+            })
+            .collect())
+    }
+
+    pub fn is_loopback(&self) -> bool {
+        self.ip.is_loopback()
+    }
+
+    pub fn is_link_local(&self) -> bool {
+        match self.ip {
+            IpAddr::V4(ip) => ip.is_link_local(),
+            IpAddr::V6(ip) => ip.segments()[0] & 0xffc0 == 0xfe80,
+        }
+    }
+
+    /// Returns the index of the interface, which scopes IPv6 link-local
+    /// addresses, or 0 if it cannot be found.
+    #[cfg(unix)]
+    fn index(&self) -> u32 {
+        match std::ffi::CString::new(self.name.as_str()) {
+            Ok(name) => unsafe { libc::if_nametoindex(name.as_ptr()) },
+            Err(_) => 0,
+        }
+    }
+
+    /// Returns 0, as interface indices are only looked up on Unix.
+    #[cfg(not(unix))]
+    fn index(&self) -> u32 {
+        0
+    }
+}
+
+/// Which interfaces to gather host candidates on.
+#[derive(Clone, Debug)]
+pub struct HostConfig {
+    pub ipv4: bool,
+    pub ipv6: bool,
+    pub loopback: bool,
+    pub link_local: bool,
+    /// Whether to use interfaces whose names start with one of
+    /// `virtual_prefixes`.
+    pub virtual_interfaces: bool,
+    pub virtual_prefixes: Vec<String>,
+    /// The names of the only interfaces to use, or all if empty.
+    pub interfaces: Vec<String>,
+    /// The inclusive range of ports to bind to, or any port if `None`.
+    pub port_range: Option<(u16, u16)>,
+}
+
+impl HostConfig {
+    /// Returns whether candidates should be gathered on `interface`.
+    pub fn allows(&self, interface: &Interface) -> bool {
+        let family = match interface.ip {
+            IpAddr::V4(_) => self.ipv4,
+            IpAddr::V6(_) => self.ipv6,
+        };
+
+        family
+            && (self.loopback || !interface.is_loopback())
+            && (self.link_local || !interface.is_link_local())
+            && (self.virtual_interfaces
+                || !self
+                    .virtual_prefixes
+                    .iter()
+                    .any(|prefix| interface.name.starts_with(prefix.as_str())))
+            && (self.interfaces.is_empty() || self.interfaces.contains(&interface.name))
+            && !interface.ip.is_unspecified()
+    }
+}
+
+impl Default for HostConfig {
+    fn default() -> Self {
+        Self {
+            ipv4: true,
+            ipv6: true,
+            loopback: false,
+            link_local: false,
+            virtual_interfaces: false,
+            virtual_prefixes: VIRTUAL_PREFIXES.iter().map(|&s| s.to_owned()).collect(),
+            interfaces: Vec::new(),
+            port_range: None,
+        }
+    }
+}
+
+/// A host candidate and the socket bound to it.
+#[derive(Debug)]
+pub struct HostCandidate {
+    pub candidate: Candidate,
+    pub socket: UdpSocket,
+}
+
+/// Gathers host candidates for components 1 to `components` on the local
+/// interfaces `config` allows, defined in
+/// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-5.1.1.1).
+///
+/// Each socket is bound as the stream is polled, so candidates can be
+/// trickled as they come. Failing to bind one address does not end the
+/// stream.
+pub fn gather_host(
+    config: &HostConfig,
+    components: u16,
+) -> io::Result<impl Stream<Item = io::Result<HostCandidate>>> {
+    Ok(gather_host_on(Interface::list()?, config, components))
+}
+
+/// Gathers host candidates like `gather_host`, but on the given interfaces.
+pub fn gather_host_on(
+    interfaces: Vec<Interface>,
+    config: &HostConfig,
+    components: u16,
+) -> impl Stream<Item = io::Result<HostCandidate>> {
+    let interfaces: Vec<_> = interfaces
+        .into_iter()
+        .filter(|interface| config.allows(interface))
+        .collect();
+    let multihomed = interfaces.len() > 1;
+    let port_range = config.port_range;
+
+    let addrs = interfaces
+        .into_iter()
+        .enumerate()
+        .flat_map(move |(i, interface)| {
+            let local_preference = if multihomed {
+                candidate::local_preference(interface.ip, i.min(255) as u8)
+            } else {
+                DEFAULT_LOCAL_PREFERENCE
+            };
+            (1..=components).map(move |component| (component, interface.clone(), local_preference))
+        });
+
+    stream::iter(addrs.map(move |(component, interface, local_preference)| {
+        let socket = bind(&interface, port_range)?;
+        let addr = socket.local_addr()?;
+        Ok(HostCandidate {
+            candidate: Candidate::host(component, Transport::Udp, addr, local_preference),
+            socket: UdpSocket::from_std(socket, &Handle::default())?,
+        })
+    }))
+}
+
+fn bind(interface: &Interface, port_range: Option<(u16, u16)>) -> io::Result<StdUdpSocket> {
+    let addr = |port| match interface.ip {
+        IpAddr::V6(ip) if interface.is_link_local() => {
+            SocketAddr::V6(SocketAddrV6::new(ip, port, 0, interface.index()))
+        }
+        ip => SocketAddr::new(ip, port),
+    };
+
+    let (min, max) = match port_range {
+        Some(range) => range,
+        None => return StdUdpSocket::bind(addr(0)),
+    };
+
+    let mut result = Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "empty port range",
+    ));
+    for port in min..=max {
+        result = StdUdpSocket::bind(addr(port));
+        match &result {
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => continue,
+            _ => break,
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+
+    fn interface(name: &str, ip: &str) -> Interface {
+        Interface {
+            name: name.to_owned(),
+            ip: ip.parse().unwrap(),
+        }
+    }
+
+    fn loopback_config() -> HostConfig {
+        HostConfig {
+            loopback: true,
+            interfaces: vec!["lo".to_owned()],
+            ..HostConfig::default()
+        }
+    }
+
+    fn loopback_interfaces() -> Vec<Interface> {
+        vec![interface("lo", "127.0.0.1"), interface("lo", "::1")]
+    }
+
+    #[test]
+    fn policy() {
+        let config = HostConfig::default();
+        assert!(config.allows(&interface("eth0", "192.0.2.1")));
+        assert!(config.allows(&interface("eth0", "2001:db8::1")));
+        assert!(!config.allows(&interface("lo", "127.0.0.1")));
+        assert!(!config.allows(&interface("lo", "::1")));
+        assert!(!config.allows(&interface("eth0", "169.254.0.1")));
+        assert!(!config.allows(&interface("eth0", "fe80::1")));
+        assert!(!config.allows(&interface("docker0", "172.17.0.1")));
+        assert!(!config.allows(&interface("veth1234", "172.17.0.2")));
+
+        let config = HostConfig {
+            ipv6: false,
+            link_local: true,
+            virtual_interfaces: true,
+            ..HostConfig::default()
+        };
+        assert!(!config.allows(&interface("eth0", "2001:db8::1")));
+        assert!(config.allows(&interface("eth0", "169.254.0.1")));
+        assert!(config.allows(&interface("docker0", "172.17.0.1")));
+
+        let config = HostConfig {
+            interfaces: vec!["eth1".to_owned()],
+            ..HostConfig::default()
+        };
+        assert!(!config.allows(&interface("eth0", "192.0.2.1")));
+        assert!(config.allows(&interface("eth1", "192.0.2.2")));
+    }
+
+    #[test]
+    fn loopback() {
+        tokio_test::block_on(async {
+            let candidates: Vec<_> = gather_host_on(loopback_interfaces(), &loopback_config(), 2)
+                .map(|result| result.unwrap().candidate)
+                .collect()
+                .await;
+            assert_eq!(candidates.len(), 4);
+
+            for (candidate, (ip, component)) in
+                candidates
+                    .iter()
+                    .zip(&[("127.0.0.1", 1), ("127.0.0.1", 2), ("::1", 1), ("::1", 2)])
+            {
+                assert_eq!(candidate.addr.ip(), ip.parse::<IpAddr>().unwrap());
+                assert_ne!(candidate.addr.port(), 0);
+                assert_eq!(candidate.component, *component);
+                assert_eq!(candidate.base, Some(candidate.addr));
+            }
+
+            // IPv6 is preferred on a multihomed host.
+            assert!(candidates[2].priority > candidates[0].priority);
+            assert_eq!(candidates[0].foundation, candidates[1].foundation);
+            assert_ne!(candidates[0].foundation, candidates[2].foundation);
+        });
+    }
+
+    #[test]
+    fn port_range() {
+        tokio_test::block_on(async {
+            let taken = StdUdpSocket::bind("127.0.0.1:0").unwrap();
+            let port = taken.local_addr().unwrap().port();
+            let config = HostConfig {
+                ipv6: false,
+                port_range: Some((port, port.saturating_add(100))),
+                ..loopback_config()
+            };
+
+            let mut candidates = gather_host_on(loopback_interfaces(), &config, 1);
+            let host = candidates.next().await.unwrap().unwrap();
+            assert!(host.candidate.addr.port() > port);
+            assert_eq!(host.socket.local_addr().unwrap(), host.candidate.addr);
+            assert_eq!(host.candidate.priority >> 8 & 0xffff, 65535);
+            assert!(candidates.next().await.is_none());
+
+            let config = HostConfig {
+                port_range: Some((port, port)),
+                ..config
+            };
+            let mut candidates = gather_host_on(loopback_interfaces(), &config, 1);
+            let err = candidates.next().await.unwrap().unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        });
+    }
+
+    #[test]
+    fn local_interfaces() {
+        tokio_test::block_on(async {
+            let interfaces = Interface::list().unwrap();
+            assert!(interfaces.iter().any(Interface::is_loopback));
+
+            let config = HostConfig {
+                loopback: true,
+                interfaces: Vec::new(),
+                ..loopback_config()
+            };
+            let candidates: Vec<_> = gather_host(&config, 1).unwrap().collect().await;
+            assert!(candidates
+                .iter()
+                .filter_map(|result| result.as_ref().ok())
+                .any(|host| host.candidate.addr.ip().is_loopback()));
+        });
+    }
+}
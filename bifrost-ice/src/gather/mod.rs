@@ -0,0 +1,7 @@
+mod host;
+mod monitor;
+mod server;
+
+pub use self::host::{gather_host, gather_host_on, HostCandidate, HostConfig, Interface};
+pub use self::monitor::{gather_on, watch_interfaces, Gathered, InterfaceChange, InterfaceMonitor};
+pub use self::server::{gather_server, RelayedCandidate, ServerConfig, ServerEvent, DEFAULT_TA};
@@ -0,0 +1,375 @@
+use crate::candidate::Candidate;
+use crate::gather::{
+    gather_host_on, gather_server, HostCandidate, HostConfig, Interface, RelayedCandidate,
+    ServerConfig, ServerEvent,
+};
+use futures_util::future::Either;
+use futures_util::stream::{self, Stream, StreamExt};
+use std::io;
+use std::time::Duration;
+use tokio_timer::Interval;
+
+/// A change of the local network interfaces.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum InterfaceChange {
+    Added(Interface),
+    Removed(Interface),
+}
+
+/// Tracks the local interfaces a `HostConfig` allows, and tells how they
+/// changed between two listings.
+#[derive(Clone, Debug)]
+pub struct InterfaceMonitor {
+    config: HostConfig,
+    interfaces: Vec<Interface>,
+}
+
+impl InterfaceMonitor {
+    pub fn new(config: HostConfig) -> Self {
+        Self {
+            config,
+            interfaces: Vec::new(),
+        }
+    }
+
+    /// Returns the interfaces of the last listing.
+    pub fn interfaces(&self) -> &[Interface] {
+        &self.interfaces
+    }
+
+    /// Replaces the interfaces with a new listing, and returns the changes:
+    /// removals first, then additions.
+    pub fn update(&mut self, interfaces: Vec<Interface>) -> Vec<InterfaceChange> {
+        let config = &self.config;
+        let interfaces: Vec<_> = interfaces
+            .into_iter()
+            .filter(|interface| config.allows(interface))
+            .collect();
+
+        let removed = self
+            .interfaces
+            .iter()
+            .filter(|interface| !interfaces.contains(interface))
+            .cloned()
+            .map(InterfaceChange::Removed);
+        let added = interfaces
+            .iter()
+            .filter(|interface| !self.interfaces.contains(interface))
+            .cloned()
+            .map(InterfaceChange::Added);
+        let changes = removed.chain(added).collect();
+
+        self.interfaces = interfaces;
+        changes
+    }
+}
+
+/// Watches the local interfaces `config` allows, listing them every
+/// `interval`, and on Linux also as soon as the kernel reports a change of
+/// links or addresses over netlink. If netlink cannot be watched, the error
+/// is logged and the interfaces are only listed every `interval`.
+///
+/// Every change is passed to `Agent::handle_network_change`, and candidates
+/// are gathered on added interfaces with `gather_on`.
+pub fn watch_interfaces(
+    config: HostConfig,
+    interval: Duration,
+) -> io::Result<impl Stream<Item = InterfaceChange>> {
+    let mut monitor = InterfaceMonitor::new(config);
+    monitor.update(Interface::list()?);
+
+    let ticks = Interval::new_interval(interval).map(|_| ());
+    let changes = match netlink::watch() {
+        Ok(watch) => Either::Left(watch),
+        Err(e) => {
+            log::warn!("cannot watch interfaces over netlink: {}", e);
+            Either::Right(stream::empty())
+        }
+    };
+    let wakeups = stream::select(ticks, changes);
+    Ok(wakeups
+        .map(move |()| match Interface::list() {
+            Ok(interfaces) => monitor.update(interfaces),
+            Err(_) => Vec::new(),
+        })
+        .map(stream::iter)
+        .flatten())
+}
+
+/// The sockets of the candidates gathered on an interface, which must stay
+/// open for as long as the candidates are in use.
+pub struct Gathered {
+    pub hosts: Vec<HostCandidate>,
+    pub relayed: Vec<RelayedCandidate>,
+}
+
+/// Gathers host candidates for components 1 to `components` on `interface`,
+/// then server reflexive and relayed candidates from them, calling
+/// `on_candidate` with each candidate as soon as it is gathered.
+///
+/// `on_candidate` typically adds the candidate to an agent with
+/// `Agent::add_local_candidate`, which trickles it. Servers that fail are
+/// skipped, as gathering goes on with the others.
+pub async fn gather_on<F>(
+    interface: Interface,
+    host_config: &HostConfig,
+    server_config: &ServerConfig,
+    components: u16,
+    mut on_candidate: F,
+) -> io::Result<Gathered>
+where
+    F: FnMut(Candidate),
+{
+    let mut hosts = Vec::new();
+    let mut candidates = gather_host_on(vec![interface], host_config, components);
+    while let Some(host) = candidates.next().await {
+        let host = host?;
+        on_candidate(host.candidate.clone());
+        hosts.push(host);
+    }
+
+    let mut relayed = Vec::new();
+    {
+        let mut events = gather_server(&mut hosts, server_config);
+        while let Some(event) = events.next().await {
+            match event {
+                ServerEvent::ServerReflexive { candidate, .. } => on_candidate(candidate),
+                ServerEvent::Relayed(candidate) => {
+                    on_candidate(candidate.candidate.clone());
+                    relayed.push(candidate);
+                }
+                ServerEvent::Failed { .. } => {}
+            }
+        }
+    }
+
+    Ok(Gathered { hosts, relayed })
+}
+
+#[cfg(target_os = "linux")]
+mod netlink {
+    use futures_util::stream::Stream;
+    use std::io;
+    use std::mem;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use std::thread;
+    use std::time::Duration;
+    use tokio_sync::mpsc;
+
+    /// How long the reading thread waits for a message before checking
+    /// whether the stream was dropped.
+    pub const RECV_TIMEOUT: Duration = Duration::from_millis(500);
+
+    /// A stream that yields whenever the kernel reports a change of links
+    /// or addresses.
+    pub struct Watch {
+        rx: mpsc::UnboundedReceiver<()>,
+        pub(super) dropped: Arc<AtomicBool>,
+    }
+
+    impl Stream for Watch {
+        type Item = ();
+
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<()>> {
+            self.rx.poll_recv(cx)
+        }
+    }
+
+    impl Drop for Watch {
+        fn drop(&mut self) {
+            self.dropped.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Returns a stream that yields whenever the kernel reports a change of
+    /// links or addresses.
+    ///
+    /// The messages are read on a thread of their own. Receiving times out
+    /// every `RECV_TIMEOUT`, so the thread exits soon after the stream is
+    /// dropped even if no message arrives.
+    pub fn watch() -> io::Result<Watch> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups =
+            (libc::RTMGRP_LINK | libc::RTMGRP_IPV4_IFADDR | libc::RTMGRP_IPV6_IFADDR) as u32;
+        let timeout = libc::timeval {
+            tv_sec: RECV_TIMEOUT.as_secs() as libc::time_t,
+            tv_usec: RECV_TIMEOUT.subsec_micros() as libc::suseconds_t,
+        };
+        let res = unsafe {
+            libc::bind(
+                fd,
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        let res = if res < 0 {
+            res
+        } else {
+            unsafe {
+                libc::setsockopt(
+                    fd,
+                    libc::SOL_SOCKET,
+                    libc::SO_RCVTIMEO,
+                    &timeout as *const libc::timeval as *const libc::c_void,
+                    mem::size_of::<libc::timeval>() as libc::socklen_t,
+                )
+            }
+        };
+        if res < 0 {
+            let err = io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(err);
+        }
+
+        let (mut tx, rx) = mpsc::unbounded_channel();
+        let dropped = Arc::new(AtomicBool::new(false));
+        let watch = Watch {
+            rx,
+            dropped: Arc::clone(&dropped),
+        };
+        thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            while !dropped.load(Ordering::SeqCst) {
+                let len =
+                    unsafe { libc::recv(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+                if len < 0 {
+                    match io::Error::last_os_error().kind() {
+                        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted => continue,
+                        _ => break,
+                    }
+                }
+                if tx.try_send(()).is_err() {
+                    break;
+                }
+            }
+            unsafe { libc::close(fd) };
+        });
+        Ok(watch)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod netlink {
+    use futures_util::stream::{self, Stream};
+    use std::io;
+
+    pub fn watch() -> io::Result<impl Stream<Item = ()>> {
+        Ok(stream::empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::{Agent, AgentConfig, Credentials, Event, Role};
+    use crate::candidate::CandidateType;
+    use crate::test_util;
+    use bifrost_stun::uri::IceServer;
+    use std::net::Ipv4Addr;
+
+    fn interface(name: &str, ip: &str) -> Interface {
+        Interface {
+            name: name.to_owned(),
+            ip: ip.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn update() {
+        let wifi = interface("wlan0", "192.168.1.2");
+        let ethernet = interface("eth0", "10.0.0.2");
+        let mut monitor = InterfaceMonitor::new(HostConfig::default());
+
+        let changes = monitor.update(vec![interface("lo", "127.0.0.1"), wifi.clone()]);
+        assert_eq!(changes, [InterfaceChange::Added(wifi.clone())]);
+        assert_eq!(monitor.interfaces().len(), 1);
+        assert!(monitor.update(vec![wifi.clone()]).is_empty());
+
+        let changes = monitor.update(vec![ethernet.clone()]);
+        assert_eq!(
+            changes,
+            [
+                InterfaceChange::Removed(wifi),
+                InterfaceChange::Added(ethernet)
+            ]
+        );
+    }
+
+    #[test]
+    fn gather() {
+        tokio_test::block_on(async {
+            let nat = test_util::start_nat_server(Ipv4Addr::new(203, 0, 113, 1)).await;
+            let turn = test_util::start_turn_server().await;
+            let server_config = ServerConfig::new(vec![
+                IceServer::new(vec![nat]),
+                IceServer::new(vec![turn]).with_credentials("alice", "hunter2"),
+            ]);
+            let host_config = HostConfig {
+                loopback: true,
+                ..HostConfig::default()
+            };
+            let mut agent = Agent::new(AgentConfig {
+                trickle: true,
+                ..AgentConfig::new(Role::Controlling)
+            });
+            let stream = agent.add_stream(1, Credentials::random());
+
+            let lo = interface("lo", "127.0.0.1");
+            let gathered = gather_on(lo, &host_config, &server_config, 1, |candidate| {
+                agent.add_local_candidate(stream, candidate)
+            })
+            .await
+            .unwrap();
+            assert_eq!(gathered.hosts.len(), 1);
+            assert_eq!(gathered.relayed.len(), 1);
+
+            let mut kinds: Vec<_> = agent
+                .local_candidates(stream)
+                .iter()
+                .map(|candidate| candidate.kind)
+                .collect();
+            assert_eq!(kinds.remove(0), CandidateType::Host);
+            assert!(kinds.contains(&CandidateType::ServerReflexive));
+            assert!(kinds.contains(&CandidateType::Relayed));
+
+            // Every candidate is trickled.
+            let mut trickled = 0;
+            while let Some(event) = agent.poll_event() {
+                if let Event::LocalCandidate { .. } = event {
+                    trickled += 1;
+                }
+            }
+            assert_eq!(trickled, 3);
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn netlink_thread_exits() {
+        let watch = match netlink::watch() {
+            Ok(watch) => watch,
+            // Netlink sockets may not be available in a sandbox.
+            Err(_) => return,
+        };
+        let dropped = std::sync::Arc::clone(&watch.dropped);
+        drop(watch);
+
+        std::thread::sleep(netlink::RECV_TIMEOUT * 3);
+        assert_eq!(std::sync::Arc::strong_count(&dropped), 1);
+    }
+}
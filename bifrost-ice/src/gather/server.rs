@@ -0,0 +1,523 @@
+use crate::candidate::{Candidate, Transport, DEFAULT_LOCAL_PREFERENCE};
+use crate::gather::HostCandidate;
+use crate::stun;
+use bifrost_stun::agent::Agent;
+use bifrost_stun::message::attribute::{ErrorCode, XorMappedAddress};
+use bifrost_stun::message::{Class, Message, Method};
+use bifrost_stun::uri::{self, IceServer, IceServerUri};
+use bifrost_turn::client::{Credentials, ErrorResponse};
+use bifrost_turn::transport::Connection;
+use futures_util::future::{self, Either};
+use futures_util::stream::{self, FuturesUnordered, Stream, StreamExt};
+use std::collections::HashSet;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use tokio_sync::mpsc;
+
+/// The default pacing interval between new transactions, defined in
+/// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-14.2).
+pub const DEFAULT_TA: Duration = Duration::from_millis(50);
+
+const MAX_DATAGRAM_LEN: usize = 1500;
+
+/// Which STUN and TURN servers to gather candidates from, and how.
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    pub servers: Vec<IceServer>,
+    /// The interval between starting two transactions.
+    pub ta: Duration,
+    /// The initial retransmission timeout of Binding requests, which doubles
+    /// after every retransmission, defined in
+    /// [RFC 5389](https://tools.ietf.org/html/rfc5389#section-7.2.1).
+    pub rto: Duration,
+    /// The number of Binding requests to send before giving up.
+    pub max_requests: u32,
+}
+
+impl ServerConfig {
+    pub fn new(servers: Vec<IceServer>) -> Self {
+        Self {
+            servers,
+            ta: DEFAULT_TA,
+            rto: Duration::from_millis(500),
+            max_requests: 7,
+        }
+    }
+}
+
+/// A relayed candidate and the connection to the TURN server holding its
+/// allocation.
+pub struct RelayedCandidate {
+    pub candidate: Candidate,
+    pub connection: Connection,
+}
+
+/// The outcome of a request to a STUN or TURN server.
+pub enum ServerEvent {
+    /// A server reflexive candidate gathered from `hosts[host]`.
+    ServerReflexive {
+        host: usize,
+        candidate: Candidate,
+    },
+    Relayed(RelayedCandidate),
+    /// A request to the server at `uri` failed. Gathering goes on with the
+    /// other servers.
+    Failed {
+        uri: IceServerUri,
+        error: io::Error,
+    },
+}
+
+type EventStream<'a> = Pin<Box<dyn Stream<Item = ServerEvent> + 'a>>;
+
+/// Gathers server reflexive candidates by sending Binding requests from
+/// every host candidate to every STUN server reachable over UDP, and relayed
+/// candidates by making an allocation per component on every TURN server,
+/// defined in [RFC 8445](https://tools.ietf.org/html/rfc8445#section-5.1.1.2).
+///
+/// Transactions are started `config.ta` apart and run in parallel. The
+/// stream ends once every transaction has succeeded or failed. Redundant
+/// candidates, whose transport address and base are those of a candidate
+/// gathered earlier, are left out.
+pub fn gather_server<'a>(
+    hosts: &'a mut [HostCandidate],
+    config: &'a ServerConfig,
+) -> impl Stream<Item = ServerEvent> + 'a {
+    let mut seen: HashSet<_> = hosts
+        .iter()
+        .map(|host| (host.candidate.addr, host.candidate.addr))
+        .collect();
+    let components = hosts
+        .iter()
+        .map(|host| host.candidate.component)
+        .max()
+        .unwrap_or(0);
+
+    stream::once(Box::pin(start_transactions(hosts, config, components)))
+        .flatten()
+        .filter(move |event| {
+            let new = match event {
+                ServerEvent::ServerReflexive { candidate, .. }
+                | ServerEvent::Relayed(RelayedCandidate { candidate, .. }) => {
+                    seen.insert((candidate.addr, candidate.base.unwrap_or(candidate.addr)))
+                }
+                ServerEvent::Failed { .. } => true,
+            };
+            future::ready(new)
+        })
+}
+
+/// Resolves the STUN servers, then starts the transactions of `gather_server`.
+async fn start_transactions<'a>(
+    hosts: &'a mut [HostCandidate],
+    config: &'a ServerConfig,
+    components: u16,
+) -> stream::SelectAll<EventStream<'a>> {
+    let mut streams: Vec<EventStream<'a>> = Vec::new();
+    let mut stun_servers = Vec::new();
+    for uri in config.servers.iter().flat_map(|server| &server.urls) {
+        if uri.scheme.is_secure() || uri.transport() != uri::Transport::Udp {
+            continue;
+        }
+        match uri.socket_addrs().await {
+            Ok(addrs) => stun_servers.push((uri, addrs)),
+            Err(error) => {
+                streams.push(Box::pin(stream::once(future::ready(ServerEvent::Failed {
+                    uri: uri.clone(),
+                    error,
+                }))))
+            }
+        }
+    }
+
+    let start = Instant::now();
+    let mut transactions = 0;
+    for (i, host) in hosts.iter_mut().enumerate() {
+        let (requests_tx, requests) = mpsc::unbounded_channel();
+        let on_send: RequestSender = Box::new(move |msg, server| {
+            let res = requests_tx
+                .clone()
+                .try_send((msg, server))
+                .map_err(|_| io::Error::from(io::ErrorKind::NotConnected));
+            future::ready(res)
+        });
+        let agent = Rc::new(Agent::new(on_send).with_retransmits(config.rto, config.max_requests));
+
+        let mut bindings = FuturesUnordered::new();
+        for (uri, addrs) in &stun_servers {
+            let base = host.candidate.addr;
+            let server = match addrs.iter().find(|addr| addr.is_ipv4() == base.is_ipv4()) {
+                Some(&server) => server,
+                None => continue,
+            };
+            let deadline = start + config.ta * transactions;
+            transactions += 1;
+
+            let uri = (*uri).clone();
+            let agent = Rc::clone(&agent);
+            let binding: Binding = Box::pin(async move {
+                tokio_timer::delay(deadline).await;
+                let msg = Message::new(Class::Request, Method::BINDING, agent.transaction_id());
+                let res = agent.send(msg, server).await;
+                (uri, server, res)
+            });
+            bindings.push(binding);
+        }
+
+        let gatherer = BaseGatherer {
+            index: i,
+            host,
+            agent,
+            requests,
+            bindings,
+            buf: vec![0; MAX_DATAGRAM_LEN],
+        };
+        streams.push(Box::pin(stream::unfold(
+            gatherer,
+            |mut gatherer| async move {
+                let event = gatherer.next().await?;
+                Some((event, gatherer))
+            },
+        )));
+    }
+
+    for server in &config.servers {
+        if !server.urls.iter().any(|uri| uri.scheme.is_turn()) {
+            continue;
+        }
+        for component in 1..=components {
+            let delay = config.ta * transactions;
+            transactions += 1;
+            streams.push(Box::pin(stream::once(async move {
+                tokio_timer::delay_for(delay).await;
+                allocate(server, component).await
+            })));
+        }
+    }
+
+    stream::select_all(streams)
+}
+
+/// Makes an allocation for `component` on the TURN server configured by
+/// `server`, trying each of its TURN URIs in order until one succeeds.
+async fn allocate(server: &IceServer, component: u16) -> ServerEvent {
+    let mut failed = None;
+    for uri in server.urls.iter().filter(|uri| uri.scheme.is_turn()) {
+        let res = match Credentials::from_ice_server(server) {
+            Ok(credentials) => allocate_on(uri, credentials, component).await,
+            Err(error) => Err(error),
+        };
+        match res {
+            Ok(relayed) => return ServerEvent::Relayed(relayed),
+            Err(error) => {
+                failed = Some(ServerEvent::Failed {
+                    uri: uri.clone(),
+                    error,
+                })
+            }
+        }
+    }
+
+    // `gather_server` only allocates on servers with TURN URIs.
+    failed.expect("no TURN URIs")
+}
+
+async fn allocate_on(
+    uri: &IceServerUri,
+    credentials: Credentials,
+    component: u16,
+) -> io::Result<RelayedCandidate> {
+    let connection = Connection::connect_uri(uri, credentials).await?;
+    let allocation = connection.client().allocate().await?;
+    let mapped_addr = allocation
+        .mapped_addr
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing XOR-MAPPED-ADDRESS"))?;
+
+    let candidate = Candidate::relayed(
+        component,
+        Transport::Udp,
+        allocation.relayed_addr,
+        mapped_addr,
+        connection.client().server().ip(),
+        DEFAULT_LOCAL_PREFERENCE,
+    );
+    Ok(RelayedCandidate {
+        candidate,
+        connection,
+    })
+}
+
+type RequestSender = Box<dyn Fn(Message, SocketAddr) -> future::Ready<io::Result<()>>>;
+
+/// A Binding transaction with a STUN server, which resolves to the URI and
+/// address of the server along with the response.
+type Binding = Pin<Box<dyn Future<Output = (IceServerUri, SocketAddr, io::Result<Message>)>>>;
+
+/// Runs the Binding transactions from one host candidate.
+///
+/// The transactions go through a STUN `Agent`, which queues the requests it
+/// sends. Since the socket is only borrowed from the host candidate, it is
+/// not split; instead, `next` sends the queued requests and passes the
+/// received responses to the agent while waiting for a transaction to
+/// finish.
+struct BaseGatherer<'a> {
+    index: usize,
+    host: &'a mut HostCandidate,
+    agent: Rc<Agent<RequestSender>>,
+    requests: mpsc::UnboundedReceiver<(Message, SocketAddr)>,
+    bindings: FuturesUnordered<Binding>,
+    buf: Vec<u8>,
+}
+
+/// What `BaseGatherer::next` has to handle next.
+enum Ready {
+    Response(Option<(IceServerUri, SocketAddr, io::Result<Message>)>),
+    Request(Option<(Message, SocketAddr)>),
+    Datagram(io::Result<(usize, SocketAddr)>),
+}
+
+impl BaseGatherer<'_> {
+    /// Returns the outcome of the next transaction to finish, or `None` if
+    /// all of them have.
+    async fn next(&mut self) -> Option<ServerEvent> {
+        loop {
+            if self.bindings.is_empty() {
+                return None;
+            }
+
+            let ready = {
+                let responses = self.bindings.next();
+                let requests = Box::pin(self.requests.recv());
+                let datagrams = Box::pin(self.host.socket.recv_from(&mut self.buf));
+                match future::select(responses, future::select(requests, datagrams)).await {
+                    Either::Left((response, _)) => Ready::Response(response),
+                    Either::Right((Either::Left((request, _)), _)) => Ready::Request(request),
+                    Either::Right((Either::Right((datagram, _)), _)) => Ready::Datagram(datagram),
+                }
+            };
+
+            match ready {
+                Ready::Response(Some((uri, server, res))) => {
+                    return Some(self.on_response(uri, server, res));
+                }
+                Ready::Response(None) => return None,
+                Ready::Request(Some((msg, server))) => {
+                    // A request that fails to go out is retransmitted like a
+                    // lost one.
+                    let _ = self.host.socket.send_to(&stun::encode(msg), server).await;
+                }
+                Ready::Datagram(Ok((len, from))) => {
+                    if let Some(msg) = stun::decode(&self.buf[..len]) {
+                        self.agent.on_recv(msg, from).await;
+                    }
+                }
+                // Errors such as ICMP unreachables can be told apart from
+                // timeouts only on some platforms, so both are retried.
+                Ready::Request(None) | Ready::Datagram(Err(_)) => {}
+            }
+        }
+    }
+
+    fn on_response(
+        &self,
+        uri: IceServerUri,
+        server: SocketAddr,
+        res: io::Result<Message>,
+    ) -> ServerEvent {
+        let msg = match res {
+            Ok(msg) => msg,
+            Err(error) => return ServerEvent::Failed { uri, error },
+        };
+
+        let error = match (msg.class, msg.attr::<XorMappedAddress>()) {
+            (Class::SuccessResponse, Some(XorMappedAddress(addr))) => {
+                let host = &self.host.candidate;
+                let candidate = Candidate::server_reflexive(
+                    host.component,
+                    host.transport,
+                    addr,
+                    host.addr,
+                    server.ip(),
+                    // The local preference of the host candidate.
+                    (host.priority >> 8) as u16,
+                );
+                return ServerEvent::ServerReflexive {
+                    host: self.index,
+                    candidate,
+                };
+            }
+            (Class::FailureResponse, _) => match msg.attr::<ErrorCode>() {
+                Some(code) => ErrorResponse(code).into(),
+                None => io::Error::new(io::ErrorKind::InvalidData, "invalid error response"),
+            },
+            _ => io::Error::new(io::ErrorKind::InvalidData, "invalid Binding response"),
+        };
+
+        ServerEvent::Failed { uri, error }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::candidate::CandidateType;
+    use crate::gather::{gather_host_on, HostConfig, Interface};
+    use crate::test_util::{start_nat_server, start_turn_server};
+    use std::net::Ipv4Addr;
+    use tokio_net::udp::UdpSocket;
+
+    async fn loopback_hosts(components: u16) -> Vec<HostCandidate> {
+        let interfaces = vec![Interface {
+            name: "lo".to_owned(),
+            ip: Ipv4Addr::LOCALHOST.into(),
+        }];
+        let config = HostConfig {
+            loopback: true,
+            ..HostConfig::default()
+        };
+        gather_host_on(interfaces, &config, components)
+            .map(Result::unwrap)
+            .collect()
+            .await
+    }
+
+    fn new_test_config(servers: Vec<IceServer>) -> ServerConfig {
+        ServerConfig {
+            rto: Duration::from_millis(50),
+            max_requests: 3,
+            ..ServerConfig::new(servers)
+        }
+    }
+
+    #[test]
+    fn server_reflexive() {
+        tokio_test::block_on(async {
+            let mut hosts = loopback_hosts(2).await;
+            let nat = start_nat_server(Ipv4Addr::new(203, 0, 113, 1)).await;
+            let other_nat = start_nat_server(Ipv4Addr::new(203, 0, 113, 1)).await;
+            // Nothing answers on this socket.
+            let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let silent: IceServerUri = format!("stun:{}", silent.local_addr().unwrap())
+                .parse()
+                .unwrap();
+
+            let config = new_test_config(vec![
+                IceServer::new(vec![nat, silent.clone()]),
+                IceServer::new(vec![other_nat]),
+            ]);
+            let events: Vec<_> = gather_server(&mut hosts, &config).collect().await;
+
+            let mut candidates = Vec::new();
+            let mut failures = Vec::new();
+            for event in events {
+                match event {
+                    ServerEvent::ServerReflexive { host, candidate } => {
+                        candidates.push((host, candidate))
+                    }
+                    ServerEvent::Failed { uri, error } => failures.push((uri, error)),
+                    ServerEvent::Relayed(_) => panic!("unexpected relayed candidate"),
+                }
+            }
+
+            // Both NAT servers map each host to the same address, so only
+            // one candidate per host is kept.
+            assert_eq!(candidates.len(), 2);
+            for (host, candidate) in candidates {
+                let base = hosts[host].candidate.addr;
+                assert_eq!(candidate.kind, CandidateType::ServerReflexive);
+                assert_eq!(candidate.component, hosts[host].candidate.component);
+                assert_eq!(
+                    candidate.addr,
+                    SocketAddr::new(Ipv4Addr::new(203, 0, 113, 1).into(), base.port())
+                );
+                assert_eq!(candidate.base, Some(base));
+                assert_eq!(candidate.related_addr, Some(base));
+                assert!(candidate.priority < hosts[host].candidate.priority);
+            }
+
+            assert_eq!(failures.len(), 2);
+            for (uri, error) in failures {
+                assert_eq!(uri, silent);
+                assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+            }
+        });
+    }
+
+    #[test]
+    fn relayed() {
+        tokio_test::block_on(async {
+            let mut hosts = loopback_hosts(2).await;
+            let turn = start_turn_server().await;
+            let config = new_test_config(vec![
+                IceServer::new(vec![turn.clone()]).with_credentials("alice", "hunter2"),
+                IceServer::new(vec![turn.clone()]).with_credentials("alice", "wrong"),
+            ]);
+
+            let mut relayed = Vec::new();
+            let mut failures = 0;
+            let mut events = gather_server(&mut hosts, &config);
+            while let Some(event) = events.next().await {
+                match event {
+                    // Without a NAT, server reflexive candidates are the
+                    // same as host candidates.
+                    ServerEvent::ServerReflexive { .. } => panic!("redundant candidate"),
+                    ServerEvent::Relayed(candidate) => relayed.push(candidate),
+                    ServerEvent::Failed { uri, error } => {
+                        assert_eq!(uri, turn);
+                        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+                        failures += 1;
+                    }
+                }
+            }
+
+            assert_eq!(failures, 2);
+            let mut components: Vec<_> = relayed
+                .iter()
+                .map(|relayed| relayed.candidate.component)
+                .collect();
+            components.sort();
+            assert_eq!(components, vec![1, 2]);
+
+            for RelayedCandidate {
+                candidate,
+                connection,
+            } in relayed
+            {
+                let allocation = connection.client().allocation().await.unwrap();
+                assert_eq!(candidate.kind, CandidateType::Relayed);
+                assert_eq!(candidate.addr, allocation.relayed_addr);
+                assert_eq!(candidate.base, Some(allocation.relayed_addr));
+                assert_eq!(candidate.related_addr, allocation.mapped_addr);
+            }
+        });
+    }
+
+    #[test]
+    fn relayed_fallback() {
+        tokio_test::block_on(async {
+            let mut hosts = loopback_hosts(2).await;
+            // Nothing listens on this port.
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let closed = format!("turn:{}?transport=tcp", listener.local_addr().unwrap());
+            drop(listener);
+            let closed: IceServerUri = closed.parse().unwrap();
+            let turn = start_turn_server().await;
+            let config = new_test_config(vec![
+                IceServer::new(vec![closed, turn]).with_credentials("alice", "hunter2")
+            ]);
+
+            let events: Vec<_> = gather_server(&mut hosts, &config).collect().await;
+            assert_eq!(events.len(), 2);
+            for event in events {
+                match event {
+                    ServerEvent::Relayed(_) => {}
+                    _ => panic!("expected a relayed candidate"),
+                }
+            }
+        });
+    }
+}
@@ -1,3 +1,13 @@
+pub mod agent;
+pub mod candidate;
+pub mod gather;
+pub mod mdns;
+pub mod sdp;
+
+mod stun;
+#[cfg(test)]
+mod test_util;
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -0,0 +1,448 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::{Duration, Instant};
+
+/// The port of multicast DNS, defined in
+/// [RFC 6762](https://tools.ietf.org/html/rfc6762#section-3).
+pub const MDNS_PORT: u16 = 5353;
+
+/// The IPv4 multicast group of multicast DNS, defined in
+/// [RFC 6762](https://tools.ietf.org/html/rfc6762#section-3).
+pub const MDNS_IPV4: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+
+/// The IPv6 multicast group of multicast DNS, defined in
+/// [RFC 6762](https://tools.ietf.org/html/rfc6762#section-3).
+pub const MDNS_IPV6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+
+/// How long answers may be cached, recommended for hostnames by
+/// [RFC 6762](https://tools.ietf.org/html/rfc6762#section-10).
+const TTL: u32 = 120;
+
+/// The interval between the first two queries for a hostname, doubled
+/// after each, defined in
+/// [RFC 6762](https://tools.ietf.org/html/rfc6762#section-5.2).
+const QUERY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The number of queries sent for a hostname before giving up on it.
+const MAX_QUERIES: u32 = 3;
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+
+/// The top bit of the class of a question asking for a unicast response,
+/// or of an answer flushing caches, defined in
+/// [RFC 6762](https://tools.ietf.org/html/rfc6762#section-10.2).
+const CLASS_TOP_BIT: u16 = 0x8000;
+
+/// The QR and AA bits, set in all responses.
+const FLAGS_RESPONSE: u16 = 0x8400;
+
+const HEADER_LEN: usize = 12;
+
+/// Returns whether `name` is a multicast DNS hostname, in `.local`.
+pub fn is_mdns_hostname(name: &str) -> bool {
+    name.len() <= 255
+        && name.to_ascii_lowercase().ends_with(".local")
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Generates a hostname that hides the IP address of a host candidate: a
+/// random version 4 UUID in `.local`, defined in
+/// [draft-ietf-mmusic-mdns-ice-candidates](https://tools.ietf.org/html/draft-ietf-mmusic-mdns-ice-candidates-02#section-3.1.1).
+pub fn random_hostname() -> String {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = bytes[6] & 0x0f | 0x40;
+    bytes[8] = bytes[8] & 0x3f | 0x80;
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!(
+        "{}-{}-{}-{}-{}.local",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Something that happened to a hostname being resolved.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum MdnsEvent {
+    Resolved {
+        hostname: String,
+        ip: IpAddr,
+    },
+    /// No answer came to any of the queries for the hostname.
+    Unresolved {
+        hostname: String,
+    },
+}
+
+/// A multicast DNS responder for the hostnames of local candidates, and
+/// resolver of those of remote candidates, defined in
+/// [RFC 6762](https://tools.ietf.org/html/rfc6762).
+///
+/// Like the agent, it performs no I/O. The application sends the packets it
+/// asks for to the multicast group from port 5353, and passes it the packets
+/// received there.
+#[derive(Clone, Debug, Default)]
+pub struct Mdns {
+    /// The IP addresses of the registered hostnames, in lowercase.
+    hostnames: HashMap<String, IpAddr>,
+    /// The hostnames being resolved, with the number of queries sent for
+    /// each and when the next one is due.
+    queries: HashMap<String, (u32, Instant)>,
+    transmits: VecDeque<Vec<u8>>,
+    events: VecDeque<MdnsEvent>,
+}
+
+impl Mdns {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a random hostname for a local `ip`, which queries are then
+    /// answered for, and returns it.
+    pub fn register(&mut self, ip: IpAddr) -> String {
+        let hostname = random_hostname();
+        self.hostnames.insert(hostname.clone(), ip);
+        hostname
+    }
+
+    pub fn unregister(&mut self, hostname: &str) {
+        self.hostnames.remove(&hostname.to_ascii_lowercase());
+    }
+
+    /// Starts resolving the hostname of a remote candidate.
+    pub fn resolve(&mut self, now: Instant, hostname: &str) {
+        let hostname = hostname.to_ascii_lowercase();
+        if self.queries.contains_key(&hostname) {
+            return;
+        }
+        self.transmits.push_back(encode_query(&hostname));
+        self.queries.insert(hostname, (1, now + QUERY_INTERVAL));
+    }
+
+    /// Returns when `handle_timeout` should next be called.
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        self.queries.values().map(|&(_, deadline)| deadline).min()
+    }
+
+    pub fn handle_timeout(&mut self, now: Instant) {
+        let transmits = &mut self.transmits;
+        let events = &mut self.events;
+        self.queries.retain(|hostname, (queries, deadline)| {
+            if *deadline > now {
+                return true;
+            }
+            if *queries >= MAX_QUERIES {
+                events.push_back(MdnsEvent::Unresolved {
+                    hostname: hostname.clone(),
+                });
+                return false;
+            }
+            transmits.push_back(encode_query(hostname));
+            *deadline = now + QUERY_INTERVAL * 2u32.pow(*queries);
+            *queries += 1;
+            true
+        });
+    }
+
+    /// Returns the next packet to send to the multicast group.
+    pub fn poll_transmit(&mut self) -> Option<Vec<u8>> {
+        self.transmits.pop_front()
+    }
+
+    pub fn poll_event(&mut self) -> Option<MdnsEvent> {
+        self.events.pop_front()
+    }
+
+    /// Handles a packet received from the multicast group. Returns `false`
+    /// if it is not a DNS message.
+    pub fn handle_receive(&mut self, data: &[u8]) -> bool {
+        let packet = match Packet::decode(data) {
+            Some(packet) => packet,
+            None => return false,
+        };
+
+        if !packet.response {
+            for (name, kind) in packet.questions {
+                let ip = match self.hostnames.get(&name.to_ascii_lowercase()) {
+                    Some(&ip) => ip,
+                    None => continue,
+                };
+                let matches = match ip {
+                    IpAddr::V4(_) => kind == TYPE_A || kind == TYPE_ANY,
+                    IpAddr::V6(_) => kind == TYPE_AAAA || kind == TYPE_ANY,
+                };
+                if matches {
+                    self.transmits.push_back(encode_response(&name, ip));
+                }
+            }
+            return true;
+        }
+
+        for (name, ip) in packet.answers {
+            let hostname = name.to_ascii_lowercase();
+            if self.queries.remove(&hostname).is_some() {
+                self.events.push_back(MdnsEvent::Resolved { hostname, ip });
+            }
+        }
+        true
+    }
+}
+
+/// The parts of a DNS message, defined in
+/// [RFC 1035](https://tools.ietf.org/html/rfc1035#section-4.1), that matter
+/// for hostnames.
+struct Packet {
+    response: bool,
+    /// The names and types asked about.
+    questions: Vec<(String, u16)>,
+    /// The addresses of names, from the A and AAAA records of all sections.
+    answers: Vec<(String, IpAddr)>,
+}
+
+impl Packet {
+    fn decode(data: &[u8]) -> Option<Self> {
+        let mut pos = 0;
+        let _id = read_u16(data, &mut pos)?;
+        let flags = read_u16(data, &mut pos)?;
+        let questions = read_u16(data, &mut pos)?;
+        let records = (0..3)
+            .map(|_| read_u16(data, &mut pos).map(u32::from))
+            .sum::<Option<u32>>()?;
+
+        let mut packet = Self {
+            response: flags & 0x8000 != 0,
+            questions: Vec::new(),
+            answers: Vec::new(),
+        };
+        for _ in 0..questions {
+            let name = read_name(data, &mut pos)?;
+            let kind = read_u16(data, &mut pos)?;
+            let _class = read_u16(data, &mut pos)?;
+            packet.questions.push((name, kind));
+        }
+        for _ in 0..records {
+            let name = read_name(data, &mut pos)?;
+            let kind = read_u16(data, &mut pos)?;
+            let class = read_u16(data, &mut pos)? & !CLASS_TOP_BIT;
+            let _ttl = data.get(pos..pos + 4)?;
+            pos += 4;
+            let len = usize::from(read_u16(data, &mut pos)?);
+            let rdata = data.get(pos..pos + len)?;
+            pos += len;
+
+            let ip = match (class, kind, rdata.len()) {
+                (CLASS_IN, TYPE_A, 4) => IpAddr::from([rdata[0], rdata[1], rdata[2], rdata[3]]),
+                (CLASS_IN, TYPE_AAAA, 16) => {
+                    let mut octets = [0; 16];
+                    octets.copy_from_slice(rdata);
+                    IpAddr::from(octets)
+                }
+                _ => continue,
+            };
+            packet.answers.push((name, ip));
+        }
+        Some(packet)
+    }
+}
+
+fn read_u16(data: &[u8], pos: &mut usize) -> Option<u16> {
+    let bytes = data.get(*pos..*pos + 2)?;
+    *pos += 2;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+/// Reads a name, following compression pointers, defined in
+/// [RFC 1035](https://tools.ietf.org/html/rfc1035#section-4.1.4).
+fn read_name(data: &[u8], pos: &mut usize) -> Option<String> {
+    let mut labels = Vec::new();
+    let mut next = *pos;
+    let mut jumps = 0;
+    loop {
+        let len = *data.get(next)?;
+        if len & 0xc0 == 0xc0 {
+            // Pointers only go back, but loops are cut short anyway.
+            jumps += 1;
+            if jumps > 16 {
+                return None;
+            }
+            if jumps == 1 {
+                *pos = next + 2;
+            }
+            next = usize::from(u16::from_be_bytes([len & 0x3f, *data.get(next + 1)?]));
+            continue;
+        }
+
+        let len = usize::from(len);
+        next += 1;
+        if len == 0 {
+            break;
+        }
+        let label = data.get(next..next + len)?;
+        labels.push(String::from_utf8(label.to_vec()).ok()?);
+        next += len;
+    }
+    if jumps == 0 {
+        *pos = next;
+    }
+    Some(labels.join("."))
+}
+
+fn encode_header(flags: u16, questions: u16, answers: u16, buf: &mut Vec<u8>) {
+    for field in &[0, flags, questions, answers, 0, 0] {
+        buf.extend_from_slice(&field.to_be_bytes());
+    }
+}
+
+fn encode_name(name: &str, buf: &mut Vec<u8>) {
+    for label in name.split('.') {
+        buf.push(label.len() as u8);
+        buf.extend_from_slice(label.as_bytes());
+    }
+    buf.push(0);
+}
+
+/// Encodes a query for the IPv4 and IPv6 addresses of `hostname`.
+fn encode_query(hostname: &str) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(HEADER_LEN + 2 * (hostname.len() + 6));
+    encode_header(0, 2, 0, &mut buf);
+    for &kind in &[TYPE_A, TYPE_AAAA] {
+        encode_name(hostname, &mut buf);
+        buf.extend_from_slice(&kind.to_be_bytes());
+        buf.extend_from_slice(&CLASS_IN.to_be_bytes());
+    }
+    buf
+}
+
+/// Encodes a response with the address of `hostname`, which flushes any
+/// other cached for it.
+fn encode_response(hostname: &str, ip: IpAddr) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(HEADER_LEN + hostname.len() + 28);
+    encode_header(FLAGS_RESPONSE, 0, 1, &mut buf);
+    encode_name(hostname, &mut buf);
+    let (kind, rdata) = match ip {
+        IpAddr::V4(ip) => (TYPE_A, ip.octets().to_vec()),
+        IpAddr::V6(ip) => (TYPE_AAAA, ip.octets().to_vec()),
+    };
+    buf.extend_from_slice(&kind.to_be_bytes());
+    buf.extend_from_slice(&(CLASS_IN | CLASS_TOP_BIT).to_be_bytes());
+    buf.extend_from_slice(&TTL.to_be_bytes());
+    buf.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    buf.extend_from_slice(&rdata);
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hostnames() {
+        let hostname = random_hostname();
+        assert_eq!(hostname.len(), 36 + ".local".len());
+        assert_eq!(&hostname[14..15], "4");
+        assert!(is_mdns_hostname(&hostname));
+        assert_ne!(hostname, random_hostname());
+
+        assert!(is_mdns_hostname("Host-1.LOCAL"));
+        assert!(!is_mdns_hostname("local"));
+        assert!(!is_mdns_hostname("host.example"));
+        assert!(!is_mdns_hostname("host..local"));
+        assert!(!is_mdns_hostname("192.0.2.1"));
+    }
+
+    #[test]
+    fn resolve() {
+        let now = Instant::now();
+        let mut responder = Mdns::new();
+        let ip = "192.0.2.1".parse().unwrap();
+        let hostname = responder.register(ip);
+        responder.register("2001:db8::1".parse().unwrap());
+
+        let mut resolver = Mdns::new();
+        resolver.resolve(now, &hostname.to_ascii_uppercase());
+        resolver.resolve(now, "unknown.local");
+        resolver.resolve(now, &hostname);
+        for _ in 0..2 {
+            let query = resolver.poll_transmit().unwrap();
+            assert!(responder.handle_receive(&query));
+        }
+        assert_eq!(resolver.poll_transmit(), None);
+
+        // Only the query for the registered hostname, and its A question,
+        // is answered.
+        let response = responder.poll_transmit().unwrap();
+        assert_eq!(responder.poll_transmit(), None);
+        assert!(resolver.handle_receive(&response));
+        assert_eq!(
+            resolver.poll_event(),
+            Some(MdnsEvent::Resolved { hostname, ip })
+        );
+        assert_eq!(resolver.poll_event(), None);
+        assert_eq!(resolver.queries.len(), 1);
+
+        assert!(!resolver.handle_receive(b"\x00\x00\x84\x00\x00"));
+    }
+
+    #[test]
+    fn unresolved() {
+        let mut now = Instant::now();
+        let mut resolver = Mdns::new();
+        resolver.resolve(now, "unknown.local");
+        assert!(resolver.poll_transmit().is_some());
+
+        // Queries are sent 1 s and then 2 s apart, and the last one is
+        // given up on after 4 s.
+        let mut times = Vec::new();
+        while let Some(timeout) = resolver.poll_timeout() {
+            now = timeout;
+            resolver.handle_timeout(now);
+            if resolver.poll_transmit().is_some() {
+                times.push(now);
+            }
+        }
+        let start = times[0] - QUERY_INTERVAL;
+        assert_eq!(times, [start + QUERY_INTERVAL, start + QUERY_INTERVAL * 3]);
+        assert_eq!(now, start + QUERY_INTERVAL * 7);
+        assert_eq!(
+            resolver.poll_event(),
+            Some(MdnsEvent::Unresolved {
+                hostname: "unknown.local".to_owned()
+            })
+        );
+    }
+
+    #[test]
+    fn compressed() {
+        // A response repeating the question, whose answer points back to
+        // the name in it.
+        let mut response = Vec::new();
+        encode_header(FLAGS_RESPONSE, 1, 1, &mut response);
+        encode_name("host.local", &mut response);
+        response.extend_from_slice(&[0, 1, 0, 1]);
+        response.extend_from_slice(&[0xc0, 12, 0, 1, 0x80, 1, 0, 0, 0, 120, 0, 4]);
+        response.extend_from_slice(&[192, 0, 2, 1]);
+
+        let packet = Packet::decode(&response).unwrap();
+        assert!(packet.response);
+        assert_eq!(packet.questions, [("host.local".to_owned(), TYPE_A)]);
+        assert_eq!(
+            packet.answers,
+            [("host.local".to_owned(), "192.0.2.1".parse().unwrap())]
+        );
+
+        // A pointer to itself.
+        let mut response = Vec::new();
+        encode_header(FLAGS_RESPONSE, 0, 1, &mut response);
+        response.extend_from_slice(&[0xc0, 12]);
+        assert!(Packet::decode(&response).is_none());
+    }
+}
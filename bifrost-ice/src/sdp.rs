@@ -0,0 +1,41 @@
+use bifrost_sdp::{Attribute, SessionDescription};
+
+/// The name of the session-level attribute of lite agents, defined in
+/// [RFC 8839](https://tools.ietf.org/html/rfc8839#section-5.3).
+pub const ICE_LITE: &str = "ice-lite";
+
+/// Returns the `a=ice-lite` attribute.
+pub fn ice_lite() -> Attribute {
+    Attribute {
+        name: ICE_LITE.to_owned(),
+        value: None,
+    }
+}
+
+/// Returns whether a session description is from a lite agent.
+pub fn is_ice_lite(session: &SessionDescription) -> bool {
+    session
+        .attributes
+        .iter()
+        .any(|attribute| attribute.name == ICE_LITE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn ice_lite_attribute() {
+        assert_eq!(ice_lite().to_string(), "a=ice-lite\r\n");
+
+        let sdp = "v=0\r\n\
+                   o=- 0 0 IN IP4 198.51.100.1\r\n\
+                   s=-\r\n\
+                   t=0 0\r\n";
+        let mut session = SessionDescription::from_str(sdp).unwrap();
+        assert!(!is_ice_lite(&session));
+        session.attributes.push(ice_lite());
+        assert!(is_ice_lite(&session));
+    }
+}
@@ -0,0 +1,21 @@
+use bifrost_stun::codec::MessageCodec;
+use bifrost_stun::message::Message;
+use bytes::BytesMut;
+use tokio_codec::{Decoder, Encoder};
+
+/// Encodes a STUN message into a datagram.
+pub fn encode(msg: Message) -> Vec<u8> {
+    let mut buf = BytesMut::new();
+    // Encoding into a growable buffer cannot fail.
+    let _ = MessageCodec::new().encode(msg, &mut buf);
+    buf.to_vec()
+}
+
+/// Decodes a datagram into a STUN message. Returns `None` if it is not one.
+pub fn decode(datagram: &[u8]) -> Option<Message> {
+    let mut buf = BytesMut::from(datagram);
+    match MessageCodec::new().decode(&mut buf) {
+        Ok(Some(Some(msg))) if buf.is_empty() => Some(msg),
+        _ => None,
+    }
+}
@@ -0,0 +1,245 @@
+use crate::agent::{Agent, Credentials, Transmit};
+use crate::candidate::{Candidate, TcpType, Transport, ACTIVE_TCP_PORT, DEFAULT_LOCAL_PREFERENCE};
+use crate::stun;
+use bifrost_stun::message::attribute::XorMappedAddress;
+use bifrost_stun::message::{Class, Message};
+use bifrost_stun::uri::IceServerUri;
+use bifrost_turn::server::{Server, ServerConfig};
+use std::collections::{HashMap, HashSet};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+use tokio_net::udp::UdpSocket;
+
+/// A simulated network that delivers the datagrams agents send each other
+/// instantly, and advances time to their next timeouts.
+///
+/// TCP connections are opened as needed from active candidates, from a new
+/// ephemeral port each.
+pub struct Network {
+    pub now: Instant,
+    /// The agent each local base address belongs to.
+    endpoints: HashMap<(Transport, SocketAddr), usize>,
+    /// The ephemeral addresses of TCP connections, by the base of the active
+    /// candidate they were opened from and the address they were opened to.
+    connections: HashMap<(SocketAddr, SocketAddr), SocketAddr>,
+    /// Endpoint-independent NAT mappings from private to public addresses.
+    /// Private addresses cannot be reached directly.
+    mappings: HashMap<SocketAddr, SocketAddr>,
+    /// Addresses datagrams from and to are dropped.
+    blocked: HashSet<SocketAddr>,
+}
+
+impl Network {
+    pub fn new() -> Self {
+        Self {
+            now: Instant::now(),
+            endpoints: HashMap::new(),
+            connections: HashMap::new(),
+            mappings: HashMap::new(),
+            blocked: HashSet::new(),
+        }
+    }
+
+    /// Adds a host candidate of `agent` on `addr`, for component 1 of
+    /// `stream`.
+    pub fn add_host(&mut self, agents: &mut [Agent], agent: usize, stream: usize, addr: &str) {
+        self.add_component_host(agents, agent, stream, 1, addr);
+    }
+
+    /// Adds a host candidate of `agent` on `addr`, for `component` of
+    /// `stream`.
+    pub fn add_component_host(
+        &mut self,
+        agents: &mut [Agent],
+        agent: usize,
+        stream: usize,
+        component: u16,
+        addr: &str,
+    ) {
+        let candidate = component_host(component, addr);
+        self.endpoints
+            .insert((Transport::Udp, candidate.addr), agent);
+        agents[agent].add_local_candidate(stream, candidate);
+    }
+
+    /// Adds a TCP host candidate of `agent` on `addr`, for component 1 of
+    /// `stream`, and returns it.
+    pub fn add_tcp_host(
+        &mut self,
+        agents: &mut [Agent],
+        agent: usize,
+        stream: usize,
+        tcp_type: TcpType,
+        addr: &str,
+    ) -> Candidate {
+        let candidate =
+            Candidate::tcp_host(1, tcp_type, addr.parse().unwrap(), DEFAULT_LOCAL_PREFERENCE);
+        self.endpoints
+            .insert((Transport::Tcp, candidate.addr), agent);
+        agents[agent].add_local_candidate(stream, candidate.clone());
+        candidate
+    }
+
+    pub fn add_mapping(&mut self, private: &str, public: &str) {
+        self.mappings
+            .insert(private.parse().unwrap(), public.parse().unwrap());
+    }
+
+    /// Drops the datagrams from and to `addr` from now on.
+    pub fn block(&mut self, addr: &str) {
+        self.blocked.insert(addr.parse().unwrap());
+    }
+
+    /// Runs the agents for `duration` of simulated time.
+    pub fn run(&mut self, agents: &mut [Agent], duration: Duration) {
+        let deadline = self.now + duration;
+        loop {
+            self.deliver(agents);
+
+            match agents.iter().filter_map(Agent::poll_timeout).min() {
+                Some(timeout) if timeout <= deadline => {
+                    self.now = self.now.max(timeout);
+                    for agent in agents.iter_mut() {
+                        match agent.poll_timeout() {
+                            Some(timeout) if timeout <= self.now => agent.handle_timeout(self.now),
+                            _ => (),
+                        }
+                    }
+                }
+                _ => break,
+            }
+        }
+        self.now = deadline;
+    }
+
+    fn deliver(&mut self, agents: &mut [Agent]) {
+        loop {
+            let mut idle = true;
+            for i in 0..agents.len() {
+                while let Some(transmit) = agents[i].poll_transmit() {
+                    idle = false;
+                    self.route(agents, transmit);
+                }
+            }
+            if idle {
+                return;
+            }
+        }
+    }
+
+    fn route(&mut self, agents: &mut [Agent], transmit: Transmit) {
+        if transmit.transport == Transport::Tcp {
+            return self.route_tcp(agents, transmit);
+        }
+        if self.mappings.contains_key(&transmit.destination)
+            || self.blocked.contains(&transmit.source)
+            || self.blocked.contains(&transmit.destination)
+        {
+            return;
+        }
+
+        let from = *self
+            .mappings
+            .get(&transmit.source)
+            .unwrap_or(&transmit.source);
+        let to = self
+            .mappings
+            .iter()
+            .find(|(_, public)| **public == transmit.destination)
+            .map_or(transmit.destination, |(private, _)| *private);
+        if let Some(&agent) = self.endpoints.get(&(Transport::Udp, to)) {
+            agents[agent].handle_receive(self.now, Transport::Udp, to, from, &transmit.data);
+        }
+    }
+
+    fn route_tcp(&mut self, agents: &mut [Agent], transmit: Transmit) {
+        let Transmit {
+            source,
+            destination,
+            ..
+        } = transmit;
+        if self.blocked.contains(&source) || self.blocked.contains(&destination) {
+            return;
+        }
+
+        // Active candidates connect from a new ephemeral port, which their
+        // connections are then sent to.
+        let (to, from) = if source.port() == ACTIVE_TCP_PORT {
+            let port = 49152 + self.connections.len() as u16;
+            let ephemeral = *self
+                .connections
+                .entry((source, destination))
+                .or_insert_with(|| SocketAddr::new(source.ip(), port));
+            (destination, ephemeral)
+        } else {
+            match self
+                .connections
+                .iter()
+                .find(|(_, ephemeral)| **ephemeral == destination)
+            {
+                Some((&(active, _), _)) => (active, source),
+                None => (destination, source),
+            }
+        };
+        if let Some(&agent) = self.endpoints.get(&(Transport::Tcp, to)) {
+            agents[agent].handle_receive(self.now, Transport::Tcp, to, from, &transmit.data);
+        }
+    }
+}
+
+pub fn host(addr: &str) -> Candidate {
+    component_host(1, addr)
+}
+
+pub fn component_host(component: u16, addr: &str) -> Candidate {
+    Candidate::host(
+        component,
+        Transport::Udp,
+        addr.parse().unwrap(),
+        DEFAULT_LOCAL_PREFERENCE,
+    )
+}
+
+/// Exchanges the credentials of `stream` between two agents.
+pub fn exchange_credentials(agents: &mut [Agent], stream: usize) {
+    let credentials: Vec<Credentials> = agents
+        .iter()
+        .map(|agent| agent.local_credentials(stream).clone())
+        .collect();
+    agents[0].set_remote_credentials(stream, credentials[1].clone());
+    agents[1].set_remote_credentials(stream, credentials[0].clone());
+}
+
+/// Starts a STUN server that maps every address to `mapped_ip`.
+pub async fn start_nat_server(mapped_ip: Ipv4Addr) -> IceServerUri {
+    let mut socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let uri = format!("stun:{}", socket.local_addr().unwrap());
+
+    tokio_executor::spawn(async move {
+        let mut buf = vec![0; 1500];
+        while let Ok((len, from)) = socket.recv_from(&mut buf).await {
+            let req = stun::decode(&buf[..len]).unwrap();
+            let mut res = Message::new(Class::SuccessResponse, req.method, req.transaction_id);
+            let mapped_addr = SocketAddr::new(mapped_ip.into(), from.port());
+            res.add_attr(&XorMappedAddress(mapped_addr));
+            socket.send_to(&stun::encode(res), from).await.unwrap();
+        }
+    });
+
+    uri.parse().unwrap()
+}
+
+/// Starts a TURN server with the user `alice`, whose password is `hunter2`.
+pub async fn start_turn_server() -> IceServerUri {
+    let mut config = ServerConfig::new("bifrost.rs", Ipv4Addr::LOCALHOST.into());
+    config.add_user("alice", "hunter2");
+    let server = Server::new(config);
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let uri = format!("turn:{}", socket.local_addr().unwrap());
+
+    tokio_executor::spawn(async move {
+        server.serve_udp(socket).await.unwrap();
+    });
+
+    uri.parse().unwrap()
+}
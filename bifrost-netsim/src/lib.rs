@@ -0,0 +1,425 @@
+pub mod nat;
+pub mod network;
+
+#[cfg(test)]
+mod tests {
+    use crate::nat::{NatConfig, NatType};
+    use crate::network::{LinkConfig, Network, Socket};
+    use bifrost_ice::agent::{Agent, AgentConfig, ConnectionState, Role};
+    use bifrost_ice::candidate::{Candidate, Transport, DEFAULT_LOCAL_PREFERENCE};
+    use bifrost_stun::agent::Timer;
+    use bifrost_stun::codec::MessageCodec;
+    use bifrost_stun::message::attribute::{Data, XorMappedAddress, XorPeerAddress};
+    use bifrost_stun::message::{Class, Message, Method, TransactionId};
+    use bifrost_turn::client::{Client, Credentials as TurnCredentials, Event};
+    use bifrost_turn::codec::FrameCodec;
+    use bifrost_turn::frame::Frame;
+    use bytes::BytesMut;
+    use futures_util::future::{self, Either};
+    use futures_util::task::{waker, ArcWake};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::future::Future;
+    use std::net::{IpAddr, SocketAddr};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+    use tokio_codec::{Decoder, Encoder};
+
+    const SERVER: &str = "192.0.2.1:3478";
+
+    /// The datagrams received during a simulation: when, from where, to
+    /// where, and what.
+    type Trace = Vec<(Duration, SocketAddr, SocketAddr, Vec<u8>)>;
+
+    fn encode(msg: Message) -> Vec<u8> {
+        let mut buf = BytesMut::new();
+        MessageCodec::new().encode(msg, &mut buf).unwrap();
+        buf.to_vec()
+    }
+
+    fn decode(data: Vec<u8>) -> Message {
+        let mut buf = BytesMut::from(data);
+        MessageCodec::new()
+            .decode(&mut buf)
+            .unwrap()
+            .unwrap()
+            .unwrap()
+    }
+
+    /// Creates a network with a server on `SERVER`, and a client on
+    /// 10.0.0.2:5000 behind a port-restricted NAT on 203.0.113.1.
+    fn new_network() -> (Network, Socket, Socket) {
+        new_network_seeded(0)
+    }
+
+    fn new_network_seeded(seed: u64) -> (Network, Socket, Socket) {
+        let network = Network::new(seed);
+        let server: SocketAddr = SERVER.parse().unwrap();
+        network.add_host(server.ip());
+        network.add_nat(
+            "203.0.113.1".parse().unwrap(),
+            NatConfig::new(NatType::PortRestricted),
+        );
+        network.add_host_behind("10.0.0.2".parse().unwrap(), "203.0.113.1".parse().unwrap());
+
+        let server = network.bind(server).unwrap();
+        let client = network.bind("10.0.0.2:5000".parse().unwrap()).unwrap();
+        (network, server, client)
+    }
+
+    /// Grants the requests `server` has received, telling each client its
+    /// address in XOR-MAPPED-ADDRESS.
+    fn serve(server: &Socket) {
+        while let Some((data, from)) = server.recv_from() {
+            let req = decode(data);
+            if req.class != Class::Request {
+                continue;
+            }
+            let mut res = Message::new(Class::SuccessResponse, req.method, req.transaction_id);
+            res.add_attr(&XorMappedAddress(from));
+            server.send_to(&encode(res), from).unwrap();
+        }
+    }
+
+    /// Returns a timer that sleeps on the clock of `network`.
+    fn timer(network: &Network) -> Timer {
+        let network = network.clone();
+        Arc::new(move |duration| Box::pin(network.sleep(duration)))
+    }
+
+    /// Records whether a task has been woken.
+    struct Woken(AtomicBool);
+
+    impl ArcWake for Woken {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Runs `future` on the clock of `network`, advancing the network
+    /// whenever the future is pending and has not been woken.
+    fn block_on<F: Future>(network: &Network, future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let woken = Arc::new(Woken(AtomicBool::new(false)));
+        let waker = waker(woken.clone());
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            if !woken.0.swap(false, Ordering::SeqCst) {
+                let timeout = network.poll_timeout().expect("nothing left to wait for");
+                network.advance_to(timeout);
+            }
+        }
+    }
+
+    /// Returns `Pending` once without waking the task, so that `block_on`
+    /// advances the network unless something else is ready.
+    async fn yield_now() {
+        let mut yielded = false;
+        future::poll_fn(|_| {
+            if yielded {
+                Poll::Ready(())
+            } else {
+                yielded = true;
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Runs `future` on the clock of `network` while `server` grants
+    /// requests, passing what `client` receives to `on_recv` and recording
+    /// it in `trace`.
+    fn serve_until<F, R, Fut>(
+        network: &Network,
+        (server, client): (&Socket, &Socket),
+        trace: &mut Trace,
+        future: F,
+        on_recv: R,
+    ) -> F::Output
+    where
+        F: Future,
+        R: Fn(Vec<u8>, SocketAddr) -> Fut,
+        Fut: Future,
+    {
+        let start = network.now();
+        let pump = async move {
+            loop {
+                serve(server);
+                while let Some((data, from)) = client.recv_from() {
+                    let at = network.now() - start;
+                    trace.push((at, from, client.local_addr(), data.clone()));
+                    on_recv(data, from).await;
+                }
+                yield_now().await;
+            }
+        };
+        match block_on(network, future::select(Box::pin(future), Box::pin(pump))) {
+            Either::Left((output, _)) => output,
+            Either::Right(((), _)) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn stun_agent() {
+        let (_network, server, client) = new_network();
+        let socket = client.clone();
+        let agent = bifrost_stun::agent::Agent::new(move |msg, addr| {
+            future::ready(socket.send_to(&encode(msg), addr))
+        });
+
+        tokio_test::block_on(async {
+            let uri = format!("stun:{}", SERVER).parse().unwrap();
+            let respond = async {
+                serve(&server);
+                let (data, from) = client.recv_from().unwrap();
+                agent.on_recv(decode(data), from).await;
+            };
+            let (mapped, ()) = future::join(agent.binding(&uri), respond).await;
+            assert_eq!(mapped.unwrap(), "203.0.113.1:49152".parse().unwrap());
+        });
+    }
+
+    #[test]
+    fn turn_client() {
+        let (_network, server, client) = new_network();
+        let socket = client.clone();
+        let credentials = TurnCredentials::new("user", "pass");
+        let turn = Client::new(server.local_addr(), credentials, move |frame, addr| {
+            let mut buf = BytesMut::new();
+            let res = FrameCodec::datagram()
+                .encode(frame, &mut buf)
+                .and_then(|()| socket.send_to(&buf, addr));
+            future::ready(res)
+        });
+        let peer: SocketAddr = "198.51.100.1:4000".parse().unwrap();
+
+        tokio_test::block_on(async {
+            turn.send_to(b"hello", peer).await.unwrap();
+            let (data, mapped) = server.recv_from().unwrap();
+            let ind = decode(data);
+            assert_eq!(ind.method, Method::SEND);
+            assert_eq!(ind.attr::<XorPeerAddress>().unwrap().0, peer);
+            assert_eq!(ind.attr::<Data>().unwrap().0, b"hello");
+
+            // The server relays data from the peer back through the mapping.
+            let mut ind = Message::new(Class::Indication, Method::DATA, TransactionId::random());
+            ind.add_attr(&XorPeerAddress(peer));
+            ind.add_attr(&Data(b"world".to_vec()));
+            server.send_to(&encode(ind), mapped).unwrap();
+
+            let (data, from) = client.recv_from().unwrap();
+            let mut buf = BytesMut::from(data);
+            let frame: Frame = FrameCodec::datagram()
+                .decode(&mut buf)
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            let event = turn.on_recv(frame, from).await;
+            assert_eq!(event, Some(Event::Data(b"world".to_vec(), peer)));
+        });
+    }
+
+    /// Runs ICE agents, each with the sockets bound to the bases of its
+    /// local candidates, for `duration` of simulated time, recording what
+    /// they receive in `trace`.
+    fn run(
+        network: &Network,
+        agents: &mut [(Agent, Socket)],
+        duration: Duration,
+        trace: &mut Trace,
+    ) {
+        let start = network.now();
+        let deadline = start + duration;
+        loop {
+            let now = network.now();
+            for (agent, socket) in agents.iter_mut() {
+                if agent.poll_timeout().map(|timeout| timeout <= now) == Some(true) {
+                    agent.handle_timeout(now);
+                }
+                while let Some((data, from)) = socket.recv_from() {
+                    trace.push((now - start, from, socket.local_addr(), data.clone()));
+                    agent.handle_receive(now, Transport::Udp, socket.local_addr(), from, &data);
+                }
+                while let Some(transmit) = agent.poll_transmit() {
+                    assert_eq!(transmit.source, socket.local_addr());
+                    socket
+                        .send_to(&transmit.data, transmit.destination)
+                        .unwrap();
+                }
+            }
+
+            let timeouts = agents.iter().filter_map(|(agent, _)| agent.poll_timeout());
+            match timeouts.chain(network.poll_timeout()).min() {
+                Some(timeout) if timeout <= deadline => network.advance_to(timeout),
+                _ => break,
+            }
+        }
+        network.advance_to(deadline);
+    }
+
+    /// Connects two ICE agents, each on a host behind a NAT of the given
+    /// type, with host and server reflexive candidates, and returns their
+    /// final states. The network and the agents are seeded with `seed`.
+    fn connect(seed: u64, nat_types: [NatType; 2], trace: &mut Trace) -> Vec<ConnectionState> {
+        let network = Network::new(seed);
+        let stun: SocketAddr = SERVER.parse().unwrap();
+        network.add_host(stun.ip());
+        let stun = network.bind(stun).unwrap();
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        let mut agents = Vec::new();
+        for (i, &nat_type) in nat_types.iter().enumerate() {
+            let external = IpAddr::from([203, 0, 113, i as u8 + 1]);
+            let internal = IpAddr::from([10, 0, i as u8, 2]);
+            network.add_nat(external, NatConfig::new(nat_type));
+            network.add_host_behind(internal, external);
+            let socket = network.bind(SocketAddr::new(internal, 5000)).unwrap();
+
+            let id = TransactionId::from_rng(&mut rng);
+            let req = Message::new(Class::Request, Method::BINDING, id);
+            socket.send_to(&encode(req), stun.local_addr()).unwrap();
+            serve(&stun);
+            let res = decode(socket.recv_from().unwrap().0);
+            let mapped = res.attr::<XorMappedAddress>().unwrap().0;
+
+            let link = LinkConfig {
+                delay: Duration::from_millis(10),
+                jitter: Duration::from_millis(5),
+                ..LinkConfig::default()
+            };
+            network.set_link(internal, link);
+
+            let role = if i == 0 {
+                Role::Controlling
+            } else {
+                Role::Controlled
+            };
+            let config = AgentConfig {
+                seed: Some(seed + i as u64),
+                ..AgentConfig::new(role)
+            };
+            let mut agent = Agent::new(config);
+            let credentials = agent.random_credentials();
+            let stream = agent.add_stream(1, credentials);
+            let base = socket.local_addr();
+            agent.add_local_candidate(
+                stream,
+                Candidate::host(1, Transport::Udp, base, DEFAULT_LOCAL_PREFERENCE),
+            );
+            agent.add_local_candidate(
+                stream,
+                Candidate::server_reflexive(
+                    1,
+                    Transport::Udp,
+                    mapped,
+                    base,
+                    stun.local_addr().ip(),
+                    DEFAULT_LOCAL_PREFERENCE,
+                ),
+            );
+            agents.push((agent, socket));
+        }
+
+        for i in 0..2 {
+            let peer = &agents[1 - i].0;
+            let credentials = peer.local_credentials(0).clone();
+            let candidates = peer.local_candidates(0).to_vec();
+            let agent = &mut agents[i].0;
+            agent.set_remote_credentials(0, credentials);
+            for candidate in candidates {
+                agent.add_remote_candidate(0, candidate);
+            }
+        }
+        for (agent, _) in agents.iter_mut() {
+            agent.start(network.now());
+        }
+
+        // Long enough for checks that get no response to fail.
+        run(&network, &mut agents, Duration::from_secs(90), trace);
+        agents.iter().map(|(agent, _)| agent.state()).collect()
+    }
+
+    #[test]
+    fn ice_agents() {
+        use ConnectionState::{Completed, Failed};
+        use NatType::{FullCone, PortRestricted, Symmetric};
+
+        let connect = |nat_types| connect(0, nat_types, &mut Trace::new());
+        assert_eq!(connect([PortRestricted, PortRestricted]), [Completed; 2]);
+        assert_eq!(connect([Symmetric, FullCone]), [Completed; 2]);
+        assert_eq!(connect([Symmetric, Symmetric]), [Failed; 2]);
+    }
+
+    /// Runs a STUN agent, a TURN client and ICE agents over lossy links,
+    /// all seeded with `seed` and timed by the network, and returns what
+    /// they received.
+    fn simulate(seed: u64) -> Trace {
+        let (network, server, client) = new_network_seeded(seed);
+        let lossy = LinkConfig {
+            loss: 0.3,
+            delay: Duration::from_millis(10),
+            jitter: Duration::from_millis(5),
+        };
+        network.set_link(client.local_addr().ip(), lossy);
+        let mut trace = Trace::new();
+
+        let socket = client.clone();
+        let agent = bifrost_stun::agent::Agent::new(move |msg, addr| {
+            future::ready(socket.send_to(&encode(msg), addr))
+        })
+        .with_seed(seed)
+        .with_timer(timer(&network));
+        // Requests may time out on the lossy link; the trace records what
+        // happened either way.
+        let uri = format!("stun:{}", SERVER).parse().unwrap();
+        for _ in 0..5 {
+            let binding = agent.binding(&uri);
+            let on_recv = |data, from| agent.on_recv(decode(data), from);
+            let _ = serve_until(&network, (&server, &client), &mut trace, binding, on_recv);
+        }
+
+        let socket = client.clone();
+        let credentials = TurnCredentials::new("user", "pass");
+        let turn = Client::new(server.local_addr(), credentials, move |frame, addr| {
+            let mut buf = BytesMut::new();
+            let res = FrameCodec::datagram()
+                .encode(frame, &mut buf)
+                .and_then(|()| socket.send_to(&buf, addr));
+            future::ready(res)
+        })
+        .with_seed(seed)
+        .with_timer(timer(&network));
+        for i in 0..5 {
+            let peer = IpAddr::from([198, 51, 100, i]);
+            let permission = turn.create_permission(peer);
+            let on_recv = |data: Vec<u8>, from| {
+                let mut buf = BytesMut::from(data);
+                let frame = FrameCodec::datagram().decode(&mut buf).unwrap().unwrap();
+                turn.on_recv(frame.unwrap(), from)
+            };
+            let _ = serve_until(
+                &network,
+                (&server, &client),
+                &mut trace,
+                permission,
+                on_recv,
+            );
+        }
+
+        let states = connect(seed, [NatType::PortRestricted; 2], &mut trace);
+        assert_eq!(states, [ConnectionState::Completed; 2]);
+        trace
+    }
+
+    #[test]
+    fn deterministic() {
+        let trace = simulate(1);
+        assert_eq!(simulate(1), trace);
+        assert_ne!(simulate(2), trace);
+    }
+}
@@ -0,0 +1,303 @@
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+/// The default time a mapping lasts without outbound traffic, defined in
+/// [RFC 4787](https://tools.ietf.org/html/rfc4787#section-4.3).
+pub const DEFAULT_MAPPING_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// The first external port a NAT maps internal addresses to.
+const FIRST_PORT: u16 = 49152;
+
+/// How a NAT maps and filters, in the terms of
+/// [RFC 3489](https://tools.ietf.org/html/rfc3489#section-5), with the
+/// mapping and filtering behavior each one stands for, defined in
+/// [RFC 4787](https://tools.ietf.org/html/rfc4787#section-4.1).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum NatType {
+    /// Endpoint-independent mapping and filtering.
+    FullCone,
+    /// Endpoint-independent mapping and address-dependent filtering.
+    Restricted,
+    /// Endpoint-independent mapping and address and port-dependent
+    /// filtering.
+    PortRestricted,
+    /// Address and port-dependent mapping and filtering.
+    Symmetric,
+}
+
+/// The behavior of a NAT.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct NatConfig {
+    pub nat_type: NatType,
+    /// Whether hosts behind the NAT can reach each other at their external
+    /// addresses, defined in
+    /// [RFC 4787](https://tools.ietf.org/html/rfc4787#section-6).
+    pub hairpinning: bool,
+    /// How long a mapping lasts without outbound traffic.
+    pub mapping_timeout: Duration,
+}
+
+impl NatConfig {
+    pub fn new(nat_type: NatType) -> Self {
+        Self {
+            nat_type,
+            hairpinning: false,
+            mapping_timeout: DEFAULT_MAPPING_TIMEOUT,
+        }
+    }
+}
+
+/// An external port mapped to an internal address.
+#[derive(Clone, Debug)]
+struct Binding {
+    /// The key of the mapping to the port.
+    mapping: (SocketAddr, Option<SocketAddr>),
+    internal: SocketAddr,
+    /// The addresses the internal address has sent to over the mapping.
+    remotes: HashSet<SocketAddr>,
+    expiry: Instant,
+}
+
+/// A NAT with a single external address.
+#[derive(Clone, Debug)]
+pub(crate) struct Nat {
+    pub config: NatConfig,
+    external_ip: IpAddr,
+    next_port: u16,
+    /// The external ports by internal address, and by remote address too if
+    /// mapping is address and port-dependent.
+    mappings: HashMap<(SocketAddr, Option<SocketAddr>), u16>,
+    bindings: HashMap<u16, Binding>,
+}
+
+impl Nat {
+    pub fn new(external_ip: IpAddr, config: NatConfig) -> Self {
+        Self {
+            config,
+            external_ip,
+            next_port: FIRST_PORT,
+            mappings: HashMap::new(),
+            bindings: HashMap::new(),
+        }
+    }
+
+    /// Returns the external address a datagram from `internal` to `remote`
+    /// is sent from, creating or refreshing its mapping, or `None` if a new
+    /// mapping is needed and every port is in use.
+    pub fn map(
+        &mut self,
+        now: Instant,
+        internal: SocketAddr,
+        remote: SocketAddr,
+    ) -> Option<SocketAddr> {
+        let key = match self.config.nat_type {
+            NatType::Symmetric => (internal, Some(remote)),
+            _ => (internal, None),
+        };
+
+        let bindings = &self.bindings;
+        let live = self
+            .mappings
+            .get(&key)
+            .cloned()
+            .filter(|port| bindings[port].expiry > now);
+        let port = match live {
+            Some(port) => port,
+            None => {
+                // An expired mapping is replaced by one on a new port.
+                if let Some(port) = self.mappings.remove(&key) {
+                    self.bindings.remove(&port);
+                }
+                let port = self.allocate_port(now)?;
+                self.mappings.insert(key, port);
+                self.bindings.insert(
+                    port,
+                    Binding {
+                        mapping: key,
+                        internal,
+                        remotes: HashSet::new(),
+                        expiry: now,
+                    },
+                );
+                port
+            }
+        };
+
+        let binding = self.bindings.get_mut(&port).unwrap();
+        binding.remotes.insert(remote);
+        binding.expiry = now + self.config.mapping_timeout;
+        Some(SocketAddr::new(self.external_ip, port))
+    }
+
+    /// Returns the internal address a datagram from `remote` to the external
+    /// `port` is let through to, if any.
+    pub fn filter(&self, now: Instant, port: u16, remote: SocketAddr) -> Option<SocketAddr> {
+        let binding = self.bindings.get(&port).filter(|b| b.expiry > now)?;
+        let allowed = match self.config.nat_type {
+            NatType::FullCone => true,
+            NatType::Restricted => binding.remotes.iter().any(|r| r.ip() == remote.ip()),
+            NatType::PortRestricted | NatType::Symmetric => binding.remotes.contains(&remote),
+        };
+        if allowed {
+            Some(binding.internal)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the next port without a live binding, wrapping around after
+    /// the last one, or `None` if there is none. An expired binding on the
+    /// port is removed along with its mapping.
+    fn allocate_port(&mut self, now: Instant) -> Option<u16> {
+        for _ in FIRST_PORT..=u16::MAX {
+            let port = self.next_port;
+            self.next_port = match self.next_port.checked_add(1) {
+                Some(port) => port,
+                None => FIRST_PORT,
+            };
+
+            match self.bindings.get(&port) {
+                Some(binding) if binding.expiry > now => continue,
+                Some(binding) => {
+                    self.mappings.remove(&binding.mapping);
+                    self.bindings.remove(&port);
+                }
+                None => (),
+            }
+            return Some(port);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn mapping() {
+        let now = Instant::now();
+        let internal = addr("10.0.0.2:5000");
+        let remotes = [addr("192.0.2.1:3478"), addr("192.0.2.2:4000")];
+
+        let mut nat = Nat::new(
+            "203.0.113.1".parse().unwrap(),
+            NatConfig::new(NatType::FullCone),
+        );
+        let mapped = nat.map(now, internal, remotes[0]).unwrap();
+        assert_eq!(mapped, addr("203.0.113.1:49152"));
+        assert_eq!(nat.map(now, internal, remotes[1]).unwrap(), mapped);
+
+        let config = NatConfig::new(NatType::Symmetric);
+        let mut nat = Nat::new("203.0.113.1".parse().unwrap(), config);
+        let mapped = nat.map(now, internal, remotes[0]).unwrap();
+        assert_ne!(nat.map(now, internal, remotes[1]).unwrap(), mapped);
+        assert_eq!(nat.map(now, internal, remotes[0]).unwrap(), mapped);
+    }
+
+    #[test]
+    fn filtering() {
+        let now = Instant::now();
+        let internal = addr("10.0.0.2:5000");
+        let remote = addr("192.0.2.1:3478");
+        let same_ip = addr("192.0.2.1:4000");
+        let other = addr("192.0.2.2:3478");
+
+        let cases = [
+            (NatType::FullCone, [true, true, true]),
+            (NatType::Restricted, [true, true, false]),
+            (NatType::PortRestricted, [true, false, false]),
+            (NatType::Symmetric, [true, false, false]),
+        ];
+        for &(nat_type, allowed) in &cases {
+            let mut nat = Nat::new("203.0.113.1".parse().unwrap(), NatConfig::new(nat_type));
+            let port = nat.map(now, internal, remote).unwrap().port();
+            for (&from, &allowed) in [remote, same_ip, other].iter().zip(&allowed) {
+                let expected = if allowed { Some(internal) } else { None };
+                assert_eq!(nat.filter(now, port, from), expected, "{:?}", nat_type);
+            }
+            assert_eq!(nat.filter(now, port + 1, remote), None);
+        }
+    }
+
+    #[test]
+    fn timeout() {
+        let now = Instant::now();
+        let internal = addr("10.0.0.2:5000");
+        let remote = addr("192.0.2.1:3478");
+
+        let mut nat = Nat::new(
+            "203.0.113.1".parse().unwrap(),
+            NatConfig::new(NatType::PortRestricted),
+        );
+        let mapped = nat.map(now, internal, remote).unwrap();
+
+        // Outbound traffic keeps the mapping alive.
+        let now = now + DEFAULT_MAPPING_TIMEOUT / 2;
+        assert_eq!(nat.map(now, internal, remote).unwrap(), mapped);
+        let now = now + DEFAULT_MAPPING_TIMEOUT / 2;
+        assert_eq!(nat.filter(now, mapped.port(), remote), Some(internal));
+
+        let now = now + DEFAULT_MAPPING_TIMEOUT;
+        assert_eq!(nat.filter(now, mapped.port(), remote), None);
+        assert_ne!(nat.map(now, internal, remote).unwrap(), mapped);
+    }
+
+    #[test]
+    fn port_wrap_around() {
+        let now = Instant::now();
+        let first_internal = addr("10.0.0.2:5000");
+        let remote = addr("192.0.2.1:3478");
+
+        let mut nat = Nat::new(
+            "203.0.113.1".parse().unwrap(),
+            NatConfig::new(NatType::FullCone),
+        );
+        let first = nat.map(now, first_internal, remote).unwrap();
+        assert_eq!(first.port(), FIRST_PORT);
+        nat.next_port = u16::MAX;
+        let last = nat.map(now, addr("10.0.0.3:5000"), remote).unwrap();
+        assert_eq!(last.port(), u16::MAX);
+
+        // Ports still bound are skipped after wrapping around.
+        let next = nat.map(now, addr("10.0.0.4:5000"), remote).unwrap();
+        assert_eq!(next.port(), FIRST_PORT + 1);
+        assert_eq!(nat.filter(now, first.port(), remote), Some(first_internal));
+
+        // Expired ports are reused, and the address that was mapped to one
+        // gets a new mapping without disturbing the one reusing its port.
+        let now = now + DEFAULT_MAPPING_TIMEOUT / 2;
+        assert_eq!(nat.map(now, addr("10.0.0.3:5000"), remote), Some(last));
+        assert_eq!(nat.map(now, addr("10.0.0.4:5000"), remote), Some(next));
+        let now = now + DEFAULT_MAPPING_TIMEOUT / 2;
+        nat.next_port = u16::MAX;
+        let reused = nat.map(now, addr("10.0.0.5:5000"), remote).unwrap();
+        assert_eq!(reused.port(), FIRST_PORT);
+        let remapped = nat.map(now, first_internal, remote).unwrap();
+        assert_eq!(remapped.port(), FIRST_PORT + 2);
+        assert_eq!(
+            nat.filter(now, reused.port(), remote),
+            Some(addr("10.0.0.5:5000"))
+        );
+    }
+
+    #[test]
+    fn ports_exhausted() {
+        let now = Instant::now();
+        let remote = addr("192.0.2.1:3478");
+
+        let config = NatConfig::new(NatType::Symmetric);
+        let mut nat = Nat::new("203.0.113.1".parse().unwrap(), config);
+        let internal = addr("10.0.0.2:5000");
+        for port in FIRST_PORT..=u16::MAX {
+            let remote = SocketAddr::new(remote.ip(), port);
+            assert!(nat.map(now, internal, remote).is_some());
+        }
+        assert_eq!(nat.map(now, internal, remote), None);
+    }
+}
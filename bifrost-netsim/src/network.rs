@@ -0,0 +1,530 @@
+use crate::nat::{Nat, NatConfig};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::future::Future;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+/// The conditions of the link between a host and the rest of the network.
+/// A datagram goes through the links of both its sender and its receiver.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LinkConfig {
+    /// The probability that a datagram is lost, between 0 and 1.
+    pub loss: f64,
+    /// The one-way delay of every datagram.
+    pub delay: Duration,
+    /// The maximum extra delay of a datagram, chosen uniformly at random.
+    /// Datagrams sent less than this apart may be reordered.
+    pub jitter: Duration,
+}
+
+impl LinkConfig {
+    /// Returns the conditions of a datagram going through both `self` and
+    /// `other`.
+    fn join(self, other: Self) -> Self {
+        Self {
+            loss: 1.0 - (1.0 - self.loss) * (1.0 - other.loss),
+            delay: self.delay + other.delay,
+            jitter: self.jitter + other.jitter,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Host {
+    /// The external address of the NAT the host is behind, if any.
+    nat: Option<IpAddr>,
+    link: LinkConfig,
+}
+
+/// A datagram in flight, with the addresses the receiver sees.
+#[derive(Clone, Debug)]
+struct Datagram {
+    source: SocketAddr,
+    destination: SocketAddr,
+    data: Vec<u8>,
+}
+
+struct Inner {
+    now: Instant,
+    rng: StdRng,
+    hosts: HashMap<IpAddr, Host>,
+    /// The NATs, by external address.
+    nats: HashMap<IpAddr, Nat>,
+    /// The datagrams received on every bound address, oldest first.
+    sockets: HashMap<SocketAddr, VecDeque<(Vec<u8>, SocketAddr)>>,
+    /// The datagrams in flight, by arrival time and then by the order they
+    /// were sent in.
+    in_flight: BTreeMap<(Instant, u64), Datagram>,
+    sent: u64,
+    /// The tasks waiting on a `Sleep`, by deadline and then by the order the
+    /// sleeps were created in.
+    sleepers: BTreeMap<(Instant, u64), Waker>,
+    sleeps: u64,
+}
+
+impl Inner {
+    fn add_host(&mut self, ip: IpAddr, nat: Option<IpAddr>) {
+        assert!(
+            !self.hosts.contains_key(&ip) && !self.nats.contains_key(&ip),
+            "address {} already in use",
+            ip
+        );
+        let link = LinkConfig::default();
+        self.hosts.insert(ip, Host { nat, link });
+    }
+
+    fn send(&mut self, from: SocketAddr, to: SocketAddr, data: Vec<u8>) {
+        let (source, destination) = match self.route(from, to) {
+            Some(route) => route,
+            None => return,
+        };
+
+        let link = self.hosts[&from.ip()]
+            .link
+            .join(self.hosts[&destination.ip()].link);
+        if link.loss > 0.0 && self.rng.gen::<f64>() < link.loss {
+            return;
+        }
+        let mut delay = link.delay;
+        if link.jitter > Duration::from_secs(0) {
+            let jitter = self.rng.gen_range(0, link.jitter.as_nanos() as u64 + 1);
+            delay += Duration::from_nanos(jitter);
+        }
+
+        self.sent += 1;
+        let datagram = Datagram {
+            source,
+            destination,
+            data,
+        };
+        self.in_flight
+            .insert((self.now + delay, self.sent), datagram);
+    }
+
+    /// Returns the source and destination addresses a datagram from `from`
+    /// to `to` arrives with, or `None` if it cannot arrive.
+    fn route(&mut self, from: SocketAddr, to: SocketAddr) -> Option<(SocketAddr, SocketAddr)> {
+        let now = self.now;
+        let from_nat = self.hosts[&from.ip()].nat;
+
+        // Hosts behind the same NAT, or on the public network, reach each
+        // other directly.
+        let to_host = self.hosts.get(&to.ip()).cloned();
+        if let Some(host) = to_host {
+            if host.nat == from_nat {
+                return Some((from, to));
+            }
+        }
+
+        let source = match from_nat {
+            Some(ip) => {
+                let nat = self.nats.get_mut(&ip).unwrap();
+                if ip == to.ip() && !nat.config.hairpinning {
+                    return None;
+                }
+                nat.map(now, from, to)?
+            }
+            None => from,
+        };
+        let destination = match (self.nats.get(&to.ip()), to_host) {
+            (Some(nat), _) => nat.filter(now, to.port(), source)?,
+            (None, Some(Host { nat: None, .. })) => to,
+            // Private addresses behind other NATs are unreachable.
+            _ => return None,
+        };
+        Some((source, destination))
+    }
+
+    /// Moves the datagrams that have arrived by now to their sockets.
+    fn deliver(&mut self) {
+        while let Some(&key) = self.in_flight.keys().next() {
+            if key.0 > self.now {
+                break;
+            }
+            let datagram = self.in_flight.remove(&key).unwrap();
+            if let Some(queue) = self.sockets.get_mut(&datagram.destination) {
+                queue.push_back((datagram.data, datagram.source));
+            }
+        }
+    }
+
+    /// Returns the wakers of the sleeps that have ended by now.
+    fn wake(&mut self) -> Vec<Waker> {
+        let later = self.sleepers.split_off(&(self.now, u64::MAX));
+        let ended = mem::replace(&mut self.sleepers, later);
+        ended.values().cloned().collect()
+    }
+}
+
+/// A simulated network of hosts, optionally behind NATs, with a fake clock.
+///
+/// The network is deterministic for a given seed: time passes only when the
+/// network is advanced, and datagrams are lost and delayed by a seeded random
+/// number generator. What runs on it is too if it is seeded and timed by the
+/// network: ICE agents with a seed in their configuration, and STUN agents
+/// and TURN clients with a seed and a timer that sleeps with `sleep`. Only
+/// UDP is simulated. Host addresses are unique across the network, even
+/// behind different NATs, and NATs cannot be nested.
+///
+/// The network is shared by its clones, so that the transport callbacks of
+/// STUN agents and TURN clients can send through it.
+#[derive(Clone)]
+pub struct Network {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Network {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                now: Instant::now(),
+                rng: StdRng::seed_from_u64(seed),
+                hosts: HashMap::new(),
+                nats: HashMap::new(),
+                sockets: HashMap::new(),
+                in_flight: BTreeMap::new(),
+                sent: 0,
+                sleepers: BTreeMap::new(),
+                sleeps: 0,
+            })),
+        }
+    }
+
+    /// Returns the current simulated time.
+    pub fn now(&self) -> Instant {
+        self.inner().now
+    }
+
+    /// Adds a host on the public network.
+    ///
+    /// Panics if `ip` is already in use.
+    pub fn add_host(&self, ip: IpAddr) {
+        self.inner().add_host(ip, None);
+    }
+
+    /// Adds a NAT whose external address is `ip` on the public network.
+    ///
+    /// Panics if `ip` is already in use.
+    pub fn add_nat(&self, ip: IpAddr, config: NatConfig) {
+        let mut inner = self.inner();
+        assert!(
+            !inner.hosts.contains_key(&ip) && !inner.nats.contains_key(&ip),
+            "address {} already in use",
+            ip
+        );
+        inner.nats.insert(ip, Nat::new(ip, config));
+    }
+
+    /// Adds a host behind the NAT whose external address is `nat`.
+    ///
+    /// Panics if `ip` is already in use, or there is no such NAT.
+    pub fn add_host_behind(&self, ip: IpAddr, nat: IpAddr) {
+        let mut inner = self.inner();
+        assert!(inner.nats.contains_key(&nat), "no NAT at {}", nat);
+        inner.add_host(ip, Some(nat));
+    }
+
+    /// Sets the conditions of the link of the host `ip`.
+    ///
+    /// Panics if there is no such host.
+    pub fn set_link(&self, ip: IpAddr, link: LinkConfig) {
+        let mut inner = self.inner();
+        inner.hosts.get_mut(&ip).expect("no such host").link = link;
+    }
+
+    /// Binds a socket to `addr`, which must be on a host.
+    pub fn bind(&self, addr: SocketAddr) -> io::Result<Socket> {
+        let mut inner = self.inner();
+        if !inner.hosts.contains_key(&addr.ip()) {
+            return Err(io::ErrorKind::AddrNotAvailable.into());
+        }
+        if inner.sockets.contains_key(&addr) {
+            return Err(io::ErrorKind::AddrInUse.into());
+        }
+        inner.sockets.insert(addr, VecDeque::new());
+        Ok(Socket {
+            network: self.clone(),
+            addr,
+        })
+    }
+
+    /// Returns a future that completes once the clock has advanced by
+    /// `duration`.
+    pub fn sleep(&self, duration: Duration) -> Sleep {
+        let mut inner = self.inner();
+        inner.sleeps += 1;
+        Sleep {
+            network: self.clone(),
+            key: (inner.now + duration, inner.sleeps),
+        }
+    }
+
+    /// Returns when the next datagram in flight arrives or the next sleep a
+    /// task waits on ends.
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        let inner = self.inner();
+        let arrival = inner.in_flight.keys().next().map(|&(at, _)| at);
+        let wakeup = inner.sleepers.keys().next().map(|&(at, _)| at);
+        arrival.into_iter().chain(wakeup).min()
+    }
+
+    /// Advances the clock by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        let now = self.now() + duration;
+        self.advance_to(now);
+    }
+
+    /// Advances the clock to `now`, if it is not already past it.
+    pub fn advance_to(&self, now: Instant) {
+        let wakers = {
+            let mut inner = self.inner();
+            inner.now = inner.now.max(now);
+            inner.deliver();
+            inner.wake()
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    fn inner(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap()
+    }
+}
+
+/// A future that completes once the clock of a network reaches a deadline,
+/// returned by `Network::sleep`.
+pub struct Sleep {
+    network: Network,
+    key: (Instant, u64),
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut inner = self.network.inner();
+        if inner.now >= self.key.0 {
+            return Poll::Ready(());
+        }
+        inner.sleepers.insert(self.key, cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.network.inner().sleepers.remove(&self.key);
+    }
+}
+
+/// A UDP socket bound to an address on a simulated network.
+#[derive(Clone)]
+pub struct Socket {
+    network: Network,
+    addr: SocketAddr,
+}
+
+impl Socket {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Sends `data` to `addr`. Like over UDP, datagrams that are lost or
+    /// cannot be delivered are dropped silently.
+    pub fn send_to(&self, data: &[u8], addr: SocketAddr) -> io::Result<()> {
+        self.network.inner().send(self.addr, addr, data.to_vec());
+        Ok(())
+    }
+
+    /// Returns the next datagram received, and the address it came from, if
+    /// any has arrived by now.
+    pub fn recv_from(&self) -> Option<(Vec<u8>, SocketAddr)> {
+        let mut inner = self.network.inner();
+        inner.deliver();
+        inner.sockets.get_mut(&self.addr)?.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nat::NatType;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    fn recv_all(socket: &Socket) -> Vec<(Vec<u8>, SocketAddr)> {
+        let mut datagrams = Vec::new();
+        while let Some(datagram) = socket.recv_from() {
+            datagrams.push(datagram);
+        }
+        datagrams
+    }
+
+    #[test]
+    fn nat() {
+        let network = Network::new(0);
+        network.add_host(ip("192.0.2.1"));
+        network.add_host(ip("192.0.2.2"));
+        network.add_nat(ip("203.0.113.1"), NatConfig::new(NatType::PortRestricted));
+        network.add_host_behind(ip("10.0.0.2"), ip("203.0.113.1"));
+        network.add_host_behind(ip("10.0.0.3"), ip("203.0.113.1"));
+
+        let server = network.bind(addr("192.0.2.1:3478")).unwrap();
+        let other = network.bind(addr("192.0.2.2:3478")).unwrap();
+        let client = network.bind(addr("10.0.0.2:5000")).unwrap();
+        let neighbor = network.bind(addr("10.0.0.3:5000")).unwrap();
+
+        // Private addresses are reachable only from behind the same NAT.
+        server.send_to(b"hi", client.local_addr()).unwrap();
+        assert_eq!(client.recv_from(), None);
+        neighbor.send_to(b"hi", client.local_addr()).unwrap();
+        assert_eq!(
+            client.recv_from(),
+            Some((b"hi".to_vec(), neighbor.local_addr()))
+        );
+
+        client.send_to(b"ping", server.local_addr()).unwrap();
+        let (data, mapped) = server.recv_from().unwrap();
+        assert_eq!(data, b"ping");
+        assert_eq!(mapped, addr("203.0.113.1:49152"));
+
+        server.send_to(b"pong", mapped).unwrap();
+        other.send_to(b"pong", mapped).unwrap();
+        assert_eq!(recv_all(&client), [(b"pong".to_vec(), server.local_addr())]);
+    }
+
+    #[test]
+    fn hairpinning() {
+        for &hairpinning in &[false, true] {
+            let network = Network::new(0);
+            network.add_host(ip("192.0.2.1"));
+            let config = NatConfig {
+                hairpinning,
+                ..NatConfig::new(NatType::FullCone)
+            };
+            network.add_nat(ip("203.0.113.1"), config);
+            network.add_host_behind(ip("10.0.0.2"), ip("203.0.113.1"));
+            network.add_host_behind(ip("10.0.0.3"), ip("203.0.113.1"));
+
+            let server = network.bind(addr("192.0.2.1:3478")).unwrap();
+            let a = network.bind(addr("10.0.0.2:5000")).unwrap();
+            let b = network.bind(addr("10.0.0.3:5000")).unwrap();
+            a.send_to(b"", server.local_addr()).unwrap();
+            b.send_to(b"", server.local_addr()).unwrap();
+            let a_mapped = server.recv_from().unwrap().1;
+            let b_mapped = server.recv_from().unwrap().1;
+
+            b.send_to(b"hi", a_mapped).unwrap();
+            let expected = if hairpinning {
+                Some((b"hi".to_vec(), b_mapped))
+            } else {
+                None
+            };
+            assert_eq!(a.recv_from(), expected);
+        }
+    }
+
+    #[test]
+    fn delay() {
+        let network = Network::new(0);
+        network.add_host(ip("192.0.2.1"));
+        network.add_host(ip("192.0.2.2"));
+        let link = LinkConfig {
+            delay: Duration::from_millis(20),
+            ..LinkConfig::default()
+        };
+        network.set_link(ip("192.0.2.1"), link);
+        network.set_link(ip("192.0.2.2"), link);
+
+        let a = network.bind(addr("192.0.2.1:1000")).unwrap();
+        let b = network.bind(addr("192.0.2.2:2000")).unwrap();
+        let start = network.now();
+        a.send_to(b"hi", b.local_addr()).unwrap();
+        assert_eq!(
+            network.poll_timeout(),
+            Some(start + Duration::from_millis(40))
+        );
+
+        network.advance(Duration::from_millis(39));
+        assert_eq!(b.recv_from(), None);
+        network.advance_to(network.poll_timeout().unwrap());
+        assert_eq!(b.recv_from(), Some((b"hi".to_vec(), a.local_addr())));
+        assert_eq!(network.poll_timeout(), None);
+    }
+
+    /// Sends 100 datagrams over a link with `link`, and returns the ones
+    /// received, in order.
+    fn transfer(seed: u64, link: LinkConfig) -> Vec<u8> {
+        let network = Network::new(seed);
+        network.add_host(ip("192.0.2.1"));
+        network.add_host(ip("192.0.2.2"));
+        network.set_link(ip("192.0.2.1"), link);
+
+        let a = network.bind(addr("192.0.2.1:1000")).unwrap();
+        let b = network.bind(addr("192.0.2.2:2000")).unwrap();
+        for i in 0..100 {
+            a.send_to(&[i], b.local_addr()).unwrap();
+            network.advance(Duration::from_millis(1));
+        }
+        network.advance(Duration::from_secs(1));
+        recv_all(&b).into_iter().map(|(data, _)| data[0]).collect()
+    }
+
+    #[test]
+    fn loss() {
+        let lossy = LinkConfig {
+            loss: 0.5,
+            ..LinkConfig::default()
+        };
+        let received = transfer(1, lossy);
+        assert!(received.len() > 10 && received.len() < 90);
+        assert!(received.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(transfer(1, lossy), received);
+        assert_ne!(transfer(2, lossy), received);
+
+        let dead = LinkConfig {
+            loss: 1.0,
+            ..LinkConfig::default()
+        };
+        assert!(transfer(1, dead).is_empty());
+    }
+
+    #[test]
+    fn reordering() {
+        let jittery = LinkConfig {
+            jitter: Duration::from_millis(50),
+            ..LinkConfig::default()
+        };
+        let received = transfer(1, jittery);
+        let mut sorted = received.clone();
+        sorted.sort();
+        assert_eq!(sorted, (0..100).collect::<Vec<_>>());
+        assert_ne!(received, sorted);
+        assert_eq!(transfer(1, jittery), received);
+    }
+
+    #[test]
+    fn bind() {
+        let network = Network::new(0);
+        network.add_host(ip("192.0.2.1"));
+        network.bind(addr("192.0.2.1:1000")).unwrap();
+        let err = network.bind(addr("192.0.2.1:1000")).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        let err = network.bind(addr("192.0.2.2:1000")).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AddrNotAvailable);
+    }
+}
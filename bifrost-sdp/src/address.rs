@@ -0,0 +1,141 @@
+use std::fmt;
+use std::net::IpAddr;
+
+/// The network type of an address, defined in
+/// [RFC 4566](https://tools.ietf.org/html/rfc4566#section-8.2.6).
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum NetworkType {
+    /// `IN`, the Internet.
+    Internet,
+    Unknown(String),
+}
+
+impl From<&str> for NetworkType {
+    fn from(s: &str) -> Self {
+        match s {
+            "IN" => NetworkType::Internet,
+            s => NetworkType::Unknown(s.to_owned()),
+        }
+    }
+}
+
+impl fmt::Display for NetworkType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkType::Internet => f.write_str("IN"),
+            NetworkType::Unknown(s) => f.write_str(s),
+        }
+    }
+}
+
+/// The type of an address, defined in
+/// [RFC 4566](https://tools.ietf.org/html/rfc4566#section-8.2.7).
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum AddressType {
+    Ip4,
+    Ip6,
+    Unknown(String),
+}
+
+impl From<&str> for AddressType {
+    fn from(s: &str) -> Self {
+        match s {
+            "IP4" => AddressType::Ip4,
+            "IP6" => AddressType::Ip6,
+            s => AddressType::Unknown(s.to_owned()),
+        }
+    }
+}
+
+impl fmt::Display for AddressType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressType::Ip4 => f.write_str("IP4"),
+            AddressType::Ip6 => f.write_str("IP6"),
+            AddressType::Unknown(s) => f.write_str(s),
+        }
+    }
+}
+
+/// An address in an origin or connection data line.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Address {
+    Ip(IpAddr),
+    /// A fully qualified domain name.
+    Fqdn(String),
+    /// An address of an unknown type, kept as it is.
+    Other(String),
+}
+
+impl Address {
+    /// Parses an address of type `address_type`. An IP address must be of
+    /// that type, while addresses of unknown types are kept as they are.
+    pub(crate) fn parse(address_type: &AddressType, s: &str) -> Option<Self> {
+        if let AddressType::Unknown(_) = address_type {
+            return Some(Address::Other(s.to_owned()));
+        }
+        match s.parse::<IpAddr>() {
+            Ok(ip) if ip.is_ipv4() == (*address_type == AddressType::Ip4) => Some(Address::Ip(ip)),
+            Ok(_) => None,
+            Err(_) if is_fqdn(s) => Some(Address::Fqdn(s.to_owned())),
+            Err(_) => None,
+        }
+    }
+}
+
+/// Returns whether `s` looks like a domain name rather than a malformed IP
+/// address, which is all numeric.
+fn is_fqdn(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+        && !s
+            .split('.')
+            .all(|label| label.chars().all(|c| c.is_ascii_digit()))
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Ip(ip) => ip.fmt(f),
+            Address::Fqdn(fqdn) => f.write_str(fqdn),
+            Address::Other(s) => f.write_str(s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            Address::parse(&AddressType::Ip4, "10.47.16.5"),
+            Some(Address::Ip("10.47.16.5".parse().unwrap()))
+        );
+        assert_eq!(
+            Address::parse(&AddressType::Ip6, "2001:db8::1"),
+            Some(Address::Ip("2001:db8::1".parse().unwrap()))
+        );
+        assert_eq!(
+            Address::parse(&AddressType::Ip4, "host.example.com"),
+            Some(Address::Fqdn("host.example.com".to_owned()))
+        );
+        assert_eq!(
+            Address::parse(&AddressType::from("NSAP"), "47.0005/80"),
+            Some(Address::Other("47.0005/80".to_owned()))
+        );
+        assert_eq!(
+            Address::parse(&AddressType::Ip4, "1host.example.com"),
+            Some(Address::Fqdn("1host.example.com".to_owned()))
+        );
+
+        assert_eq!(Address::parse(&AddressType::Ip4, "2001:db8::1"), None);
+        assert_eq!(Address::parse(&AddressType::Ip6, "10.47.16.5"), None);
+        assert_eq!(Address::parse(&AddressType::Ip4, "10.47.16.5/127"), None);
+        // Malformed IP addresses are not domain names.
+        assert_eq!(Address::parse(&AddressType::Ip4, "10.0.0.999"), None);
+        assert_eq!(Address::parse(&AddressType::Ip4, "1.2.3"), None);
+    }
+}
@@ -1,7 +1,8 @@
 use crate::util;
-use crate::Parse;
-use nom::bytes::complete::tag;
+use crate::{Address, AddressType, NetworkType, Parse};
+use nom::bytes::complete::{is_not, tag};
 use nom::character::complete::line_ending;
+use nom::combinator::map_res;
 use nom::IResult;
 use std::fmt;
 
@@ -9,18 +10,31 @@ use std::fmt;
 /// [RFC 4566](https://tools.ietf.org/html/rfc4566#section-5.7).
 #[derive(Clone, Debug, PartialEq)]
 pub struct ConnectionData {
-    pub network_type: String,
-    pub address_type: String,
-    pub connection_address: String,
+    pub network_type: NetworkType,
+    pub address_type: AddressType,
+    /// The address, or the base address of a range of multicast addresses.
+    pub connection_address: Address,
+    /// The TTL of an IP4 multicast address, which it must have.
+    pub ttl: Option<u8>,
+    /// The number of multicast addresses from the base one, if more than
+    /// one.
+    pub address_count: Option<u32>,
 }
 
 impl fmt::Display for ConnectionData {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(
+        write!(
             f,
-            "c={} {} {}\r",
+            "c={} {} {}",
             self.network_type, self.address_type, self.connection_address
-        )
+        )?;
+        if let Some(ttl) = self.ttl {
+            write!(f, "/{}", ttl)?;
+        }
+        if let Some(address_count) = self.address_count {
+            write!(f, "/{}", address_count)?;
+        }
+        writeln!(f, "\r")
     }
 }
 
@@ -35,7 +49,9 @@ impl Parse for ConnectionData {
         let (rest, address_type) = util::parse_field(rest)?;
         let (rest, _) = tag(" ")(rest)?;
 
-        let (rest, connection_address) = util::parse_field(rest)?;
+        let (rest, (connection_address, ttl, address_count)) = map_res(is_not(" \r\n"), |s| {
+            parse_connection_address(&address_type, s)
+        })(rest)?;
         let (rest, _) = line_ending(rest)?;
 
         Ok((
@@ -44,11 +60,44 @@ impl Parse for ConnectionData {
                 network_type,
                 address_type,
                 connection_address,
+                ttl,
+                address_count,
             },
         ))
     }
 }
 
+/// Parses `<base>[/<ttl>][/<number of addresses>]`, where IP4 multicast
+/// addresses must have a TTL, IP6 ones have none, and unicast addresses
+/// have neither.
+fn parse_connection_address(
+    address_type: &AddressType,
+    s: &str,
+) -> Result<(Address, Option<u8>, Option<u32>), ()> {
+    if let AddressType::Unknown(_) = address_type {
+        return Ok((Address::Other(s.to_owned()), None, None));
+    }
+
+    let fields: Vec<_> = s.split('/').collect();
+    let base = Address::parse(address_type, fields[0]).ok_or(())?;
+    let multicast = match &base {
+        Address::Ip(ip) => ip.is_multicast(),
+        Address::Fqdn(_) | Address::Other(_) => false,
+    };
+    let parse_ttl = |ttl: &str| ttl.parse().map_err(|_| ());
+    let parse_count = |count: &str| count.parse().map_err(|_| ());
+    let (ttl, address_count) = match (address_type, multicast, &fields[1..]) {
+        (_, false, []) | (AddressType::Ip6, true, []) => (None, None),
+        (AddressType::Ip4, true, [ttl]) => (Some(parse_ttl(ttl)?), None),
+        (AddressType::Ip4, true, [ttl, count]) => {
+            (Some(parse_ttl(ttl)?), Some(parse_count(count)?))
+        }
+        (AddressType::Ip6, true, [count]) => (None, Some(parse_count(count)?)),
+        _ => return Err(()),
+    };
+    Ok((base, ttl, address_count))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -60,17 +109,70 @@ mod tests {
             "c=IN IP4 224.2.1.1/127/3\r\n rest\n",
             " rest\n",
             &ConnectionData {
-                network_type: "IN".to_owned(),
-                address_type: "IP4".to_owned(),
-                connection_address: "224.2.1.1/127/3".to_owned(),
+                network_type: NetworkType::Internet,
+                address_type: AddressType::Ip4,
+                connection_address: Address::Ip("224.2.1.1".parse().unwrap()),
+                ttl: Some(127),
+                address_count: Some(3),
             },
             "c=IN IP4 224.2.1.1/127/3\r\n",
         );
+
+        assert_parse_display(
+            "c=IN IP6 FF15::101/3\r\n",
+            "",
+            &ConnectionData {
+                network_type: NetworkType::Internet,
+                address_type: AddressType::Ip6,
+                connection_address: Address::Ip("ff15::101".parse().unwrap()),
+                ttl: None,
+                address_count: Some(3),
+            },
+            "c=IN IP6 ff15::101/3\r\n",
+        );
+
+        assert_parse_display(
+            "c=IN IP4 host.example.com\r\n",
+            "",
+            &ConnectionData {
+                network_type: NetworkType::Internet,
+                address_type: AddressType::Ip4,
+                connection_address: Address::Fqdn("host.example.com".to_owned()),
+                ttl: None,
+                address_count: None,
+            },
+            "c=IN IP4 host.example.com\r\n",
+        );
+
+        assert_parse_display(
+            "c=ATM NSAP 47.0005.80.ffe100.0000.f215.0b70.0001/1\r\n",
+            "",
+            &ConnectionData {
+                network_type: NetworkType::Unknown("ATM".to_owned()),
+                address_type: AddressType::Unknown("NSAP".to_owned()),
+                connection_address: Address::Other(
+                    "47.0005.80.ffe100.0000.f215.0b70.0001/1".to_owned(),
+                ),
+                ttl: None,
+                address_count: None,
+            },
+            "c=ATM NSAP 47.0005.80.ffe100.0000.f215.0b70.0001/1\r\n",
+        );
     }
 
     #[test]
     fn invalid() {
         assert_err::<ConnectionData>("c=IN IP4\r\n");
         assert_err::<ConnectionData>("c=IN IP4 224.2.1.1/127/3 foo\r\n");
+        // IP4 multicast addresses need a TTL, which IP6 ones cannot have.
+        assert_err::<ConnectionData>("c=IN IP4 224.2.1.1\r\n");
+        assert_err::<ConnectionData>("c=IN IP4 224.2.1.1/256\r\n");
+        assert_err::<ConnectionData>("c=IN IP6 FF15::101/127/3\r\n");
+        // Unicast addresses have neither.
+        assert_err::<ConnectionData>("c=IN IP4 192.0.2.1/127\r\n");
+        assert_err::<ConnectionData>("c=IN IP6 2001:db8::1/3\r\n");
+        // The address must be of its type.
+        assert_err::<ConnectionData>("c=IN IP4 2001:db8::1\r\n");
+        assert_err::<ConnectionData>("c=IN IP6 192.0.2.1\r\n");
     }
 }
@@ -0,0 +1,1130 @@
+use crate::{
+    Address, AddressType, Attribute, ConnectionData, Direction, Fingerprint, Fmtp, Group, Instant,
+    MediaDescription, MediaInformation, Msid, NetworkType, Origin, RtcpFb, RtpMap,
+    SessionDescription, SessionName, Setup, TimeDescription, Timing, Version, WebRtcAttribute,
+};
+use std::error::Error;
+use std::fmt;
+use std::net::Ipv4Addr;
+use vec1::{vec1, Vec1};
+
+const PROTO: &str = "UDP/TLS/RTP/SAVPF";
+
+/// A codec a transceiver can send or receive.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Codec {
+    pub payload_type: u8,
+    pub encoding_name: String,
+    pub clock_rate: u32,
+    pub channels: Option<u16>,
+    /// The format parameters, sent in `a=fmtp`.
+    pub parameters: Vec<(String, Option<String>)>,
+    /// The RTCP feedback types, sent in `a=rtcp-fb`, such as `nack pli`.
+    pub feedback: Vec<String>,
+}
+
+impl Codec {
+    pub fn new(payload_type: u8, encoding_name: &str, clock_rate: u32) -> Self {
+        Self {
+            payload_type,
+            encoding_name: encoding_name.to_owned(),
+            clock_rate,
+            channels: None,
+            parameters: Vec::new(),
+            feedback: Vec::new(),
+        }
+    }
+
+    /// Returns the value of the format parameter `name`.
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters
+            .iter()
+            .find(|(other, _)| other == name)
+            .and_then(|(_, value)| value.as_ref().map(String::as_str))
+    }
+
+    fn is_rtx(&self) -> bool {
+        self.encoding_name.eq_ignore_ascii_case("rtx")
+    }
+
+    /// Returns whether `self` and `other` are the same codec, as in
+    /// [RFC 3264](https://tools.ietf.org/html/rfc3264#section-6.1), taking
+    /// the H.264 profile and packetization mode into account.
+    fn matches(&self, other: &Codec) -> bool {
+        if !self
+            .encoding_name
+            .eq_ignore_ascii_case(&other.encoding_name)
+            || self.clock_rate != other.clock_rate
+            || self.channels.unwrap_or(1) != other.channels.unwrap_or(1)
+        {
+            return false;
+        }
+        if self.encoding_name.eq_ignore_ascii_case("H264") {
+            let profile = |codec: &Codec| {
+                let profile_level_id = codec.parameter("profile-level-id").unwrap_or("42");
+                profile_level_id.get(..2).map(str::to_ascii_lowercase)
+            };
+            let mode = |codec: &Codec| {
+                let mode = codec.parameter("packetization-mode").unwrap_or("0");
+                mode.to_owned()
+            };
+            return profile(self) == profile(other) && mode(self) == mode(other);
+        }
+        true
+    }
+
+    /// Returns the codecs of a media description, in the order of its
+    /// formats.
+    fn from_media(media: &MediaDescription) -> Vec<Self> {
+        let attributes = typed_attributes(&media.attributes);
+        let mut codecs = Vec::new();
+        for format in media.media_information.formats.iter() {
+            let payload_type = match format.parse() {
+                Ok(payload_type) => payload_type,
+                Err(_) => continue,
+            };
+            let rtp_map = attributes.iter().find_map(|attribute| match attribute {
+                WebRtcAttribute::RtpMap(rtp_map) if rtp_map.payload_type == payload_type => {
+                    Some(rtp_map.clone())
+                }
+                _ => None,
+            });
+            let mut codec = match rtp_map.or_else(|| static_rtp_map(payload_type)) {
+                Some(rtp_map) => Self {
+                    channels: rtp_map.channels,
+                    ..Self::new(payload_type, &rtp_map.encoding_name, rtp_map.clock_rate)
+                },
+                None => continue,
+            };
+            for attribute in &attributes {
+                match attribute {
+                    WebRtcAttribute::Fmtp(fmtp) if fmtp.payload_type == payload_type => {
+                        codec.parameters = fmtp.parameters.clone();
+                    }
+                    WebRtcAttribute::RtcpFb(rtcp_fb)
+                        if rtcp_fb.payload_type.unwrap_or(payload_type) == payload_type =>
+                    {
+                        let mut feedback = rtcp_fb.feedback_type.clone();
+                        if let Some(parameters) = &rtcp_fb.parameters {
+                            feedback.push(' ');
+                            feedback.push_str(parameters);
+                        }
+                        codec.feedback.push(feedback);
+                    }
+                    _ => {}
+                }
+            }
+            codecs.push(codec);
+        }
+        codecs
+    }
+
+    fn to_attributes(&self) -> Vec<WebRtcAttribute> {
+        let mut attributes = vec![WebRtcAttribute::RtpMap(RtpMap {
+            payload_type: self.payload_type,
+            encoding_name: self.encoding_name.clone(),
+            clock_rate: self.clock_rate,
+            channels: self.channels,
+        })];
+        for feedback in &self.feedback {
+            let mut fields = feedback.splitn(2, ' ');
+            attributes.push(WebRtcAttribute::RtcpFb(RtcpFb {
+                payload_type: Some(self.payload_type),
+                feedback_type: fields.next().unwrap_or_default().to_owned(),
+                parameters: fields.next().map(String::from),
+            }));
+        }
+        if !self.parameters.is_empty() {
+            attributes.push(WebRtcAttribute::Fmtp(Fmtp {
+                payload_type: self.payload_type,
+                parameters: self.parameters.clone(),
+                spaced: false,
+            }));
+        }
+        attributes
+    }
+}
+
+/// Returns the encoding of a static payload type, defined in
+/// [RFC 3551](https://tools.ietf.org/html/rfc3551#section-6), that WebRTC
+/// endpoints may offer without an `a=rtpmap`.
+fn static_rtp_map(payload_type: u8) -> Option<RtpMap> {
+    let (encoding_name, clock_rate) = match payload_type {
+        0 => ("PCMU", 8000),
+        8 => ("PCMA", 8000),
+        9 => ("G722", 8000),
+        _ => return None,
+    };
+    Some(RtpMap {
+        payload_type,
+        encoding_name: encoding_name.to_owned(),
+        clock_rate,
+        channels: None,
+    })
+}
+
+/// Returns the codecs of `offered` that match `codecs`, in the order of
+/// `codecs` but with the payload types of `offered`, followed by the
+/// retransmission codecs of `offered` associated with them.
+fn intersect_codecs(codecs: &[Codec], offered: &[Codec]) -> Vec<Codec> {
+    let mut accepted = Vec::new();
+    for codec in codecs.iter().filter(|codec| !codec.is_rtx()) {
+        let matching = offered.iter().find(|other| {
+            codec.matches(other)
+                && accepted
+                    .iter()
+                    .all(|accepted: &Codec| accepted.payload_type != other.payload_type)
+        });
+        if let Some(other) = matching {
+            accepted.push(Codec {
+                payload_type: other.payload_type,
+                parameters: codec.parameters.clone(),
+                feedback: codec
+                    .feedback
+                    .iter()
+                    .filter(|feedback| other.feedback.contains(feedback))
+                    .cloned()
+                    .collect(),
+                ..other.clone()
+            });
+        }
+    }
+
+    if let Some(rtx) = codecs.iter().find(|codec| codec.is_rtx()) {
+        let associated: Vec<_> = accepted
+            .iter()
+            .map(|codec| codec.payload_type.to_string())
+            .collect();
+        for other in offered.iter().filter(|other| other.matches(rtx)) {
+            let apt = other.parameter("apt").unwrap_or_default();
+            if associated.iter().any(|payload_type| payload_type == apt) {
+                accepted.push(other.clone());
+            }
+        }
+    }
+    accepted
+}
+
+fn typed_attributes(attributes: &[Attribute]) -> Vec<WebRtcAttribute> {
+    attributes.iter().map(WebRtcAttribute::from).collect()
+}
+
+/// Returns the direction of a media description, or that of the session if
+/// it has none.
+fn media_direction(session: &SessionDescription, media: &MediaDescription) -> Direction {
+    let direction = |attributes: &[Attribute]| {
+        typed_attributes(attributes)
+            .into_iter()
+            .find_map(|attribute| match attribute {
+                WebRtcAttribute::Direction(direction) => Some(direction),
+                _ => None,
+            })
+    };
+    direction(&media.attributes)
+        .or_else(|| direction(&session.attributes))
+        .unwrap_or(Direction::SendRecv)
+}
+
+fn media_mid(media: &MediaDescription) -> Option<String> {
+    typed_attributes(&media.attributes)
+        .into_iter()
+        .find_map(|attribute| match attribute {
+            WebRtcAttribute::Mid(mid) => Some(mid),
+            _ => None,
+        })
+}
+
+fn is_rejected(media: &MediaDescription) -> bool {
+    media.media_information.port.split('/').next() == Some("0")
+}
+
+/// A media section of a description, as JSEP keeps them in order.
+#[derive(Clone, Debug, PartialEq)]
+struct MediaSection {
+    mid: String,
+    media_type: String,
+    proto: String,
+    formats: Vec1<String>,
+}
+
+/// Returns the mids of the media descriptions of `session`, and the rest of
+/// their m= lines.
+fn media_sections(session: &SessionDescription) -> Result<Vec<MediaSection>, JsepError> {
+    let mut sections: Vec<MediaSection> = Vec::new();
+    for (index, media) in session.media_descriptions.iter().enumerate() {
+        let mid = media_mid(media).ok_or(JsepError::MissingMid(index))?;
+        if sections.iter().any(|section| section.mid == mid) {
+            return Err(JsepError::DuplicateMid(mid));
+        }
+        sections.push(MediaSection {
+            mid,
+            media_type: media.media_information.media_type.clone(),
+            proto: media.media_information.proto.clone(),
+            formats: media.media_information.formats.clone(),
+        });
+    }
+    Ok(sections)
+}
+
+/// An error negotiating descriptions.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum JsepError {
+    /// The media description at this index has no `a=mid`.
+    MissingMid(usize),
+    /// Two media descriptions have this mid.
+    DuplicateMid(String),
+    /// The media descriptions remove or reorder those of the previous
+    /// negotiation, or those of the offer in an answer, or an answer changes
+    /// the media type of one.
+    MediaMismatch,
+    /// An answer was applied with no offer pending.
+    NoPendingOffer,
+}
+
+impl fmt::Display for JsepError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsepError::MissingMid(index) => write!(f, "m= line {} has no mid", index),
+            JsepError::DuplicateMid(mid) => write!(f, "duplicate mid {:?}", mid),
+            JsepError::MediaMismatch => f.write_str("m= lines do not match"),
+            JsepError::NoPendingOffer => f.write_str("no offer pending"),
+        }
+    }
+}
+
+impl Error for JsepError {}
+
+/// A pair of an RTP sender and receiver sharing a media description, as in
+/// [RFC 8829](https://tools.ietf.org/html/rfc8829#section-3.4.1).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transceiver {
+    /// The mid of the media description, assigned when it is first offered
+    /// or associated with one of a remote offer.
+    pub mid: Option<String>,
+    /// The media type, such as `audio` or `video`.
+    pub media_type: String,
+    /// The direction wanted.
+    pub direction: Direction,
+    /// The codecs, in the order of preference.
+    pub codecs: Vec<Codec>,
+    /// The media stream and track sent, if any.
+    pub msid: Option<Msid>,
+    /// Whether the transceiver is stopped, and its media description
+    /// rejected.
+    pub stopped: bool,
+    /// The direction negotiated, from the point of view of this end.
+    pub current_direction: Option<Direction>,
+    /// The codecs negotiated, with the payload types of the answer.
+    pub negotiated_codecs: Vec<Codec>,
+}
+
+impl Transceiver {
+    pub fn new(media_type: &str, direction: Direction, codecs: Vec<Codec>) -> Self {
+        Self {
+            mid: None,
+            media_type: media_type.to_owned(),
+            direction,
+            codecs,
+            msid: None,
+            stopped: false,
+            current_direction: None,
+            negotiated_codecs: Vec::new(),
+        }
+    }
+
+    fn has_mid(&self, mid: &str) -> bool {
+        self.mid.iter().any(|own| own == mid)
+    }
+
+    fn is_active(&self) -> bool {
+        !self.stopped && !self.codecs.is_empty()
+    }
+}
+
+/// The largest session ID, whose highest bit must be zero, as defined in
+/// [RFC 8829](https://tools.ietf.org/html/rfc8829#section-5.2.1).
+const MAX_SESSION_ID: u64 = (1 << 63) - 1;
+
+/// Generates offers and answers, defined in
+/// [RFC 8829](https://tools.ietf.org/html/rfc8829#section-5).
+///
+/// Answers become the local description as they are generated, and offers
+/// once their answer is applied. All media share the same ICE credentials
+/// and DTLS fingerprint, and are offered in a single BUNDLE group.
+#[derive(Clone, Debug)]
+pub struct JsepSession {
+    session_id: u64,
+    session_version: u64,
+    ice_ufrag: String,
+    ice_pwd: String,
+    fingerprint: Fingerprint,
+    pub transceivers: Vec<Transceiver>,
+    /// The media sections negotiated, in order.
+    sections: Vec<MediaSection>,
+    local: Option<SessionDescription>,
+    remote: Option<SessionDescription>,
+    pending_offer: Option<SessionDescription>,
+}
+
+impl JsepSession {
+    /// Creates a session with the local ICE credentials and the fingerprint
+    /// of the DTLS certificate. `session_id` should be random; only its low
+    /// 63 bits are used, as
+    /// [RFC 8829](https://tools.ietf.org/html/rfc8829#section-5.2.1) requires.
+    pub fn new(session_id: u64, ice_ufrag: &str, ice_pwd: &str, fingerprint: Fingerprint) -> Self {
+        Self {
+            session_id: session_id & MAX_SESSION_ID,
+            session_version: 0,
+            ice_ufrag: ice_ufrag.to_owned(),
+            ice_pwd: ice_pwd.to_owned(),
+            fingerprint,
+            transceivers: Vec::new(),
+            sections: Vec::new(),
+            local: None,
+            remote: None,
+            pending_offer: None,
+        }
+    }
+
+    /// Adds a transceiver, and returns its index.
+    pub fn add_transceiver(&mut self, transceiver: Transceiver) -> usize {
+        self.transceivers.push(transceiver);
+        self.transceivers.len() - 1
+    }
+
+    pub fn local_description(&self) -> Option<&SessionDescription> {
+        self.local.as_ref()
+    }
+
+    pub fn remote_description(&self) -> Option<&SessionDescription> {
+        self.remote.as_ref()
+    }
+
+    fn transceiver(&mut self, mid: &str) -> Option<&mut Transceiver> {
+        self.transceivers
+            .iter_mut()
+            .find(|transceiver| transceiver.has_mid(mid))
+    }
+
+    /// Generates an offer, as in
+    /// [RFC 8829](https://tools.ietf.org/html/rfc8829#section-5.2).
+    ///
+    /// Media negotiated before keep their order and mids, with those of
+    /// stopped transceivers rejected. Media of transceivers added since
+    /// follow, with new mids.
+    pub fn create_offer(&mut self) -> SessionDescription {
+        let mut media_descriptions = Vec::new();
+        let mut bundle = Vec::new();
+        for section in self.sections.clone() {
+            let active = self
+                .transceiver(&section.mid)
+                .filter(|transceiver| transceiver.is_active())
+                .cloned();
+            media_descriptions.push(match active {
+                Some(transceiver) => {
+                    bundle.push(section.mid.clone());
+                    self.offer_media(&transceiver, &section.mid)
+                }
+                None => rejected_media(&section),
+            });
+        }
+
+        for i in 0..self.transceivers.len() {
+            let transceiver = &self.transceivers[i];
+            let negotiated = transceiver
+                .mid
+                .iter()
+                .any(|mid| self.sections.iter().any(|section| section.mid == *mid));
+            if negotiated || !transceiver.is_active() {
+                continue;
+            }
+            let mid = match &transceiver.mid {
+                Some(mid) => mid.clone(),
+                None => {
+                    let mid = self.next_mid();
+                    self.transceivers[i].mid = Some(mid.clone());
+                    mid
+                }
+            };
+            media_descriptions.push(self.offer_media(&self.transceivers[i], &mid));
+            bundle.push(mid);
+        }
+
+        let offer = self.describe(bundle, media_descriptions);
+        self.pending_offer = Some(offer.clone());
+        offer
+    }
+
+    /// Returns the smallest number not yet used as a mid.
+    fn next_mid(&self) -> String {
+        let used = |mid: &str| {
+            self.sections.iter().any(|section| section.mid == mid)
+                || self
+                    .transceivers
+                    .iter()
+                    .any(|transceiver| transceiver.has_mid(mid))
+        };
+        (0..)
+            .map(|i: u32| i.to_string())
+            .find(|mid| !used(mid))
+            .unwrap()
+    }
+
+    fn offer_media(&self, transceiver: &Transceiver, mid: &str) -> MediaDescription {
+        let mut attributes = self.transport_attributes(Setup::ActPass);
+        attributes.push(WebRtcAttribute::Mid(mid.to_owned()));
+        attributes.push(WebRtcAttribute::Direction(transceiver.direction));
+        if transceiver.direction.sends() {
+            attributes.extend(transceiver.msid.clone().map(WebRtcAttribute::Msid));
+        }
+        attributes.push(WebRtcAttribute::RtcpMux);
+        attributes.push(WebRtcAttribute::RtcpRsize);
+        for codec in &transceiver.codecs {
+            attributes.extend(codec.to_attributes());
+        }
+        accepted_media(
+            &transceiver.media_type,
+            PROTO,
+            &transceiver.codecs,
+            attributes,
+        )
+    }
+
+    /// Applies the answer to the pending offer, as in
+    /// [RFC 8829](https://tools.ietf.org/html/rfc8829#section-5.10).
+    ///
+    /// Transceivers whose media the answer rejects are stopped.
+    pub fn apply_answer(&mut self, answer: &SessionDescription) -> Result<(), JsepError> {
+        let offer = self
+            .pending_offer
+            .as_ref()
+            .ok_or(JsepError::NoPendingOffer)?;
+        let offered = media_sections(offer)?;
+        let answered = media_sections(answer)?;
+        let matches = offered.len() == answered.len()
+            && offered.iter().zip(&answered).all(|(offered, answered)| {
+                offered.mid == answered.mid && offered.media_type == answered.media_type
+            });
+        if !matches {
+            return Err(JsepError::MediaMismatch);
+        }
+
+        for (section, media) in offered.iter().zip(&answer.media_descriptions) {
+            let direction = media_direction(answer, media);
+            let transceiver = match self.transceiver(&section.mid) {
+                Some(transceiver) => transceiver,
+                None => continue,
+            };
+            if is_rejected(media) {
+                transceiver.stopped = true;
+                transceiver.current_direction = None;
+                transceiver.negotiated_codecs.clear();
+            } else {
+                transceiver.current_direction = Some(direction.reverse());
+                transceiver.negotiated_codecs = Codec::from_media(media);
+            }
+        }
+
+        self.sections = offered;
+        self.local = self.pending_offer.take();
+        self.remote = Some(answer.clone());
+        Ok(())
+    }
+
+    /// Generates an answer to a remote offer, as in
+    /// [RFC 8829](https://tools.ietf.org/html/rfc8829#section-5.3), and
+    /// applies it.
+    ///
+    /// Each media description offered is associated with the transceiver of
+    /// its mid and media type, or else the first unassociated one of its
+    /// media type unless it has port 0. It is rejected if it has port 0, if
+    /// there is no such transceiver or it is stopped, or if no codec
+    /// matches. The answer accepts the offered BUNDLE group, less the
+    /// rejected mids.
+    ///
+    /// Re-offers must keep the media descriptions negotiated before, in the
+    /// same order. A pending local offer is discarded, along with the mids
+    /// it assigned.
+    pub fn create_answer(
+        &mut self,
+        offer: &SessionDescription,
+    ) -> Result<SessionDescription, JsepError> {
+        let offered = media_sections(offer)?;
+        let kept = self
+            .sections
+            .iter()
+            .zip(&offered)
+            .all(|(section, offered)| section.mid == offered.mid);
+        if offered.len() < self.sections.len() || !kept {
+            return Err(JsepError::MediaMismatch);
+        }
+
+        if self.pending_offer.take().is_some() {
+            let sections = &self.sections;
+            for transceiver in &mut self.transceivers {
+                let negotiated = transceiver
+                    .mid
+                    .iter()
+                    .any(|mid| sections.iter().any(|section| section.mid == *mid));
+                if !negotiated {
+                    transceiver.mid = None;
+                }
+            }
+        }
+
+        let offer_attributes = typed_attributes(&offer.attributes);
+        let mut media_descriptions = Vec::new();
+        let mut accepted_mids = Vec::new();
+        for (section, media) in offered.iter().zip(&offer.media_descriptions) {
+            let attributes = typed_attributes(&media.attributes);
+            let index = match self.associate(section, is_rejected(media)) {
+                Some(index) => index,
+                None => {
+                    media_descriptions.push(rejected_media(section));
+                    continue;
+                }
+            };
+            let transceiver = &self.transceivers[index];
+            let codecs = intersect_codecs(&transceiver.codecs, &Codec::from_media(media));
+            if is_rejected(media) || transceiver.stopped || codecs.is_empty() {
+                let transceiver = &mut self.transceivers[index];
+                transceiver.stopped = true;
+                transceiver.current_direction = None;
+                transceiver.negotiated_codecs.clear();
+                media_descriptions.push(rejected_media(section));
+                continue;
+            }
+
+            let offered_direction = media_direction(offer, media);
+            let direction = Direction::from_flags(
+                transceiver.direction.sends() && offered_direction.receives(),
+                transceiver.direction.receives() && offered_direction.sends(),
+            );
+            let setup = attributes
+                .iter()
+                .chain(&offer_attributes)
+                .find_map(|attribute| match attribute {
+                    WebRtcAttribute::Setup(Setup::Active) => Some(Setup::Passive),
+                    WebRtcAttribute::Setup(_) => Some(Setup::Active),
+                    _ => None,
+                })
+                .unwrap_or(Setup::Active);
+
+            let mut answer_attributes = self.transport_attributes(setup);
+            answer_attributes.push(WebRtcAttribute::Mid(section.mid.clone()));
+            answer_attributes.push(WebRtcAttribute::Direction(direction));
+            if direction.sends() {
+                answer_attributes.extend(transceiver.msid.clone().map(WebRtcAttribute::Msid));
+            }
+            for flag in &[WebRtcAttribute::RtcpMux, WebRtcAttribute::RtcpRsize] {
+                if attributes.contains(flag) {
+                    answer_attributes.push(flag.clone());
+                }
+            }
+            for codec in &codecs {
+                answer_attributes.extend(codec.to_attributes());
+            }
+            media_descriptions.push(accepted_media(
+                &section.media_type,
+                &section.proto,
+                &codecs,
+                answer_attributes,
+            ));
+
+            let transceiver = &mut self.transceivers[index];
+            transceiver.current_direction = Some(direction);
+            transceiver.negotiated_codecs = codecs;
+            accepted_mids.push(section.mid.clone());
+        }
+
+        let bundle = offer_attributes
+            .into_iter()
+            .find_map(|attribute| match attribute {
+                WebRtcAttribute::Group(group) if group.semantics == "BUNDLE" => Some(group.mids),
+                _ => None,
+            })
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|mid| accepted_mids.contains(mid))
+            .collect();
+        let answer = self.describe(bundle, media_descriptions);
+        self.local = Some(answer.clone());
+
+        self.sections = offered;
+        self.remote = Some(offer.clone());
+        Ok(answer)
+    }
+
+    /// Returns the index of the transceiver of an offered media section,
+    /// associating one with it if none is and it is not `rejected`, or
+    /// `None` if the one associated is of another media type.
+    fn associate(&mut self, section: &MediaSection, rejected: bool) -> Option<usize> {
+        let associated = self
+            .transceivers
+            .iter()
+            .position(|transceiver| transceiver.has_mid(&section.mid));
+        if let Some(index) = associated {
+            if self.transceivers[index].media_type != section.media_type {
+                return None;
+            }
+            return Some(index);
+        }
+        if rejected {
+            return None;
+        }
+        let index = self.transceivers.iter().position(|transceiver| {
+            transceiver.mid.is_none()
+                && transceiver.is_active()
+                && transceiver.media_type == section.media_type
+        })?;
+        self.transceivers[index].mid = Some(section.mid.clone());
+        Some(index)
+    }
+
+    fn transport_attributes(&self, setup: Setup) -> Vec<WebRtcAttribute> {
+        vec![
+            WebRtcAttribute::IceUfrag(self.ice_ufrag.clone()),
+            WebRtcAttribute::IcePwd(self.ice_pwd.clone()),
+            WebRtcAttribute::IceOptions(vec!["trickle".to_owned()]),
+            WebRtcAttribute::Fingerprint(self.fingerprint.clone()),
+            WebRtcAttribute::Setup(setup),
+        ]
+    }
+
+    /// Returns a description of `media_descriptions`, with a BUNDLE group of
+    /// `bundle` if not empty, and a session version incremented if it
+    /// differs from the last description generated.
+    fn describe(
+        &mut self,
+        bundle: Vec<String>,
+        media_descriptions: Vec<MediaDescription>,
+    ) -> SessionDescription {
+        let mut attributes = Vec::new();
+        if !bundle.is_empty() {
+            let group = WebRtcAttribute::Group(Group {
+                semantics: "BUNDLE".to_owned(),
+                mids: bundle,
+            });
+            attributes.push(group.to_attribute());
+        }
+        let mut description = SessionDescription {
+            version: Version,
+            origin: Origin {
+                username: "-".to_owned(),
+                session_id: self.session_id,
+                session_version: self.session_version,
+                network_type: NetworkType::Internet,
+                address_type: AddressType::Ip4,
+                unicast_address: Address::Ip(Ipv4Addr::UNSPECIFIED.into()),
+            },
+            session_name: SessionName("-".to_owned()),
+            session_information: None,
+            uri: None,
+            email_address: None,
+            phone_number: None,
+            connection_data: None,
+            bandwidth: None,
+            time_descriptions: vec1![TimeDescription {
+                timing: Timing {
+                    start_time: Instant::from_secs(0),
+                    stop_time: Instant::from_secs(0),
+                },
+                repeat_times: vec![],
+            }],
+            time_zones: None,
+            encryption_key: None,
+            attributes,
+            media_descriptions,
+        };
+        if let Some(last) = self.pending_offer.as_ref().or(self.local.as_ref()) {
+            if *last != description {
+                self.session_version += 1;
+                description.origin.session_version = self.session_version;
+            }
+        }
+        description
+    }
+}
+
+fn connection_data() -> ConnectionData {
+    ConnectionData {
+        network_type: NetworkType::Internet,
+        address_type: AddressType::Ip4,
+        connection_address: Address::Ip(Ipv4Addr::UNSPECIFIED.into()),
+        ttl: None,
+        address_count: None,
+    }
+}
+
+fn accepted_media(
+    media_type: &str,
+    proto: &str,
+    codecs: &[Codec],
+    attributes: Vec<WebRtcAttribute>,
+) -> MediaDescription {
+    let formats = codecs
+        .iter()
+        .map(|codec| codec.payload_type.to_string())
+        .collect();
+    MediaDescription {
+        media_information: MediaInformation {
+            media_type: media_type.to_owned(),
+            port: "9".to_owned(),
+            proto: proto.to_owned(),
+            formats: Vec1::try_from_vec(formats).unwrap(),
+        },
+        media_title: None,
+        connection_data: Some(connection_data()),
+        bandwidths: vec![],
+        encryption_key: None,
+        attributes: attributes.iter().map(Attribute::from).collect(),
+    }
+}
+
+/// Returns a media description rejecting a section, with port 0 and only
+/// its mid, as in [RFC 8829](https://tools.ietf.org/html/rfc8829#section-5.2.2).
+fn rejected_media(section: &MediaSection) -> MediaDescription {
+    MediaDescription {
+        media_information: MediaInformation {
+            media_type: section.media_type.clone(),
+            port: "0".to_owned(),
+            proto: section.proto.clone(),
+            formats: section.formats.clone(),
+        },
+        media_title: None,
+        connection_data: Some(connection_data()),
+        bandwidths: vec![],
+        encryption_key: None,
+        attributes: vec![WebRtcAttribute::Mid(section.mid.clone()).to_attribute()],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fingerprint() -> Fingerprint {
+        Fingerprint {
+            hash_function: "sha-256".to_owned(),
+            fingerprint: vec![0xab; 4],
+        }
+    }
+
+    fn opus() -> Codec {
+        Codec {
+            channels: Some(2),
+            parameters: vec![("minptime".to_owned(), Some("10".to_owned()))],
+            feedback: vec!["transport-cc".to_owned()],
+            ..Codec::new(111, "opus", 48000)
+        }
+    }
+
+    fn video_codecs() -> Vec<Codec> {
+        let vp8 = Codec {
+            feedback: vec!["nack".to_owned(), "nack pli".to_owned()],
+            ..Codec::new(96, "VP8", 90000)
+        };
+        let rtx = Codec {
+            parameters: vec![("apt".to_owned(), Some("96".to_owned()))],
+            ..Codec::new(97, "rtx", 90000)
+        };
+        vec![vp8, rtx]
+    }
+
+    fn session(ice_ufrag: &str) -> JsepSession {
+        JsepSession::new(1, ice_ufrag, "password", fingerprint())
+    }
+
+    fn mids(session: &SessionDescription) -> Vec<String> {
+        media_sections(session)
+            .unwrap()
+            .into_iter()
+            .map(|section| section.mid)
+            .collect()
+    }
+
+    #[test]
+    fn offer() {
+        let mut session = session("local");
+        let mut audio = Transceiver::new("audio", Direction::SendRecv, vec![opus()]);
+        audio.msid = Some(Msid {
+            stream_id: "stream".to_owned(),
+            track_id: Some("audio".to_owned()),
+        });
+        session.add_transceiver(audio);
+        session.add_transceiver(Transceiver::new(
+            "video",
+            Direction::RecvOnly,
+            video_codecs(),
+        ));
+
+        assert_eq!(
+            session.create_offer().to_string(),
+            "v=0\r\n\
+             o=- 1 0 IN IP4 0.0.0.0\r\n\
+             s=-\r\n\
+             t=0 0\r\n\
+             a=group:BUNDLE 0 1\r\n\
+             m=audio 9 UDP/TLS/RTP/SAVPF 111\r\n\
+             c=IN IP4 0.0.0.0\r\n\
+             a=ice-ufrag:local\r\n\
+             a=ice-pwd:password\r\n\
+             a=ice-options:trickle\r\n\
+             a=fingerprint:sha-256 AB:AB:AB:AB\r\n\
+             a=setup:actpass\r\n\
+             a=mid:0\r\n\
+             a=sendrecv\r\n\
+             a=msid:stream audio\r\n\
+             a=rtcp-mux\r\n\
+             a=rtcp-rsize\r\n\
+             a=rtpmap:111 opus/48000/2\r\n\
+             a=rtcp-fb:111 transport-cc\r\n\
+             a=fmtp:111 minptime=10\r\n\
+             m=video 9 UDP/TLS/RTP/SAVPF 96 97\r\n\
+             c=IN IP4 0.0.0.0\r\n\
+             a=ice-ufrag:local\r\n\
+             a=ice-pwd:password\r\n\
+             a=ice-options:trickle\r\n\
+             a=fingerprint:sha-256 AB:AB:AB:AB\r\n\
+             a=setup:actpass\r\n\
+             a=mid:1\r\n\
+             a=recvonly\r\n\
+             a=rtcp-mux\r\n\
+             a=rtcp-rsize\r\n\
+             a=rtpmap:96 VP8/90000\r\n\
+             a=rtcp-fb:96 nack\r\n\
+             a=rtcp-fb:96 nack pli\r\n\
+             a=rtpmap:97 rtx/90000\r\n\
+             a=fmtp:97 apt=96\r\n"
+        );
+        assert_eq!(session.transceivers[0].mid, Some("0".to_owned()));
+        assert_eq!(session.transceivers[1].mid, Some("1".to_owned()));
+
+        // Nothing changed, so neither does the version.
+        assert_eq!(session.create_offer().origin.session_version, 0);
+
+        // Session IDs are limited to 63 bits.
+        let mut session = JsepSession::new(u64::MAX, "local", "password", fingerprint());
+        assert_eq!(session.create_offer().origin.session_id, (1 << 63) - 1);
+    }
+
+    const REMOTE_OFFER: &str = "v=0\r\n\
+                                o=- 4611731400430051336 2 IN IP4 127.0.0.1\r\n\
+                                s=-\r\n\
+                                t=0 0\r\n\
+                                a=group:BUNDLE a v d\r\n\
+                                m=audio 9 UDP/TLS/RTP/SAVPF 103 109 0\r\n\
+                                c=IN IP4 0.0.0.0\r\n\
+                                a=ice-ufrag:remote\r\n\
+                                a=ice-pwd:remotepassword\r\n\
+                                a=fingerprint:sha-256 CD:CD:CD:CD\r\n\
+                                a=setup:actpass\r\n\
+                                a=mid:a\r\n\
+                                a=sendonly\r\n\
+                                a=rtcp-mux\r\n\
+                                a=rtpmap:103 ISAC/16000\r\n\
+                                a=rtpmap:109 opus/48000/2\r\n\
+                                a=rtcp-fb:109 transport-cc\r\n\
+                                a=rtcp-fb:109 nack\r\n\
+                                a=fmtp:109 useinbandfec=1\r\n\
+                                m=video 9 UDP/TLS/RTP/SAVPF 100 101 120 121\r\n\
+                                c=IN IP4 0.0.0.0\r\n\
+                                a=mid:v\r\n\
+                                a=rtcp-mux\r\n\
+                                a=rtpmap:100 VP9/90000\r\n\
+                                a=rtpmap:101 rtx/90000\r\n\
+                                a=fmtp:101 apt=100\r\n\
+                                a=rtpmap:120 VP8/90000\r\n\
+                                a=rtcp-fb:120 nack pli\r\n\
+                                a=rtpmap:121 rtx/90000\r\n\
+                                a=fmtp:121 apt=120\r\n\
+                                m=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\n\
+                                c=IN IP4 0.0.0.0\r\n\
+                                a=mid:d\r\n\
+                                a=sctp-port:5000\r\n\
+                                m=audio 0 UDP/TLS/RTP/SAVPF 0\r\n\
+                                c=IN IP4 0.0.0.0\r\n\
+                                a=mid:x\r\n";
+
+    #[test]
+    fn answer() {
+        let mut session = session("local");
+        session.add_transceiver(Transceiver::new("audio", Direction::SendRecv, vec![opus()]));
+        session.add_transceiver(Transceiver::new(
+            "video",
+            Direction::SendRecv,
+            video_codecs(),
+        ));
+        session.add_transceiver(Transceiver::new("audio", Direction::SendRecv, vec![opus()]));
+
+        let offer = REMOTE_OFFER.parse().unwrap();
+        let answer = session.create_answer(&offer).unwrap();
+        assert_eq!(
+            answer.to_string(),
+            "v=0\r\n\
+             o=- 1 0 IN IP4 0.0.0.0\r\n\
+             s=-\r\n\
+             t=0 0\r\n\
+             a=group:BUNDLE a v\r\n\
+             m=audio 9 UDP/TLS/RTP/SAVPF 109\r\n\
+             c=IN IP4 0.0.0.0\r\n\
+             a=ice-ufrag:local\r\n\
+             a=ice-pwd:password\r\n\
+             a=ice-options:trickle\r\n\
+             a=fingerprint:sha-256 AB:AB:AB:AB\r\n\
+             a=setup:active\r\n\
+             a=mid:a\r\n\
+             a=recvonly\r\n\
+             a=rtcp-mux\r\n\
+             a=rtpmap:109 opus/48000/2\r\n\
+             a=rtcp-fb:109 transport-cc\r\n\
+             a=fmtp:109 minptime=10\r\n\
+             m=video 9 UDP/TLS/RTP/SAVPF 120 121\r\n\
+             c=IN IP4 0.0.0.0\r\n\
+             a=ice-ufrag:local\r\n\
+             a=ice-pwd:password\r\n\
+             a=ice-options:trickle\r\n\
+             a=fingerprint:sha-256 AB:AB:AB:AB\r\n\
+             a=setup:active\r\n\
+             a=mid:v\r\n\
+             a=sendrecv\r\n\
+             a=rtcp-mux\r\n\
+             a=rtpmap:120 VP8/90000\r\n\
+             a=rtcp-fb:120 nack pli\r\n\
+             a=rtpmap:121 rtx/90000\r\n\
+             a=fmtp:121 apt=120\r\n\
+             m=application 0 UDP/DTLS/SCTP webrtc-datachannel\r\n\
+             c=IN IP4 0.0.0.0\r\n\
+             a=mid:d\r\n\
+             m=audio 0 UDP/TLS/RTP/SAVPF 0\r\n\
+             c=IN IP4 0.0.0.0\r\n\
+             a=mid:x\r\n"
+        );
+
+        let audio = &session.transceivers[0];
+        assert_eq!(audio.mid, Some("a".to_owned()));
+        assert_eq!(audio.current_direction, Some(Direction::RecvOnly));
+        assert_eq!(audio.negotiated_codecs[0].payload_type, 109);
+        assert_eq!(session.transceivers[1].mid, Some("v".to_owned()));
+        // The port 0 m= line is not associated with the spare transceiver.
+        assert_eq!(session.transceivers[2].mid, None);
+        assert!(!session.transceivers[2].stopped);
+    }
+
+    #[test]
+    fn negotiate() {
+        let mut offerer = session("offerer");
+        let mut answerer = session("answerer");
+        offerer.add_transceiver(Transceiver::new("audio", Direction::SendOnly, vec![opus()]));
+        offerer.add_transceiver(Transceiver::new(
+            "video",
+            Direction::SendRecv,
+            video_codecs(),
+        ));
+        answerer.add_transceiver(Transceiver::new(
+            "video",
+            Direction::SendRecv,
+            video_codecs(),
+        ));
+        answerer.add_transceiver(Transceiver::new("audio", Direction::SendRecv, vec![opus()]));
+
+        let offer = offerer.create_offer();
+        let answer = answerer.create_answer(&offer).unwrap();
+        assert_eq!(mids(&answer), ["0", "1"]);
+        // An answer must keep the media type of each offered m= line.
+        let mut changed = answer.clone();
+        changed.media_descriptions[0].media_information.media_type = "video".to_owned();
+        assert_eq!(
+            offerer.apply_answer(&changed),
+            Err(JsepError::MediaMismatch)
+        );
+        offerer.apply_answer(&answer).unwrap();
+        assert_eq!(offerer.local_description(), Some(&offer));
+        assert_eq!(
+            offerer.transceivers[0].current_direction,
+            Some(Direction::SendOnly)
+        );
+        assert_eq!(
+            answerer.transceivers[1].current_direction,
+            Some(Direction::RecvOnly)
+        );
+        assert_eq!(offerer.transceivers[1].negotiated_codecs, video_codecs());
+        assert_eq!(
+            offerer.apply_answer(&answer),
+            Err(JsepError::NoPendingOffer)
+        );
+
+        // The answerer re-offers, keeping the order and mids, with its audio
+        // stopped and a new transceiver added.
+        answerer.transceivers[1].stopped = true;
+        answerer.add_transceiver(Transceiver::new("audio", Direction::RecvOnly, vec![opus()]));
+        let offer = answerer.create_offer();
+        assert_eq!(mids(&offer), ["0", "1", "2"]);
+        assert_eq!(offer.origin.session_version, 1);
+        assert!(is_rejected(&offer.media_descriptions[0]));
+        assert!(!is_rejected(&offer.media_descriptions[1]));
+
+        let answer = offerer.create_answer(&offer).unwrap();
+        assert_eq!(mids(&answer), ["0", "1", "2"]);
+        assert!(offerer.transceivers[0].stopped);
+        answerer.apply_answer(&answer).unwrap();
+        // The offerer has no transceiver left for the new audio.
+        assert!(answerer.transceivers[2].stopped);
+        assert_eq!(
+            answerer.transceivers[0].current_direction,
+            Some(Direction::SendRecv)
+        );
+
+        // Removing or reordering m= lines is an error.
+        let mut reordered = offer.clone();
+        reordered.media_descriptions.swap(0, 1);
+        assert_eq!(
+            offerer.create_answer(&reordered),
+            Err(JsepError::MediaMismatch)
+        );
+        let mut removed = offer;
+        removed.media_descriptions.pop();
+        assert_eq!(
+            offerer.create_answer(&removed),
+            Err(JsepError::MediaMismatch)
+        );
+        removed.media_descriptions[0].attributes.clear();
+        assert_eq!(
+            offerer.create_answer(&removed),
+            Err(JsepError::MissingMid(0))
+        );
+    }
+
+    #[test]
+    fn glare() {
+        let mut local = session("local");
+        let mut remote = session("remote");
+        local.add_transceiver(Transceiver::new(
+            "video",
+            Direction::SendRecv,
+            video_codecs(),
+        ));
+        local.add_transceiver(Transceiver::new("audio", Direction::SendRecv, vec![opus()]));
+        remote.add_transceiver(Transceiver::new("audio", Direction::SendRecv, vec![opus()]));
+        local.create_offer();
+        assert_eq!(local.transceivers[0].mid, Some("0".to_owned()));
+
+        // The remote offer reuses mid 0 for audio, and discards the local
+        // offer along with its mids.
+        let offer = remote.create_offer();
+        let answer = local.create_answer(&offer).unwrap();
+        assert!(!is_rejected(&answer.media_descriptions[0]));
+        assert_eq!(local.transceivers[0].mid, None);
+        assert!(!local.transceivers[0].stopped);
+        assert_eq!(local.transceivers[1].mid, Some("0".to_owned()));
+        assert_eq!(local.apply_answer(&answer), Err(JsepError::NoPendingOffer));
+
+        // A negotiated mid offered for another media type is rejected.
+        let mut offer = remote.create_offer();
+        offer.media_descriptions[0].media_information.media_type = "video".to_owned();
+        let answer = local.create_answer(&offer).unwrap();
+        assert!(is_rejected(&answer.media_descriptions[0]));
+        assert!(!local.transceivers[0].stopped);
+        assert_eq!(local.transceivers[0].mid, None);
+    }
+}
@@ -0,0 +1,274 @@
+use crate::{ParseSdpError, SessionDescription};
+use std::fmt;
+
+/// A deviation from RFC 4566 that lenient parsing accepted.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct SdpWarning {
+    /// The number of the line, from 1.
+    pub line: usize,
+    pub kind: SdpWarningKind,
+}
+
+/// The kind of an `SdpWarning`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SdpWarningKind {
+    /// The line ends with a bare `\n` rather than `\r\n`.
+    BareLineFeed,
+    /// The last line has no line ending.
+    MissingLineEnding,
+    /// The line ends with whitespace, which was removed.
+    TrailingWhitespace,
+    /// The line is empty, and was skipped.
+    EmptyLine,
+    /// The line comes after lines that must follow it, and was moved before
+    /// them.
+    OutOfOrder,
+}
+
+impl fmt::Display for SdpWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self.kind {
+            SdpWarningKind::BareLineFeed => "line ends with a bare line feed",
+            SdpWarningKind::MissingLineEnding => "line has no line ending",
+            SdpWarningKind::TrailingWhitespace => "trailing whitespace removed",
+            SdpWarningKind::EmptyLine => "empty line skipped",
+            SdpWarningKind::OutOfOrder => "line out of order moved",
+        };
+        write!(f, "line {}: {}", self.line, description)
+    }
+}
+
+impl SessionDescription {
+    /// Parses a session description, tolerating deviations from
+    /// [RFC 4566](https://tools.ietf.org/html/rfc4566#section-5) common in
+    /// the wild: bare `\n` line endings, trailing whitespace, empty lines,
+    /// and lines out of order within the session or a media description.
+    ///
+    /// Returns the description along with a warning for each deviation.
+    /// Its `Display` output is canonical, with `\r\n` line endings and
+    /// lines in order. Errors locate lines in `s` as given.
+    pub fn parse_lenient(s: &str) -> Result<(Self, Vec<SdpWarning>), ParseSdpError> {
+        let mut warnings = Vec::new();
+        let mut warn = |line, kind| warnings.push(SdpWarning { line, kind });
+
+        // The lines kept, with their numbers in `s`.
+        let mut lines = Vec::new();
+        let mut rest = s;
+        let mut number = 0;
+        while !rest.is_empty() {
+            number += 1;
+            let (line, ending) = match rest.find('\n') {
+                Some(i) => {
+                    let line = &rest[..i];
+                    rest = &rest[i + 1..];
+                    if line.ends_with('\r') {
+                        (line.trim_end_matches('\r'), None)
+                    } else {
+                        (line, Some(SdpWarningKind::BareLineFeed))
+                    }
+                }
+                None => {
+                    let line = rest;
+                    rest = "";
+                    (line, Some(SdpWarningKind::MissingLineEnding))
+                }
+            };
+
+            let mut trimmed = line.trim_end();
+            // RFC 4566 recommends `s= ` for an empty session name, whose
+            // space is not trailing whitespace.
+            if trimmed == "s=" && line.len() > trimmed.len() {
+                trimmed = "s= ";
+            }
+            if trimmed.is_empty() {
+                warn(number, SdpWarningKind::EmptyLine);
+                continue;
+            }
+            if let Some(kind) = ending {
+                warn(number, kind);
+            }
+            if trimmed.len() < line.len() {
+                warn(number, SdpWarningKind::TrailingWhitespace);
+            }
+            lines.push((number, trimmed));
+        }
+
+        // Each section is sorted by the rank of its types of line, which
+        // keeps repeat times after their timing.
+        let mut start = 0;
+        while start < lines.len() {
+            let end = lines[start + 1..]
+                .iter()
+                .position(|(_, line)| line.starts_with("m="))
+                .map_or(lines.len(), |i| start + 1 + i);
+            let section = &mut lines[start..end];
+            let media = start > 0 || section[0].1.starts_with("m=");
+
+            let mut ranks = Vec::with_capacity(section.len());
+            for &(number, line) in section.iter() {
+                let previous = ranks.last().cloned().unwrap_or(0);
+                let rank = line_rank(line, media).unwrap_or(previous);
+                if ranks.iter().any(|&other| other > rank) {
+                    warn(number, SdpWarningKind::OutOfOrder);
+                }
+                ranks.push(rank);
+            }
+            let mut ranked: Vec<_> = ranks.into_iter().zip(section.iter().cloned()).collect();
+            ranked.sort_by_key(|&(rank, _)| rank);
+            for (line, (_, ranked)) in section.iter_mut().zip(ranked) {
+                *line = ranked;
+            }
+            start = end;
+        }
+        warnings.sort_by_key(|warning| warning.line);
+
+        let mut canonical = String::new();
+        for (_, line) in &lines {
+            canonical.push_str(line);
+            canonical.push_str("\r\n");
+        }
+        match canonical.parse() {
+            Ok(session) => Ok((session, warnings)),
+            Err(err) => {
+                let ParseSdpError { line, .. } = err;
+                let (line, text) = match lines.get(line - 1) {
+                    Some(&(number, _)) => (number, original_line(s, number)),
+                    None => (number + 1, String::new()),
+                };
+                Err(ParseSdpError { line, text, ..err })
+            }
+        }
+    }
+}
+
+/// Returns the rank of a line in the order of
+/// [RFC 4566](https://tools.ietf.org/html/rfc4566#section-5), in a media
+/// description if `media`, or `None` for an unknown type.
+fn line_rank(line: &str, media: bool) -> Option<u8> {
+    let mut chars = line.chars();
+    let line_type = match (chars.next()?, chars.next()) {
+        (line_type, Some('=')) => line_type,
+        _ => return None,
+    };
+    // Repeat times rank with the timing they follow.
+    let line_type = if line_type == 'r' && !media {
+        't'
+    } else {
+        line_type
+    };
+    let order = if media { "micbka" } else { "vosiuepcbtzka" };
+    order.find(line_type).map(|i| i as u8)
+}
+
+/// Returns line `number` of `s`, without its line ending.
+fn original_line(s: &str, number: usize) -> String {
+    s.split('\n')
+        .nth(number - 1)
+        .unwrap_or_default()
+        .trim_end_matches('\r')
+        .to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LENIENT_SDP: &str = "v=0\n\
+                               o=- 1 1 IN IP4 192.0.2.1 \n\
+                               s=-\r\n\
+                               t=0 0\n\
+                               \n\
+                               a=group:BUNDLE 0\n\
+                               c=IN IP4 192.0.2.1\n\
+                               m=audio 9 UDP/TLS/RTP/SAVPF 111\n\
+                               a=mid:0\n\
+                               b=AS:64\n\
+                               c=IN IP4 0.0.0.0\n\
+                               a=rtpmap:111 opus/48000/2";
+
+    #[test]
+    fn lenient() {
+        assert!(LENIENT_SDP.parse::<SessionDescription>().is_err());
+
+        let (session, warnings) = SessionDescription::parse_lenient(LENIENT_SDP).unwrap();
+        assert_eq!(
+            session.to_string(),
+            "v=0\r\n\
+             o=- 1 1 IN IP4 192.0.2.1\r\n\
+             s=-\r\n\
+             c=IN IP4 192.0.2.1\r\n\
+             t=0 0\r\n\
+             a=group:BUNDLE 0\r\n\
+             m=audio 9 UDP/TLS/RTP/SAVPF 111\r\n\
+             c=IN IP4 0.0.0.0\r\n\
+             b=AS:64\r\n\
+             a=mid:0\r\n\
+             a=rtpmap:111 opus/48000/2\r\n"
+        );
+
+        use SdpWarningKind::*;
+        let warnings: Vec<_> = warnings
+            .iter()
+            .map(|warning| (warning.line, warning.kind))
+            .collect();
+        assert_eq!(
+            warnings,
+            [
+                (1, BareLineFeed),
+                (2, BareLineFeed),
+                (2, TrailingWhitespace),
+                (4, BareLineFeed),
+                (5, EmptyLine),
+                (6, BareLineFeed),
+                (7, BareLineFeed),
+                (7, OutOfOrder),
+                (8, BareLineFeed),
+                (9, BareLineFeed),
+                (10, BareLineFeed),
+                (10, OutOfOrder),
+                (11, BareLineFeed),
+                (11, OutOfOrder),
+                (12, MissingLineEnding),
+            ]
+        );
+
+        // A canonical description parses without warnings.
+        let canonical = session.to_string();
+        let (reparsed, warnings) = SessionDescription::parse_lenient(&canonical).unwrap();
+        assert_eq!(reparsed, session);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn empty_session_name() {
+        let sdp = "v=0\r\no=- 1 1 IN IP4 192.0.2.1\r\ns= \r\nt=0 0\r\n";
+        let (session, warnings) = SessionDescription::parse_lenient(sdp).unwrap();
+        assert_eq!(session, sdp.parse().unwrap());
+        assert!(warnings.is_empty());
+
+        let (session, warnings) =
+            SessionDescription::parse_lenient(&sdp.replace("s= ", "s=  ")).unwrap();
+        assert_eq!(session.to_string(), sdp);
+        assert_eq!(
+            warnings,
+            [SdpWarning {
+                line: 3,
+                kind: SdpWarningKind::TrailingWhitespace,
+            }]
+        );
+    }
+
+    #[test]
+    fn error() {
+        let err = SessionDescription::parse_lenient("v=0\n\no=- x 1 IN IP4 0.0.0.0\ns=-\nt=0 0\n")
+            .unwrap_err();
+        assert_eq!((err.line, err.column), (3, 5));
+        assert_eq!(err.text, "o=- x 1 IN IP4 0.0.0.0");
+        assert_eq!(err.expected, "a valid o= line");
+
+        let err =
+            SessionDescription::parse_lenient("v=0\no=- 1 1 IN IP4 0.0.0.0\ns=-\n").unwrap_err();
+        assert_eq!((err.line, err.text.as_str()), (4, ""));
+        assert_eq!(err.expected, "t= after s=");
+    }
+}
@@ -1,9 +1,12 @@
+mod address;
 mod attribute;
 mod bandwidth;
 mod connection_data;
 mod email_address;
 mod encryption_key;
 mod information;
+mod jsep;
+mod lenient;
 mod media_description;
 mod media_information;
 mod ntp;
@@ -19,28 +22,36 @@ mod timing;
 mod uri;
 mod util;
 mod version;
+mod webrtc_attribute;
 
 #[cfg(test)]
 mod test_util;
 
+pub use self::address::{Address, AddressType, NetworkType};
 pub use self::attribute::Attribute;
 pub use self::bandwidth::Bandwidth;
 pub use self::connection_data::ConnectionData;
 pub use self::email_address::EmailAddress;
 pub use self::encryption_key::EncryptionKey;
 pub use self::information::Information;
+pub use self::jsep::{Codec, JsepError, JsepSession, Transceiver};
+pub use self::lenient::{SdpWarning, SdpWarningKind};
 pub use self::media_description::MediaDescription;
 pub use self::media_information::MediaInformation;
 pub use self::ntp::{Duration, Instant};
 pub use self::origin::Origin;
 pub use self::phone_number::PhoneNumber;
 pub use self::repeat_times::RepeatTimes;
-pub use self::session_description::SessionDescription;
+pub use self::session_description::{ParseSdpError, SessionDescription};
 pub use self::session_name::SessionName;
 pub use self::time_description::TimeDescription;
 pub use self::time_zones::{TimeZone, TimeZones};
 pub use self::timing::Timing;
 pub use self::uri::Uri;
 pub use self::version::Version;
+pub use self::webrtc_attribute::{
+    Direction, Extmap, Fingerprint, Fmtp, Group, Msid, ParseAttributeError, Rtcp, RtcpFb, RtpMap,
+    Setup, Ssrc, SsrcGroup, WebRtcAttribute,
+};
 
 use self::parse::Parse;
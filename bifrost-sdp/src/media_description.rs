@@ -61,6 +61,7 @@ impl Parse for MediaDescription {
 mod tests {
     use super::*;
     use crate::test_util::{assert_err, assert_parse_display};
+    use crate::{Address, AddressType, NetworkType};
     use vec1::vec1;
 
     #[test]
@@ -135,9 +136,11 @@ mod tests {
                 },
                 media_title: Some(Information("good stuff".to_owned())),
                 connection_data: Some(ConnectionData {
-                    network_type: "IN".to_owned(),
-                    address_type: "IP4".to_owned(),
-                    connection_address: "224.2.1.1/127/3".to_owned(),
+                    network_type: NetworkType::Internet,
+                    address_type: AddressType::Ip4,
+                    connection_address: Address::Ip("224.2.1.1".parse().unwrap()),
+                    ttl: Some(127),
+                    address_count: Some(3),
                 }),
                 bandwidths: vec![
                     Bandwidth {
@@ -202,9 +205,11 @@ mod tests {
                 },
                 media_title: None,
                 connection_data: Some(ConnectionData {
-                    network_type: "IN".to_owned(),
-                    address_type: "IP4".to_owned(),
-                    connection_address: "224.2.1.1/127/3".to_owned(),
+                    network_type: NetworkType::Internet,
+                    address_type: AddressType::Ip4,
+                    connection_address: Address::Ip("224.2.1.1".parse().unwrap()),
+                    ttl: Some(127),
+                    address_count: Some(3),
                 }),
                 bandwidths: vec![],
                 encryption_key: None,
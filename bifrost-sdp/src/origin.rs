@@ -1,7 +1,8 @@
 use crate::util;
-use crate::Parse;
-use nom::bytes::complete::tag;
+use crate::{Address, AddressType, NetworkType, Parse};
+use nom::bytes::complete::{is_not, tag};
 use nom::character::complete::line_ending;
+use nom::combinator::map_opt;
 use nom::IResult;
 use std::fmt;
 
@@ -12,9 +13,9 @@ pub struct Origin {
     pub username: String,
     pub session_id: u64,
     pub session_version: u64,
-    pub network_type: String,
-    pub address_type: String,
-    pub unicast_address: String,
+    pub network_type: NetworkType,
+    pub address_type: AddressType,
+    pub unicast_address: Address,
 }
 
 impl fmt::Display for Origin {
@@ -52,7 +53,8 @@ impl Parse for Origin {
         let (rest, address_type) = util::parse_field(rest)?;
         let (rest, _) = tag(" ")(rest)?;
 
-        let (rest, unicast_address) = util::parse_field(rest)?;
+        let (rest, unicast_address) =
+            map_opt(is_not(" \r\n"), |s| Address::parse(&address_type, s))(rest)?;
         let (rest, _) = line_ending(rest)?;
 
         Ok((
@@ -72,7 +74,7 @@ impl Parse for Origin {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::test_util::assert_parse_display;
+    use crate::test_util::{assert_err, assert_parse_display};
 
     #[test]
     fn valid() {
@@ -83,9 +85,9 @@ mod tests {
                 username: "-".to_owned(),
                 session_id: 4_858_251_974_351_650_128,
                 session_version: 2,
-                network_type: "IN".to_owned(),
-                address_type: "IP4".to_owned(),
-                unicast_address: "127.0.0.1".to_owned(),
+                network_type: NetworkType::Internet,
+                address_type: AddressType::Ip4,
+                unicast_address: Address::Ip("127.0.0.1".parse().unwrap()),
             },
             "o=- 4858251974351650128 2 IN IP4 127.0.0.1\r\n",
         );
@@ -97,11 +99,31 @@ mod tests {
                 username: "jdoe".to_owned(),
                 session_id: 2_890_844_526,
                 session_version: 2_890_842_807,
-                network_type: "IN".to_owned(),
-                address_type: "IP4".to_owned(),
-                unicast_address: "10.47.16.5".to_owned(),
+                network_type: NetworkType::Internet,
+                address_type: AddressType::Ip4,
+                unicast_address: Address::Ip("10.47.16.5".parse().unwrap()),
             },
             "o=jdoe 2890844526 2890842807 IN IP4 10.47.16.5\r\n",
         );
+
+        assert_parse_display(
+            "o=- 1 1 IN IP6 host.example.com\r\n",
+            "",
+            &Origin {
+                username: "-".to_owned(),
+                session_id: 1,
+                session_version: 1,
+                network_type: NetworkType::Internet,
+                address_type: AddressType::Ip6,
+                unicast_address: Address::Fqdn("host.example.com".to_owned()),
+            },
+            "o=- 1 1 IN IP6 host.example.com\r\n",
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        assert_err::<Origin>("o=- 1 1 IN IP4 2001:db8::1\r\n");
+        assert_err::<Origin>("o=- 1 1 IN IP4 10.47.16.5/127\r\n");
     }
 }
@@ -3,7 +3,9 @@ use crate::{
     MediaDescription, Origin, Parse, PhoneNumber, SessionName, TimeDescription, TimeZones, Uri,
     Version,
 };
+use nom::error::ErrorKind;
 use nom::IResult;
+use std::error::Error;
 use std::fmt;
 use std::str::FromStr;
 use vec1::Vec1;
@@ -101,22 +103,126 @@ impl Parse for SessionDescription {
     }
 }
 
-#[derive(Debug, PartialEq)]
-pub struct ParseSdpError;
+/// An error parsing a session description, with the line it is on.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseSdpError {
+    /// The number of the line, from 1.
+    pub line: usize,
+    /// The column in the line where parsing failed, in characters from 1.
+    pub column: usize,
+    /// The text of the line, without its line ending.
+    pub text: String,
+    /// What was expected instead, such as `t= after s=`.
+    pub expected: String,
+    /// The kind of error the parser ran into, or `Eof` for a line after a
+    /// complete session description.
+    pub kind: ErrorKind,
+}
+
+impl ParseSdpError {
+    /// Locates an error at `rest`, the part of `input` left where parsing
+    /// failed.
+    fn new(input: &str, rest: &str, kind: ErrorKind) -> Self {
+        let offset = input.len() - rest.len();
+        let start = input[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let end = input[start..].find('\n').map_or(input.len(), |i| start + i);
+        let text = input[start..end].trim_end_matches('\r');
+        let line = input[..start].matches('\n').count() + 1;
+        let column = input[start..offset].chars().count() + 1;
+
+        // The lines before tell which types of line may come next.
+        let types: Vec<_> = input[..start]
+            .lines()
+            .filter_map(|line| line.chars().next())
+            .collect();
+        let previous = types.last().cloned();
+        let media = types.contains(&'m');
+        let (allowed, required) = next_line_types(previous, media);
+
+        let line_type = text.chars().next();
+        let expected = match (line_type, required) {
+            (Some(line_type), _) if column > 1 || allowed.contains(line_type) => {
+                format!("a valid {}= line", line_type)
+            }
+            (_, Some(required)) => match previous {
+                Some(previous) => format!("{}= after {}=", required, previous),
+                None => format!("{}= first", required),
+            },
+            (_, None) => {
+                let allowed: Vec<_> = allowed.chars().map(|c| format!("{}=", c)).collect();
+                format!(
+                    "{} or the end after {}=",
+                    allowed.join(", "),
+                    previous.unwrap_or_default()
+                )
+            }
+        };
+
+        Self {
+            line,
+            column,
+            text: text.to_owned(),
+            expected,
+            kind,
+        }
+    }
+}
+
+impl fmt::Display for ParseSdpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: expected {}, found {:?} ({})",
+            self.line,
+            self.column,
+            self.expected,
+            self.text,
+            self.kind.description()
+        )
+    }
+}
+
+impl Error for ParseSdpError {}
+
+/// Returns the types of line that may follow one of type `previous`, in a
+/// media description if `media`, and the one among them that must come
+/// next, if any, per
+/// [RFC 4566](https://tools.ietf.org/html/rfc4566#section-5).
+fn next_line_types(previous: Option<char>, media: bool) -> (&'static str, Option<char>) {
+    match (previous, media) {
+        (None, _) => ("v", Some('v')),
+        (Some('v'), false) => ("o", Some('o')),
+        (Some('o'), false) => ("s", Some('s')),
+        (Some('s'), false) => ("iuepcbt", Some('t')),
+        (Some('i'), false) => ("uepcbt", Some('t')),
+        (Some('u'), false) => ("epcbt", Some('t')),
+        (Some('e'), false) => ("pcbt", Some('t')),
+        (Some('p'), false) => ("cbt", Some('t')),
+        (Some('c'), false) => ("bt", Some('t')),
+        (Some('b'), false) => ("t", Some('t')),
+        (Some('t'), false) | (Some('r'), false) => ("trzkam", None),
+        (Some('z'), false) => ("kam", None),
+        (Some('k'), false) | (Some('a'), false) => ("am", None),
+        (Some('m'), true) => ("icbkam", None),
+        (Some('i'), true) => ("cbkam", None),
+        (Some('c'), true) | (Some('b'), true) => ("bkam", None),
+        (Some('k'), true) | (Some('a'), true) => ("am", None),
+        _ => ("", None),
+    }
+}
 
 impl FromStr for SessionDescription {
     type Err = ParseSdpError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Self::parse(s)
-            .map_err(|_| ParseSdpError)
-            .and_then(|(rest, value)| {
-                if rest.is_empty() {
-                    Ok(value)
-                } else {
-                    Err(ParseSdpError)
-                }
-            })
+        match Self::parse(s) {
+            Ok(("", value)) => Ok(value),
+            Ok((rest, _)) => Err(ParseSdpError::new(s, rest, ErrorKind::Eof)),
+            Err(nom::Err::Error((rest, kind))) | Err(nom::Err::Failure((rest, kind))) => {
+                Err(ParseSdpError::new(s, rest, kind))
+            }
+            Err(nom::Err::Incomplete(_)) => Err(ParseSdpError::new(s, "", ErrorKind::Complete)),
+        }
     }
 }
 
@@ -124,7 +230,10 @@ impl FromStr for SessionDescription {
 mod tests {
     use super::*;
     use crate::test_util::assert_parse_display;
-    use crate::{Duration, Instant, MediaInformation, RepeatTimes, TimeZone, Timing};
+    use crate::{
+        Address, AddressType, Duration, Instant, MediaInformation, NetworkType, RepeatTimes,
+        TimeZone, Timing,
+    };
     use lazy_static::lazy_static;
     use vec1::vec1;
 
@@ -170,9 +279,9 @@ a=rtpmap:99 h263-1998/90000
                 username: "jdoe".to_owned(),
                 session_id: 2_890_844_526,
                 session_version: 2_890_842_807,
-                network_type: "IN".to_owned(),
-                address_type: "IP4".to_owned(),
-                unicast_address: "10.47.16.5".to_owned(),
+                network_type: NetworkType::Internet,
+                address_type: AddressType::Ip4,
+                unicast_address: Address::Ip("10.47.16.5".parse().unwrap()),
             },
             session_name: SessionName("SDP Seminar".to_owned()),
             session_information: Some(Information(
@@ -184,9 +293,11 @@ a=rtpmap:99 h263-1998/90000
             email_address: Some(EmailAddress("j.doe@example.com (Jane Doe)".to_owned())),
             phone_number: None,
             connection_data: Some(ConnectionData {
-                network_type: "IN".to_owned(),
-                address_type: "IP4".to_owned(),
-                connection_address: "224.2.36.42/127".to_owned(),
+                network_type: NetworkType::Internet,
+                address_type: AddressType::Ip4,
+                connection_address: Address::Ip("224.2.36.42".parse().unwrap()),
+                ttl: Some(127),
+                address_count: None,
             }),
             bandwidth: Some(Bandwidth {
                 experimental: true,
@@ -268,7 +379,7 @@ a=rtpmap:99 h263-1998/90000
             &format!("{}more", EXAMPLE_SDP_INPUT),
             "more",
             &*EXAMPLE_SDP,
-            &EXAMPLE_SDP_OUTPUT,
+            EXAMPLE_SDP_OUTPUT,
         );
     }
 
@@ -282,13 +393,58 @@ a=rtpmap:99 h263-1998/90000
         let invalid_str_1 = "foo";
         assert_eq!(
             invalid_str_1.parse::<SessionDescription>(),
-            Err(ParseSdpError)
+            Err(ParseSdpError {
+                line: 1,
+                column: 1,
+                text: "foo".to_owned(),
+                expected: "v= first".to_owned(),
+                kind: ErrorKind::Tag,
+            })
         );
 
         let invalid_str_2 = format!("{}more", EXAMPLE_SDP_INPUT);
         assert_eq!(
             invalid_str_2.parse::<SessionDescription>(),
-            Err(ParseSdpError)
+            Err(ParseSdpError {
+                line: 17,
+                column: 1,
+                text: "more".to_owned(),
+                expected: "a valid m= line".to_owned(),
+                kind: ErrorKind::Eof,
+            })
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let parse_err = |s: &str| s.parse::<SessionDescription>().unwrap_err();
+
+        let err = parse_err("v=0\r\no=- 1 x IN IP4 0.0.0.0\r\ns=-\r\nt=0 0\r\n");
+        assert_eq!((err.line, err.column), (2, 7));
+        assert_eq!(err.text, "o=- 1 x IN IP4 0.0.0.0");
+        assert_eq!(err.expected, "a valid o= line");
+        assert_eq!(err.kind, ErrorKind::MapRes);
+
+        let err = parse_err("v=0\no=- 1 1 IN IP4 0.0.0.0\ns=-\na=foo\nt=0 0\n");
+        assert_eq!((err.line, err.column), (4, 1));
+        assert_eq!(err.expected, "t= after s=");
+        assert_eq!(
+            err.to_string(),
+            "line 4, column 1: expected t= after s=, found \"a=foo\" (Tag)"
         );
+
+        // An optional line that fails to parse is reported as invalid,
+        // rather than out of place.
+        let err = parse_err("v=0\no=- 1 1 IN IP4 0.0.0.0\ns=-\nc=IN IP4\nt=0 0\n");
+        assert_eq!((err.line, err.text.as_str()), (4, "c=IN IP4"));
+        assert_eq!(err.expected, "a valid c= line");
+
+        let err = parse_err("v=0\no=- 1 1 IN IP4 0.0.0.0\ns=-\nt=0 0\nm=audio 9 RTP/AVP 0\nu=x\n");
+        assert_eq!(err.line, 6);
+        assert_eq!(err.expected, "i=, c=, b=, k=, a=, m= or the end after m=");
+
+        let err = parse_err("v=0\r\n");
+        assert_eq!((err.line, err.column, err.text.as_str()), (2, 1, ""));
+        assert_eq!(err.expected, "o= after v=");
     }
 }
@@ -5,7 +5,7 @@ use nom::IResult;
 use std::str::FromStr;
 
 /// Parses the input until a whitespace or a newline.
-pub fn parse_field<'a, T: From<&'a str>>(input: &'a str) -> IResult<&str, T> {
+pub fn parse_field<'a, T: From<&'a str>>(input: &'a str) -> IResult<&'a str, T> {
     map(is_not(" \r\n"), T::from)(input)
 }
 
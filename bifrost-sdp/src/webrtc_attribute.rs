@@ -0,0 +1,965 @@
+use crate::Attribute;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// An attribute used by WebRTC, defined in
+/// [RFC 8829](https://tools.ietf.org/html/rfc8829#section-5.2.1) and the
+/// documents it refers to, parsed from an `Attribute`.
+///
+/// Attributes of other names, and those that would not format back to the
+/// exact same text, are kept as `Other`, so that converting to and from
+/// `Attribute` is lossless.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WebRtcAttribute {
+    RtpMap(RtpMap),
+    Fmtp(Fmtp),
+    RtcpFb(RtcpFb),
+    Extmap(Extmap),
+    /// `a=mid`, defined in
+    /// [RFC 5888](https://tools.ietf.org/html/rfc5888#section-4).
+    Mid(String),
+    Msid(Msid),
+    Ssrc(Ssrc),
+    SsrcGroup(SsrcGroup),
+    Group(Group),
+    Setup(Setup),
+    Fingerprint(Fingerprint),
+    /// `a=ice-ufrag`, defined in
+    /// [RFC 8839](https://tools.ietf.org/html/rfc8839#section-5.4).
+    IceUfrag(String),
+    /// `a=ice-pwd`, defined in
+    /// [RFC 8839](https://tools.ietf.org/html/rfc8839#section-5.4).
+    IcePwd(String),
+    /// `a=ice-options`, defined in
+    /// [RFC 8839](https://tools.ietf.org/html/rfc8839#section-5.6).
+    IceOptions(Vec<String>),
+    Rtcp(Rtcp),
+    /// `a=rtcp-mux`, defined in
+    /// [RFC 5761](https://tools.ietf.org/html/rfc5761#section-5.1.1).
+    RtcpMux,
+    /// `a=rtcp-rsize`, defined in
+    /// [RFC 5506](https://tools.ietf.org/html/rfc5506#section-5).
+    RtcpRsize,
+    Direction(Direction),
+    Other(Attribute),
+}
+
+impl WebRtcAttribute {
+    pub fn from_attribute(attribute: &Attribute) -> Self {
+        match Self::parse(&attribute.name, attribute.value.as_ref()) {
+            Some(typed) if typed.to_attribute() == *attribute => typed,
+            _ => WebRtcAttribute::Other(attribute.clone()),
+        }
+    }
+
+    fn parse(name: &str, value: Option<&String>) -> Option<Self> {
+        let attribute = match (name, value) {
+            ("rtpmap", Some(value)) => WebRtcAttribute::RtpMap(value.parse().ok()?),
+            ("fmtp", Some(value)) => WebRtcAttribute::Fmtp(value.parse().ok()?),
+            ("rtcp-fb", Some(value)) => WebRtcAttribute::RtcpFb(value.parse().ok()?),
+            ("extmap", Some(value)) => WebRtcAttribute::Extmap(value.parse().ok()?),
+            ("mid", Some(value)) if !value.is_empty() => WebRtcAttribute::Mid(value.clone()),
+            ("msid", Some(value)) => WebRtcAttribute::Msid(value.parse().ok()?),
+            ("ssrc", Some(value)) => WebRtcAttribute::Ssrc(value.parse().ok()?),
+            ("ssrc-group", Some(value)) => WebRtcAttribute::SsrcGroup(value.parse().ok()?),
+            ("group", Some(value)) => WebRtcAttribute::Group(value.parse().ok()?),
+            ("setup", Some(value)) => WebRtcAttribute::Setup(value.parse().ok()?),
+            ("fingerprint", Some(value)) => WebRtcAttribute::Fingerprint(value.parse().ok()?),
+            ("ice-ufrag", Some(value)) if !value.is_empty() => {
+                WebRtcAttribute::IceUfrag(value.clone())
+            }
+            ("ice-pwd", Some(value)) if !value.is_empty() => WebRtcAttribute::IcePwd(value.clone()),
+            ("ice-options", Some(value)) => {
+                WebRtcAttribute::IceOptions(value.split(' ').map(String::from).collect())
+            }
+            ("rtcp", Some(value)) => WebRtcAttribute::Rtcp(value.parse().ok()?),
+            ("rtcp-mux", None) => WebRtcAttribute::RtcpMux,
+            ("rtcp-rsize", None) => WebRtcAttribute::RtcpRsize,
+            (name, None) => WebRtcAttribute::Direction(name.parse().ok()?),
+            _ => return None,
+        };
+        Some(attribute)
+    }
+
+    pub fn to_attribute(&self) -> Attribute {
+        let (name, value) = match self {
+            WebRtcAttribute::RtpMap(rtpmap) => ("rtpmap", Some(rtpmap.to_string())),
+            WebRtcAttribute::Fmtp(fmtp) => ("fmtp", Some(fmtp.to_string())),
+            WebRtcAttribute::RtcpFb(rtcp_fb) => ("rtcp-fb", Some(rtcp_fb.to_string())),
+            WebRtcAttribute::Extmap(extmap) => ("extmap", Some(extmap.to_string())),
+            WebRtcAttribute::Mid(mid) => ("mid", Some(mid.clone())),
+            WebRtcAttribute::Msid(msid) => ("msid", Some(msid.to_string())),
+            WebRtcAttribute::Ssrc(ssrc) => ("ssrc", Some(ssrc.to_string())),
+            WebRtcAttribute::SsrcGroup(group) => ("ssrc-group", Some(group.to_string())),
+            WebRtcAttribute::Group(group) => ("group", Some(group.to_string())),
+            WebRtcAttribute::Setup(setup) => ("setup", Some(setup.to_string())),
+            WebRtcAttribute::Fingerprint(fingerprint) => {
+                ("fingerprint", Some(fingerprint.to_string()))
+            }
+            WebRtcAttribute::IceUfrag(ufrag) => ("ice-ufrag", Some(ufrag.clone())),
+            WebRtcAttribute::IcePwd(pwd) => ("ice-pwd", Some(pwd.clone())),
+            WebRtcAttribute::IceOptions(options) => ("ice-options", Some(options.join(" "))),
+            WebRtcAttribute::Rtcp(rtcp) => ("rtcp", Some(rtcp.to_string())),
+            WebRtcAttribute::RtcpMux => ("rtcp-mux", None),
+            WebRtcAttribute::RtcpRsize => ("rtcp-rsize", None),
+            WebRtcAttribute::Direction(direction) => (direction.as_str(), None),
+            WebRtcAttribute::Other(attribute) => return attribute.clone(),
+        };
+        Attribute {
+            name: name.to_owned(),
+            value,
+        }
+    }
+}
+
+impl From<&Attribute> for WebRtcAttribute {
+    fn from(attribute: &Attribute) -> Self {
+        Self::from_attribute(attribute)
+    }
+}
+
+impl From<&WebRtcAttribute> for Attribute {
+    fn from(attribute: &WebRtcAttribute) -> Self {
+        attribute.to_attribute()
+    }
+}
+
+/// Formats the attribute as an `a=` line.
+impl fmt::Display for WebRtcAttribute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.to_attribute().fmt(f)
+    }
+}
+
+/// An error parsing the value of a WebRTC attribute.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ParseAttributeError;
+
+impl fmt::Display for ParseAttributeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid attribute value")
+    }
+}
+
+impl Error for ParseAttributeError {}
+
+fn parse<T: FromStr>(s: &str) -> Result<T, ParseAttributeError> {
+    s.parse().map_err(|_| ParseAttributeError)
+}
+
+/// Splits `s` at its first occurrence of `separator`.
+fn split_once(s: &str, separator: char) -> Option<(&str, &str)> {
+    let i = s.find(separator)?;
+    Some((&s[..i], &s[i + separator.len_utf8()..]))
+}
+
+/// `a=rtpmap:<payload type> <encoding name>/<clock rate>[/<channels>]`,
+/// defined in [RFC 4566](https://tools.ietf.org/html/rfc4566#section-6).
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct RtpMap {
+    pub payload_type: u8,
+    pub encoding_name: String,
+    pub clock_rate: u32,
+    /// The number of audio channels.
+    pub channels: Option<u16>,
+}
+
+impl FromStr for RtpMap {
+    type Err = ParseAttributeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (payload_type, encoding) = split_once(s, ' ').ok_or(ParseAttributeError)?;
+        let mut fields = encoding.split('/');
+        let encoding_name = fields.next().ok_or(ParseAttributeError)?;
+        let clock_rate = parse(fields.next().ok_or(ParseAttributeError)?)?;
+        let channels = fields.next().map(parse).transpose()?;
+        if encoding_name.is_empty() || fields.next().is_some() {
+            return Err(ParseAttributeError);
+        }
+        Ok(Self {
+            payload_type: parse(payload_type)?,
+            encoding_name: encoding_name.to_owned(),
+            clock_rate,
+            channels,
+        })
+    }
+}
+
+impl fmt::Display for RtpMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}/{}",
+            self.payload_type, self.encoding_name, self.clock_rate
+        )?;
+        if let Some(channels) = self.channels {
+            write!(f, "/{}", channels)?;
+        }
+        Ok(())
+    }
+}
+
+/// `a=fmtp:<payload type> <parameters>`, defined in
+/// [RFC 4566](https://tools.ietf.org/html/rfc4566#section-6), with the
+/// parameters separated by `;`, most of which are `<name>=<value>`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Fmtp {
+    pub payload_type: u8,
+    pub parameters: Vec<(String, Option<String>)>,
+    /// Whether the parameters are separated by `; ` rather than `;`.
+    pub spaced: bool,
+}
+
+impl Fmtp {
+    /// Returns the value of the parameter `name`.
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters
+            .iter()
+            .find(|(other, _)| other == name)
+            .and_then(|(_, value)| value.as_ref().map(String::as_str))
+    }
+}
+
+impl FromStr for Fmtp {
+    type Err = ParseAttributeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (payload_type, parameters) = split_once(s, ' ').ok_or(ParseAttributeError)?;
+        let spaced = parameters.contains("; ");
+        let parameters = parameters
+            .split(';')
+            .map(|parameter| match split_once(parameter, '=') {
+                Some((name, value)) => (name.trim().to_owned(), Some(value.trim().to_owned())),
+                None => (parameter.trim().to_owned(), None),
+            })
+            .collect();
+        Ok(Self {
+            payload_type: parse(payload_type)?,
+            parameters,
+            spaced,
+        })
+    }
+}
+
+impl fmt::Display for Fmtp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ", self.payload_type)?;
+        for (i, (name, value)) in self.parameters.iter().enumerate() {
+            if i > 0 {
+                f.write_str(if self.spaced { "; " } else { ";" })?;
+            }
+            f.write_str(name)?;
+            if let Some(value) = value {
+                write!(f, "={}", value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `a=rtcp-fb:<payload type> <type> [<parameters>]`, defined in
+/// [RFC 4585](https://tools.ietf.org/html/rfc4585#section-4.2).
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct RtcpFb {
+    /// The payload type the feedback applies to, or `None` for all of them.
+    pub payload_type: Option<u8>,
+    pub feedback_type: String,
+    pub parameters: Option<String>,
+}
+
+impl FromStr for RtcpFb {
+    type Err = ParseAttributeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (payload_type, feedback) = split_once(s, ' ').ok_or(ParseAttributeError)?;
+        let payload_type = match payload_type {
+            "*" => None,
+            payload_type => Some(parse(payload_type)?),
+        };
+        let (feedback_type, parameters) = match split_once(feedback, ' ') {
+            Some((feedback_type, parameters)) => (feedback_type, Some(parameters.to_owned())),
+            None => (feedback, None),
+        };
+        if feedback_type.is_empty() {
+            return Err(ParseAttributeError);
+        }
+        Ok(Self {
+            payload_type,
+            feedback_type: feedback_type.to_owned(),
+            parameters,
+        })
+    }
+}
+
+impl fmt::Display for RtcpFb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.payload_type {
+            Some(payload_type) => write!(f, "{} {}", payload_type, self.feedback_type)?,
+            None => write!(f, "* {}", self.feedback_type)?,
+        }
+        if let Some(parameters) = &self.parameters {
+            write!(f, " {}", parameters)?;
+        }
+        Ok(())
+    }
+}
+
+/// `a=extmap:<id>[/<direction>] <URI> [<attributes>]`, defined in
+/// [RFC 8285](https://tools.ietf.org/html/rfc8285#section-8).
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Extmap {
+    pub id: u16,
+    pub direction: Option<Direction>,
+    pub uri: String,
+    pub attributes: Option<String>,
+}
+
+impl FromStr for Extmap {
+    type Err = ParseAttributeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (id, rest) = split_once(s, ' ').ok_or(ParseAttributeError)?;
+        let (id, direction) = match split_once(id, '/') {
+            Some((id, direction)) => (id, Some(parse(direction)?)),
+            None => (id, None),
+        };
+        let (uri, attributes) = match split_once(rest, ' ') {
+            Some((uri, attributes)) => (uri, Some(attributes.to_owned())),
+            None => (rest, None),
+        };
+        if uri.is_empty() {
+            return Err(ParseAttributeError);
+        }
+        Ok(Self {
+            id: parse(id)?,
+            direction,
+            uri: uri.to_owned(),
+            attributes,
+        })
+    }
+}
+
+impl fmt::Display for Extmap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.id)?;
+        if let Some(direction) = self.direction {
+            write!(f, "/{}", direction)?;
+        }
+        write!(f, " {}", self.uri)?;
+        if let Some(attributes) = &self.attributes {
+            write!(f, " {}", attributes)?;
+        }
+        Ok(())
+    }
+}
+
+/// `a=msid:<stream id> [<track id>]`, defined in
+/// [RFC 8830](https://tools.ietf.org/html/rfc8830#section-2).
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Msid {
+    pub stream_id: String,
+    pub track_id: Option<String>,
+}
+
+impl FromStr for Msid {
+    type Err = ParseAttributeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<_> = s.split(' ').collect();
+        if fields.iter().any(|field| field.is_empty()) {
+            return Err(ParseAttributeError);
+        }
+        match fields[..] {
+            [stream_id] => Ok(Self {
+                stream_id: stream_id.to_owned(),
+                track_id: None,
+            }),
+            [stream_id, track_id] => Ok(Self {
+                stream_id: stream_id.to_owned(),
+                track_id: Some(track_id.to_owned()),
+            }),
+            _ => Err(ParseAttributeError),
+        }
+    }
+}
+
+impl fmt::Display for Msid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.stream_id)?;
+        if let Some(track_id) = &self.track_id {
+            write!(f, " {}", track_id)?;
+        }
+        Ok(())
+    }
+}
+
+/// `a=ssrc:<SSRC> <attribute>[:<value>]`, defined in
+/// [RFC 5576](https://tools.ietf.org/html/rfc5576#section-4.1).
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Ssrc {
+    pub ssrc: u32,
+    pub attribute: String,
+    pub value: Option<String>,
+}
+
+impl FromStr for Ssrc {
+    type Err = ParseAttributeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (ssrc, attribute) = split_once(s, ' ').ok_or(ParseAttributeError)?;
+        let (attribute, value) = match split_once(attribute, ':') {
+            Some((attribute, value)) => (attribute, Some(value.to_owned())),
+            None => (attribute, None),
+        };
+        if attribute.is_empty() {
+            return Err(ParseAttributeError);
+        }
+        Ok(Self {
+            ssrc: parse(ssrc)?,
+            attribute: attribute.to_owned(),
+            value,
+        })
+    }
+}
+
+impl fmt::Display for Ssrc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.ssrc, self.attribute)?;
+        if let Some(value) = &self.value {
+            write!(f, ":{}", value)?;
+        }
+        Ok(())
+    }
+}
+
+/// `a=ssrc-group:<semantics> <SSRC>...`, defined in
+/// [RFC 5576](https://tools.ietf.org/html/rfc5576#section-4.2).
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct SsrcGroup {
+    pub semantics: String,
+    pub ssrcs: Vec<u32>,
+}
+
+impl FromStr for SsrcGroup {
+    type Err = ParseAttributeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.split(' ');
+        let semantics = fields.next().unwrap_or_default();
+        if semantics.is_empty() {
+            return Err(ParseAttributeError);
+        }
+        Ok(Self {
+            semantics: semantics.to_owned(),
+            ssrcs: fields.map(parse).collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl fmt::Display for SsrcGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.semantics)?;
+        self.ssrcs
+            .iter()
+            .try_for_each(|ssrc| write!(f, " {}", ssrc))
+    }
+}
+
+/// `a=group:<semantics> <mid>...`, defined in
+/// [RFC 5888](https://tools.ietf.org/html/rfc5888#section-5), such as the
+/// BUNDLE group.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Group {
+    pub semantics: String,
+    pub mids: Vec<String>,
+}
+
+impl FromStr for Group {
+    type Err = ParseAttributeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.split(' ');
+        let semantics = fields.next().unwrap_or_default();
+        if semantics.is_empty() {
+            return Err(ParseAttributeError);
+        }
+        Ok(Self {
+            semantics: semantics.to_owned(),
+            mids: fields.map(String::from).collect(),
+        })
+    }
+}
+
+impl fmt::Display for Group {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.semantics)?;
+        self.mids.iter().try_for_each(|mid| write!(f, " {}", mid))
+    }
+}
+
+/// `a=setup`, the DTLS role of an endpoint, defined in
+/// [RFC 4145](https://tools.ietf.org/html/rfc4145#section-4).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Setup {
+    Active,
+    Passive,
+    ActPass,
+    HoldConn,
+}
+
+impl FromStr for Setup {
+    type Err = ParseAttributeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "active" => Ok(Setup::Active),
+            "passive" => Ok(Setup::Passive),
+            "actpass" => Ok(Setup::ActPass),
+            "holdconn" => Ok(Setup::HoldConn),
+            _ => Err(ParseAttributeError),
+        }
+    }
+}
+
+impl fmt::Display for Setup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Setup::Active => "active",
+            Setup::Passive => "passive",
+            Setup::ActPass => "actpass",
+            Setup::HoldConn => "holdconn",
+        })
+    }
+}
+
+/// `a=fingerprint:<hash function> <fingerprint>`, of the certificate used
+/// for DTLS, defined in
+/// [RFC 8122](https://tools.ietf.org/html/rfc8122#section-5). The
+/// fingerprint is written as uppercase hexadecimal bytes separated by `:`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Fingerprint {
+    pub hash_function: String,
+    pub fingerprint: Vec<u8>,
+}
+
+impl FromStr for Fingerprint {
+    type Err = ParseAttributeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hash_function, fingerprint) = split_once(s, ' ').ok_or(ParseAttributeError)?;
+        let fingerprint = fingerprint
+            .split(':')
+            .map(|byte| match byte.len() {
+                2 => u8::from_str_radix(byte, 16).map_err(|_| ParseAttributeError),
+                _ => Err(ParseAttributeError),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            hash_function: hash_function.to_owned(),
+            fingerprint,
+        })
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ", self.hash_function)?;
+        for (i, byte) in self.fingerprint.iter().enumerate() {
+            if i > 0 {
+                f.write_str(":")?;
+            }
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// `a=rtcp:<port> [<network type> <address type> <address>]`, defined in
+/// [RFC 3605](https://tools.ietf.org/html/rfc3605#section-2.1).
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Rtcp {
+    pub port: u16,
+    /// The network type, address type and address, if any.
+    pub address: Option<(String, String, String)>,
+}
+
+impl FromStr for Rtcp {
+    type Err = ParseAttributeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<_> = s.split(' ').collect();
+        let address = match fields[1..] {
+            [] => None,
+            [network_type, address_type, address] => Some((
+                network_type.to_owned(),
+                address_type.to_owned(),
+                address.to_owned(),
+            )),
+            _ => return Err(ParseAttributeError),
+        };
+        Ok(Self {
+            port: parse(fields[0])?,
+            address,
+        })
+    }
+}
+
+impl fmt::Display for Rtcp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.port)?;
+        if let Some((network_type, address_type, address)) = &self.address {
+            write!(f, " {} {} {}", network_type, address_type, address)?;
+        }
+        Ok(())
+    }
+}
+
+/// The direction of a media stream, defined in
+/// [RFC 4566](https://tools.ietf.org/html/rfc4566#section-6).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Direction {
+    SendRecv,
+    SendOnly,
+    RecvOnly,
+    Inactive,
+}
+
+impl Direction {
+    pub fn from_flags(sends: bool, receives: bool) -> Self {
+        match (sends, receives) {
+            (true, true) => Direction::SendRecv,
+            (true, false) => Direction::SendOnly,
+            (false, true) => Direction::RecvOnly,
+            (false, false) => Direction::Inactive,
+        }
+    }
+
+    pub fn sends(self) -> bool {
+        self == Direction::SendRecv || self == Direction::SendOnly
+    }
+
+    pub fn receives(self) -> bool {
+        self == Direction::SendRecv || self == Direction::RecvOnly
+    }
+
+    /// Returns the direction as seen from the other end.
+    pub fn reverse(self) -> Self {
+        Self::from_flags(self.receives(), self.sends())
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Direction::SendRecv => "sendrecv",
+            Direction::SendOnly => "sendonly",
+            Direction::RecvOnly => "recvonly",
+            Direction::Inactive => "inactive",
+        }
+    }
+}
+
+impl FromStr for Direction {
+    type Err = ParseAttributeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sendrecv" => Ok(Direction::SendRecv),
+            "sendonly" => Ok(Direction::SendOnly),
+            "recvonly" => Ok(Direction::RecvOnly),
+            "inactive" => Ok(Direction::Inactive),
+            _ => Err(ParseAttributeError),
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SessionDescription;
+
+    const CHROME_SDP: &str = "v=0\r\n\
+                              o=- 4611731400430051336 2 IN IP4 127.0.0.1\r\n\
+                              s=-\r\n\
+                              t=0 0\r\n\
+                              a=group:BUNDLE 0 1\r\n\
+                              a=extmap-allow-mixed\r\n\
+                              a=msid-semantic: WMS stream\r\n\
+                              m=audio 9 UDP/TLS/RTP/SAVPF 111 103 126\r\n\
+                              c=IN IP4 0.0.0.0\r\n\
+                              a=rtcp:9 IN IP4 0.0.0.0\r\n\
+                              a=ice-ufrag:Bz8L\r\n\
+                              a=ice-pwd:c1H/1Uc8Pmsv/8ZzaeQ/9tOn\r\n\
+                              a=ice-options:trickle\r\n\
+                              a=fingerprint:sha-256 7B:8B:F0:65:5F:78:E2:51:3B:AC:6F:F3:3F:46:1B:35:DC:B8:5F:64:1A:24:C2:43:F0:A1:58:D0:A1:2C:19:08\r\n\
+                              a=setup:actpass\r\n\
+                              a=mid:0\r\n\
+                              a=extmap:1 urn:ietf:params:rtp-hdrext:ssrc-audio-level\r\n\
+                              a=extmap:2 http://www.webrtc.org/experiments/rtp-hdrext/abs-send-time\r\n\
+                              a=sendrecv\r\n\
+                              a=msid:stream track0\r\n\
+                              a=rtcp-mux\r\n\
+                              a=rtpmap:111 opus/48000/2\r\n\
+                              a=rtcp-fb:111 transport-cc\r\n\
+                              a=fmtp:111 minptime=10;useinbandfec=1\r\n\
+                              a=rtpmap:103 ISAC/16000\r\n\
+                              a=rtpmap:126 telephone-event/8000\r\n\
+                              a=ssrc:3735928559 cname:4TOk42mSjXCkVIa6\r\n\
+                              a=ssrc:3735928559 msid:stream track0\r\n\
+                              m=video 9 UDP/TLS/RTP/SAVPF 96 97\r\n\
+                              c=IN IP4 0.0.0.0\r\n\
+                              a=rtcp:9 IN IP4 0.0.0.0\r\n\
+                              a=ice-ufrag:Bz8L\r\n\
+                              a=ice-pwd:c1H/1Uc8Pmsv/8ZzaeQ/9tOn\r\n\
+                              a=ice-options:trickle\r\n\
+                              a=fingerprint:sha-256 7B:8B:F0:65:5F:78:E2:51:3B:AC:6F:F3:3F:46:1B:35:DC:B8:5F:64:1A:24:C2:43:F0:A1:58:D0:A1:2C:19:08\r\n\
+                              a=setup:actpass\r\n\
+                              a=mid:1\r\n\
+                              a=extmap:14 urn:ietf:params:rtp-hdrext:toffset\r\n\
+                              a=sendrecv\r\n\
+                              a=msid:stream track1\r\n\
+                              a=rtcp-mux\r\n\
+                              a=rtcp-rsize\r\n\
+                              a=rtpmap:96 VP8/90000\r\n\
+                              a=rtcp-fb:96 goog-remb\r\n\
+                              a=rtcp-fb:96 ccm fir\r\n\
+                              a=rtcp-fb:96 nack\r\n\
+                              a=rtcp-fb:96 nack pli\r\n\
+                              a=rtpmap:97 rtx/90000\r\n\
+                              a=fmtp:97 apt=96\r\n\
+                              a=ssrc-group:FID 2231627014 632943048\r\n\
+                              a=ssrc:2231627014 cname:4TOk42mSjXCkVIa6\r\n\
+                              a=ssrc:632943048 cname:4TOk42mSjXCkVIa6\r\n";
+
+    const FIREFOX_SDP: &str = "v=0\r\n\
+                               o=mozilla...THIS_IS_SDPARTA-68.0 5127301479449484386 0 IN IP4 0.0.0.0\r\n\
+                               s=-\r\n\
+                               t=0 0\r\n\
+                               a=sendrecv\r\n\
+                               a=fingerprint:sha-256 3A:2D:1C:6F:55:2C:9B:3E:80:17:27:D7:8D:E5:1A:24:3B:9D:7E:16:CA:0B:91:75:5E:29:8D:4F:E6:39:7F:C2\r\n\
+                               a=group:BUNDLE 0 1\r\n\
+                               a=ice-options:trickle\r\n\
+                               a=msid-semantic:WMS *\r\n\
+                               m=audio 9 UDP/TLS/RTP/SAVPF 109 9 0 8 101\r\n\
+                               c=IN IP4 0.0.0.0\r\n\
+                               a=sendrecv\r\n\
+                               a=extmap:1 urn:ietf:params:rtp-hdrext:ssrc-audio-level\r\n\
+                               a=extmap:2/recvonly urn:ietf:params:rtp-hdrext:csrc-audio-level\r\n\
+                               a=extmap:3 urn:ietf:params:rtp-hdrext:sdes:mid\r\n\
+                               a=fmtp:109 maxplaybackrate=48000;stereo=1;useinbandfec=1\r\n\
+                               a=fmtp:101 0-15\r\n\
+                               a=ice-pwd:b7f1d41d2e4c4d2e8a1b9f6a2d3c4e5f\r\n\
+                               a=ice-ufrag:8f3a1c2b\r\n\
+                               a=mid:0\r\n\
+                               a=msid:{7c0b1b48-1b0e-4d3c-9f2e-0a6a5b3e1f2d} {4e3c5c1a-2b6f-4f55-b5e2-9a0d6f1c3b7e}\r\n\
+                               a=rtcp-mux\r\n\
+                               a=rtpmap:109 opus/48000/2\r\n\
+                               a=rtpmap:9 G722/8000/1\r\n\
+                               a=rtpmap:0 PCMU/8000\r\n\
+                               a=rtpmap:8 PCMA/8000\r\n\
+                               a=rtpmap:101 telephone-event/8000\r\n\
+                               a=setup:actpass\r\n\
+                               a=ssrc:2655508255 cname:{d1e2a3b4-c5d6-4e7f-8a9b-0c1d2e3f4a5b}\r\n\
+                               m=video 9 UDP/TLS/RTP/SAVPF 120 124 121 125 126 127 97 98\r\n\
+                               c=IN IP4 0.0.0.0\r\n\
+                               a=recvonly\r\n\
+                               a=extmap:3 urn:ietf:params:rtp-hdrext:sdes:mid\r\n\
+                               a=extmap:4 http://www.webrtc.org/experiments/rtp-hdrext/abs-send-time\r\n\
+                               a=extmap:5 urn:ietf:params:rtp-hdrext:toffset\r\n\
+                               a=fmtp:126 profile-level-id=42e01f;level-asymmetry-allowed=1;packetization-mode=1\r\n\
+                               a=fmtp:97 profile-level-id=42e01f;level-asymmetry-allowed=1\r\n\
+                               a=fmtp:120 max-fs=12288;max-fr=60\r\n\
+                               a=fmtp:124 apt=120\r\n\
+                               a=ice-pwd:b7f1d41d2e4c4d2e8a1b9f6a2d3c4e5f\r\n\
+                               a=ice-ufrag:8f3a1c2b\r\n\
+                               a=mid:1\r\n\
+                               a=rtcp-fb:120 nack\r\n\
+                               a=rtcp-fb:120 nack pli\r\n\
+                               a=rtcp-fb:120 ccm fir\r\n\
+                               a=rtcp-fb:120 goog-remb\r\n\
+                               a=rtcp-mux\r\n\
+                               a=rtcp-rsize\r\n\
+                               a=rtpmap:120 VP8/90000\r\n\
+                               a=rtpmap:124 rtx/90000\r\n\
+                               a=rtpmap:121 VP9/90000\r\n\
+                               a=rtpmap:125 rtx/90000\r\n\
+                               a=rtpmap:126 H264/90000\r\n\
+                               a=rtpmap:127 rtx/90000\r\n\
+                               a=rtpmap:97 H264/90000\r\n\
+                               a=rtpmap:98 rtx/90000\r\n\
+                               a=setup:actpass\r\n\
+                               a=ssrc:3006093493 cname:{d1e2a3b4-c5d6-4e7f-8a9b-0c1d2e3f4a5b}\r\n\
+                               a=ssrc:1766231484 cname:{d1e2a3b4-c5d6-4e7f-8a9b-0c1d2e3f4a5b}\r\n\
+                               a=ssrc-group:FID 3006093493 1766231484\r\n";
+
+    fn attribute(name: &str, value: Option<&str>) -> Attribute {
+        Attribute {
+            name: name.to_owned(),
+            value: value.map(String::from),
+        }
+    }
+
+    /// Converts each attribute of `sdp` to a typed one and back, checking
+    /// that only those of names not covered are kept as `Other`.
+    fn assert_round_trip(sdp: &str) {
+        let mut session: SessionDescription = sdp.parse().unwrap();
+        let attributes = session.attributes.iter_mut().chain(
+            session
+                .media_descriptions
+                .iter_mut()
+                .flat_map(|media| media.attributes.iter_mut()),
+        );
+        for attribute in attributes {
+            let typed = WebRtcAttribute::from(&*attribute);
+            match &typed {
+                WebRtcAttribute::Other(other) => assert!(
+                    ["extmap-allow-mixed", "msid-semantic"].contains(&other.name.as_str()),
+                    "{:?} kept as Other",
+                    other
+                ),
+                typed => assert_eq!(typed.to_string(), attribute.to_string()),
+            }
+            *attribute = typed.to_attribute();
+        }
+        assert_eq!(session.to_string(), sdp);
+    }
+
+    #[test]
+    fn chrome() {
+        assert_round_trip(CHROME_SDP);
+    }
+
+    #[test]
+    fn firefox() {
+        assert_round_trip(FIREFOX_SDP);
+    }
+
+    #[test]
+    fn typed() {
+        let typed = |name, value| WebRtcAttribute::from(&attribute(name, value));
+
+        assert_eq!(
+            typed("rtpmap", Some("111 opus/48000/2")),
+            WebRtcAttribute::RtpMap(RtpMap {
+                payload_type: 111,
+                encoding_name: "opus".to_owned(),
+                clock_rate: 48000,
+                channels: Some(2),
+            })
+        );
+        match typed("fmtp", Some("101 0-15")) {
+            WebRtcAttribute::Fmtp(fmtp) => {
+                assert_eq!(fmtp.parameters, [("0-15".to_owned(), None)]);
+                assert_eq!(fmtp.parameter("0-15"), None);
+            }
+            other => panic!("{:?}", other),
+        }
+        match typed("fmtp", Some("97 apt=96")) {
+            WebRtcAttribute::Fmtp(fmtp) => assert_eq!(fmtp.parameter("apt"), Some("96")),
+            other => panic!("{:?}", other),
+        }
+        match typed("fmtp", Some("111 minptime=10; useinbandfec=1")) {
+            WebRtcAttribute::Fmtp(fmtp) => {
+                assert!(fmtp.spaced);
+                assert_eq!(fmtp.parameter("useinbandfec"), Some("1"));
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(
+            typed("rtcp-fb", Some("* nack pli")),
+            WebRtcAttribute::RtcpFb(RtcpFb {
+                payload_type: None,
+                feedback_type: "nack".to_owned(),
+                parameters: Some("pli".to_owned()),
+            })
+        );
+        assert_eq!(
+            typed("extmap", Some("2/recvonly urn:x")),
+            WebRtcAttribute::Extmap(Extmap {
+                id: 2,
+                direction: Some(Direction::RecvOnly),
+                uri: "urn:x".to_owned(),
+                attributes: None,
+            })
+        );
+        assert_eq!(
+            typed("ssrc", Some("1 cname:a:b")),
+            WebRtcAttribute::Ssrc(Ssrc {
+                ssrc: 1,
+                attribute: "cname".to_owned(),
+                value: Some("a:b".to_owned()),
+            })
+        );
+        assert_eq!(
+            typed("group", Some("BUNDLE")),
+            WebRtcAttribute::Group(Group {
+                semantics: "BUNDLE".to_owned(),
+                mids: vec![],
+            })
+        );
+        assert_eq!(
+            typed("setup", Some("passive")),
+            WebRtcAttribute::Setup(Setup::Passive)
+        );
+        assert_eq!(
+            typed("fingerprint", Some("sha-1 0A:FF")),
+            WebRtcAttribute::Fingerprint(Fingerprint {
+                hash_function: "sha-1".to_owned(),
+                fingerprint: vec![0x0a, 0xff],
+            })
+        );
+        assert_eq!(
+            typed("rtcp", Some("53020")),
+            WebRtcAttribute::Rtcp(Rtcp {
+                port: 53020,
+                address: None,
+            })
+        );
+        assert_eq!(
+            typed("ice-options", Some("trickle ice2")),
+            WebRtcAttribute::IceOptions(vec!["trickle".to_owned(), "ice2".to_owned()])
+        );
+        assert_eq!(typed("rtcp-mux", None), WebRtcAttribute::RtcpMux);
+        assert_eq!(
+            typed("inactive", None),
+            WebRtcAttribute::Direction(Direction::Inactive)
+        );
+
+        assert_eq!(
+            WebRtcAttribute::Mid("audio".to_owned()).to_string(),
+            "a=mid:audio\r\n"
+        );
+    }
+
+    #[test]
+    fn other() {
+        let other = |name, value| {
+            let attribute = attribute(name, value);
+            assert_eq!(
+                WebRtcAttribute::from(&attribute),
+                WebRtcAttribute::Other(attribute)
+            );
+        };
+
+        other("candidate", Some("1 1 udp 1 192.0.2.1 9 typ host"));
+        other("rtpmap", Some("x opus/48000"));
+        other("rtpmap", Some("111 opus"));
+        other("rtpmap", None);
+        other("rtcp-mux", Some("1"));
+        other("setup", Some("both"));
+        other("rtcp", Some("9 IN IP4"));
+        other("ssrc-group", Some("FID 1 x"));
+        other("extmap", Some("1/both urn:x"));
+        other("msid", Some("stream  track"));
+        other("msid", Some("stream track extra"));
+        // These would not format back to the same text.
+        other("fingerprint", Some("sha-256 0a:ff"));
+        other("rtpmap", Some("111 opus/048000"));
+        other("fmtp", Some("111 minptime=10; useinbandfec=1;stereo=1"));
+    }
+}
@@ -1,20 +1,30 @@
-use crate::message::{Message, TransactionId};
-use std::collections::HashMap;
+mod transactions;
+
+pub use self::transactions::{
+    Sleep, Timer, Transaction, Transactions, DEFAULT_MAX_REQUESTS, DEFAULT_RTO, DEFAULT_TIMEOUT,
+};
+
+use crate::message::attribute::XorMappedAddress;
+use crate::message::{Class, Message, Method, TransactionId};
+use crate::uri::{IceServer, IceServerUri, Scheme, Transport};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use std::future::Future;
 use std::io;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use tokio_sync::{oneshot, Mutex};
-use tokio_timer::Timeout;
-
-type TransactionKey = (TransactionId, SocketAddr);
-type TransactionMap = HashMap<TransactionKey, oneshot::Sender<Message>>;
 
+/// A STUN client over an unreliable transport, which retransmits requests
+/// until they are answered.
 #[derive(Clone)]
 pub struct Agent<F> {
     on_send: F,
-    transactions: Arc<Mutex<TransactionMap>>,
+    transactions: Transactions,
+    rto: Duration,
+    max_requests: u32,
+    /// Draws the transaction IDs of the requests the agent creates.
+    rng: Arc<Mutex<StdRng>>,
 }
 
 impl<F, Fut> Agent<F>
@@ -25,40 +35,96 @@ where
     pub fn new(on_send: F) -> Self {
         Self {
             on_send,
-            transactions: Arc::new(Mutex::new(HashMap::new())),
+            transactions: Transactions::new(),
+            rto: DEFAULT_RTO,
+            max_requests: DEFAULT_MAX_REQUESTS,
+            rng: Arc::new(Mutex::new(StdRng::from_entropy())),
         }
     }
 
+    /// Sets the initial retransmission timeout and the number of requests to
+    /// send before giving up, defined in
+    /// [RFC 5389](https://tools.ietf.org/html/rfc5389#section-7.2.1).
+    pub fn with_retransmits(mut self, rto: Duration, max_requests: u32) -> Self {
+        self.rto = rto;
+        self.max_requests = max_requests;
+        self
+    }
+
+    /// Seeds the random number generator the transaction IDs of the
+    /// requests the agent creates are drawn from.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Arc::new(Mutex::new(StdRng::seed_from_u64(seed)));
+        self
+    }
+
+    /// Sets the timer requests are retransmitted and time out on, instead of
+    /// the system clock.
+    pub fn with_timer(mut self, timer: Timer) -> Self {
+        self.transactions = Transactions::with_timer(timer);
+        self
+    }
+
+    /// Returns a new transaction ID for a request sent with `send`.
+    pub fn transaction_id(&self) -> TransactionId {
+        TransactionId::from_rng(&mut *self.rng.lock().unwrap())
+    }
+
     pub async fn send(&self, msg: Message, addr: SocketAddr) -> io::Result<Message> {
-        let (tx, rx) = oneshot::channel();
+        let transaction = self.transactions.start(msg.transaction_id, addr).await;
 
-        // TODO: Handle key conflicts.
+        // Let the callback actually send out the message.
+        let send = || (self.on_send)(msg.clone(), addr);
         self.transactions
-            .lock()
+            .retransmit(transaction, self.rto, self.max_requests, send)
             .await
-            .insert((msg.transaction_id, addr), tx);
+    }
 
-        // Let the callback actually send out the message.
-        (self.on_send)(msg, addr).await?;
+    /// Sends a Binding request to the STUN server at `uri`, and returns the
+    /// server reflexive address in the response. Only servers reachable over
+    /// UDP are supported, which TURN servers are too.
+    pub async fn binding(&self, uri: &IceServerUri) -> io::Result<SocketAddr> {
+        if uri.scheme.is_secure() || uri.transport() != Transport::Udp {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "STUN server not reachable over UDP",
+            ));
+        }
+        let addr = uri
+            .socket_addrs()
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::AddrNotAvailable))?;
 
-        // TODO: Clean up transaction on timeout.
-        let res = Timeout::new(rx, Duration::from_secs(3))
-            .await
-            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?;
+        let msg = Message::new(Class::Request, Method::BINDING, self.transaction_id());
+        let res = self.send(msg, addr).await?;
+        match res.attr::<XorMappedAddress>() {
+            Some(attr) if res.class == Class::SuccessResponse => Ok(attr.0),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid Binding response",
+            )),
+        }
+    }
+
+    /// Sends a Binding request to the STUN server configured by `server`,
+    /// trying each of its `stun:` URIs in order, and returns the server
+    /// reflexive address in the first successful response.
+    pub async fn binding_ice_server(&self, server: &IceServer) -> io::Result<SocketAddr> {
+        let mut last_err = io::Error::new(io::ErrorKind::InvalidInput, "no STUN URIs");
+        for uri in server.urls.iter().filter(|uri| uri.scheme == Scheme::Stun) {
+            match self.binding(uri).await {
+                Ok(addr) => return Ok(addr),
+                Err(e) => last_err = e,
+            }
+        }
 
-        res.map_err(|_| io::Error::from(io::ErrorKind::Other))
+        Err(last_err)
     }
 
     pub async fn on_recv(&self, msg: Message, addr: SocketAddr) {
-        let tx = self
-            .transactions
-            .lock()
-            .await
-            .remove(&(msg.transaction_id, addr));
-
-        if let Some(tx) = tx {
-            let _ = tx.send(msg);
-        }
+        self.transactions.complete(msg, addr).await;
     }
 }
 
@@ -68,6 +134,8 @@ mod tests {
     use crate::test_util;
     use futures_util::future;
     use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
 
     #[test]
     fn basic() {
@@ -102,4 +170,138 @@ mod tests {
             assert_eq!(done.load(Ordering::SeqCst), len);
         });
     }
+
+    #[test]
+    fn timeout() {
+        tokio_test::block_on(async {
+            let transactions = Transactions::new();
+            let addr = test_util::get_test_addrs()[0];
+            let msg = test_util::new_test_msg(addr);
+
+            let transaction = transactions.start(msg.transaction_id, addr).await;
+            let res = transactions
+                .finish(transaction, Duration::from_millis(100))
+                .await;
+            assert_eq!(res.unwrap_err().kind(), io::ErrorKind::TimedOut);
+
+            // A late response no longer matches any transaction.
+            assert!(transactions.complete(msg, addr).await.is_some());
+        });
+    }
+
+    #[test]
+    fn retransmit() {
+        tokio_test::block_on(async {
+            let transactions = Transactions::new();
+            let addr = test_util::get_test_addrs()[0];
+            let msg = test_util::new_test_msg(addr);
+            let sent = Arc::new(AtomicUsize::new(0));
+
+            // Only the third request gets a response.
+            let transaction = transactions.start(msg.transaction_id, addr).await;
+            let res = transactions
+                .retransmit(transaction, Duration::from_millis(10), 7, || {
+                    let t = transactions.clone();
+                    let msg = msg.clone();
+                    let s = Arc::clone(&sent);
+                    async move {
+                        if s.fetch_add(1, Ordering::SeqCst) == 2 {
+                            t.complete(msg, addr).await;
+                        }
+                        Ok(())
+                    }
+                })
+                .await;
+            assert!(res.is_ok());
+            assert_eq!(sent.load(Ordering::SeqCst), 3);
+
+            // Without any response, it gives up after the last request.
+            sent.store(0, Ordering::SeqCst);
+            let transaction = transactions.start(msg.transaction_id, addr).await;
+            let res = transactions
+                .retransmit(transaction, Duration::from_millis(10), 3, || {
+                    sent.fetch_add(1, Ordering::SeqCst);
+                    future::ok(())
+                })
+                .await;
+            assert_eq!(res.unwrap_err().kind(), io::ErrorKind::TimedOut);
+            assert_eq!(sent.load(Ordering::SeqCst), 3);
+            assert!(transactions.complete(msg, addr).await.is_some());
+        });
+    }
+
+    #[test]
+    fn binding() {
+        tokio_test::block_on(async {
+            let (tx, mut rx) = tokio_sync::mpsc::unbounded_channel();
+            let agent = Agent::new(move |msg, addr| {
+                let res = tx
+                    .clone()
+                    .try_send((msg, addr))
+                    .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe));
+                future::ready(res)
+            });
+            let mapped_addr = test_util::get_test_addrs()[0];
+
+            let a = agent.clone();
+            tokio_executor::spawn(async move {
+                let (req, addr): (Message, SocketAddr) = rx.recv().await.unwrap();
+                assert_eq!(addr, "127.0.0.1:3478".parse().unwrap());
+                let mut res = Message::new(Class::SuccessResponse, req.method, req.transaction_id);
+                res.add_attr(&XorMappedAddress(mapped_addr));
+                a.on_recv(res, addr).await;
+            });
+
+            let uri = "stun:127.0.0.1".parse().unwrap();
+            assert_eq!(agent.binding(&uri).await.unwrap(), mapped_addr);
+
+            let uri = "stuns:127.0.0.1".parse().unwrap();
+            let err = agent.binding(&uri).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        });
+    }
+
+    #[test]
+    fn binding_ice_server() {
+        tokio_test::block_on(async {
+            // The server is unreachable on port 3478, but not on 3479.
+            let unreachable: SocketAddr = "127.0.0.1:3478".parse().unwrap();
+            let (tx, mut rx) = tokio_sync::mpsc::unbounded_channel();
+            let agent = Agent::new(move |msg, addr| {
+                let res = if addr == unreachable {
+                    Err(io::Error::from(io::ErrorKind::ConnectionRefused))
+                } else {
+                    tx.clone()
+                        .try_send((msg, addr))
+                        .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+                };
+                future::ready(res)
+            });
+            let mapped_addr = test_util::get_test_addrs()[0];
+
+            let a = agent.clone();
+            tokio_executor::spawn(async move {
+                let (req, addr): (Message, SocketAddr) = rx.recv().await.unwrap();
+                assert_eq!(addr, "127.0.0.1:3479".parse().unwrap());
+                let mut res = Message::new(Class::SuccessResponse, req.method, req.transaction_id);
+                res.add_attr(&XorMappedAddress(mapped_addr));
+                a.on_recv(res, addr).await;
+            });
+
+            let server = IceServer::new(vec![
+                "stuns:127.0.0.1".parse().unwrap(),
+                "turn:127.0.0.1:3479".parse().unwrap(),
+                "stun:127.0.0.1".parse().unwrap(),
+                "stun:127.0.0.1:3479".parse().unwrap(),
+            ]);
+            assert_eq!(
+                agent.binding_ice_server(&server).await.unwrap(),
+                mapped_addr
+            );
+
+            let server = IceServer::new(vec!["turn:127.0.0.1".parse().unwrap()]);
+            let err = agent.binding_ice_server(&server).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        });
+    }
 }
@@ -0,0 +1,172 @@
+use crate::message::{Message, TransactionId};
+use futures_util::future::{self, Either};
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_sync::{oneshot, Mutex};
+
+type TransactionKey = (TransactionId, SocketAddr);
+type TransactionMap = HashMap<TransactionKey, oneshot::Sender<Message>>;
+
+/// A future that completes once a duration has passed.
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Returns a `Sleep` for the given duration. Transactions wait on it for
+/// responses, so a timer that runs on a simulated clock makes them time out
+/// and retransmit on that clock.
+pub type Timer = Arc<dyn Fn(Duration) -> Sleep + Send + Sync>;
+
+/// The default time to wait for the response to a request.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// The default initial retransmission timeout over unreliable transports,
+/// defined in [RFC 5389](https://tools.ietf.org/html/rfc5389#section-7.2.1).
+pub const DEFAULT_RTO: Duration = Duration::from_millis(500);
+
+/// The default number of requests to send over unreliable transports before
+/// giving up, Rc in [RFC 5389](https://tools.ietf.org/html/rfc5389#section-7.2.1).
+pub const DEFAULT_MAX_REQUESTS: u32 = 7;
+
+/// How long to wait for a response to the last request, in multiples of the
+/// initial retransmission timeout, Rm in
+/// [RFC 5389](https://tools.ietf.org/html/rfc5389#section-7.2.1).
+const LAST_WAIT_FACTOR: u32 = 16;
+
+/// A table of outstanding client transactions, keyed by transaction ID and the
+/// address the request was sent to.
+///
+/// This is the bookkeeping behind `Agent`, exposed for protocols that send
+/// more than plain STUN messages over the same transport.
+#[derive(Clone)]
+pub struct Transactions {
+    map: Arc<Mutex<TransactionMap>>,
+    timer: Timer,
+}
+
+/// An outstanding client transaction.
+pub struct Transaction {
+    key: TransactionKey,
+    rx: oneshot::Receiver<Message>,
+}
+
+impl Default for Transactions {
+    fn default() -> Self {
+        Self::with_timer(Arc::new(|duration| {
+            Box::pin(tokio_timer::delay_for(duration))
+        }))
+    }
+}
+
+impl Transactions {
+    /// Creates a table whose transactions run on the system clock.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a table whose transactions wait with `timer`.
+    pub fn with_timer(timer: Timer) -> Self {
+        Self {
+            map: Arc::new(Mutex::new(HashMap::new())),
+            timer,
+        }
+    }
+
+    /// Starts a transaction for a request that is about to be sent to `addr`.
+    pub async fn start(&self, tr_id: TransactionId, addr: SocketAddr) -> Transaction {
+        let (tx, rx) = oneshot::channel();
+        let key = (tr_id, addr);
+
+        // A random transaction ID makes conflicts practically impossible; if
+        // one does happen, the older transaction is abandoned.
+        self.map.lock().await.insert(key, tx);
+
+        Transaction { key, rx }
+    }
+
+    /// Waits up to `timeout` for the response to `transaction`. The
+    /// transaction is cleaned up whether or not a response arrives.
+    pub async fn finish(&self, transaction: Transaction, timeout: Duration) -> io::Result<Message> {
+        let Transaction { key, mut rx } = transaction;
+
+        match self.timeout(&mut rx, timeout).await {
+            Some(Ok(msg)) => Ok(msg),
+            Some(Err(_)) => Err(io::Error::from(io::ErrorKind::Other)),
+            None => {
+                self.cancel(key).await;
+                Err(io::Error::from(io::ErrorKind::TimedOut))
+            }
+        }
+    }
+
+    /// Waits for the response to `transaction` over an unreliable transport,
+    /// calling `send` to send the request up to `max_requests` times. The
+    /// wait between two requests starts at `rto` and doubles every time, and
+    /// the last request gets 16 times the initial `rto`, as defined in
+    /// [RFC 5389](https://tools.ietf.org/html/rfc5389#section-7.2.1).
+    pub async fn retransmit<S, Fut>(
+        &self,
+        transaction: Transaction,
+        rto: Duration,
+        max_requests: u32,
+        mut send: S,
+    ) -> io::Result<Message>
+    where
+        S: FnMut() -> Fut,
+        Fut: Future<Output = io::Result<()>>,
+    {
+        let Transaction { key, mut rx } = transaction;
+        let mut wait = rto;
+
+        for requests in 1..=max_requests {
+            if let Err(e) = send().await {
+                self.cancel(key).await;
+                return Err(e);
+            }
+
+            if requests == max_requests {
+                wait = rto * LAST_WAIT_FACTOR;
+            }
+            match self.timeout(&mut rx, wait).await {
+                Some(Ok(msg)) => return Ok(msg),
+                Some(Err(_)) => return Err(io::Error::from(io::ErrorKind::Other)),
+                None => wait *= 2,
+            }
+        }
+
+        self.cancel(key).await;
+        Err(io::Error::from(io::ErrorKind::TimedOut))
+    }
+
+    /// Completes the transaction `msg` is a response to. Returns `msg` back if
+    /// it does not belong to any outstanding transaction.
+    pub async fn complete(&self, msg: Message, addr: SocketAddr) -> Option<Message> {
+        let tx = self.map.lock().await.remove(&(msg.transaction_id, addr));
+
+        match tx {
+            Some(tx) => {
+                let _ = tx.send(msg);
+                None
+            }
+            None => Some(msg),
+        }
+    }
+
+    async fn cancel(&self, key: TransactionKey) {
+        self.map.lock().await.remove(&key);
+    }
+
+    /// Waits up to `duration` on the timer for `future`.
+    async fn timeout<T>(&self, future: T, duration: Duration) -> Option<T::Output>
+    where
+        T: Future + Unpin,
+    {
+        match future::select(future, (self.timer)(duration)).await {
+            Either::Left((output, _)) => Some(output),
+            Either::Right(_) => None,
+        }
+    }
+}
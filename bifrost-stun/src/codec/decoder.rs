@@ -20,7 +20,7 @@ impl Decoder for MessageCodec {
         // available at once.
         if self.header.is_none() {
             // TODO: Make maximum length customizable.
-            let max_len = u16::max_value() - HEADER_LEN;
+            let max_len = u16::MAX - HEADER_LEN;
             self.header = match parse_header_streaming(src, max_len) {
                 Ok((_, header)) => Some(header),
                 Err(nom::Err::Incomplete(_)) => return Ok(None),
@@ -121,10 +121,12 @@ fn parse_attribute(input: &[u8]) -> IResult<&[u8], RawAttribute> {
     // bits are ignored, and may be any value.
     let padded_len = (unpadded_len + 3) & !0b11;
     let (rest, value) = take(padded_len)(rest)?;
-    let value = Vec::from(&value[..unpadded_len as usize]);
+    let (value, padding) = value.split_at(unpadded_len as usize);
 
     // OK to unwrap because we already verified the length above.
-    let attr = RawAttribute::new(r#type, value).unwrap();
+    let attr = RawAttribute::new(r#type, Vec::from(value))
+        .unwrap()
+        .with_padding(padding);
 
     Ok((rest, attr))
 }
@@ -9,33 +9,44 @@ impl Encoder for MessageCodec {
     type Error = io::Error;
 
     fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        let attrs_len: usize = item
-            .attributes
-            .iter()
-            .map(|a| ATTR_HEADER_LEN as usize + a.padded_len() as usize)
-            .sum();
+        let attrs_len = attributes_len(&item.attributes);
 
         // TODO: Make maximum length customizable.
-        if attrs_len >= (u16::max_value() - HEADER_LEN) as usize {
+        if attrs_len >= (u16::MAX - HEADER_LEN) as usize {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
                 "attributes length too large",
             ));
         }
-        let attrs_len = attrs_len as u16;
-        let total_len = HEADER_LEN + attrs_len;
 
-        dst.reserve(total_len as usize);
-        encode_class_and_method(item.class, item.method, dst);
-        encode_len(attrs_len, dst);
-        encode_magic_cookie(dst);
-        encode_transaction_id(&item.transaction_id, dst);
-        encode_attributes(&item.attributes, dst);
+        encode_partial(&item, item.attributes.len(), 0, dst);
 
         Ok(())
     }
 }
 
+/// Encodes the header and the first `count` attributes of `msg`, with the
+/// length field covering `extra_len` more bytes of attributes to be appended
+/// later. This is the input to MESSAGE-INTEGRITY.
+pub(crate) fn encode_partial(msg: &Message, count: usize, extra_len: u16, dst: &mut BytesMut) {
+    let attrs = &msg.attributes[..count];
+    let attrs_len = attributes_len(attrs) as u16 + extra_len;
+
+    dst.reserve((HEADER_LEN + attrs_len) as usize);
+    encode_class_and_method(msg.class, msg.method, dst);
+    encode_len(attrs_len, dst);
+    encode_magic_cookie(dst);
+    encode_transaction_id(&msg.transaction_id, dst);
+    encode_attributes(attrs, dst);
+}
+
+fn attributes_len(attrs: &[RawAttribute]) -> usize {
+    attrs
+        .iter()
+        .map(|a| ATTR_HEADER_LEN as usize + a.padded_len() as usize)
+        .sum()
+}
+
 fn encode_class_and_method(class: Class, method: Method, dst: &mut BytesMut) {
     let c = class.as_byte();
     let m = method.as_bytes();
@@ -64,9 +75,7 @@ fn encode_attributes(attrs: &[RawAttribute], dst: &mut BytesMut) {
         dst.put_u16_be(attr.r#type());
         dst.put_u16_be(attr.unpadded_len());
         dst.put_slice(attr.value());
-        for _ in attr.unpadded_len()..attr.padded_len() {
-            dst.put_u8(0);
-        }
+        dst.put_slice(attr.padding());
     }
 }
 
@@ -0,0 +1,107 @@
+use bytes::{BufMut, BytesMut};
+use std::io;
+use tokio_codec::{Decoder, Encoder};
+
+/// The length of the header of a frame.
+const LEN_LEN: usize = 2;
+
+/// A codec for the framing of STUN and media packets over a stream, such as
+/// a TCP connection, defined in
+/// [RFC 4571](https://tools.ietf.org/html/rfc4571#section-2): each packet
+/// is preceded by its length as a 16-bit unsigned integer.
+///
+/// Decoded frames are whole packets, which may then be decoded with
+/// [`MessageCodec`](struct.MessageCodec.html) if they are STUN messages.
+#[derive(Default)]
+pub struct FramingCodec;
+
+impl FramingCodec {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Decoder for FramingCodec {
+    type Item = BytesMut;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if src.len() < LEN_LEN {
+            return Ok(None);
+        }
+        let len = usize::from(u16::from_be_bytes([src[0], src[1]]));
+
+        // Wait for the entire packet to be available.
+        if src.len() < LEN_LEN + len {
+            src.reserve(LEN_LEN + len - src.len());
+            return Ok(None);
+        }
+
+        src.advance(LEN_LEN);
+        Ok(Some(src.split_to(len)))
+    }
+}
+
+impl Encoder for FramingCodec {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        if item.len() > usize::from(u16::MAX) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "packet too large to frame",
+            ));
+        }
+
+        dst.reserve(LEN_LEN + item.len());
+        dst.put_u16_be(item.len() as u16);
+        dst.put_slice(&item);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut codec = FramingCodec::new();
+        let mut bytes = BytesMut::new();
+        codec.encode(b"stun".to_vec(), &mut bytes).unwrap();
+        codec.encode(Vec::new(), &mut bytes).unwrap();
+        codec.encode(b"rtp".to_vec(), &mut bytes).unwrap();
+        assert_eq!(&bytes[..6], b"\x00\x04stun");
+
+        let mut frames = Vec::new();
+        while let Some(frame) = codec.decode(&mut bytes).unwrap() {
+            frames.push(frame);
+        }
+        assert_eq!(frames, [&b"stun"[..], b"", b"rtp"]);
+        assert!(bytes.is_empty());
+    }
+
+    #[test]
+    fn incomplete() {
+        let mut codec = FramingCodec::new();
+        let mut bytes = BytesMut::new();
+        for &byte in b"\x00\x03rt" {
+            bytes.put_u8(byte);
+            assert_eq!(codec.decode(&mut bytes).unwrap(), None);
+        }
+
+        bytes.put_slice(b"p\x00");
+        assert_eq!(codec.decode(&mut bytes).unwrap().unwrap(), &b"rtp"[..]);
+        assert_eq!(codec.decode(&mut bytes).unwrap(), None);
+        assert_eq!(&bytes[..], b"\x00");
+    }
+
+    #[test]
+    fn too_large() {
+        let mut bytes = BytesMut::new();
+        let packet = vec![0; usize::from(u16::MAX) + 1];
+        assert!(FramingCodec::new().encode(packet, &mut bytes).is_err());
+        assert!(bytes.is_empty());
+    }
+}
@@ -1,10 +1,15 @@
 mod decoder;
 mod encoder;
+mod framing;
+
+pub use self::framing::FramingCodec;
+
+pub(crate) use self::encoder::encode_partial;
 
 use crate::message::{Class, Method, TransactionId};
 
-const HEADER_LEN: u16 = 20;
-const ATTR_HEADER_LEN: u16 = 4;
+pub(crate) const HEADER_LEN: u16 = 20;
+pub(crate) const ATTR_HEADER_LEN: u16 = 4;
 
 #[derive(Default)]
 pub struct MessageCodec {
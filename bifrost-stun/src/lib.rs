@@ -1,6 +1,7 @@
 pub mod agent;
 pub mod codec;
 pub mod message;
+pub mod uri;
 
 #[cfg(test)]
 mod test_util;
@@ -0,0 +1,31 @@
+use crate::message::attribute::Attribute;
+use crate::message::{RawAttribute, TransactionId};
+
+/// The ADDITIONAL-ADDRESS-FAMILY attribute, defined in
+/// [RFC 8656](https://tools.ietf.org/html/rfc8656#section-18.11).
+///
+/// A client asks for a dual-stack allocation, with an IPv6 relayed transport
+/// address on top of the IPv4 one, by sending it with the only allowed value.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AdditionalAddressFamily(pub u8);
+
+impl AdditionalAddressFamily {
+    pub const IPV6: Self = Self(0x02);
+}
+
+impl Attribute for AdditionalAddressFamily {
+    const TYPE: u16 = 0x8000;
+
+    fn from_raw(raw: &[u8], _: &TransactionId) -> Option<Self> {
+        // The Family field is followed by three bytes of RFFU.
+        if raw.len() == 4 {
+            Some(Self(raw[0]))
+        } else {
+            None
+        }
+    }
+
+    fn to_raw(&self, _: &TransactionId) -> RawAttribute {
+        RawAttribute::new(Self::TYPE, vec![self.0, 0, 0, 0]).unwrap()
+    }
+}
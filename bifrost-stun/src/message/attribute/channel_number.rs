@@ -0,0 +1,37 @@
+use crate::message::attribute::Attribute;
+use crate::message::{RawAttribute, TransactionId};
+
+/// The CHANNEL-NUMBER attribute, defined in
+/// [RFC 5766](https://tools.ietf.org/html/rfc5766#section-14.1).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ChannelNumber(pub u16);
+
+impl ChannelNumber {
+    /// The smallest channel number a client may bind.
+    pub const MIN: u16 = 0x4000;
+    /// The largest channel number a client may bind.
+    pub const MAX: u16 = 0x7ffe;
+
+    pub fn is_valid(self) -> bool {
+        self.0 >= Self::MIN && self.0 <= Self::MAX
+    }
+}
+
+impl Attribute for ChannelNumber {
+    const TYPE: u16 = 0x000c;
+
+    fn from_raw(raw: &[u8], _: &TransactionId) -> Option<Self> {
+        // A 16-bit unsigned integer, followed by a two-octet RFFU (Reserved
+        // For Future Use) field.
+        if raw.len() == 4 {
+            Some(Self(u16::from_be_bytes([raw[0], raw[1]])))
+        } else {
+            None
+        }
+    }
+
+    fn to_raw(&self, _: &TransactionId) -> RawAttribute {
+        let [b0, b1] = self.0.to_be_bytes();
+        RawAttribute::new(Self::TYPE, vec![b0, b1, 0, 0]).unwrap()
+    }
+}
@@ -0,0 +1,21 @@
+use crate::message::attribute::Attribute;
+use crate::message::{RawAttribute, TransactionId};
+use std::convert::TryInto;
+
+/// The CONNECTION-ID attribute, defined in
+/// [RFC 6062](https://tools.ietf.org/html/rfc6062#section-6.2.1).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ConnectionId(pub u32);
+
+impl Attribute for ConnectionId {
+    const TYPE: u16 = 0x002a;
+
+    fn from_raw(raw: &[u8], _: &TransactionId) -> Option<Self> {
+        // The value is a 32-bit unsigned integer chosen by the server.
+        Some(Self(u32::from_be_bytes(raw.try_into().ok()?)))
+    }
+
+    fn to_raw(&self, _: &TransactionId) -> RawAttribute {
+        RawAttribute::new(Self::TYPE, self.0.to_be_bytes().to_vec()).unwrap()
+    }
+}
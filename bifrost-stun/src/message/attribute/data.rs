@@ -0,0 +1,19 @@
+use crate::message::attribute::Attribute;
+use crate::message::{RawAttribute, TransactionId};
+
+/// The DATA attribute, defined in
+/// [RFC 5766](https://tools.ietf.org/html/rfc5766#section-14.4).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Data(pub Vec<u8>);
+
+impl Attribute for Data {
+    const TYPE: u16 = 0x0013;
+
+    fn from_raw(raw: &[u8], _: &TransactionId) -> Option<Self> {
+        Some(Self(raw.to_vec()))
+    }
+
+    fn to_raw(&self, _: &TransactionId) -> RawAttribute {
+        RawAttribute::new(Self::TYPE, self.0.clone()).unwrap()
+    }
+}
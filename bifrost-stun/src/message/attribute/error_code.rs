@@ -0,0 +1,112 @@
+use crate::message::attribute::Attribute;
+use crate::message::{RawAttribute, TransactionId};
+use std::str;
+
+/// The ERROR-CODE attribute, defined in
+/// [RFC 5389](https://tools.ietf.org/html/rfc5389#section-15.6).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ErrorCode {
+    pub code: u16,
+    pub reason: String,
+}
+
+impl ErrorCode {
+    pub const TRY_ALTERNATE: u16 = 300;
+    pub const BAD_REQUEST: u16 = 400;
+    pub const UNAUTHORIZED: u16 = 401;
+    pub const UNKNOWN_ATTRIBUTE: u16 = 420;
+    pub const STALE_NONCE: u16 = 438;
+    pub const SERVER_ERROR: u16 = 500;
+
+    // TURN error codes, defined in
+    // [RFC 5766](https://tools.ietf.org/html/rfc5766#section-15).
+    pub const FORBIDDEN: u16 = 403;
+    pub const ALLOCATION_MISMATCH: u16 = 437;
+    pub const WRONG_CREDENTIALS: u16 = 441;
+    pub const UNSUPPORTED_TRANSPORT_PROTOCOL: u16 = 442;
+    pub const ALLOCATION_QUOTA_REACHED: u16 = 486;
+    pub const INSUFFICIENT_CAPACITY: u16 = 508;
+
+    // TURN error codes for TCP allocations, defined in
+    // [RFC 6062](https://tools.ietf.org/html/rfc6062#section-6.3).
+    pub const CONNECTION_ALREADY_EXISTS: u16 = 446;
+    pub const CONNECTION_TIMEOUT_OR_FAILURE: u16 = 447;
+
+    // TURN error codes for IPv6, defined in
+    // [RFC 6156](https://tools.ietf.org/html/rfc6156#section-10.2).
+    pub const ADDRESS_FAMILY_NOT_SUPPORTED: u16 = 440;
+    pub const PEER_ADDRESS_FAMILY_MISMATCH: u16 = 443;
+
+    // ICE error codes, defined in
+    // [RFC 8445](https://tools.ietf.org/html/rfc8445#section-16.2).
+    pub const ROLE_CONFLICT: u16 = 487;
+
+    pub fn new(code: u16, reason: &str) -> Self {
+        Self {
+            code,
+            reason: reason.to_owned(),
+        }
+    }
+}
+
+impl Attribute for ErrorCode {
+    const TYPE: u16 = 0x0009;
+
+    fn from_raw(raw: &[u8], _: &TransactionId) -> Option<Self> {
+        if raw.len() < 4 {
+            return None;
+        }
+
+        // The Class represents the hundreds digit of the error code. The value
+        // MUST be between 3 and 6. The Number represents the error code modulo
+        // 100, and its value MUST be between 0 and 99.
+        let class = raw[2] & 0b111;
+        let number = raw[3];
+        if !(3..=6).contains(&class) || number > 99 {
+            return None;
+        }
+
+        let reason = str::from_utf8(&raw[4..]).ok()?;
+
+        Some(Self {
+            code: u16::from(class) * 100 + u16::from(number),
+            reason: reason.to_owned(),
+        })
+    }
+
+    fn to_raw(&self, _: &TransactionId) -> RawAttribute {
+        let mut buf = Vec::with_capacity(4 + self.reason.len());
+        buf.extend(&[0, 0, (self.code / 100) as u8, (self.code % 100) as u8]);
+        buf.extend(self.reason.as_bytes());
+
+        RawAttribute::new(Self::TYPE, buf).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TR_ID: TransactionId = TransactionId::new([0; 12]);
+
+    #[test]
+    fn valid() {
+        let raw = [0x00, 0x00, 0x04, 0x26, b'S', b't', b'a', b'l', b'e'];
+        assert_eq!(
+            ErrorCode::from_raw(&raw, &TR_ID),
+            Some(ErrorCode::new(ErrorCode::STALE_NONCE, "Stale"))
+        );
+
+        let attr = ErrorCode::new(ErrorCode::UNAUTHORIZED, "Unauthorized");
+        let raw = attr.to_raw(&TR_ID);
+        assert_eq!(&raw.value()[..4], &[0x00, 0x00, 0x04, 0x01]);
+        assert_eq!(ErrorCode::from_raw(raw.value(), &TR_ID), Some(attr));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(ErrorCode::from_raw(&[0x00, 0x00, 0x04], &TR_ID).is_none());
+        assert!(ErrorCode::from_raw(&[0x00, 0x00, 0x02, 0x00], &TR_ID).is_none());
+        assert!(ErrorCode::from_raw(&[0x00, 0x00, 0x04, 0x64], &TR_ID).is_none());
+    }
+}
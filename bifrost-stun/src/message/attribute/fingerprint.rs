@@ -0,0 +1,37 @@
+use crate::message::attribute::Attribute;
+use crate::message::{RawAttribute, TransactionId};
+use crc32fast::Hasher;
+use std::convert::TryInto;
+
+/// The FINGERPRINT attribute, defined in
+/// [RFC 5389](https://tools.ietf.org/html/rfc5389#section-15.5).
+///
+/// Use `Message::add_fingerprint` and `Message::verify_fingerprint` rather
+/// than constructing it directly, since its value depends on the rest of the
+/// message.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Fingerprint(pub u32);
+
+impl Fingerprint {
+    /// The encoded length of the attribute value.
+    pub const LEN: u16 = 4;
+
+    /// Computes the CRC-32 of `input` XOR'ed with 0x5354554e.
+    pub fn compute(input: &[u8]) -> Self {
+        let mut hasher = Hasher::new();
+        hasher.update(input);
+        Self(hasher.finalize() ^ 0x5354_554e)
+    }
+}
+
+impl Attribute for Fingerprint {
+    const TYPE: u16 = 0x8028;
+
+    fn from_raw(raw: &[u8], _: &TransactionId) -> Option<Self> {
+        Some(Self(u32::from_be_bytes(raw.try_into().ok()?)))
+    }
+
+    fn to_raw(&self, _: &TransactionId) -> RawAttribute {
+        RawAttribute::new(Self::TYPE, self.0.to_be_bytes().to_vec()).unwrap()
+    }
+}
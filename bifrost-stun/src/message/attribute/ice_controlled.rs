@@ -0,0 +1,21 @@
+use crate::message::attribute::Attribute;
+use crate::message::{RawAttribute, TransactionId};
+use std::convert::TryInto;
+
+/// The ICE-CONTROLLED attribute, defined in
+/// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-16.1). It carries
+/// the tie-breaker used to resolve role conflicts.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct IceControlled(pub u64);
+
+impl Attribute for IceControlled {
+    const TYPE: u16 = 0x8029;
+
+    fn from_raw(raw: &[u8], _: &TransactionId) -> Option<Self> {
+        Some(Self(u64::from_be_bytes(raw.try_into().ok()?)))
+    }
+
+    fn to_raw(&self, _: &TransactionId) -> RawAttribute {
+        RawAttribute::new(Self::TYPE, self.0.to_be_bytes().to_vec()).unwrap()
+    }
+}
@@ -0,0 +1,26 @@
+use crate::message::attribute::Attribute;
+use crate::message::{RawAttribute, TransactionId};
+use std::convert::TryInto;
+use std::time::Duration;
+
+/// The LIFETIME attribute, defined in
+/// [RFC 5766](https://tools.ietf.org/html/rfc5766#section-14.2).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Lifetime(pub Duration);
+
+impl Attribute for Lifetime {
+    const TYPE: u16 = 0x000d;
+
+    fn from_raw(raw: &[u8], _: &TransactionId) -> Option<Self> {
+        // The value portion of this attribute is 4-bytes long and consists of
+        // a 32-bit unsigned integral value representing the number of seconds
+        // remaining until expiration.
+        let secs = u32::from_be_bytes(raw.try_into().ok()?);
+        Some(Self(Duration::from_secs(u64::from(secs))))
+    }
+
+    fn to_raw(&self, _: &TransactionId) -> RawAttribute {
+        let secs = self.0.as_secs().min(u64::from(u32::MAX)) as u32;
+        RawAttribute::new(Self::TYPE, secs.to_be_bytes().to_vec()).unwrap()
+    }
+}
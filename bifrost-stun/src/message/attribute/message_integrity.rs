@@ -0,0 +1,74 @@
+use crate::message::attribute::Attribute;
+use crate::message::{RawAttribute, TransactionId};
+use hmac::{Hmac, Mac};
+use md5::{Digest, Md5};
+use sha1::Sha1;
+use std::convert::TryInto;
+
+/// The MESSAGE-INTEGRITY attribute, defined in
+/// [RFC 5389](https://tools.ietf.org/html/rfc5389#section-15.4).
+///
+/// Use `Message::add_message_integrity` and
+/// `Message::verify_message_integrity` rather than constructing it directly,
+/// since its value depends on the attributes preceding it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MessageIntegrity(pub [u8; 20]);
+
+impl MessageIntegrity {
+    /// The encoded length of the attribute value.
+    pub const LEN: u16 = 20;
+
+    /// Computes the HMAC-SHA1 of `input` with `key`.
+    pub fn compute(key: &[u8], input: &[u8]) -> Self {
+        let mut mac = Hmac::<Sha1>::new_varkey(key).unwrap();
+        mac.input(input);
+        Self(mac.result().code().as_slice().try_into().unwrap())
+    }
+
+    /// Derives the key for long-term credentials, which is
+    /// `MD5(username ":" realm ":" password)`.
+    pub fn long_term_key(username: &str, realm: &str, password: &str) -> Vec<u8> {
+        let input = format!("{}:{}:{}", username, realm, password);
+        Md5::digest(input.as_bytes()).to_vec()
+    }
+
+    /// Derives the key for short-term credentials, which is the password
+    /// itself.
+    pub fn short_term_key(password: &str) -> Vec<u8> {
+        password.as_bytes().to_vec()
+    }
+}
+
+impl Attribute for MessageIntegrity {
+    const TYPE: u16 = 0x0008;
+
+    fn from_raw(raw: &[u8], _: &TransactionId) -> Option<Self> {
+        raw.try_into().ok().map(Self)
+    }
+
+    fn to_raw(&self, _: &TransactionId) -> RawAttribute {
+        RawAttribute::new(Self::TYPE, self.0.to_vec()).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_term_key() {
+        // Credentials from RFC 5769, section 2.4.
+        let key = MessageIntegrity::long_term_key(
+            "\u{30de}\u{30c8}\u{30ea}\u{30c3}\u{30af}\u{30b9}",
+            "example.org",
+            "TheMatrIX",
+        );
+        assert_eq!(
+            key,
+            [
+                0xe8, 0xca, 0x7a, 0xd5, 0x9d, 0x5e, 0xb0, 0x51, 0x8e, 0x31, 0x29, 0x11, 0xd2, 0xda,
+                0xb2, 0xa9
+            ]
+        );
+    }
+}
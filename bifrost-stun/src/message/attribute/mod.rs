@@ -1,6 +1,48 @@
+mod additional_address_family;
+mod channel_number;
+mod connection_id;
+mod data;
+mod error_code;
+mod fingerprint;
+mod ice_controlled;
+mod ice_controlling;
+mod lifetime;
+mod message_integrity;
+mod nonce;
+mod priority;
+mod realm;
+mod requested_address_family;
+mod requested_transport;
+mod software;
+mod unknown_attributes;
+mod use_candidate;
+mod username;
 mod xor_mapped_address;
+mod xor_peer_address;
+mod xor_relayed_address;
 
+pub use self::additional_address_family::AdditionalAddressFamily;
+pub use self::channel_number::ChannelNumber;
+pub use self::connection_id::ConnectionId;
+pub use self::data::Data;
+pub use self::error_code::ErrorCode;
+pub use self::fingerprint::Fingerprint;
+pub use self::ice_controlled::IceControlled;
+pub use self::ice_controlling::IceControlling;
+pub use self::lifetime::Lifetime;
+pub use self::message_integrity::MessageIntegrity;
+pub use self::nonce::Nonce;
+pub use self::priority::Priority;
+pub use self::realm::Realm;
+pub use self::requested_address_family::RequestedAddressFamily;
+pub use self::requested_transport::RequestedTransport;
+pub use self::software::Software;
+pub use self::unknown_attributes::UnknownAttributes;
+pub use self::use_candidate::UseCandidate;
+pub use self::username::Username;
 pub use self::xor_mapped_address::XorMappedAddress;
+pub use self::xor_peer_address::XorPeerAddress;
+pub use self::xor_relayed_address::XorRelayedAddress;
 
 use crate::message::{RawAttribute, TransactionId};
 
@@ -11,3 +53,9 @@ pub trait Attribute: Sized {
 
     fn to_raw(&self, tr_id: &TransactionId) -> RawAttribute;
 }
+
+/// Returns whether an attribute type is comprehension-required, i.e. whether
+/// a message containing it must be rejected if the type is not understood.
+pub fn is_comprehension_required(r#type: u16) -> bool {
+    r#type < 0x8000
+}
@@ -0,0 +1,27 @@
+use crate::message::attribute::Attribute;
+use crate::message::{RawAttribute, TransactionId};
+use std::str;
+
+/// The NONCE attribute, defined in
+/// [RFC 5389](https://tools.ietf.org/html/rfc5389#section-15.8).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Nonce(pub String);
+
+impl Attribute for Nonce {
+    const TYPE: u16 = 0x0015;
+
+    fn from_raw(raw: &[u8], _: &TransactionId) -> Option<Self> {
+        // It MUST be less than 128 characters (which can be as long as 763
+        // bytes).
+        let nonce = str::from_utf8(raw).ok()?;
+        if nonce.chars().count() < 128 {
+            Some(Self(nonce.to_owned()))
+        } else {
+            None
+        }
+    }
+
+    fn to_raw(&self, _: &TransactionId) -> RawAttribute {
+        RawAttribute::new(Self::TYPE, self.0.as_bytes().to_vec()).unwrap()
+    }
+}
@@ -0,0 +1,20 @@
+use crate::message::attribute::Attribute;
+use crate::message::{RawAttribute, TransactionId};
+use std::convert::TryInto;
+
+/// The PRIORITY attribute, defined in
+/// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-16.1).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Priority(pub u32);
+
+impl Attribute for Priority {
+    const TYPE: u16 = 0x0024;
+
+    fn from_raw(raw: &[u8], _: &TransactionId) -> Option<Self> {
+        Some(Self(u32::from_be_bytes(raw.try_into().ok()?)))
+    }
+
+    fn to_raw(&self, _: &TransactionId) -> RawAttribute {
+        RawAttribute::new(Self::TYPE, self.0.to_be_bytes().to_vec()).unwrap()
+    }
+}
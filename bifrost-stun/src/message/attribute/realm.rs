@@ -0,0 +1,27 @@
+use crate::message::attribute::Attribute;
+use crate::message::{RawAttribute, TransactionId};
+use std::str;
+
+/// The REALM attribute, defined in
+/// [RFC 5389](https://tools.ietf.org/html/rfc5389#section-15.7).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Realm(pub String);
+
+impl Attribute for Realm {
+    const TYPE: u16 = 0x0014;
+
+    fn from_raw(raw: &[u8], _: &TransactionId) -> Option<Self> {
+        // It MUST be a UTF-8 encoded sequence of less than 128 characters
+        // (which can be as long as 763 bytes).
+        let realm = str::from_utf8(raw).ok()?;
+        if realm.chars().count() < 128 {
+            Some(Self(realm.to_owned()))
+        } else {
+            None
+        }
+    }
+
+    fn to_raw(&self, _: &TransactionId) -> RawAttribute {
+        RawAttribute::new(Self::TYPE, self.0.as_bytes().to_vec()).unwrap()
+    }
+}
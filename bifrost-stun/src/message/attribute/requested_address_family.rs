@@ -0,0 +1,38 @@
+use crate::message::attribute::Attribute;
+use crate::message::{RawAttribute, TransactionId};
+use std::net::IpAddr;
+
+/// The REQUESTED-ADDRESS-FAMILY attribute, defined in
+/// [RFC 6156](https://tools.ietf.org/html/rfc6156#section-4.1.1).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RequestedAddressFamily(pub u8);
+
+impl RequestedAddressFamily {
+    pub const IPV4: Self = Self(0x01);
+    pub const IPV6: Self = Self(0x02);
+
+    /// Returns the family of `ip`.
+    pub fn of(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(_) => Self::IPV4,
+            IpAddr::V6(_) => Self::IPV6,
+        }
+    }
+}
+
+impl Attribute for RequestedAddressFamily {
+    const TYPE: u16 = 0x0017;
+
+    fn from_raw(raw: &[u8], _: &TransactionId) -> Option<Self> {
+        // The Family field is followed by three bytes of RFFU.
+        if raw.len() == 4 {
+            Some(Self(raw[0]))
+        } else {
+            None
+        }
+    }
+
+    fn to_raw(&self, _: &TransactionId) -> RawAttribute {
+        RawAttribute::new(Self::TYPE, vec![self.0, 0, 0, 0]).unwrap()
+    }
+}
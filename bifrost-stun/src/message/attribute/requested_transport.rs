@@ -0,0 +1,33 @@
+use crate::message::attribute::Attribute;
+use crate::message::{RawAttribute, TransactionId};
+
+/// The REQUESTED-TRANSPORT attribute, defined in
+/// [RFC 5766](https://tools.ietf.org/html/rfc5766#section-14.7).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RequestedTransport(pub u8);
+
+impl RequestedTransport {
+    /// The IANA protocol number for TCP, defined in
+    /// [RFC 6062](https://tools.ietf.org/html/rfc6062#section-6.1).
+    pub const TCP: Self = Self(6);
+    /// The IANA protocol number for UDP.
+    pub const UDP: Self = Self(17);
+}
+
+impl Attribute for RequestedTransport {
+    const TYPE: u16 = 0x0019;
+
+    fn from_raw(raw: &[u8], _: &TransactionId) -> Option<Self> {
+        // The Protocol field specifies the desired protocol, followed by three
+        // bytes of RFFU.
+        if raw.len() == 4 {
+            Some(Self(raw[0]))
+        } else {
+            None
+        }
+    }
+
+    fn to_raw(&self, _: &TransactionId) -> RawAttribute {
+        RawAttribute::new(Self::TYPE, vec![self.0, 0, 0, 0]).unwrap()
+    }
+}
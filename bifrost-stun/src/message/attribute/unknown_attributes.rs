@@ -0,0 +1,30 @@
+use crate::message::attribute::Attribute;
+use crate::message::{RawAttribute, TransactionId};
+
+/// The UNKNOWN-ATTRIBUTES attribute, defined in
+/// [RFC 5389](https://tools.ietf.org/html/rfc5389#section-15.9).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UnknownAttributes(pub Vec<u16>);
+
+impl Attribute for UnknownAttributes {
+    const TYPE: u16 = 0x000a;
+
+    fn from_raw(raw: &[u8], _: &TransactionId) -> Option<Self> {
+        let chunks = raw.chunks_exact(2);
+        if !chunks.remainder().is_empty() {
+            return None;
+        }
+
+        let types = chunks.map(|x| u16::from_be_bytes([x[0], x[1]])).collect();
+        Some(Self(types))
+    }
+
+    fn to_raw(&self, _: &TransactionId) -> RawAttribute {
+        let buf = self
+            .0
+            .iter()
+            .flat_map(|x| x.to_be_bytes().to_vec())
+            .collect();
+        RawAttribute::new(Self::TYPE, buf).unwrap()
+    }
+}
@@ -0,0 +1,24 @@
+use crate::message::attribute::Attribute;
+use crate::message::{RawAttribute, TransactionId};
+
+/// The USE-CANDIDATE attribute, defined in
+/// [RFC 8445](https://tools.ietf.org/html/rfc8445#section-16.1).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct UseCandidate;
+
+impl Attribute for UseCandidate {
+    const TYPE: u16 = 0x0025;
+
+    fn from_raw(raw: &[u8], _: &TransactionId) -> Option<Self> {
+        // The attribute has no content.
+        if raw.is_empty() {
+            Some(Self)
+        } else {
+            None
+        }
+    }
+
+    fn to_raw(&self, _: &TransactionId) -> RawAttribute {
+        RawAttribute::new(Self::TYPE, Vec::new()).unwrap()
+    }
+}
@@ -0,0 +1,51 @@
+use crate::message::attribute::Attribute;
+use crate::message::{RawAttribute, TransactionId};
+use std::str;
+
+/// The USERNAME attribute, defined in
+/// [RFC 5389](https://tools.ietf.org/html/rfc5389#section-15.3).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Username(pub String);
+
+impl Username {
+    /// The value of USERNAME MUST be less than 513 bytes.
+    pub const MAX_LEN: usize = 512;
+}
+
+impl Attribute for Username {
+    const TYPE: u16 = 0x0006;
+
+    fn from_raw(raw: &[u8], _: &TransactionId) -> Option<Self> {
+        if raw.len() > Self::MAX_LEN {
+            return None;
+        }
+        str::from_utf8(raw).ok().map(|s| Self(s.to_owned()))
+    }
+
+    fn to_raw(&self, _: &TransactionId) -> RawAttribute {
+        RawAttribute::new(Self::TYPE, self.0.as_bytes().to_vec()).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TR_ID: TransactionId = TransactionId::new([0; 12]);
+
+    #[test]
+    fn valid() {
+        let raw = Username("evtj:h6vY".to_owned()).to_raw(&TR_ID);
+        assert_eq!(raw.value(), b"evtj:h6vY");
+        assert_eq!(
+            Username::from_raw(raw.value(), &TR_ID),
+            Some(Username("evtj:h6vY".to_owned()))
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(Username::from_raw(&[0xff, 0xfe], &TR_ID).is_none());
+        assert!(Username::from_raw(&[b'a'; 513], &TR_ID).is_none());
+    }
+}
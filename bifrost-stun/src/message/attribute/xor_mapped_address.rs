@@ -15,44 +15,54 @@ impl Attribute for XorMappedAddress {
     const TYPE: u16 = 0x0020;
 
     fn from_raw(raw: &[u8], tr_id: &TransactionId) -> Option<Self> {
-        parse(raw, tr_id)
-            .ok()
-            .and_then(|(rest, attr)| if rest.is_empty() { Some(attr) } else { None })
+        decode_xor_address(raw, tr_id).map(Self)
     }
 
-    // TODO: Add tests
     fn to_raw(&self, tr_id: &TransactionId) -> RawAttribute {
-        let is_ipv4 = self.0.is_ipv4();
-        let len: u16 = if is_ipv4 { 8 } else { 20 };
-        let mut buf = Vec::with_capacity(len as usize);
-
-        buf.push(0);
-        buf.push(if is_ipv4 { 1 } else { 2 });
-
-        let x_port = self.0.port() ^ u16::from_be_bytes([MAGIC_COOKIE[0], MAGIC_COOKIE[1]]);
-        buf.extend(&x_port.to_be_bytes());
-
-        match self.0.ip() {
-            IpAddr::V4(addr) => {
-                let bytes = xor_4_bytes(&addr.octets(), MAGIC_COOKIE);
-                buf.extend(&bytes);
-            }
-            IpAddr::V6(addr) => {
-                let mut xor_bytes = [0u8; 16];
-                xor_bytes[..4].copy_from_slice(&MAGIC_COOKIE);
-                xor_bytes[4..].copy_from_slice(tr_id.as_bytes());
-
-                let bytes = xor_16_bytes(&addr.octets(), xor_bytes);
-
-                buf.extend(&bytes);
-            }
+        RawAttribute::new(Self::TYPE, encode_xor_address(self.0, tr_id)).unwrap()
+    }
+}
+
+/// Decodes an XOR'ed transport address, shared by XOR-MAPPED-ADDRESS and the
+/// TURN attributes that reuse its encoding.
+pub(crate) fn decode_xor_address(raw: &[u8], tr_id: &TransactionId) -> Option<SocketAddr> {
+    parse(raw, tr_id)
+        .ok()
+        .and_then(|(rest, addr)| if rest.is_empty() { Some(addr) } else { None })
+}
+
+/// Encodes a transport address the way XOR-MAPPED-ADDRESS does.
+pub(crate) fn encode_xor_address(addr: SocketAddr, tr_id: &TransactionId) -> Vec<u8> {
+    let is_ipv4 = addr.is_ipv4();
+    let len: u16 = if is_ipv4 { 8 } else { 20 };
+    let mut buf = Vec::with_capacity(len as usize);
+
+    buf.push(0);
+    buf.push(if is_ipv4 { 1 } else { 2 });
+
+    let x_port = addr.port() ^ u16::from_be_bytes([MAGIC_COOKIE[0], MAGIC_COOKIE[1]]);
+    buf.extend(&x_port.to_be_bytes());
+
+    match addr.ip() {
+        IpAddr::V4(addr) => {
+            let bytes = xor_4_bytes(&addr.octets(), MAGIC_COOKIE);
+            buf.extend(&bytes);
         }
+        IpAddr::V6(addr) => {
+            let mut xor_bytes = [0u8; 16];
+            xor_bytes[..4].copy_from_slice(&MAGIC_COOKIE);
+            xor_bytes[4..].copy_from_slice(tr_id.as_bytes());
+
+            let bytes = xor_16_bytes(&addr.octets(), xor_bytes);
 
-        RawAttribute::new(Self::TYPE, buf).unwrap()
+            buf.extend(&bytes);
+        }
     }
+
+    buf
 }
 
-fn parse<'a>(input: &'a [u8], tr_id: &TransactionId) -> IResult<&'a [u8], XorMappedAddress> {
+fn parse<'a>(input: &'a [u8], tr_id: &TransactionId) -> IResult<&'a [u8], SocketAddr> {
     // The first 8 bits of the XOR_MAPPED-ADDRESS MUST be set to 0 and MUST be
     // ignored by receivers.
     let (rest, _) = tag([0])(input)?;
@@ -90,7 +100,7 @@ fn parse<'a>(input: &'a [u8], tr_id: &TransactionId) -> IResult<&'a [u8], XorMap
         (rest, IpAddr::from(xor_16_bytes(x_addr, xor_bytes)))
     };
 
-    Ok((rest, XorMappedAddress(SocketAddr::new(ip_addr, port))))
+    Ok((rest, SocketAddr::new(ip_addr, port)))
 }
 
 fn xor_4_bytes(a: &[u8], mut b: [u8; 4]) -> [u8; 4] {
@@ -170,6 +180,23 @@ mod tests {
         assert_eq!(attr, expected);
     }
 
+    #[test]
+    fn round_trip() {
+        for addr in &[
+            "213.141.156.236:48583".parse().unwrap(),
+            "[2001:db8:85a3:8d3:1319:8a2e:370:7348]:443"
+                .parse()
+                .unwrap(),
+        ] {
+            let raw = XorMappedAddress(*addr).to_raw(&TR_ID);
+            assert_eq!(raw.r#type(), XorMappedAddress::TYPE);
+            assert_eq!(
+                XorMappedAddress::from_raw(raw.value(), &TR_ID),
+                Some(XorMappedAddress(*addr))
+            );
+        }
+    }
+
     #[test]
     fn nonzero_prefix() {
         let raw = [0x42, 0x01, 0x9c, 0xd5, 0xf4, 0x9f, 0x38, 0xae];
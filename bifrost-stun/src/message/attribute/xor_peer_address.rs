@@ -0,0 +1,21 @@
+use crate::message::attribute::xor_mapped_address::{decode_xor_address, encode_xor_address};
+use crate::message::attribute::Attribute;
+use crate::message::{RawAttribute, TransactionId};
+use std::net::SocketAddr;
+
+/// The XOR-PEER-ADDRESS attribute, defined in
+/// [RFC 5766](https://tools.ietf.org/html/rfc5766#section-14.3).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct XorPeerAddress(pub SocketAddr);
+
+impl Attribute for XorPeerAddress {
+    const TYPE: u16 = 0x0012;
+
+    fn from_raw(raw: &[u8], tr_id: &TransactionId) -> Option<Self> {
+        decode_xor_address(raw, tr_id).map(Self)
+    }
+
+    fn to_raw(&self, tr_id: &TransactionId) -> RawAttribute {
+        RawAttribute::new(Self::TYPE, encode_xor_address(self.0, tr_id)).unwrap()
+    }
+}
@@ -1,9 +1,24 @@
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct Method([u8; 2]);
 
 impl Method {
     pub const BINDING: Self = Self::from_low_12_bits([0, 1]);
 
+    // TURN methods, defined in
+    // [RFC 5766](https://tools.ietf.org/html/rfc5766#section-13).
+    pub const ALLOCATE: Self = Self::from_low_12_bits([0, 3]);
+    pub const REFRESH: Self = Self::from_low_12_bits([0, 4]);
+    pub const SEND: Self = Self::from_low_12_bits([0, 6]);
+    pub const DATA: Self = Self::from_low_12_bits([0, 7]);
+    pub const CREATE_PERMISSION: Self = Self::from_low_12_bits([0, 8]);
+    pub const CHANNEL_BIND: Self = Self::from_low_12_bits([0, 9]);
+
+    // TURN methods for TCP allocations, defined in
+    // [RFC 6062](https://tools.ietf.org/html/rfc6062#section-6.1).
+    pub const CONNECT: Self = Self::from_low_12_bits([0, 0x0a]);
+    pub const CONNECTION_BIND: Self = Self::from_low_12_bits([0, 0x0b]);
+    pub const CONNECTION_ATTEMPT: Self = Self::from_low_12_bits([0, 0x0c]);
+
     pub const fn from_low_12_bits(mut bits: [u8; 2]) -> Self {
         bits[0] &= 0b1111;
         Self(bits)
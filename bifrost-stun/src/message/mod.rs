@@ -10,13 +10,15 @@ pub use self::method::Method;
 pub use self::raw_attribute::RawAttribute;
 pub use self::transaction_id::TransactionId;
 
-use crate::message::attribute::Attribute;
+use crate::codec;
+use crate::message::attribute::{Attribute, Fingerprint, MessageIntegrity};
+use bytes::BytesMut;
 
 pub(crate) const MAGIC_COOKIE: [u8; 4] = [0x21, 0x12, 0xa4, 0x42];
 
 /// Represents a STUN message, defined in
 /// [RFC 5389](https://tools.ietf.org/html/rfc5389#section-6).
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Message {
     pub class: Class,
     pub method: Method,
@@ -25,8 +27,23 @@ pub struct Message {
 }
 
 impl Message {
+    pub fn new(class: Class, method: Method, transaction_id: TransactionId) -> Self {
+        Self {
+            class,
+            method,
+            transaction_id,
+            attributes: vec![],
+        }
+    }
+
     pub fn attr<T: Attribute>(&self) -> Option<T> {
-        self.attributes.iter().find_map(|attr| {
+        self.attrs().next()
+    }
+
+    /// Returns all attributes of type `T`, for attributes that may appear more
+    /// than once in a message.
+    pub fn attrs<T: Attribute>(&self) -> impl Iterator<Item = T> + '_ {
+        self.attributes.iter().filter_map(move |attr| {
             if attr.r#type() == T::TYPE {
                 T::from_raw(attr.value(), &self.transaction_id)
             } else {
@@ -34,4 +51,174 @@ impl Message {
             }
         })
     }
+
+    pub fn add_attr<T: Attribute>(&mut self, attr: &T) {
+        self.attributes.push(attr.to_raw(&self.transaction_id));
+    }
+
+    /// Appends a MESSAGE-INTEGRITY attribute computed over the message so far.
+    /// No other attributes except FINGERPRINT may be added afterwards.
+    pub fn add_message_integrity(&mut self, key: &[u8]) {
+        let integrity = self.compute_message_integrity(self.attributes.len(), key);
+        self.add_attr(&integrity);
+    }
+
+    /// Checks the MESSAGE-INTEGRITY attribute against `key`. Returns `false`
+    /// if the message does not have one.
+    pub fn verify_message_integrity(&self, key: &[u8]) -> bool {
+        let pos = self
+            .attributes
+            .iter()
+            .position(|attr| attr.r#type() == MessageIntegrity::TYPE);
+
+        match pos {
+            Some(pos) => {
+                let expected = self.compute_message_integrity(pos, key);
+                MessageIntegrity::from_raw(self.attributes[pos].value(), &self.transaction_id)
+                    == Some(expected)
+            }
+            None => false,
+        }
+    }
+
+    /// Appends a FINGERPRINT attribute computed over the message so far. No
+    /// other attributes may be added afterwards.
+    pub fn add_fingerprint(&mut self) {
+        let fingerprint = self.compute_fingerprint(self.attributes.len());
+        self.add_attr(&fingerprint);
+    }
+
+    /// Checks that the last attribute is a correct FINGERPRINT. Returns
+    /// `false` if the message does not end with one.
+    pub fn verify_fingerprint(&self) -> bool {
+        match self.attributes.last() {
+            Some(attr) if attr.r#type() == Fingerprint::TYPE => {
+                let expected = self.compute_fingerprint(self.attributes.len() - 1);
+                Fingerprint::from_raw(attr.value(), &self.transaction_id) == Some(expected)
+            }
+            _ => false,
+        }
+    }
+
+    fn compute_fingerprint(&self, count: usize) -> Fingerprint {
+        // The CRC-32 is computed over the STUN message up to, but excluding,
+        // the FINGERPRINT attribute, with the length field including it.
+        let mut input = BytesMut::new();
+        codec::encode_partial(
+            self,
+            count,
+            codec::ATTR_HEADER_LEN + Fingerprint::LEN,
+            &mut input,
+        );
+        Fingerprint::compute(&input)
+    }
+
+    fn compute_message_integrity(&self, count: usize, key: &[u8]) -> MessageIntegrity {
+        // The text used as input to HMAC is the STUN message, including the
+        // header, up to and including the attribute preceding the
+        // MESSAGE-INTEGRITY attribute, with the length field adjusted to
+        // point to the end of the MESSAGE-INTEGRITY attribute.
+        let mut input = BytesMut::new();
+        codec::encode_partial(
+            self,
+            count,
+            codec::ATTR_HEADER_LEN + MessageIntegrity::LEN,
+            &mut input,
+        );
+        MessageIntegrity::compute(key, &input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::MessageCodec;
+    use crate::message::attribute::{IceControlled, Priority, UseCandidate, Username};
+    use tokio_codec::Decoder;
+
+    // Sample request from RFC 5769, section 2.1.
+    const SAMPLE_REQUEST: &[u8] = &[
+        0x00, 0x01, 0x00, 0x58, 0x21, 0x12, 0xa4, 0x42, 0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6,
+        0x86, 0xfa, 0x87, 0xdf, 0xae, 0x80, 0x22, 0x00, 0x10, 0x53, 0x54, 0x55, 0x4e, 0x20, 0x74,
+        0x65, 0x73, 0x74, 0x20, 0x63, 0x6c, 0x69, 0x65, 0x6e, 0x74, 0x00, 0x24, 0x00, 0x04, 0x6e,
+        0x00, 0x01, 0xff, 0x80, 0x29, 0x00, 0x08, 0x93, 0x2f, 0xf9, 0xb1, 0x51, 0x26, 0x3b, 0x36,
+        0x00, 0x06, 0x00, 0x09, 0x65, 0x76, 0x74, 0x6a, 0x3a, 0x68, 0x36, 0x76, 0x59, 0x20, 0x20,
+        0x20, 0x00, 0x08, 0x00, 0x14, 0x9a, 0xea, 0xa7, 0x0c, 0xbf, 0xd8, 0xcb, 0x56, 0x78, 0x1e,
+        0xf2, 0xb5, 0xb2, 0xd3, 0xf2, 0x49, 0xc1, 0xb5, 0x71, 0xa2, 0x80, 0x28, 0x00, 0x04, 0xe5,
+        0x7a, 0x3b, 0xcf,
+    ];
+
+    const SAMPLE_PASSWORD: &str = "VOkJxbRl1RmTxUk/WvJxBt";
+
+    fn decode(bytes: &[u8]) -> Message {
+        let mut bytes = BytesMut::from(bytes);
+        match MessageCodec::new().decode(&mut bytes) {
+            Ok(Some(Some(msg))) => msg,
+            x => panic!("failed to decode {:?}", x),
+        }
+    }
+
+    #[test]
+    fn verify_message_integrity() {
+        let msg = decode(SAMPLE_REQUEST);
+        let key = MessageIntegrity::short_term_key(SAMPLE_PASSWORD);
+
+        assert_eq!(
+            msg.attr::<Username>(),
+            Some(Username("evtj:h6vY".to_owned()))
+        );
+        assert!(msg.verify_message_integrity(&key));
+        assert!(!msg.verify_message_integrity(b"wrong"));
+    }
+
+    #[test]
+    fn add_message_integrity() {
+        let tr_id = TransactionId::new([7; 12]);
+        let mut msg = Message::new(Class::Request, Method::ALLOCATE, tr_id);
+        msg.add_attr(&Username("user".to_owned()));
+        assert!(!msg.verify_message_integrity(b"key"));
+
+        msg.add_message_integrity(b"key");
+        assert!(msg.verify_message_integrity(b"key"));
+        assert!(!msg.verify_message_integrity(b"other key"));
+
+        // Tampering with a protected attribute invalidates the message.
+        msg.attributes[0] = Username("evil".to_owned()).to_raw(&tr_id);
+        assert!(!msg.verify_message_integrity(b"key"));
+    }
+
+    #[test]
+    fn ice_attributes() {
+        let msg = decode(SAMPLE_REQUEST);
+        assert_eq!(msg.attr::<Priority>(), Some(Priority(0x6e00_01ff)));
+        assert_eq!(
+            msg.attr::<IceControlled>(),
+            Some(IceControlled(0x932f_f9b1_5126_3b36))
+        );
+        assert_eq!(msg.attr::<UseCandidate>(), None);
+    }
+
+    #[test]
+    fn verify_fingerprint() {
+        let msg = decode(SAMPLE_REQUEST);
+        assert!(msg.verify_fingerprint());
+
+        let mut msg = decode(SAMPLE_REQUEST);
+        msg.transaction_id = TransactionId::new([0; 12]);
+        assert!(!msg.verify_fingerprint());
+    }
+
+    #[test]
+    fn add_fingerprint() {
+        let key = MessageIntegrity::short_term_key(SAMPLE_PASSWORD);
+        let mut msg = decode(SAMPLE_REQUEST);
+        let expected = msg.attributes.pop();
+        assert!(!msg.verify_fingerprint());
+
+        msg.add_fingerprint();
+        assert_eq!(msg.attributes.last(), expected.as_ref());
+        assert!(msg.verify_fingerprint());
+        // The fingerprint does not affect the message integrity.
+        assert!(msg.verify_message_integrity(&key));
+    }
 }
@@ -1,19 +1,27 @@
 use std::io;
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct RawAttribute {
     r#type: u16,
     value: Vec<u8>,
+    // Padding bytes may be any value, and are kept so that a decoded message
+    // re-encodes to exactly the same bytes for MESSAGE-INTEGRITY checks.
+    padding: [u8; 3],
 }
 
 impl RawAttribute {
-    /// The maximum allowed length of an attribute in bytes. Set arbitrarily to
-    /// 1024.
-    pub const MAX_LEN: u16 = 1024;
+    /// The maximum allowed length of an attribute in bytes. Set to the largest
+    /// value that still fits in a single message, since TURN DATA attributes
+    /// carry whole application datagrams.
+    pub const MAX_LEN: u16 = 65_508;
 
     pub fn new(r#type: u16, value: Vec<u8>) -> io::Result<Self> {
         if value.len() <= Self::MAX_LEN as usize {
-            Ok(Self { r#type, value })
+            Ok(Self {
+                r#type,
+                value,
+                padding: [0; 3],
+            })
         } else {
             Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
@@ -22,6 +30,11 @@ impl RawAttribute {
         }
     }
 
+    pub(crate) fn with_padding(mut self, padding: &[u8]) -> Self {
+        self.padding[..padding.len()].copy_from_slice(padding);
+        self
+    }
+
     pub fn r#type(&self) -> u16 {
         self.r#type
     }
@@ -37,6 +50,10 @@ impl RawAttribute {
     pub fn padded_len(&self) -> u16 {
         (self.unpadded_len() + 3) & !0b11
     }
+
+    pub(crate) fn padding(&self) -> &[u8] {
+        &self.padding[..(self.padded_len() - self.unpadded_len()) as usize]
+    }
 }
 
 #[cfg(test)]
@@ -1,3 +1,5 @@
+use rand::{Rng, RngCore};
+
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct TransactionId([u8; 12]);
 
@@ -6,6 +8,18 @@ impl TransactionId {
         Self(bytes)
     }
 
+    /// Generates a transaction ID uniformly and randomly, as recommended by
+    /// [RFC 5389](https://tools.ietf.org/html/rfc5389#section-6).
+    pub fn random() -> Self {
+        Self::from_rng(&mut rand::thread_rng())
+    }
+
+    /// Generates a transaction ID with `rng`, which need not be the thread
+    /// RNG, e.g. to make a simulation reproducible.
+    pub fn from_rng<R: RngCore + ?Sized>(rng: &mut R) -> Self {
+        Self(rng.gen())
+    }
+
     pub const fn as_bytes(&self) -> &[u8; 12] {
         &self.0
     }
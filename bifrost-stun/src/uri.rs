@@ -0,0 +1,375 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+use tokio_executor::blocking;
+
+/// The scheme of a STUN or TURN URI.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Scheme {
+    Stun,
+    Stuns,
+    Turn,
+    Turns,
+}
+
+impl Scheme {
+    /// Returns the port to use when the URI has none.
+    pub fn default_port(self) -> u16 {
+        match self {
+            Scheme::Stun | Scheme::Turn => 3478,
+            Scheme::Stuns | Scheme::Turns => 5349,
+        }
+    }
+
+    /// Returns whether the server is reached over TLS or DTLS.
+    pub fn is_secure(self) -> bool {
+        match self {
+            Scheme::Stun | Scheme::Turn => false,
+            Scheme::Stuns | Scheme::Turns => true,
+        }
+    }
+
+    pub fn is_turn(self) -> bool {
+        match self {
+            Scheme::Stun | Scheme::Stuns => false,
+            Scheme::Turn | Scheme::Turns => true,
+        }
+    }
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = match self {
+            Scheme::Stun => "stun",
+            Scheme::Stuns => "stuns",
+            Scheme::Turn => "turn",
+            Scheme::Turns => "turns",
+        };
+        write!(f, "{}", scheme)
+    }
+}
+
+/// The host of a STUN or TURN URI.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Host {
+    Ip(IpAddr),
+    Domain(String),
+}
+
+impl fmt::Display for Host {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Host::Ip(IpAddr::V4(ip)) => write!(f, "{}", ip),
+            Host::Ip(IpAddr::V6(ip)) => write!(f, "[{}]", ip),
+            Host::Domain(domain) => write!(f, "{}", domain),
+        }
+    }
+}
+
+/// The transport to reach a TURN server over, defined in
+/// [RFC 7065](https://tools.ietf.org/html/rfc7065#section-3.1).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Transport {
+    Udp,
+    Tcp,
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transport::Udp => write!(f, "udp"),
+            Transport::Tcp => write!(f, "tcp"),
+        }
+    }
+}
+
+/// A STUN URI, defined in
+/// [RFC 7064](https://tools.ietf.org/html/rfc7064#section-3.1), or a TURN
+/// URI, defined in
+/// [RFC 7065](https://tools.ietf.org/html/rfc7065#section-3.1).
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct IceServerUri {
+    pub scheme: Scheme,
+    pub host: Host,
+    pub port: Option<u16>,
+    /// The `transport` parameter, which only TURN URIs may have.
+    pub transport: Option<Transport>,
+}
+
+impl IceServerUri {
+    /// Returns the port, or the default for the scheme if there is none.
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or_else(|| self.scheme.default_port())
+    }
+
+    /// Returns the transport, which is UDP for `stun:` and `turn:` URIs and
+    /// TCP for `stuns:` and `turns:` URIs unless specified otherwise.
+    pub fn transport(&self) -> Transport {
+        match self.transport {
+            Some(transport) => transport,
+            None if self.scheme.is_secure() => Transport::Tcp,
+            None => Transport::Udp,
+        }
+    }
+
+    /// Returns the addresses of the server. A domain name is looked up on the
+    /// blocking thread pool.
+    pub async fn socket_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        match &self.host {
+            Host::Ip(ip) => Ok(vec![SocketAddr::new(*ip, self.port())]),
+            Host::Domain(domain) => {
+                let host = (domain.clone(), self.port());
+                blocking::run(move || Ok(host.to_socket_addrs()?.collect())).await
+            }
+        }
+    }
+}
+
+impl fmt::Display for IceServerUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.scheme, self.host)?;
+        if let Some(port) = self.port {
+            write!(f, ":{}", port)?;
+        }
+        if let Some(transport) = self.transport {
+            write!(f, "?transport={}", transport)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for IceServerUri {
+    type Err = ParseUriError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // scheme ":" host [ ":" port ] [ "?transport=" transport ]
+        let colon = s.find(':').ok_or(ParseUriError("missing scheme"))?;
+        let scheme = match s[..colon].to_ascii_lowercase().as_str() {
+            "stun" => Scheme::Stun,
+            "stuns" => Scheme::Stuns,
+            "turn" => Scheme::Turn,
+            "turns" => Scheme::Turns,
+            _ => return Err(ParseUriError("unknown scheme")),
+        };
+        let rest = &s[colon + 1..];
+
+        let (authority, query) = match rest.find('?') {
+            Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+            None => (rest, None),
+        };
+
+        let transport = match query {
+            None => None,
+            Some(_) if !scheme.is_turn() => return Err(ParseUriError("unexpected query")),
+            Some(query) => {
+                let (key, value) = match query.find('=') {
+                    Some(i) => (&query[..i], &query[i + 1..]),
+                    None => return Err(ParseUriError("invalid query")),
+                };
+                if key != "transport" {
+                    return Err(ParseUriError("invalid query"));
+                }
+                match value.to_ascii_lowercase().as_str() {
+                    "udp" => Some(Transport::Udp),
+                    "tcp" => Some(Transport::Tcp),
+                    _ => return Err(ParseUriError("unknown transport")),
+                }
+            }
+        };
+
+        let (host, port) = parse_authority(authority)?;
+        Ok(Self {
+            scheme,
+            host,
+            port,
+            transport,
+        })
+    }
+}
+
+/// Parses `host [ ":" port ]`, where the host may be an IPv6 literal in
+/// brackets.
+fn parse_authority(authority: &str) -> Result<(Host, Option<u16>), ParseUriError> {
+    let (host, port) = if authority.starts_with('[') {
+        let end = authority
+            .find(']')
+            .ok_or(ParseUriError("unterminated IPv6 literal"))?;
+        let ip = authority[1..end]
+            .parse()
+            .map_err(|_| ParseUriError("invalid IPv6 literal"))?;
+        (Host::Ip(IpAddr::V6(ip)), &authority[end + 1..])
+    } else {
+        let end = authority.find(':').unwrap_or(authority.len());
+        let host = &authority[..end];
+        let host = match host.parse() {
+            Ok(ip) => Host::Ip(IpAddr::V4(ip)),
+            Err(_) if is_reg_name(host) => Host::Domain(host.to_owned()),
+            Err(_) => return Err(ParseUriError("invalid host")),
+        };
+        (host, &authority[end..])
+    };
+
+    let port = match port {
+        "" => None,
+        port if port.starts_with(':') => Some(
+            port[1..]
+                .parse()
+                .map_err(|_| ParseUriError("invalid port"))?,
+        ),
+        _ => return Err(ParseUriError("invalid host")),
+    };
+
+    Ok((host, port))
+}
+
+/// Returns whether `host` is a valid reg-name, defined in
+/// [RFC 3986](https://tools.ietf.org/html/rfc3986#section-3.2.2), without
+/// percent-encoding, which host names cannot use anyway.
+fn is_reg_name(host: &str) -> bool {
+    !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-._~!$&'()*+,;=".contains(c))
+}
+
+/// An error parsing an `IceServerUri`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ParseUriError(&'static str);
+
+impl fmt::Display for ParseUriError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid STUN or TURN URI: {}", self.0)
+    }
+}
+
+impl Error for ParseUriError {}
+
+/// A STUN or TURN server, configured by the same URIs and credentials as an
+/// `RTCIceServer` in WebRTC.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IceServer {
+    /// URIs of the same server, possibly over different transports.
+    pub urls: Vec<IceServerUri>,
+    /// The username for TURN servers.
+    pub username: Option<String>,
+    /// The password for TURN servers.
+    pub credential: Option<String>,
+}
+
+impl IceServer {
+    pub fn new(urls: Vec<IceServerUri>) -> Self {
+        Self {
+            urls,
+            username: None,
+            credential: None,
+        }
+    }
+
+    pub fn with_credentials(mut self, username: &str, credential: &str) -> Self {
+        self.username = Some(username.to_owned());
+        self.credential = Some(credential.to_owned());
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn assert_parse_display(s: &str, expected: IceServerUri) {
+        let uri: IceServerUri = s.parse().unwrap();
+        assert_eq!(uri, expected);
+        assert_eq!(uri.to_string(), s);
+    }
+
+    #[test]
+    fn valid() {
+        assert_parse_display(
+            "stun:stun.example.com",
+            IceServerUri {
+                scheme: Scheme::Stun,
+                host: Host::Domain("stun.example.com".to_owned()),
+                port: None,
+                transport: None,
+            },
+        );
+        assert_parse_display(
+            "stuns:192.0.2.1:443",
+            IceServerUri {
+                scheme: Scheme::Stuns,
+                host: Host::Ip(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))),
+                port: Some(443),
+                transport: None,
+            },
+        );
+        assert_parse_display(
+            "turn:[2001:db8::1]:3479?transport=udp",
+            IceServerUri {
+                scheme: Scheme::Turn,
+                host: Host::Ip(IpAddr::V6("2001:db8::1".parse().unwrap())),
+                port: Some(3479),
+                transport: Some(Transport::Udp),
+            },
+        );
+        assert_parse_display(
+            "turns:relay.example.com:443?transport=tcp",
+            IceServerUri {
+                scheme: Scheme::Turns,
+                host: Host::Domain("relay.example.com".to_owned()),
+                port: Some(443),
+                transport: Some(Transport::Tcp),
+            },
+        );
+
+        let uri: IceServerUri = "TURN:[::1]".parse().unwrap();
+        assert_eq!(uri.host, Host::Ip(IpAddr::V6(Ipv6Addr::LOCALHOST)));
+        assert_eq!(uri.to_string(), "turn:[::1]");
+    }
+
+    #[test]
+    fn defaults() {
+        let uri: IceServerUri = "stun:example.com".parse().unwrap();
+        assert_eq!(uri.port(), 3478);
+        assert_eq!(uri.transport(), Transport::Udp);
+
+        let uri: IceServerUri = "turns:example.com".parse().unwrap();
+        assert_eq!(uri.port(), 5349);
+        assert_eq!(uri.transport(), Transport::Tcp);
+
+        let uri: IceServerUri = "turn:127.0.0.1?transport=tcp".parse().unwrap();
+        assert_eq!(uri.transport(), Transport::Tcp);
+        assert_eq!(
+            tokio_test::block_on(uri.socket_addrs()).unwrap(),
+            vec!["127.0.0.1:3478".parse().unwrap()]
+        );
+
+        let uri: IceServerUri = "stun:localhost:3479".parse().unwrap();
+        let addrs = tokio_test::block_on(uri.socket_addrs()).unwrap();
+        assert!(addrs.iter().all(|addr| addr.port() == 3479));
+    }
+
+    #[test]
+    fn invalid() {
+        for s in &[
+            "",
+            "stun",
+            "http://example.com",
+            "stun:",
+            "stun://example.com",
+            "stun:example.com:",
+            "stun:example.com:65536",
+            "stun:example.com?transport=udp",
+            "turn:example.com?transport=sctp",
+            "turn:example.com?foo=udp",
+            "turn:[::1",
+            "turn:[example.com]",
+            "turn:[::1]3478",
+            "turn:exa mple.com",
+        ] {
+            assert!(s.parse::<IceServerUri>().is_err(), "parsed {:?}", s);
+        }
+    }
+}
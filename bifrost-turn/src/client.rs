@@ -0,0 +1,854 @@
+use crate::frame::{ChannelData, Frame};
+use bifrost_stun::agent::{
+    Timer, Transactions, DEFAULT_MAX_REQUESTS, DEFAULT_RTO, DEFAULT_TIMEOUT,
+};
+use bifrost_stun::codec::MessageCodec;
+use bifrost_stun::message::attribute::{
+    AdditionalAddressFamily, ChannelNumber, ConnectionId, Data, ErrorCode, Lifetime,
+    MessageIntegrity, Nonce, Realm, RequestedAddressFamily, RequestedTransport, Username,
+    XorMappedAddress, XorPeerAddress, XorRelayedAddress,
+};
+use bifrost_stun::message::{Class, Message, Method, TransactionId};
+use bifrost_stun::uri::IceServer;
+use bytes::BytesMut;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_codec::{Decoder, Encoder};
+use tokio_io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_sync::{watch, Mutex};
+use tokio_timer::Timeout;
+
+/// The allocation lifetime a server uses if it does not say otherwise.
+pub const DEFAULT_LIFETIME: Duration = Duration::from_secs(600);
+
+/// How long before it expires an allocation is refreshed, at most half its
+/// lifetime.
+const ALLOCATION_REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+/// How often permissions, which last 5 minutes, are refreshed, defined in
+/// [RFC 5766](https://tools.ietf.org/html/rfc5766#section-8).
+const PERMISSION_REFRESH_INTERVAL: Duration = Duration::from_secs(240);
+
+/// How often channel bindings, which last 10 minutes, are refreshed, defined
+/// in [RFC 5766](https://tools.ietf.org/html/rfc5766#section-11).
+const CHANNEL_REFRESH_INTERVAL: Duration = Duration::from_secs(540);
+
+/// Long-term credentials, defined in
+/// [RFC 5389](https://tools.ietf.org/html/rfc5389#section-10.2).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl Credentials {
+    pub fn new(username: &str, password: &str) -> Self {
+        Self {
+            username: username.to_owned(),
+            password: password.to_owned(),
+        }
+    }
+
+    /// Returns the username and credential of `server`, which TURN servers
+    /// must have.
+    pub fn from_ice_server(server: &IceServer) -> io::Result<Self> {
+        match (&server.username, &server.credential) {
+            (Some(username), Some(credential)) => Ok(Self::new(username, credential)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "missing TURN credentials",
+            )),
+        }
+    }
+}
+
+/// The address families to allocate relayed transport addresses in, defined
+/// in [RFC 6156](https://tools.ietf.org/html/rfc6156) and
+/// [RFC 8656](https://tools.ietf.org/html/rfc8656#section-7.1).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AddressFamily {
+    Ipv4,
+    Ipv6,
+    /// Both an IPv4 and an IPv6 relayed transport address.
+    DualStack,
+}
+
+/// An allocation on a TURN server.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Allocation {
+    /// The address peers send data to.
+    pub relayed_addr: SocketAddr,
+    /// The IPv6 address peers send data to, in a dual-stack allocation.
+    pub additional_relayed_addr: Option<SocketAddr>,
+    /// The client's address as seen by the server.
+    pub mapped_addr: Option<SocketAddr>,
+    pub lifetime: Duration,
+}
+
+impl Allocation {
+    /// Returns the relayed transport address that can reach `peer`, which
+    /// must be of the same address family.
+    pub fn relayed_addr_for(&self, peer: IpAddr) -> Option<SocketAddr> {
+        Some(self.relayed_addr)
+            .into_iter()
+            .chain(self.additional_relayed_addr)
+            .find(|addr| addr.is_ipv4() == peer.is_ipv4())
+    }
+}
+
+/// Something the server sent on behalf of a peer.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+    /// Data relayed from a peer.
+    Data(Vec<u8>, SocketAddr),
+    /// A peer opened a connection to a TCP allocation, defined in
+    /// [RFC 6062](https://tools.ietf.org/html/rfc6062#section-4.4). The client
+    /// accepts it by binding a new connection to the server to the ID.
+    ConnectionAttempt(ConnectionId, SocketAddr),
+}
+
+/// A failure response from the server, carried inside an `io::Error`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ErrorResponse(pub ErrorCode);
+
+impl ErrorResponse {
+    /// Returns the error code if `err` was caused by a failure response.
+    pub fn code(err: &io::Error) -> Option<u16> {
+        err.get_ref()
+            .and_then(|e| e.downcast_ref::<Self>())
+            .map(|e| (e.0).code)
+    }
+}
+
+impl fmt::Display for ErrorResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.0.code, self.0.reason)
+    }
+}
+
+impl Error for ErrorResponse {}
+
+impl From<ErrorResponse> for io::Error {
+    fn from(err: ErrorResponse) -> Self {
+        let kind = match err.0.code {
+            ErrorCode::UNAUTHORIZED | ErrorCode::FORBIDDEN | ErrorCode::WRONG_CREDENTIALS => {
+                io::ErrorKind::PermissionDenied
+            }
+            _ => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+/// A TURN client, defined in
+/// [RFC 5766](https://tools.ietf.org/html/rfc5766).
+///
+/// Like `Agent`, the client does no I/O itself: `on_send` is called with
+/// every frame to send to the server, and every frame received from the
+/// server must be passed to `on_recv`.
+pub struct Client<F> {
+    inner: Arc<Inner<F>>,
+}
+
+struct Inner<F> {
+    server: SocketAddr,
+    credentials: Credentials,
+    on_send: F,
+    // Whether frames reach the server over TCP or TLS, in which case
+    // requests are not retransmitted.
+    reliable: bool,
+    transactions: Transactions,
+    /// Draws the transaction IDs of requests and indications.
+    rng: std::sync::Mutex<StdRng>,
+    state: Mutex<State>,
+    // When `refresh_due` next has something to refresh.
+    next_refresh_tx: watch::Sender<Option<Instant>>,
+    next_refresh_rx: watch::Receiver<Option<Instant>>,
+}
+
+#[derive(Default)]
+struct State {
+    realm: Option<Realm>,
+    nonce: Option<Nonce>,
+    allocation: Option<Allocation>,
+    relay: Option<(RequestedTransport, AddressFamily)>,
+    // When the allocation, each permission and each channel binding were
+    // last created or refreshed.
+    refreshed_at: Option<Instant>,
+    permissions: HashMap<IpAddr, Instant>,
+    channels: HashMap<SocketAddr, (ChannelNumber, Instant)>,
+}
+
+impl State {
+    fn peer_of(&self, channel: ChannelNumber) -> Option<SocketAddr> {
+        self.channels
+            .iter()
+            .find(|(_, &(c, _))| c == channel)
+            .map(|(&peer, _)| peer)
+    }
+
+    fn next_channel(&self) -> Option<ChannelNumber> {
+        (ChannelNumber::MIN..=ChannelNumber::MAX)
+            .map(ChannelNumber)
+            .find(|c| self.peer_of(*c).is_none())
+    }
+
+    fn allocation_refresh(&self) -> Option<Instant> {
+        let lifetime = self.allocation?.lifetime;
+        let margin = ALLOCATION_REFRESH_MARGIN.min(lifetime / 2);
+        Some(self.refreshed_at? + lifetime - margin)
+    }
+
+    fn next_refresh(&self) -> Option<Instant> {
+        let permissions = self
+            .permissions
+            .values()
+            .map(|&at| at + PERMISSION_REFRESH_INTERVAL);
+        let channels = self
+            .channels
+            .values()
+            .map(|&(_, at)| at + CHANNEL_REFRESH_INTERVAL);
+        let allocation = self.allocation_refresh()?;
+        permissions.chain(channels).chain(Some(allocation)).min()
+    }
+}
+
+impl<F> Clone for Client<F> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<F, Fut> Client<F>
+where
+    F: Fn(Frame, SocketAddr) -> Fut,
+    Fut: Future<Output = io::Result<()>>,
+{
+    /// Creates a client for a server reached over UDP, which retransmits
+    /// requests until they are answered.
+    pub fn new(server: SocketAddr, credentials: Credentials, on_send: F) -> Self {
+        Self::with_reliability(server, credentials, on_send, false)
+    }
+
+    /// Creates a client for a server reached over TCP or TLS, which sends
+    /// every request only once.
+    pub fn new_reliable(server: SocketAddr, credentials: Credentials, on_send: F) -> Self {
+        Self::with_reliability(server, credentials, on_send, true)
+    }
+
+    fn with_reliability(
+        server: SocketAddr,
+        credentials: Credentials,
+        on_send: F,
+        reliable: bool,
+    ) -> Self {
+        let (next_refresh_tx, next_refresh_rx) = watch::channel(None);
+        Self {
+            inner: Arc::new(Inner {
+                server,
+                credentials,
+                on_send,
+                reliable,
+                transactions: Transactions::new(),
+                rng: std::sync::Mutex::new(StdRng::from_entropy()),
+                state: Mutex::new(State::default()),
+                next_refresh_tx,
+                next_refresh_rx,
+            }),
+        }
+    }
+
+    /// Seeds the random number generator transaction IDs are drawn from.
+    pub fn with_seed(self, seed: u64) -> Self {
+        *self.inner.rng.lock().unwrap() = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    /// Sets the timer requests are retransmitted and time out on, instead of
+    /// the system clock.
+    ///
+    /// Panics if the client has been cloned.
+    pub fn with_timer(mut self, timer: Timer) -> Self {
+        let inner = Arc::get_mut(&mut self.inner).expect("client already cloned");
+        inner.transactions = Transactions::with_timer(timer);
+        self
+    }
+
+    pub fn server(&self) -> SocketAddr {
+        self.inner.server
+    }
+
+    /// Returns the current allocation, if any.
+    pub async fn allocation(&self) -> Option<Allocation> {
+        self.inner.state.lock().await.allocation
+    }
+
+    /// Creates an allocation with a UDP relay on an IPv4 address.
+    pub async fn allocate(&self) -> io::Result<Allocation> {
+        self.allocate_with(RequestedTransport::UDP, AddressFamily::Ipv4)
+            .await
+    }
+
+    /// Creates an allocation with a TCP relay, defined in
+    /// [RFC 6062](https://tools.ietf.org/html/rfc6062#section-4.1). This is
+    /// only possible over a TCP or TLS connection to the server.
+    pub async fn allocate_tcp(&self) -> io::Result<Allocation> {
+        self.allocate_with(RequestedTransport::TCP, AddressFamily::Ipv4)
+            .await
+    }
+
+    /// Creates an allocation relaying `transport` in the address families
+    /// given by `family`. A server without dual-stack support may allocate
+    /// only an IPv4 address when asked for both.
+    pub async fn allocate_with(
+        &self,
+        transport: RequestedTransport,
+        family: AddressFamily,
+    ) -> io::Result<Allocation> {
+        let res = self
+            .request(Method::ALLOCATE, |msg| {
+                msg.add_attr(&transport);
+                // Servers allocate IPv4 addresses unless asked otherwise.
+                match family {
+                    AddressFamily::Ipv4 => (),
+                    AddressFamily::Ipv6 => msg.add_attr(&RequestedAddressFamily::IPV6),
+                    AddressFamily::DualStack => msg.add_attr(&AdditionalAddressFamily::IPV6),
+                }
+            })
+            .await?;
+
+        let mut relayed_addrs = res.attrs::<XorRelayedAddress>().map(|attr| attr.0);
+        let relayed_addr = relayed_addrs
+            .next()
+            .ok_or_else(|| invalid_response("missing XOR-RELAYED-ADDRESS"))?;
+        let allocation = Allocation {
+            relayed_addr,
+            additional_relayed_addr: relayed_addrs.next(),
+            mapped_addr: res.attr::<XorMappedAddress>().map(|attr| attr.0),
+            lifetime: res
+                .attr::<Lifetime>()
+                .map_or(DEFAULT_LIFETIME, |attr| attr.0),
+        };
+
+        let mut state = self.inner.state.lock().await;
+        state.allocation = Some(allocation);
+        state.relay = Some((transport, family));
+        state.refreshed_at = Some(Instant::now());
+        self.reschedule(&state);
+        Ok(allocation)
+    }
+
+    /// Creates a new allocation after the old one was lost, e.g. because the
+    /// connection to the server was closed, and restores its permissions and
+    /// channel bindings.
+    pub async fn reallocate(&self) -> io::Result<Allocation> {
+        let (transport, family) = self
+            .inner
+            .state
+            .lock()
+            .await
+            .relay
+            .unwrap_or((RequestedTransport::UDP, AddressFamily::Ipv4));
+        let allocation = self.allocate_with(transport, family).await?;
+
+        let (permissions, channels) = {
+            let state = self.inner.state.lock().await;
+            (state.permissions.clone(), state.channels.clone())
+        };
+        for peer in permissions.keys() {
+            self.create_permission(*peer).await?;
+        }
+        for (peer, (channel, _)) in channels {
+            self.request_channel_bind(peer, channel).await?;
+        }
+
+        Ok(allocation)
+    }
+
+    /// Refreshes the allocation, asking for `lifetime`. Returns the lifetime
+    /// granted by the server.
+    pub async fn refresh(&self, lifetime: Duration) -> io::Result<Duration> {
+        let res = self
+            .request(Method::REFRESH, |msg| msg.add_attr(&Lifetime(lifetime)))
+            .await?;
+        let lifetime = res.attr::<Lifetime>().map_or(lifetime, |attr| attr.0);
+
+        let mut state = self.inner.state.lock().await;
+        if let Some(allocation) = &mut state.allocation {
+            allocation.lifetime = lifetime;
+        }
+        state.refreshed_at = Some(Instant::now());
+        self.reschedule(&state);
+        Ok(lifetime)
+    }
+
+    /// Returns a receiver of when `refresh_due` next has something to
+    /// refresh, which is `None` without an allocation. It is updated whenever
+    /// that changes.
+    pub fn next_refresh(&self) -> watch::Receiver<Option<Instant>> {
+        self.inner.next_refresh_rx.clone()
+    }
+
+    /// Refreshes the allocation, permissions and channel bindings that are
+    /// about to expire at `now`. Everything due is considered refreshed
+    /// before the requests are answered, so that calling this again while
+    /// they are pending does not repeat them.
+    pub async fn refresh_due(&self, now: Instant) -> io::Result<()> {
+        let (allocation, permissions, channels) = {
+            let mut state = self.inner.state.lock().await;
+            let allocation = match state.allocation_refresh() {
+                Some(at) if at <= now => state.allocation.map(|allocation| allocation.lifetime),
+                _ => None,
+            };
+            if allocation.is_some() {
+                state.refreshed_at = Some(now);
+            }
+
+            let mut permissions = Vec::new();
+            for (&peer, at) in &mut state.permissions {
+                if *at + PERMISSION_REFRESH_INTERVAL <= now {
+                    *at = now;
+                    permissions.push(peer);
+                }
+            }
+            let mut channels = Vec::new();
+            for (&peer, (channel, at)) in &mut state.channels {
+                if *at + CHANNEL_REFRESH_INTERVAL <= now {
+                    *at = now;
+                    channels.push((peer, *channel));
+                }
+            }
+            self.reschedule(&state);
+            (allocation, permissions, channels)
+        };
+
+        if let Some(lifetime) = allocation {
+            self.refresh(lifetime).await?;
+        }
+        for peer in permissions {
+            self.create_permission(peer).await?;
+        }
+        for (peer, channel) in channels {
+            self.request_channel_bind(peer, channel).await?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the allocation along with its permissions and channel bindings.
+    pub async fn deallocate(&self) -> io::Result<()> {
+        self.refresh(Duration::from_secs(0)).await?;
+
+        let mut state = self.inner.state.lock().await;
+        state.allocation = None;
+        state.refreshed_at = None;
+        state.permissions.clear();
+        state.channels.clear();
+        self.reschedule(&state);
+        Ok(())
+    }
+
+    /// Allows `peer` to send data to the relayed address.
+    pub async fn create_permission(&self, peer: IpAddr) -> io::Result<()> {
+        // The port portion of each attribute is ignored.
+        self.request(Method::CREATE_PERMISSION, |msg| {
+            msg.add_attr(&XorPeerAddress(SocketAddr::new(peer, 0)))
+        })
+        .await?;
+
+        let mut state = self.inner.state.lock().await;
+        state.permissions.insert(peer, Instant::now());
+        self.reschedule(&state);
+        Ok(())
+    }
+
+    /// Binds a channel to `peer`, or refreshes the existing binding, so that
+    /// data is exchanged in compact ChannelData messages.
+    pub async fn bind_channel(&self, peer: SocketAddr) -> io::Result<ChannelNumber> {
+        let channel = {
+            let state = self.inner.state.lock().await;
+            match state.channels.get(&peer) {
+                Some(&(channel, _)) => Some(channel),
+                None => state.next_channel(),
+            }
+        };
+        let channel = channel.ok_or_else(|| io::Error::other("no channel numbers available"))?;
+
+        self.request_channel_bind(peer, channel).await?;
+        Ok(channel)
+    }
+
+    /// Asks the server to open a TCP connection to `peer` from a TCP
+    /// allocation. Returns the ID to bind a new connection to the server to,
+    /// through which the client then talks to the peer.
+    pub async fn connect(&self, peer: SocketAddr) -> io::Result<ConnectionId> {
+        let res = self
+            .request(Method::CONNECT, |msg| msg.add_attr(&XorPeerAddress(peer)))
+            .await?;
+
+        res.attr::<ConnectionId>()
+            .ok_or_else(|| invalid_response("missing CONNECTION-ID"))
+    }
+
+    /// Binds `stream`, a new connection to the server, to the peer connection
+    /// identified by `id`. From then on, everything written to and read from
+    /// `stream` is exchanged with the peer as is.
+    pub async fn bind_connection<S>(&self, stream: &mut S, id: ConnectionId) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        for _ in 0..3 {
+            let (msg, key) = self
+                .new_request(Method::CONNECTION_BIND, |msg| msg.add_attr(&id))
+                .await;
+
+            let exchange = async {
+                let mut buf = BytesMut::new();
+                MessageCodec::new().encode(msg, &mut buf)?;
+                stream.write_all(&buf).await?;
+                read_message(stream).await
+            };
+            let res = Timeout::new(exchange, DEFAULT_TIMEOUT)
+                .await
+                .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+
+            if self.check_response(&res, key.as_ref()).await? {
+                return Ok(());
+            }
+        }
+
+        Err(invalid_response("authentication failed"))
+    }
+
+    /// Sends `data` to `peer` through the relay, over a channel if one is
+    /// bound and in a Send indication otherwise.
+    pub async fn send_to(&self, data: &[u8], peer: SocketAddr) -> io::Result<()> {
+        let channel = self
+            .inner
+            .state
+            .lock()
+            .await
+            .channels
+            .get(&peer)
+            .map(|&(channel, _)| channel);
+
+        let frame = match channel {
+            Some(channel) => Frame::ChannelData(ChannelData::new(channel, data.to_vec())),
+            None => {
+                let mut msg = Message::new(Class::Indication, Method::SEND, self.transaction_id());
+                msg.add_attr(&XorPeerAddress(peer));
+                msg.add_attr(&Data(data.to_vec()));
+                Frame::Message(msg)
+            }
+        };
+
+        (self.inner.on_send)(frame, self.inner.server).await
+    }
+
+    /// Handles a frame received from `addr`. Returns what the server sent on
+    /// behalf of a peer, if anything.
+    pub async fn on_recv(&self, frame: Frame, addr: SocketAddr) -> Option<Event> {
+        if addr != self.inner.server {
+            return None;
+        }
+
+        match frame {
+            Frame::Message(msg) => match msg.class {
+                Class::SuccessResponse | Class::FailureResponse => {
+                    self.inner.transactions.complete(msg, addr).await;
+                    None
+                }
+                Class::Indication if msg.method == Method::DATA => {
+                    let peer = msg.attr::<XorPeerAddress>()?.0;
+                    let data = msg.attr::<Data>()?.0;
+                    Some(Event::Data(data, peer))
+                }
+                Class::Indication if msg.method == Method::CONNECTION_ATTEMPT => {
+                    let id = msg.attr::<ConnectionId>()?;
+                    let peer = msg.attr::<XorPeerAddress>()?.0;
+                    Some(Event::ConnectionAttempt(id, peer))
+                }
+                _ => None,
+            },
+            Frame::ChannelData(ChannelData { channel, data }) => {
+                let peer = self.inner.state.lock().await.peer_of(channel)?;
+                Some(Event::Data(data, peer))
+            }
+        }
+    }
+
+    async fn request_channel_bind(
+        &self,
+        peer: SocketAddr,
+        channel: ChannelNumber,
+    ) -> io::Result<()> {
+        self.request(Method::CHANNEL_BIND, |msg| {
+            msg.add_attr(&channel);
+            msg.add_attr(&XorPeerAddress(peer));
+        })
+        .await?;
+
+        // A channel binding also installs a permission for the peer.
+        let mut state = self.inner.state.lock().await;
+        let now = Instant::now();
+        state.channels.insert(peer, (channel, now));
+        state.permissions.insert(peer.ip(), now);
+        self.reschedule(&state);
+        Ok(())
+    }
+
+    fn transaction_id(&self) -> TransactionId {
+        TransactionId::from_rng(&mut *self.inner.rng.lock().unwrap())
+    }
+
+    fn reschedule(&self, state: &State) {
+        let _ = self.inner.next_refresh_tx.broadcast(state.next_refresh());
+    }
+
+    /// Sends an authenticated request, retrying once the server has told us
+    /// its realm and a fresh nonce.
+    async fn request<B>(&self, method: Method, build: B) -> io::Result<Message>
+    where
+        B: Fn(&mut Message),
+    {
+        // The first attempt may lack a nonce, and the second may use one that
+        // has gone stale since.
+        for _ in 0..3 {
+            let (msg, key) = self.new_request(method, &build).await;
+            let res = self.send_request(msg).await?;
+            if self.check_response(&res, key.as_ref()).await? {
+                return Ok(res);
+            }
+        }
+
+        Err(invalid_response("authentication failed"))
+    }
+
+    async fn new_request<B>(&self, method: Method, build: B) -> (Message, Option<Vec<u8>>)
+    where
+        B: Fn(&mut Message),
+    {
+        let mut msg = Message::new(Class::Request, method, self.transaction_id());
+        build(&mut msg);
+        let key = self.add_credentials(&mut msg).await;
+        (msg, key)
+    }
+
+    /// Checks the response to a request sent with `key`. Returns `false` if
+    /// the request should be retried with the realm and nonce in the
+    /// response.
+    async fn check_response(&self, res: &Message, key: Option<&Vec<u8>>) -> io::Result<bool> {
+        match res.class {
+            Class::SuccessResponse => match key {
+                Some(key) if !res.verify_message_integrity(key) => {
+                    Err(invalid_response("bad MESSAGE-INTEGRITY"))
+                }
+                _ => Ok(true),
+            },
+            Class::FailureResponse => {
+                let error = res
+                    .attr::<ErrorCode>()
+                    .ok_or_else(|| invalid_response("missing ERROR-CODE"))?;
+
+                let retry = match error.code {
+                    ErrorCode::UNAUTHORIZED => key.is_none(),
+                    ErrorCode::STALE_NONCE => true,
+                    _ => false,
+                };
+                if !retry {
+                    return Err(ErrorResponse(error).into());
+                }
+
+                let mut state = self.inner.state.lock().await;
+                state.realm = res.attr::<Realm>().or_else(|| state.realm.take());
+                state.nonce = res.attr::<Nonce>();
+                if state.realm.is_none() || state.nonce.is_none() {
+                    return Err(ErrorResponse(error).into());
+                }
+                Ok(false)
+            }
+            _ => Err(invalid_response("unexpected message class")),
+        }
+    }
+
+    /// Adds USERNAME, REALM, NONCE and MESSAGE-INTEGRITY to `msg` once the
+    /// realm and nonce are known. Returns the key used.
+    async fn add_credentials(&self, msg: &mut Message) -> Option<Vec<u8>> {
+        let state = self.inner.state.lock().await;
+        let realm = state.realm.as_ref()?;
+        let nonce = state.nonce.as_ref()?;
+
+        let credentials = &self.inner.credentials;
+        let key =
+            MessageIntegrity::long_term_key(&credentials.username, &realm.0, &credentials.password);
+
+        msg.add_attr(&Username(credentials.username.clone()));
+        msg.add_attr(realm);
+        msg.add_attr(nonce);
+        msg.add_message_integrity(&key);
+        Some(key)
+    }
+
+    async fn send_request(&self, msg: Message) -> io::Result<Message> {
+        let server = self.inner.server;
+        let transactions = &self.inner.transactions;
+        let transaction = transactions.start(msg.transaction_id, server).await;
+
+        // Let the callback actually send out the message.
+        if self.inner.reliable {
+            (self.inner.on_send)(Frame::Message(msg), server).await?;
+            return transactions.finish(transaction, DEFAULT_TIMEOUT).await;
+        }
+        let send = || (self.inner.on_send)(Frame::Message(msg.clone()), server);
+        transactions
+            .retransmit(transaction, DEFAULT_RTO, DEFAULT_MAX_REQUESTS, send)
+            .await
+    }
+}
+
+/// Reads exactly one STUN message from `stream`, leaving anything after it
+/// unread.
+async fn read_message<S>(stream: &mut S) -> io::Result<Message>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut buf = vec![0; 20];
+    stream.read_exact(&mut buf).await?;
+    let len = u16::from_be_bytes([buf[2], buf[3]]) as usize;
+    buf.resize(buf.len() + len, 0);
+    stream.read_exact(&mut buf[20..]).await?;
+
+    match MessageCodec::new().decode(&mut BytesMut::from(buf))? {
+        Some(Some(msg)) => Ok(msg),
+        _ => Err(invalid_response("invalid STUN message")),
+    }
+}
+
+fn invalid_response(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::future;
+    use tokio_sync::mpsc;
+
+    #[test]
+    fn retransmit() {
+        tokio_test::block_on(async {
+            let (tx, mut rx) = mpsc::unbounded_channel();
+            let server = "127.0.0.1:3478".parse().unwrap();
+            let credentials = Credentials::new("user", "pass");
+            let client = Client::new(server, credentials, move |frame, _| {
+                let res = tx
+                    .clone()
+                    .try_send(frame)
+                    .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe));
+                future::ready(res)
+            });
+
+            let c = client.clone();
+            tokio_executor::spawn(async move {
+                // The first request is lost, and the retransmission answered.
+                let first = match rx.recv().await {
+                    Some(Frame::Message(msg)) => msg,
+                    _ => panic!("expected a request"),
+                };
+                let second = match rx.recv().await {
+                    Some(Frame::Message(msg)) => msg,
+                    _ => panic!("expected a request"),
+                };
+                assert_eq!(first.transaction_id, second.transaction_id);
+
+                let mut res =
+                    Message::new(Class::FailureResponse, second.method, second.transaction_id);
+                res.add_attr(&ErrorCode::new(ErrorCode::BAD_REQUEST, "Bad Request"));
+                c.on_recv(Frame::Message(res), server).await;
+            });
+
+            let err = client.allocate().await.unwrap_err();
+            assert_eq!(ErrorResponse::code(&err), Some(ErrorCode::BAD_REQUEST));
+        });
+    }
+
+    #[test]
+    fn refresh_due() {
+        tokio_test::block_on(async {
+            let (tx, mut rx) = mpsc::unbounded_channel();
+            let (methods_tx, mut methods) = mpsc::unbounded_channel();
+            let server = "127.0.0.1:3478".parse().unwrap();
+            let credentials = Credentials::new("user", "pass");
+            let client = Client::new(server, credentials, move |frame, _| {
+                let res = tx
+                    .clone()
+                    .try_send(frame)
+                    .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe));
+                future::ready(res)
+            });
+
+            // The server grants every request.
+            let c = client.clone();
+            tokio_executor::spawn(async move {
+                let mut methods_tx = methods_tx;
+                while let Some(frame) = rx.recv().await {
+                    let req = match frame {
+                        Frame::Message(msg) => msg,
+                        _ => continue,
+                    };
+                    let mut res =
+                        Message::new(Class::SuccessResponse, req.method, req.transaction_id);
+                    if req.method == Method::ALLOCATE {
+                        res.add_attr(&XorRelayedAddress("127.0.0.1:50000".parse().unwrap()));
+                    }
+                    let _ = methods_tx.try_send(req.method);
+                    c.on_recv(Frame::Message(res), server).await;
+                }
+            });
+
+            let start = Instant::now();
+            let permission = "10.0.0.1".parse().unwrap();
+            let channel = "10.0.0.2:5000".parse().unwrap();
+            client.allocate().await.unwrap();
+            client.create_permission(permission).await.unwrap();
+            client.bind_channel(channel).await.unwrap();
+            for _ in 0..3 {
+                methods.recv().await.unwrap();
+            }
+
+            let next = client.next_refresh().get_ref().unwrap();
+            assert!(next >= start + PERMISSION_REFRESH_INTERVAL);
+            assert!(next < start + CHANNEL_REFRESH_INTERVAL);
+
+            // After 4 minutes, only the permissions are due, including the
+            // one installed by the channel binding.
+            let now = start + Duration::from_secs(250);
+            client.refresh_due(now).await.unwrap();
+            for _ in 0..2 {
+                assert_eq!(methods.recv().await, Some(Method::CREATE_PERMISSION));
+            }
+
+            // After 9 minutes, the allocation and the channel binding are too.
+            let now = start + Duration::from_secs(550);
+            client.refresh_due(now).await.unwrap();
+            let mut due = Vec::new();
+            for _ in 0..4 {
+                due.push(methods.recv().await.unwrap());
+            }
+            assert_eq!(due[0], Method::REFRESH);
+            assert_eq!(due[3], Method::CHANNEL_BIND);
+
+            client.deallocate().await.unwrap();
+            assert_eq!(*client.next_refresh().get_ref(), None);
+        });
+    }
+}
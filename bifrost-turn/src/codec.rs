@@ -0,0 +1,235 @@
+use crate::frame::{ChannelData, Frame};
+use bifrost_stun::codec::MessageCodec;
+use bifrost_stun::message::attribute::ChannelNumber;
+use bytes::{BufMut, BytesMut};
+use std::io;
+use tokio_codec::{Decoder, Encoder};
+
+/// A codec for STUN messages and ChannelData messages sharing one transport.
+///
+/// Over UDP every datagram holds exactly one frame, and anything that is not
+/// a valid frame is dropped. Over TCP and TLS frames follow each other in a
+/// byte stream, ChannelData messages are padded to a multiple of 4 bytes, and
+/// garbage is an error since there is no way to find the next frame.
+pub struct FrameCodec {
+    message: MessageCodec,
+    stream: bool,
+}
+
+impl FrameCodec {
+    /// Creates a codec for datagram transports.
+    pub fn datagram() -> Self {
+        Self {
+            message: MessageCodec::new(),
+            stream: false,
+        }
+    }
+
+    /// Creates a codec for stream transports.
+    pub fn stream() -> Self {
+        Self {
+            message: MessageCodec::new(),
+            stream: true,
+        }
+    }
+
+    fn decode_channel_data(&mut self, src: &mut BytesMut) -> io::Result<Option<Option<Frame>>> {
+        if src.len() < ChannelData::HEADER_LEN {
+            return self.incomplete(src);
+        }
+
+        let channel = ChannelNumber(u16::from_be_bytes([src[0], src[1]]));
+        let len = u16::from_be_bytes([src[2], src[3]]) as usize;
+
+        // Over TCP and TLS, the ChannelData message MUST be padded to a
+        // multiple of four bytes in order to ensure the alignment of
+        // subsequent messages.
+        let padded_len = if self.stream { (len + 3) & !0b11 } else { len };
+        let total_len = ChannelData::HEADER_LEN + padded_len;
+        if src.len() < total_len {
+            return self.incomplete(src);
+        }
+
+        let frame = src.split_to(total_len);
+        let data = frame[ChannelData::HEADER_LEN..ChannelData::HEADER_LEN + len].to_vec();
+        Ok(Some(Some(ChannelData::new(channel, data).into())))
+    }
+
+    fn incomplete(&mut self, src: &mut BytesMut) -> io::Result<Option<Option<Frame>>> {
+        if self.stream {
+            Ok(None)
+        } else {
+            // A datagram is all there is going to be.
+            self.invalid(src)
+        }
+    }
+
+    fn invalid(&mut self, src: &mut BytesMut) -> io::Result<Option<Option<Frame>>> {
+        if self.stream {
+            Err(io::Error::new(io::ErrorKind::InvalidData, "invalid frame"))
+        } else {
+            src.clear();
+            self.message = MessageCodec::new();
+            Ok(Some(None))
+        }
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = Option<Frame>;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if src.is_empty() {
+            return self.incomplete(src);
+        }
+
+        // The first two bits are 0b00 for STUN messages and 0b01 for
+        // ChannelData messages, whose channel numbers are in the range
+        // 0x4000 through 0x7FFF.
+        match src[0] >> 6 {
+            0b00 => match self.message.decode(src)? {
+                Some(Some(msg)) => Ok(Some(Some(Frame::Message(msg)))),
+                Some(None) => self.invalid(src),
+                None => self.incomplete(src),
+            },
+            0b01 => self.decode_channel_data(src),
+            _ => self.invalid(src),
+        }
+    }
+}
+
+impl Encoder for FrameCodec {
+    type Item = Frame;
+    type Error = io::Error;
+
+    fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        match item {
+            Frame::Message(msg) => self.message.encode(msg, dst),
+            Frame::ChannelData(ChannelData { channel, data }) => {
+                if data.len() > u16::MAX as usize {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "channel data too large",
+                    ));
+                }
+
+                let padded_len = if self.stream {
+                    (data.len() + 3) & !0b11
+                } else {
+                    data.len()
+                };
+
+                dst.reserve(ChannelData::HEADER_LEN + padded_len);
+                dst.put_u16_be(channel.0);
+                dst.put_u16_be(data.len() as u16);
+                dst.put_slice(&data);
+                for _ in data.len()..padded_len {
+                    dst.put_u8(0);
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bifrost_stun::message::attribute::Software;
+    use bifrost_stun::message::{Class, Message, Method, TransactionId};
+
+    fn new_test_frames() -> Vec<Frame> {
+        let mut msg = Message::new(
+            Class::Request,
+            Method::ALLOCATE,
+            TransactionId::new([1; 12]),
+        );
+        msg.add_attr(&Software("bifrost".to_owned()));
+
+        vec![
+            Frame::Message(msg),
+            Frame::ChannelData(ChannelData::new(ChannelNumber(0x4001), vec![1, 2, 3])),
+            Frame::ChannelData(ChannelData::new(ChannelNumber(0x7ffe), vec![])),
+        ]
+    }
+
+    fn assert_frame_eq(actual: &Frame, expected: &Frame) {
+        match (actual, expected) {
+            (Frame::Message(a), Frame::Message(b)) => {
+                assert_eq!(a.class, b.class);
+                assert_eq!(a.method, b.method);
+                assert_eq!(a.transaction_id, b.transaction_id);
+                assert_eq!(a.attributes, b.attributes);
+            }
+            (Frame::ChannelData(a), Frame::ChannelData(b)) => assert_eq!(a, b),
+            _ => panic!("frame mismatch: {:?} != {:?}", actual, expected),
+        }
+    }
+
+    #[test]
+    fn datagram() {
+        let mut codec = FrameCodec::datagram();
+
+        for frame in new_test_frames() {
+            let mut bytes = BytesMut::new();
+            codec.encode(frame.clone(), &mut bytes).unwrap();
+            match codec.decode(&mut bytes) {
+                Ok(Some(Some(decoded))) => assert_frame_eq(&decoded, &frame),
+                x => panic!("failed to decode {:?}", x),
+            }
+            assert!(bytes.is_empty());
+        }
+
+        // Unpadded ChannelData.
+        let mut bytes = BytesMut::new();
+        let data = ChannelData::new(ChannelNumber(0x4000), vec![9]);
+        codec.encode(data.into(), &mut bytes).unwrap();
+        assert_eq!(bytes.len(), 5);
+
+        // Garbage and truncated frames are dropped.
+        for garbage in &[
+            &b"\xffnonsense"[..],
+            &b"\x40\x00\x00\x08abc"[..],
+            &b"\x00"[..],
+        ] {
+            let mut bytes = BytesMut::from(*garbage);
+            match codec.decode(&mut bytes) {
+                Ok(Some(None)) => (),
+                x => panic!("failed to reject {:?}", x),
+            }
+            assert!(bytes.is_empty());
+        }
+    }
+
+    #[test]
+    fn stream() {
+        let mut codec = FrameCodec::stream();
+        let frames = new_test_frames();
+
+        let mut bytes = BytesMut::new();
+        for frame in &frames {
+            codec.encode(frame.clone(), &mut bytes).unwrap();
+            assert_eq!(bytes.len() % 4, 0);
+        }
+
+        // Feed the stream a few bytes at a time.
+        let mut input = BytesMut::new();
+        let mut decoded = vec![];
+        for chunk in bytes.chunks(3) {
+            input.extend_from_slice(chunk);
+            while let Some(frame) = codec.decode(&mut input).unwrap() {
+                decoded.push(frame.unwrap());
+            }
+        }
+
+        assert_eq!(decoded.len(), frames.len());
+        for (actual, expected) in decoded.iter().zip(&frames) {
+            assert_frame_eq(actual, expected);
+        }
+
+        let mut bytes = BytesMut::from(&b"\xffnonsense"[..]);
+        assert!(codec.decode(&mut bytes).is_err());
+    }
+}
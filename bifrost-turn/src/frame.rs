@@ -0,0 +1,39 @@
+use bifrost_stun::message::attribute::ChannelNumber;
+use bifrost_stun::message::Message;
+
+/// Anything a TURN client and server exchange over the client-to-server
+/// transport.
+#[derive(Clone, Debug)]
+pub enum Frame {
+    Message(Message),
+    ChannelData(ChannelData),
+}
+
+impl From<Message> for Frame {
+    fn from(msg: Message) -> Self {
+        Frame::Message(msg)
+    }
+}
+
+impl From<ChannelData> for Frame {
+    fn from(data: ChannelData) -> Self {
+        Frame::ChannelData(data)
+    }
+}
+
+/// A ChannelData message, defined in
+/// [RFC 5766](https://tools.ietf.org/html/rfc5766#section-11.4).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ChannelData {
+    pub channel: ChannelNumber,
+    pub data: Vec<u8>,
+}
+
+impl ChannelData {
+    /// The length of the channel number and length fields.
+    pub const HEADER_LEN: usize = 4;
+
+    pub fn new(channel: ChannelNumber, data: Vec<u8>) -> Self {
+        Self { channel, data }
+    }
+}
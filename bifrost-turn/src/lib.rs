@@ -1,7 +1,8 @@
+pub mod client;
+pub mod codec;
+pub mod frame;
+pub mod server;
+pub mod transport;
+
 #[cfg(test)]
-mod tests {
-    #[test]
-    fn it_works() {
-        assert_eq!(2 + 2, 4);
-    }
-}
+mod test_util;
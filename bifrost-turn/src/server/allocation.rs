@@ -0,0 +1,286 @@
+use crate::frame::Frame;
+use crate::server::{AllocationInfo, FiveTuple, TrafficStats};
+use bifrost_stun::message::attribute::{ChannelNumber, ConnectionId};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant, SystemTime};
+use tokio_net::tcp::TcpStream;
+use tokio_sync::{mpsc, oneshot};
+
+/// How long a permission lasts unless refreshed.
+pub(crate) const PERMISSION_LIFETIME: Duration = Duration::from_secs(300);
+
+/// How long a channel binding lasts unless refreshed.
+pub(crate) const CHANNEL_LIFETIME: Duration = Duration::from_secs(600);
+
+/// Sends frames back to a client over the transport it used to reach us.
+#[derive(Clone)]
+pub(crate) struct Responder {
+    tx: mpsc::UnboundedSender<(Frame, SocketAddr)>,
+    addr: SocketAddr,
+}
+
+impl Responder {
+    pub fn new(tx: mpsc::UnboundedSender<(Frame, SocketAddr)>, addr: SocketAddr) -> Self {
+        Self { tx, addr }
+    }
+
+    pub fn send(&self, frame: Frame) {
+        // The transport being gone means the client is too.
+        let _ = self.tx.clone().try_send((frame, self.addr));
+    }
+}
+
+/// How data reaches peers from an allocation.
+pub(crate) enum Relay {
+    /// Datagrams to send from the relayed transport address.
+    Udp(mpsc::UnboundedSender<(Vec<u8>, SocketAddr)>),
+    /// Each peer gets its own connection, spliced to a connection from the
+    /// client, defined in
+    /// [RFC 6062](https://tools.ietf.org/html/rfc6062#section-5).
+    Tcp,
+}
+
+/// A relayed transport address of an allocation. Dual-stack allocations have
+/// one per address family, defined in
+/// [RFC 8656](https://tools.ietf.org/html/rfc8656#section-7.2).
+pub(crate) struct RelayedAddress {
+    pub addr: SocketAddr,
+    relay: Relay,
+    // Dropping this stops the task receiving from peers.
+    _stop: oneshot::Sender<()>,
+}
+
+impl RelayedAddress {
+    pub fn new(addr: SocketAddr, relay: Relay, stop: oneshot::Sender<()>) -> Self {
+        Self {
+            addr,
+            relay,
+            _stop: stop,
+        }
+    }
+}
+
+/// A TCP connection between the relayed transport address and a peer.
+pub(crate) struct PeerConnection {
+    pub peer: SocketAddr,
+    /// The connection until the client binds a connection of its own to it.
+    pub stream: Option<TcpStream>,
+    // Dropping this closes the connection once it is spliced.
+    pub stop: Option<oneshot::Sender<()>>,
+}
+
+/// The server side of an allocation, defined in
+/// [RFC 5766](https://tools.ietf.org/html/rfc5766#section-5).
+pub(crate) struct Allocation {
+    pub username: String,
+    pub relayed_addrs: Vec<RelayedAddress>,
+    pub expires_at: Instant,
+    pub permissions: HashMap<IpAddr, Instant>,
+    pub channels: HashMap<ChannelNumber, (SocketAddr, Instant)>,
+    pub connections: HashMap<ConnectionId, PeerConnection>,
+    pub responder: Responder,
+    pub stats: TrafficStats,
+    /// When the allocation was created, on both clocks, to report expiry
+    /// times in wall-clock time.
+    created_at: (Instant, SystemTime),
+}
+
+impl Allocation {
+    pub fn new(
+        username: String,
+        relayed_addrs: Vec<RelayedAddress>,
+        lifetime: Duration,
+        responder: Responder,
+    ) -> Self {
+        let now = Instant::now();
+        Self {
+            username,
+            relayed_addrs,
+            expires_at: now + lifetime,
+            permissions: HashMap::new(),
+            channels: HashMap::new(),
+            connections: HashMap::new(),
+            responder,
+            stats: TrafficStats::default(),
+            created_at: (now, SystemTime::now()),
+        }
+    }
+
+    pub fn info(&self, five_tuple: FiveTuple) -> AllocationInfo {
+        let now = Instant::now();
+        AllocationInfo {
+            username: self.username.clone(),
+            five_tuple,
+            relayed_addrs: self
+                .relayed_addrs
+                .iter()
+                .map(|relayed| relayed.addr)
+                .collect(),
+            permissions: self
+                .permissions
+                .iter()
+                .filter(|(_, &expires_at)| expires_at > now)
+                .map(|(&peer, &expires_at)| (peer, self.system_time(expires_at)))
+                .collect(),
+            channels: self
+                .channels
+                .iter()
+                .filter(|(_, &(_, expires_at))| expires_at > now)
+                .map(|(&channel, &(peer, expires_at))| {
+                    (channel, (peer, self.system_time(expires_at)))
+                })
+                .collect(),
+            stats: self.stats,
+            expires_at: self.system_time(self.expires_at),
+        }
+    }
+
+    /// Converts `instant`, which is after the allocation was created, to
+    /// wall-clock time.
+    fn system_time(&self, instant: Instant) -> SystemTime {
+        let (created, created_system) = self.created_at;
+        created_system + (instant - created)
+    }
+
+    /// Extends the allocation by `lifetime` from now, and forgets expired
+    /// permissions and channel bindings.
+    pub fn refresh(&mut self, lifetime: Duration) {
+        self.expires_at = Instant::now() + lifetime;
+        self.purge();
+    }
+
+    /// Forgets expired permissions and channel bindings, so that they do
+    /// not pile up over a long-lived allocation.
+    fn purge(&mut self) {
+        let now = Instant::now();
+        self.permissions.retain(|_, expires_at| *expires_at > now);
+        self.channels.retain(|_, (_, expires_at)| *expires_at > now);
+    }
+
+    pub fn has_permission(&self, peer: IpAddr) -> bool {
+        self.permissions
+            .get(&peer)
+            .is_some_and(|&expires_at| expires_at > Instant::now())
+    }
+
+    pub fn add_permission(&mut self, peer: IpAddr) {
+        self.purge();
+        self.permissions
+            .insert(peer, Instant::now() + PERMISSION_LIFETIME);
+    }
+
+    pub fn channel_peer(&self, channel: ChannelNumber) -> Option<SocketAddr> {
+        match self.channels.get(&channel) {
+            Some(&(peer, expires_at)) if expires_at > Instant::now() => Some(peer),
+            _ => None,
+        }
+    }
+
+    pub fn peer_channel(&self, peer: SocketAddr) -> Option<ChannelNumber> {
+        let now = Instant::now();
+        self.channels
+            .iter()
+            .find(|(_, &(p, expires_at))| p == peer && expires_at > now)
+            .map(|(&channel, _)| channel)
+    }
+
+    pub fn bind_channel(&mut self, channel: ChannelNumber, peer: SocketAddr) {
+        self.purge();
+        self.channels
+            .insert(channel, (peer, Instant::now() + CHANNEL_LIFETIME));
+        self.add_permission(peer.ip());
+    }
+
+    /// Returns the first relayed transport address, which identifies the
+    /// allocation.
+    pub fn relayed_addr(&self) -> SocketAddr {
+        self.relayed_addrs[0].addr
+    }
+
+    /// Returns the relayed transport address in the address family of
+    /// `peer`, if any.
+    pub fn relayed_addr_for(&self, peer: IpAddr) -> Option<&RelayedAddress> {
+        self.relayed_addrs
+            .iter()
+            .find(|relayed| relayed.addr.is_ipv4() == peer.is_ipv4())
+    }
+
+    pub fn is_tcp(&self) -> bool {
+        match self.relayed_addrs[0].relay {
+            Relay::Udp(_) => false,
+            Relay::Tcp => true,
+        }
+    }
+
+    pub fn has_connection(&self, peer: SocketAddr) -> bool {
+        self.connections.values().any(|conn| conn.peer == peer)
+    }
+
+    /// Relays `data` to `peer` if it has a permission. Returns whether the
+    /// data was relayed.
+    pub fn relay(&self, data: Vec<u8>, peer: SocketAddr) -> bool {
+        if !self.has_permission(peer.ip()) {
+            return false;
+        }
+        match self
+            .relayed_addr_for(peer.ip())
+            .map(|relayed| &relayed.relay)
+        {
+            Some(Relay::Udp(tx)) => tx.clone().try_send((data, peer)).is_ok(),
+            // Data to peers goes over the data connections.
+            Some(Relay::Tcp) => false,
+            // Peers of another address family are unreachable.
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn purge() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let addr = "127.0.0.1:3478".parse().unwrap();
+        let (stop, _) = oneshot::channel();
+        let relayed = RelayedAddress::new("127.0.0.1:50000".parse().unwrap(), Relay::Tcp, stop);
+        let mut allocation = Allocation::new(
+            "alice".to_owned(),
+            vec![relayed],
+            Duration::from_secs(600),
+            Responder::new(tx, addr),
+        );
+        let five_tuple = FiveTuple {
+            client: addr,
+            server: addr,
+            protocol: crate::server::Protocol::Tcp,
+        };
+
+        let peer: SocketAddr = "192.0.2.1:4000".parse().unwrap();
+        let stale: SocketAddr = "192.0.2.2:4000".parse().unwrap();
+        let expired = Instant::now() - Duration::from_secs(1);
+        allocation.permissions.insert(stale.ip(), expired);
+        allocation
+            .channels
+            .insert(ChannelNumber(ChannelNumber::MIN + 1), (stale, expired));
+
+        // Expired entries are not reported as current.
+        let info = allocation.info(five_tuple);
+        assert!(info.permissions.is_empty());
+        assert!(info.channels.is_empty());
+        assert!(info.expires_at > SystemTime::now());
+
+        // Nor are they kept once something else is added.
+        allocation.bind_channel(ChannelNumber(ChannelNumber::MIN), peer);
+        assert_eq!(allocation.permissions.len(), 1);
+        assert_eq!(allocation.channels.len(), 1);
+        assert!(allocation.has_permission(peer.ip()));
+        assert!(!allocation.has_permission(stale.ip()));
+
+        let info = allocation.info(five_tuple);
+        assert_eq!(info.permissions.len(), 1);
+        assert_eq!(info, allocation.info(five_tuple));
+    }
+}
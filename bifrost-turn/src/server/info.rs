@@ -0,0 +1,58 @@
+use crate::server::FiveTuple;
+use bifrost_stun::message::attribute::ChannelNumber;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::SystemTime;
+
+/// Counters of data relayed through the server.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TrafficStats {
+    /// Packets relayed from the client to peers. Over TCP allocations, these
+    /// are the chunks of the byte stream as read from the client.
+    pub packets_sent: u64,
+    pub bytes_sent: u64,
+    /// Packets relayed from peers to the client.
+    pub packets_received: u64,
+    pub bytes_received: u64,
+}
+
+impl TrafficStats {
+    pub(crate) fn add_sent(&mut self, len: usize) {
+        self.packets_sent += 1;
+        self.bytes_sent += len as u64;
+    }
+
+    pub(crate) fn add_received(&mut self, len: usize) {
+        self.packets_received += 1;
+        self.bytes_received += len as u64;
+    }
+}
+
+/// A snapshot of an allocation.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AllocationInfo {
+    pub username: String,
+    pub five_tuple: FiveTuple,
+    /// The relayed transport addresses, one per address family.
+    pub relayed_addrs: Vec<SocketAddr>,
+    /// When each permission expires, keyed by peer address. Expired
+    /// permissions are left out.
+    pub permissions: HashMap<IpAddr, SystemTime>,
+    /// The peer each channel is bound to, and when the binding expires.
+    /// Expired bindings are left out.
+    pub channels: HashMap<ChannelNumber, (SocketAddr, SystemTime)>,
+    pub stats: TrafficStats,
+    pub expires_at: SystemTime,
+}
+
+/// A change to the allocations on the server.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AllocationEvent {
+    Created(AllocationInfo),
+    Refreshed(AllocationInfo),
+    /// The allocation expired without being refreshed.
+    Expired(AllocationInfo),
+    /// The client deleted the allocation, or closed the connection it was
+    /// made over.
+    Deleted(AllocationInfo),
+}
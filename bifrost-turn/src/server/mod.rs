@@ -0,0 +1,1228 @@
+mod allocation;
+mod info;
+mod tcp;
+
+pub use self::info::{AllocationEvent, AllocationInfo, TrafficStats};
+
+use self::allocation::{Allocation, PeerConnection, Relay, RelayedAddress, Responder};
+use crate::client::DEFAULT_LIFETIME;
+use crate::codec::FrameCodec;
+use crate::frame::{ChannelData, Frame};
+use bifrost_stun::message::attribute::{
+    self, AdditionalAddressFamily, Attribute, ChannelNumber, ConnectionId, Data, ErrorCode,
+    Lifetime, MessageIntegrity, Nonce, Realm, RequestedAddressFamily, RequestedTransport, Software,
+    UnknownAttributes, Username, XorMappedAddress, XorPeerAddress, XorRelayedAddress,
+};
+use bifrost_stun::message::{Class, Message, Method};
+use futures_util::future::{self, Either};
+use futures_util::{SinkExt, StreamExt};
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio_codec::Framed;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_net::tcp::{TcpListener, TcpStream};
+use tokio_net::udp::{UdpFramed, UdpSocket};
+use tokio_sync::{mpsc, oneshot};
+
+/// How long a nonce stays valid.
+const NONCE_LIFETIME: Duration = Duration::from_secs(3600);
+
+/// Comprehension-required attributes the server understands.
+const KNOWN_ATTRIBUTES: &[u16] = &[
+    Username::TYPE,
+    MessageIntegrity::TYPE,
+    ErrorCode::TYPE,
+    UnknownAttributes::TYPE,
+    Realm::TYPE,
+    Nonce::TYPE,
+    XorMappedAddress::TYPE,
+    ChannelNumber::TYPE,
+    Lifetime::TYPE,
+    XorPeerAddress::TYPE,
+    Data::TYPE,
+    XorRelayedAddress::TYPE,
+    RequestedAddressFamily::TYPE,
+    RequestedTransport::TYPE,
+    ConnectionId::TYPE,
+];
+
+/// The transport protocol between a client and the server.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Protocol {
+    Udp,
+    Tcp,
+    Tls,
+}
+
+/// The 5-tuple identifying an allocation, defined in
+/// [RFC 5766](https://tools.ietf.org/html/rfc5766#section-2.2).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct FiveTuple {
+    pub client: SocketAddr,
+    pub server: SocketAddr,
+    pub protocol: Protocol,
+}
+
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    pub realm: String,
+    /// Passwords of the users allowed to allocate, keyed by username.
+    pub users: HashMap<String, String>,
+    /// The addresses relayed transport addresses are allocated on, at most
+    /// one per address family.
+    pub relay_ips: Vec<IpAddr>,
+    pub max_lifetime: Duration,
+    pub software: Option<String>,
+}
+
+impl ServerConfig {
+    pub fn new(realm: &str, relay_ip: IpAddr) -> Self {
+        Self {
+            realm: realm.to_owned(),
+            users: HashMap::new(),
+            relay_ips: vec![relay_ip],
+            max_lifetime: Duration::from_secs(3600),
+            software: None,
+        }
+    }
+
+    pub fn add_user(&mut self, username: &str, password: &str) {
+        self.users.insert(username.to_owned(), password.to_owned());
+    }
+}
+
+/// A TURN server, defined in
+/// [RFC 5766](https://tools.ietf.org/html/rfc5766).
+///
+/// The same server may listen on any number of UDP, TCP and TLS transports.
+#[derive(Clone)]
+pub struct Server {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    config: ServerConfig,
+    secret: [u8; 16],
+    allocations: Mutex<HashMap<FiveTuple, Allocation>>,
+    next_connection_id: AtomicU32,
+    user_stats: Mutex<HashMap<String, TrafficStats>>,
+    subscribers: Mutex<Vec<mpsc::UnboundedSender<AllocationEvent>>>,
+}
+
+impl Server {
+    pub fn new(config: ServerConfig) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                config,
+                secret: rand::random(),
+                allocations: Mutex::new(HashMap::new()),
+                next_connection_id: AtomicU32::new(rand::random()),
+                user_stats: Mutex::new(HashMap::new()),
+                subscribers: Mutex::new(vec![]),
+            }),
+        }
+    }
+
+    /// Returns a snapshot of every allocation.
+    pub fn allocations(&self) -> Vec<AllocationInfo> {
+        self.inner
+            .allocations
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&five_tuple, allocation)| allocation.info(five_tuple))
+            .collect()
+    }
+
+    /// Returns a snapshot of the allocation on `five_tuple`, if any.
+    pub fn allocation(&self, five_tuple: FiveTuple) -> Option<AllocationInfo> {
+        self.inner
+            .allocations
+            .lock()
+            .unwrap()
+            .get(&five_tuple)
+            .map(|allocation| allocation.info(five_tuple))
+    }
+
+    /// Returns the data relayed for each user so far, across all of their
+    /// allocations past and present.
+    pub fn user_stats(&self) -> HashMap<String, TrafficStats> {
+        self.inner.user_stats.lock().unwrap().clone()
+    }
+
+    /// Returns a stream of changes to the allocations from now on.
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<AllocationEvent> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.inner.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Serves clients over UDP until the socket fails.
+    pub async fn serve_udp(&self, socket: UdpSocket) -> io::Result<()> {
+        let local_addr = socket.local_addr()?;
+        let (mut sink, mut stream) = UdpFramed::new(socket, FrameCodec::datagram()).split();
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        tokio_executor::spawn(async move {
+            while let Some(item) = rx.recv().await {
+                let _ = sink.send(item).await;
+            }
+        });
+
+        while let Some(item) = stream.next().await {
+            let (frame, addr) = match item {
+                Ok((Some(frame), addr)) => (frame, addr),
+                _ => continue,
+            };
+            let five_tuple = FiveTuple {
+                client: addr,
+                server: local_addr,
+                protocol: Protocol::Udp,
+            };
+            self.handle(frame, five_tuple, &Responder::new(tx.clone(), addr))
+                .await;
+        }
+
+        Ok(())
+    }
+
+    /// Serves clients over TCP until the listener fails.
+    pub async fn serve_tcp(&self, mut listener: TcpListener) -> io::Result<()> {
+        let local_addr = listener.local_addr()?;
+
+        loop {
+            let (stream, addr) = listener.accept().await?;
+            let five_tuple = FiveTuple {
+                client: addr,
+                server: local_addr,
+                protocol: Protocol::Tcp,
+            };
+            tokio_executor::spawn(self.clone().serve_stream(stream, five_tuple));
+        }
+    }
+
+    /// Serves clients over TLS until the listener fails.
+    pub async fn serve_tls(
+        &self,
+        mut listener: TcpListener,
+        acceptor: native_tls::TlsAcceptor,
+    ) -> io::Result<()> {
+        let local_addr = listener.local_addr()?;
+        let acceptor = tokio_tls::TlsAcceptor::from(acceptor);
+
+        loop {
+            let (stream, addr) = listener.accept().await?;
+            let five_tuple = FiveTuple {
+                client: addr,
+                server: local_addr,
+                protocol: Protocol::Tls,
+            };
+
+            let server = self.clone();
+            let acceptor = acceptor.clone();
+            tokio_executor::spawn(async move {
+                if let Ok(stream) = acceptor.accept(stream).await {
+                    server.serve_stream(stream, five_tuple).await;
+                }
+            });
+        }
+    }
+
+    async fn serve_stream<S>(self, stream: S, five_tuple: FiveTuple)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let mut framed = Framed::new(stream, FrameCodec::stream());
+
+        // A connection starting with a ConnectionBind request is a data
+        // connection for a TCP allocation rather than a control connection.
+        let first = loop {
+            match framed.next().await {
+                Some(Ok(Some(Frame::Message(req))))
+                    if req.class == Class::Request && req.method == Method::CONNECTION_BIND =>
+                {
+                    let (res, bound) = self.bind_connection(&req);
+                    if framed.send(Frame::Message(res)).await.is_err() {
+                        return;
+                    }
+                    if let Some(binding) = bound {
+                        let Binding {
+                            five_tuple,
+                            id,
+                            peer,
+                            stop,
+                        } = binding;
+                        let parts = framed.into_parts();
+                        let count = |to_peer, len| self.count_spliced(five_tuple, to_peer, len);
+                        tcp::splice(parts.io, parts.read_buf, peer, stop, count).await;
+                        self.remove_connection(five_tuple, id);
+                        return;
+                    }
+                }
+                Some(Ok(frame)) => break frame,
+                _ => return,
+            }
+        };
+
+        let (mut sink, mut stream) = framed.split();
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        tokio_executor::spawn(async move {
+            while let Some((frame, _)) = rx.recv().await {
+                if sink.send(frame).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let responder = Responder::new(tx, five_tuple.client);
+        if let Some(frame) = first {
+            self.handle(frame, five_tuple, &responder).await;
+        }
+        while let Some(Ok(frame)) = stream.next().await {
+            if let Some(frame) = frame {
+                self.handle(frame, five_tuple, &responder).await;
+            }
+        }
+
+        // An allocation made over a connection goes away with it.
+        let mut allocations = self.inner.allocations.lock().unwrap();
+        if let Some(allocation) = allocations.remove(&five_tuple) {
+            self.notify(AllocationEvent::Deleted(allocation.info(five_tuple)));
+        }
+    }
+
+    async fn handle(&self, frame: Frame, five_tuple: FiveTuple, responder: &Responder) {
+        match frame {
+            Frame::Message(msg) => match msg.class {
+                Class::Request => {
+                    let res = self.handle_request(msg, five_tuple, responder).await;
+                    responder.send(Frame::Message(res));
+                }
+                Class::Indication if msg.method == Method::SEND => {
+                    self.handle_send(&msg, five_tuple);
+                }
+                _ => (),
+            },
+            Frame::ChannelData(data) => self.handle_channel_data(data, five_tuple),
+        }
+    }
+
+    async fn handle_request(
+        &self,
+        req: Message,
+        five_tuple: FiveTuple,
+        responder: &Responder,
+    ) -> Message {
+        let unknown: Vec<_> = req
+            .attributes
+            .iter()
+            .map(|attr| attr.r#type())
+            .filter(|&t| attribute::is_comprehension_required(t) && !KNOWN_ATTRIBUTES.contains(&t))
+            .collect();
+        if !unknown.is_empty() {
+            let mut res = self.error_response(
+                &req,
+                ErrorCode::new(ErrorCode::UNKNOWN_ATTRIBUTE, "Unknown Attribute"),
+            );
+            res.add_attr(&UnknownAttributes(unknown));
+            return res;
+        }
+
+        // Binding requests need no authentication.
+        if req.method == Method::BINDING {
+            let mut res = Message::new(Class::SuccessResponse, req.method, req.transaction_id);
+            res.add_attr(&XorMappedAddress(five_tuple.client));
+            self.add_software(&mut res);
+            return res;
+        }
+
+        let (username, key) = match self.authenticate(&req) {
+            Ok(x) => x,
+            Err(res) => return res,
+        };
+
+        let res = match req.method {
+            Method::ALLOCATE => self.allocate(&req, five_tuple, username, responder).await,
+            _ if !self.is_owner(five_tuple, &username) => Err(ErrorCode::new(
+                ErrorCode::WRONG_CREDENTIALS,
+                "Wrong Credentials",
+            )),
+            Method::REFRESH => self.refresh(&req, five_tuple),
+            Method::CREATE_PERMISSION => self.create_permission(&req, five_tuple),
+            Method::CHANNEL_BIND => self.bind_channel(&req, five_tuple),
+            Method::CONNECT => self.connect(&req, five_tuple).await,
+            _ => Err(ErrorCode::new(ErrorCode::BAD_REQUEST, "Bad Request")),
+        };
+
+        let mut res = match res {
+            Ok(mut res) => {
+                self.add_software(&mut res);
+                res
+            }
+            Err(error) => self.error_response(&req, error),
+        };
+        res.add_message_integrity(&key);
+        res
+    }
+
+    /// Checks the long-term credentials of `req`, defined in
+    /// [RFC 5389](https://tools.ietf.org/html/rfc5389#section-10.2.2).
+    /// Returns the username and key, or the response to send if the request
+    /// is rejected.
+    fn authenticate(&self, req: &Message) -> Result<(String, Vec<u8>), Message> {
+        let config = &self.inner.config;
+
+        let challenge = |code, reason| {
+            let mut res = self.error_response(req, ErrorCode::new(code, reason));
+            res.add_attr(&Realm(config.realm.clone()));
+            res.add_attr(&self.new_nonce());
+            res
+        };
+
+        if req.attr::<MessageIntegrity>().is_none() {
+            return Err(challenge(ErrorCode::UNAUTHORIZED, "Unauthorized"));
+        }
+
+        let (username, realm, nonce) = match (
+            req.attr::<Username>(),
+            req.attr::<Realm>(),
+            req.attr::<Nonce>(),
+        ) {
+            (Some(username), Some(realm), Some(nonce)) => (username.0, realm.0, nonce),
+            _ => {
+                let error = ErrorCode::new(ErrorCode::BAD_REQUEST, "Bad Request");
+                return Err(self.error_response(req, error));
+            }
+        };
+
+        if realm != config.realm {
+            return Err(challenge(ErrorCode::UNAUTHORIZED, "Unauthorized"));
+        }
+        if !self.check_nonce(&nonce) {
+            return Err(challenge(ErrorCode::STALE_NONCE, "Stale Nonce"));
+        }
+
+        let key = config
+            .users
+            .get(&username)
+            .map(|password| MessageIntegrity::long_term_key(&username, &realm, password));
+        match key {
+            Some(key) if req.verify_message_integrity(&key) => Ok((username, key)),
+            _ => Err(challenge(ErrorCode::UNAUTHORIZED, "Unauthorized")),
+        }
+    }
+
+    /// Issues a nonce that encodes its own expiry time, signed with a server
+    /// secret so that no per-client state is needed to validate it.
+    fn new_nonce(&self) -> Nonce {
+        let expiry = SystemTime::now() + NONCE_LIFETIME;
+        let expiry = expiry.duration_since(UNIX_EPOCH).unwrap().as_secs();
+        Nonce(format!("{:016x}{}", expiry, self.sign_nonce(expiry)))
+    }
+
+    fn check_nonce(&self, nonce: &Nonce) -> bool {
+        if nonce.0.len() < 16 || !nonce.0.is_char_boundary(16) {
+            return false;
+        }
+
+        let (expiry, signature) = nonce.0.split_at(16);
+        let expiry = match u64::from_str_radix(expiry, 16) {
+            Ok(expiry) => expiry,
+            Err(_) => return false,
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        signature == self.sign_nonce(expiry) && now < expiry
+    }
+
+    fn sign_nonce(&self, expiry: u64) -> String {
+        let mac = MessageIntegrity::compute(&self.inner.secret, &expiry.to_be_bytes());
+        mac.0[..8].iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    async fn allocate(
+        &self,
+        req: &Message,
+        five_tuple: FiveTuple,
+        username: String,
+        responder: &Responder,
+    ) -> Result<Message, ErrorCode> {
+        if self.has_allocation(five_tuple) {
+            return Err(ErrorCode::new(
+                ErrorCode::ALLOCATION_MISMATCH,
+                "Allocation Mismatch",
+            ));
+        }
+
+        let tcp = match req.attr::<RequestedTransport>() {
+            Some(RequestedTransport::UDP) => false,
+            // TCP allocations need a connection to the server to splice
+            // connections to peers to, defined in
+            // [RFC 6062](https://tools.ietf.org/html/rfc6062#section-5.1).
+            Some(RequestedTransport::TCP) if five_tuple.protocol == Protocol::Udp => {
+                return Err(ErrorCode::new(ErrorCode::BAD_REQUEST, "Bad Request"))
+            }
+            Some(RequestedTransport::TCP) => true,
+            Some(_) => {
+                return Err(ErrorCode::new(
+                    ErrorCode::UNSUPPORTED_TRANSPORT_PROTOCOL,
+                    "Unsupported Transport Protocol",
+                ))
+            }
+            None => return Err(ErrorCode::new(ErrorCode::BAD_REQUEST, "Bad Request")),
+        };
+
+        let insufficient_capacity =
+            |_| ErrorCode::new(ErrorCode::INSUFFICIENT_CAPACITY, "Insufficient Capacity");
+        let mut relayed_addrs = vec![];
+        for ip in self.relay_ips(req)? {
+            let relayed = if tcp {
+                let listener =
+                    tcp::listen(SocketAddr::new(ip, 0)).map_err(insufficient_capacity)?;
+                let addr = listener.local_addr().map_err(insufficient_capacity)?;
+                let stop = self.spawn_tcp_relay(listener, five_tuple);
+                RelayedAddress::new(addr, Relay::Tcp, stop)
+            } else {
+                let socket = UdpSocket::bind(SocketAddr::new(ip, 0))
+                    .await
+                    .map_err(insufficient_capacity)?;
+                let addr = socket.local_addr().map_err(insufficient_capacity)?;
+                let (relay_tx, stop) = self.spawn_relay(socket, five_tuple);
+                RelayedAddress::new(addr, Relay::Udp(relay_tx), stop)
+            };
+            relayed_addrs.push(relayed);
+        }
+
+        let addrs: Vec<_> = relayed_addrs.iter().map(|relayed| relayed.addr).collect();
+        let lifetime = self.lifetime(req);
+        let allocation = Allocation::new(username, relayed_addrs, lifetime, responder.clone());
+
+        // The request may have raced with another one on the same 5-tuple.
+        {
+            let mut allocations = self.inner.allocations.lock().unwrap();
+            if allocations.contains_key(&five_tuple) {
+                return Err(ErrorCode::new(
+                    ErrorCode::ALLOCATION_MISMATCH,
+                    "Allocation Mismatch",
+                ));
+            }
+            self.notify(AllocationEvent::Created(allocation.info(five_tuple)));
+            allocations.insert(five_tuple, allocation);
+        }
+        self.spawn_expiry(five_tuple, addrs[0]);
+
+        let mut res = Message::new(Class::SuccessResponse, req.method, req.transaction_id);
+        for &addr in &addrs {
+            res.add_attr(&XorRelayedAddress(addr));
+        }
+        res.add_attr(&Lifetime(lifetime));
+        res.add_attr(&XorMappedAddress(five_tuple.client));
+        Ok(res)
+    }
+
+    /// Returns the addresses to allocate relayed transport addresses on for
+    /// `req`, one per address family, defined in
+    /// [RFC 6156](https://tools.ietf.org/html/rfc6156#section-4.2) and
+    /// [RFC 8656](https://tools.ietf.org/html/rfc8656#section-7.2).
+    fn relay_ips(&self, req: &Message) -> Result<Vec<IpAddr>, ErrorCode> {
+        let not_supported = || {
+            ErrorCode::new(
+                ErrorCode::ADDRESS_FAMILY_NOT_SUPPORTED,
+                "Address Family not Supported",
+            )
+        };
+        let bad_request = || ErrorCode::new(ErrorCode::BAD_REQUEST, "Bad Request");
+
+        match (
+            req.attr::<RequestedAddressFamily>(),
+            req.attr::<AdditionalAddressFamily>(),
+        ) {
+            (Some(_), Some(_)) => Err(bad_request()),
+            (Some(RequestedAddressFamily::IPV4), None) | (None, None) => {
+                Ok(vec![self.relay_ip(true).ok_or_else(not_supported)?])
+            }
+            (Some(RequestedAddressFamily::IPV6), None) => {
+                Ok(vec![self.relay_ip(false).ok_or_else(not_supported)?])
+            }
+            (Some(_), None) => Err(not_supported()),
+            // A server without IPv6 still allocates the IPv4 address.
+            (None, Some(AdditionalAddressFamily::IPV6)) => {
+                let ipv4 = self.relay_ip(true).ok_or_else(not_supported)?;
+                Ok(Some(ipv4).into_iter().chain(self.relay_ip(false)).collect())
+            }
+            (None, Some(_)) => Err(bad_request()),
+        }
+    }
+
+    fn relay_ip(&self, ipv4: bool) -> Option<IpAddr> {
+        self.inner
+            .config
+            .relay_ips
+            .iter()
+            .cloned()
+            .find(|ip| ip.is_ipv4() == ipv4)
+    }
+
+    fn refresh(&self, req: &Message, five_tuple: FiveTuple) -> Result<Message, ErrorCode> {
+        let mut allocations = self.inner.allocations.lock().unwrap();
+        if !allocations.contains_key(&five_tuple) {
+            return Err(ErrorCode::new(
+                ErrorCode::ALLOCATION_MISMATCH,
+                "Allocation Mismatch",
+            ));
+        }
+
+        let lifetime = match req.attr::<Lifetime>() {
+            Some(Lifetime(lifetime)) if lifetime == Duration::from_secs(0) => {
+                let allocation = allocations.remove(&five_tuple).unwrap();
+                self.notify(AllocationEvent::Deleted(allocation.info(five_tuple)));
+                lifetime
+            }
+            _ => {
+                let lifetime = self.lifetime(req);
+                let allocation = allocations.get_mut(&five_tuple).unwrap();
+                allocation.refresh(lifetime);
+                self.notify(AllocationEvent::Refreshed(allocation.info(five_tuple)));
+                lifetime
+            }
+        };
+
+        let mut res = Message::new(Class::SuccessResponse, req.method, req.transaction_id);
+        res.add_attr(&Lifetime(lifetime));
+        Ok(res)
+    }
+
+    fn create_permission(
+        &self,
+        req: &Message,
+        five_tuple: FiveTuple,
+    ) -> Result<Message, ErrorCode> {
+        let mut allocations = self.inner.allocations.lock().unwrap();
+        let allocation = allocations
+            .get_mut(&five_tuple)
+            .ok_or_else(|| ErrorCode::new(ErrorCode::ALLOCATION_MISMATCH, "Allocation Mismatch"))?;
+
+        let peers: Vec<_> = req.attrs::<XorPeerAddress>().map(|attr| attr.0).collect();
+        if peers.is_empty() {
+            return Err(ErrorCode::new(ErrorCode::BAD_REQUEST, "Bad Request"));
+        }
+        if peers
+            .iter()
+            .any(|peer| allocation.relayed_addr_for(peer.ip()).is_none())
+        {
+            return Err(peer_address_family_mismatch());
+        }
+        for peer in peers {
+            allocation.add_permission(peer.ip());
+        }
+
+        Ok(Message::new(
+            Class::SuccessResponse,
+            req.method,
+            req.transaction_id,
+        ))
+    }
+
+    fn bind_channel(&self, req: &Message, five_tuple: FiveTuple) -> Result<Message, ErrorCode> {
+        let mut allocations = self.inner.allocations.lock().unwrap();
+        let allocation = allocations
+            .get_mut(&five_tuple)
+            .ok_or_else(|| ErrorCode::new(ErrorCode::ALLOCATION_MISMATCH, "Allocation Mismatch"))?;
+
+        let bad_request = || ErrorCode::new(ErrorCode::BAD_REQUEST, "Bad Request");
+        let channel = req
+            .attr::<ChannelNumber>()
+            .filter(|channel| channel.is_valid())
+            .ok_or_else(bad_request)?;
+        let peer = req.attr::<XorPeerAddress>().ok_or_else(bad_request)?.0;
+        if allocation.relayed_addr_for(peer.ip()).is_none() {
+            return Err(peer_address_family_mismatch());
+        }
+
+        // Neither the channel nor the peer may already be bound to something
+        // else.
+        let channel_taken = allocation.channel_peer(channel).is_some_and(|p| p != peer);
+        let peer_taken = allocation.peer_channel(peer).is_some_and(|c| c != channel);
+        if channel_taken || peer_taken {
+            return Err(bad_request());
+        }
+
+        allocation.bind_channel(channel, peer);
+
+        Ok(Message::new(
+            Class::SuccessResponse,
+            req.method,
+            req.transaction_id,
+        ))
+    }
+
+    /// Opens a connection to a peer from a TCP allocation, defined in
+    /// [RFC 6062](https://tools.ietf.org/html/rfc6062#section-5.2).
+    async fn connect(&self, req: &Message, five_tuple: FiveTuple) -> Result<Message, ErrorCode> {
+        let allocation_mismatch =
+            || ErrorCode::new(ErrorCode::ALLOCATION_MISMATCH, "Allocation Mismatch");
+        let bad_request = || ErrorCode::new(ErrorCode::BAD_REQUEST, "Bad Request");
+
+        let peer = req.attr::<XorPeerAddress>().ok_or_else(bad_request)?.0;
+        let relayed_addr = {
+            let allocations = self.inner.allocations.lock().unwrap();
+            let allocation = allocations
+                .get(&five_tuple)
+                .ok_or_else(allocation_mismatch)?;
+            if !allocation.is_tcp() {
+                return Err(bad_request());
+            }
+            let relayed_addr = allocation
+                .relayed_addr_for(peer.ip())
+                .ok_or_else(peer_address_family_mismatch)?
+                .addr;
+            if !allocation.has_permission(peer.ip()) {
+                return Err(ErrorCode::new(ErrorCode::FORBIDDEN, "Forbidden"));
+            }
+            if allocation.has_connection(peer) {
+                return Err(ErrorCode::new(
+                    ErrorCode::CONNECTION_ALREADY_EXISTS,
+                    "Connection Already Exists",
+                ));
+            }
+            relayed_addr
+        };
+
+        let connect =
+            tokio_timer::Timeout::new(tcp::connect(relayed_addr, peer), tcp::CONNECT_TIMEOUT);
+        let stream = match connect.await {
+            Ok(Ok(stream)) => stream,
+            _ => {
+                return Err(ErrorCode::new(
+                    ErrorCode::CONNECTION_TIMEOUT_OR_FAILURE,
+                    "Connection Timeout or Failure",
+                ))
+            }
+        };
+
+        let id = {
+            let mut allocations = self.inner.allocations.lock().unwrap();
+            let allocation = match allocations.get_mut(&five_tuple) {
+                // The allocation was deleted, and maybe replaced.
+                Some(allocation)
+                    if allocation
+                        .relayed_addr_for(peer.ip())
+                        .map(|relayed| relayed.addr)
+                        == Some(relayed_addr) =>
+                {
+                    allocation
+                }
+                _ => return Err(allocation_mismatch()),
+            };
+            if allocation.has_connection(peer) {
+                return Err(ErrorCode::new(
+                    ErrorCode::CONNECTION_ALREADY_EXISTS,
+                    "Connection Already Exists",
+                ));
+            }
+            self.add_connection(allocation, peer, stream, five_tuple)
+        };
+
+        let mut res = Message::new(Class::SuccessResponse, req.method, req.transaction_id);
+        res.add_attr(&id);
+        Ok(res)
+    }
+
+    /// Binds a data connection from a client to a peer connection, defined in
+    /// [RFC 6062](https://tools.ietf.org/html/rfc6062#section-5.4). Returns
+    /// the response, and the peer connection to splice the data connection to
+    /// on success.
+    fn bind_connection(&self, req: &Message) -> (Message, Option<Binding>) {
+        let (username, key) = match self.authenticate(req) {
+            Ok(x) => x,
+            Err(res) => return (res, None),
+        };
+
+        let bound = req.attr::<ConnectionId>().and_then(|id| {
+            let mut allocations = self.inner.allocations.lock().unwrap();
+            let (&five_tuple, conn) = allocations
+                .iter_mut()
+                .filter(|(_, allocation)| allocation.username == username)
+                .find_map(|(five_tuple, allocation)| {
+                    allocation
+                        .connections
+                        .get_mut(&id)
+                        .map(|conn| (five_tuple, conn))
+                })?;
+
+            let peer = conn.stream.take()?;
+            let (stop, stopped) = oneshot::channel();
+            conn.stop = Some(stop);
+            Some(Binding {
+                five_tuple,
+                id,
+                peer,
+                stop: stopped,
+            })
+        });
+
+        let mut res = match bound {
+            Some(_) => {
+                let mut res = Message::new(Class::SuccessResponse, req.method, req.transaction_id);
+                self.add_software(&mut res);
+                res
+            }
+            None => self.error_response(req, ErrorCode::new(ErrorCode::BAD_REQUEST, "Bad Request")),
+        };
+        res.add_message_integrity(&key);
+        (res, bound)
+    }
+
+    fn remove_connection(&self, five_tuple: FiveTuple, id: ConnectionId) {
+        if let Some(allocation) = self.inner.allocations.lock().unwrap().get_mut(&five_tuple) {
+            allocation.connections.remove(&id);
+        }
+    }
+
+    /// Counts data spliced between the client and a peer of the TCP
+    /// allocation on `five_tuple`.
+    fn count_spliced(&self, five_tuple: FiveTuple, to_peer: bool, len: usize) {
+        if let Some(allocation) = self.inner.allocations.lock().unwrap().get_mut(&five_tuple) {
+            if to_peer {
+                self.count_sent(allocation, len);
+            } else {
+                self.count_received(allocation, len);
+            }
+        }
+    }
+
+    /// Tracks a new connection to `peer` until the client binds a data
+    /// connection to it, and returns its ID.
+    fn add_connection(
+        &self,
+        allocation: &mut Allocation,
+        peer: SocketAddr,
+        stream: TcpStream,
+        five_tuple: FiveTuple,
+    ) -> ConnectionId {
+        let id = ConnectionId(
+            self.inner
+                .next_connection_id
+                .fetch_add(1, Ordering::Relaxed),
+        );
+        allocation.connections.insert(
+            id,
+            PeerConnection {
+                peer,
+                stream: Some(stream),
+                stop: None,
+            },
+        );
+
+        // Connections nobody binds to are closed.
+        let server = self.clone();
+        tokio_executor::spawn(async move {
+            tokio_timer::delay_for(tcp::BIND_TIMEOUT).await;
+            let mut allocations = server.inner.allocations.lock().unwrap();
+            if let Some(allocation) = allocations.get_mut(&five_tuple) {
+                if allocation
+                    .connections
+                    .get(&id)
+                    .is_some_and(|conn| conn.stream.is_some())
+                {
+                    allocation.connections.remove(&id);
+                }
+            }
+        });
+
+        id
+    }
+
+    fn handle_send(&self, msg: &Message, five_tuple: FiveTuple) {
+        let (peer, data) = match (msg.attr::<XorPeerAddress>(), msg.attr::<Data>()) {
+            (Some(peer), Some(data)) => (peer.0, data.0),
+            _ => return,
+        };
+
+        if let Some(allocation) = self.inner.allocations.lock().unwrap().get_mut(&five_tuple) {
+            let len = data.len();
+            if allocation.relay(data, peer) {
+                self.count_sent(allocation, len);
+            }
+        }
+    }
+
+    fn handle_channel_data(&self, data: ChannelData, five_tuple: FiveTuple) {
+        if let Some(allocation) = self.inner.allocations.lock().unwrap().get_mut(&five_tuple) {
+            let len = data.data.len();
+            match allocation.channel_peer(data.channel) {
+                Some(peer) if allocation.relay(data.data, peer) => self.count_sent(allocation, len),
+                _ => (),
+            }
+        }
+    }
+
+    /// Starts relaying between the relayed transport address and peers.
+    /// Returns the channel for data to send to peers, and the sender whose
+    /// drop stops the relay.
+    fn spawn_relay(
+        &self,
+        socket: UdpSocket,
+        five_tuple: FiveTuple,
+    ) -> (
+        mpsc::UnboundedSender<(Vec<u8>, SocketAddr)>,
+        oneshot::Sender<()>,
+    ) {
+        let (mut recv_half, mut send_half) = socket.split();
+        let (relay_tx, mut relay_rx) = mpsc::unbounded_channel::<(Vec<u8>, SocketAddr)>();
+        let (stop, mut stopped) = oneshot::channel();
+
+        tokio_executor::spawn(async move {
+            while let Some((data, peer)) = relay_rx.recv().await {
+                let _ = send_half.send_to(&data, &peer).await;
+            }
+        });
+
+        let server = self.clone();
+        tokio_executor::spawn(async move {
+            let mut buf = vec![0; 65536];
+            loop {
+                let recv = Box::pin(recv_half.recv_from(&mut buf));
+                let (len, peer) = match future::select(recv, &mut stopped).await {
+                    Either::Left((Ok(x), _)) => x,
+                    Either::Left((Err(_), _)) => continue,
+                    Either::Right(_) => break,
+                };
+                server.relay_to_client(buf[..len].to_vec(), peer, five_tuple);
+            }
+        });
+
+        (relay_tx, stop)
+    }
+
+    /// Starts accepting connections from peers to a TCP allocation. Returns
+    /// the sender whose drop stops accepting.
+    fn spawn_tcp_relay(
+        &self,
+        mut listener: TcpListener,
+        five_tuple: FiveTuple,
+    ) -> oneshot::Sender<()> {
+        let (stop, mut stopped) = oneshot::channel();
+
+        let server = self.clone();
+        tokio_executor::spawn(async move {
+            loop {
+                let accept = Box::pin(listener.accept());
+                let (stream, peer) = match future::select(accept, &mut stopped).await {
+                    Either::Left((Ok(x), _)) => x,
+                    Either::Left((Err(_), _)) => continue,
+                    Either::Right(_) => break,
+                };
+                server.offer_connection(stream, peer, five_tuple);
+            }
+        });
+
+        stop
+    }
+
+    /// Tells the client about a connection from a peer in a
+    /// ConnectionAttempt indication, defined in
+    /// [RFC 6062](https://tools.ietf.org/html/rfc6062#section-5.3). Peers
+    /// without a permission are turned away.
+    fn offer_connection(&self, stream: TcpStream, peer: SocketAddr, five_tuple: FiveTuple) {
+        let mut allocations = self.inner.allocations.lock().unwrap();
+        let allocation = match allocations.get_mut(&five_tuple) {
+            Some(allocation) if allocation.has_permission(peer.ip()) => allocation,
+            _ => return,
+        };
+
+        let id = self.add_connection(allocation, peer, stream, five_tuple);
+        let mut msg = Message::new(
+            Class::Indication,
+            Method::CONNECTION_ATTEMPT,
+            bifrost_stun::message::TransactionId::random(),
+        );
+        msg.add_attr(&id);
+        msg.add_attr(&XorPeerAddress(peer));
+        allocation.responder.send(Frame::Message(msg));
+    }
+
+    fn relay_to_client(&self, data: Vec<u8>, peer: SocketAddr, five_tuple: FiveTuple) {
+        let mut allocations = self.inner.allocations.lock().unwrap();
+        let allocation = match allocations.get_mut(&five_tuple) {
+            Some(allocation) if allocation.has_permission(peer.ip()) => allocation,
+            _ => return,
+        };
+
+        self.count_received(allocation, data.len());
+        let frame = match allocation.peer_channel(peer) {
+            Some(channel) => Frame::ChannelData(ChannelData::new(channel, data)),
+            None => {
+                let mut msg = Message::new(
+                    Class::Indication,
+                    Method::DATA,
+                    bifrost_stun::message::TransactionId::random(),
+                );
+                msg.add_attr(&XorPeerAddress(peer));
+                msg.add_attr(&Data(data));
+                Frame::Message(msg)
+            }
+        };
+        allocation.responder.send(frame);
+    }
+
+    /// Deletes the allocation once it expires without being refreshed.
+    fn spawn_expiry(&self, five_tuple: FiveTuple, relayed_addr: SocketAddr) {
+        let server = self.clone();
+        tokio_executor::spawn(async move {
+            loop {
+                let expires_at = {
+                    let mut allocations = server.inner.allocations.lock().unwrap();
+                    match allocations.get(&five_tuple) {
+                        // The allocation was deleted, and maybe replaced.
+                        Some(allocation) if allocation.relayed_addr() != relayed_addr => break,
+                        None => break,
+                        Some(allocation) if allocation.expires_at <= Instant::now() => {
+                            let allocation = allocations.remove(&five_tuple).unwrap();
+                            server.notify(AllocationEvent::Expired(allocation.info(five_tuple)));
+                            break;
+                        }
+                        Some(allocation) => allocation.expires_at,
+                    }
+                };
+                tokio_timer::delay(expires_at).await;
+            }
+        });
+    }
+
+    fn count_sent(&self, allocation: &mut Allocation, len: usize) {
+        allocation.stats.add_sent(len);
+        let mut user_stats = self.inner.user_stats.lock().unwrap();
+        let stats = user_stats.entry(allocation.username.clone()).or_default();
+        stats.add_sent(len);
+    }
+
+    fn count_received(&self, allocation: &mut Allocation, len: usize) {
+        allocation.stats.add_received(len);
+        let mut user_stats = self.inner.user_stats.lock().unwrap();
+        let stats = user_stats.entry(allocation.username.clone()).or_default();
+        stats.add_received(len);
+    }
+
+    /// Sends `event` to every subscriber still listening.
+    fn notify(&self, event: AllocationEvent) {
+        self.inner
+            .subscribers
+            .lock()
+            .unwrap()
+            .retain(|tx| tx.clone().try_send(event.clone()).is_ok());
+    }
+
+    /// Returns whether requests from `username` may use the allocation on
+    /// `five_tuple`. Only the user who created an allocation may touch it.
+    fn is_owner(&self, five_tuple: FiveTuple, username: &str) -> bool {
+        match self.inner.allocations.lock().unwrap().get(&five_tuple) {
+            Some(allocation) => allocation.username == username,
+            None => true,
+        }
+    }
+
+    fn has_allocation(&self, five_tuple: FiveTuple) -> bool {
+        self.inner
+            .allocations
+            .lock()
+            .unwrap()
+            .contains_key(&five_tuple)
+    }
+
+    /// Returns the lifetime to grant for `req`: the default unless the client
+    /// asks for longer, up to the configured maximum.
+    fn lifetime(&self, req: &Message) -> Duration {
+        let requested = req
+            .attr::<Lifetime>()
+            .map_or(DEFAULT_LIFETIME, |attr| attr.0);
+        requested
+            .max(DEFAULT_LIFETIME)
+            .min(self.inner.config.max_lifetime)
+    }
+
+    fn error_response(&self, req: &Message, error: ErrorCode) -> Message {
+        let mut res = Message::new(Class::FailureResponse, req.method, req.transaction_id);
+        res.add_attr(&error);
+        self.add_software(&mut res);
+        res
+    }
+
+    fn add_software(&self, res: &mut Message) {
+        if let Some(software) = &self.inner.config.software {
+            res.add_attr(&Software(software.clone()));
+        }
+    }
+}
+
+fn peer_address_family_mismatch() -> ErrorCode {
+    ErrorCode::new(
+        ErrorCode::PEER_ADDRESS_FAMILY_MISMATCH,
+        "Peer Address Family Mismatch",
+    )
+}
+
+/// A peer connection a client bound a data connection to.
+struct Binding {
+    five_tuple: FiveTuple,
+    id: ConnectionId,
+    peer: TcpStream,
+    // Fires when the allocation goes away.
+    stop: oneshot::Receiver<()>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::Credentials;
+    use crate::test_util::{self, timeout, TestServer};
+    use crate::transport::{Connection, Transport};
+    use bifrost_stun::message::TransactionId;
+
+    async fn connect(server: SocketAddr) -> Connection {
+        let credentials = Credentials::new(test_util::TEST_USERNAME, test_util::TEST_PASSWORD);
+        Connection::connect(server, Transport::Udp, credentials)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn authenticate() {
+        let server = Server::new(test_util::new_test_config());
+        let request = |realm: &str, nonce: &Nonce| {
+            let mut req = Message::new(Class::Request, Method::ALLOCATE, TransactionId::random());
+            req.add_attr(&Username(test_util::TEST_USERNAME.to_owned()));
+            req.add_attr(&Realm(realm.to_owned()));
+            req.add_attr(nonce);
+            let key = MessageIntegrity::long_term_key(
+                test_util::TEST_USERNAME,
+                realm,
+                test_util::TEST_PASSWORD,
+            );
+            req.add_message_integrity(&key);
+            req
+        };
+        let error_code = |res: Message| res.attr::<ErrorCode>().unwrap().code;
+
+        let nonce = server.new_nonce();
+        let req = request(test_util::TEST_REALM, &nonce);
+        assert_eq!(
+            server.authenticate(&req).unwrap().0,
+            test_util::TEST_USERNAME
+        );
+
+        // A wrong realm is challenged again rather than reported stale.
+        let res = server
+            .authenticate(&request("example.com", &nonce))
+            .unwrap_err();
+        assert!(res.attr::<Realm>().is_some());
+        assert_eq!(error_code(res), ErrorCode::UNAUTHORIZED);
+
+        let stale = Nonce(format!("{:016x}", 0));
+        let res = server.authenticate(&request(test_util::TEST_REALM, &stale));
+        assert_eq!(error_code(res.unwrap_err()), ErrorCode::STALE_NONCE);
+    }
+
+    #[test]
+    fn allocation_table() {
+        tokio_test::block_on(async {
+            let TestServer {
+                server, udp_addr, ..
+            } = test_util::start_server(test_util::new_test_config()).await;
+            let mut events = server.subscribe();
+            let mut connection = connect(udp_addr).await;
+            let client = connection.client().clone();
+
+            let relayed_addr = timeout(client.allocate()).await.unwrap().relayed_addr;
+            let five_tuple = match timeout(events.recv()).await {
+                Some(AllocationEvent::Created(info)) => {
+                    assert_eq!(info.username, test_util::TEST_USERNAME);
+                    assert_eq!(info.relayed_addrs, vec![relayed_addr]);
+                    assert_eq!(info.five_tuple.server, udp_addr);
+                    assert_eq!(info.five_tuple.protocol, Protocol::Udp);
+                    assert_eq!(info.stats, TrafficStats::default());
+                    info.five_tuple
+                }
+                event => panic!("unexpected event {:?}", event),
+            };
+
+            let mut peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let peer_addr = peer.local_addr().unwrap();
+            let channel = timeout(client.bind_channel(peer_addr)).await.unwrap();
+            let mut buf = [0; 64];
+            client.send_to(b"hello", peer_addr).await.unwrap();
+            timeout(peer.recv_from(&mut buf)).await.unwrap();
+            peer.send_to(b"hi", &relayed_addr).await.unwrap();
+            timeout(connection.recv_from()).await.unwrap();
+
+            let info = server.allocation(five_tuple).unwrap();
+            assert!(info.permissions.contains_key(&peer_addr.ip()));
+            assert_eq!(info.channels[&channel].0, peer_addr);
+            let stats = TrafficStats {
+                packets_sent: 1,
+                bytes_sent: 5,
+                packets_received: 1,
+                bytes_received: 2,
+            };
+            assert_eq!(info.stats, stats);
+            assert_eq!(server.allocations(), vec![info.clone()]);
+            assert_eq!(server.user_stats()[test_util::TEST_USERNAME], stats);
+
+            timeout(client.refresh(Duration::from_secs(1200)))
+                .await
+                .unwrap();
+            match timeout(events.recv()).await {
+                Some(AllocationEvent::Refreshed(refreshed)) => {
+                    assert!(refreshed.expires_at > info.expires_at)
+                }
+                event => panic!("unexpected event {:?}", event),
+            }
+
+            timeout(client.deallocate()).await.unwrap();
+            match timeout(events.recv()).await {
+                Some(AllocationEvent::Deleted(deleted)) => assert_eq!(deleted.stats, stats),
+                event => panic!("unexpected event {:?}", event),
+            }
+            assert!(server.allocations().is_empty());
+
+            // Users keep their totals across allocations.
+            assert_eq!(server.user_stats()[test_util::TEST_USERNAME], stats);
+        });
+    }
+
+    #[test]
+    fn expiry() {
+        tokio_test::block_on(async {
+            let mut config = test_util::new_test_config();
+            config.max_lifetime = Duration::from_millis(100);
+            let TestServer {
+                server, udp_addr, ..
+            } = test_util::start_server(config).await;
+            let mut events = server.subscribe();
+            let connection = connect(udp_addr).await;
+
+            timeout(connection.client().allocate()).await.unwrap();
+            // Nothing refreshes the allocation once the connection is gone.
+            drop(connection);
+            match timeout(events.recv()).await {
+                Some(AllocationEvent::Created(_)) => (),
+                event => panic!("unexpected event {:?}", event),
+            }
+            match timeout(events.recv()).await {
+                Some(AllocationEvent::Expired(info)) => {
+                    assert_eq!(info.username, test_util::TEST_USERNAME)
+                }
+                event => panic!("unexpected event {:?}", event),
+            }
+            assert!(server.allocations().is_empty());
+        });
+    }
+}
@@ -0,0 +1,101 @@
+use bytes::BytesMut;
+use futures_util::future;
+use net2::TcpBuilder;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio_io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_net::driver::Handle;
+use tokio_net::tcp::{TcpListener, TcpStream};
+use tokio_sync::oneshot;
+
+/// How long a peer connection waits for the client to bind a connection to
+/// it, defined in
+/// [RFC 6062](https://tools.ietf.org/html/rfc6062#section-5.3).
+pub(crate) const BIND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long to wait for a peer to accept a connection.
+pub(crate) const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Creates a socket bound to `addr` that other sockets may bind to as well,
+/// so that connections to peers come from the relayed transport address the
+/// allocation listens on.
+fn new_builder(addr: SocketAddr) -> io::Result<TcpBuilder> {
+    let builder = match addr {
+        SocketAddr::V4(_) => TcpBuilder::new_v4()?,
+        SocketAddr::V6(_) => TcpBuilder::new_v6()?,
+    };
+    builder.reuse_address(true)?;
+    #[cfg(unix)]
+    {
+        use net2::unix::UnixTcpBuilderExt;
+        builder.reuse_port(true)?;
+    }
+    builder.bind(addr)?;
+    Ok(builder)
+}
+
+/// Listens for connections from peers on `addr`.
+pub(crate) fn listen(addr: SocketAddr) -> io::Result<TcpListener> {
+    let listener = new_builder(addr)?.listen(128)?;
+    TcpListener::from_std(listener, &Handle::default())
+}
+
+/// Connects to `peer` from `local_addr`, which an allocation listens on.
+pub(crate) async fn connect(local_addr: SocketAddr, peer: SocketAddr) -> io::Result<TcpStream> {
+    let stream = new_builder(local_addr)?.to_tcp_stream()?;
+    TcpStream::connect_std(stream, &peer, &Handle::default()).await
+}
+
+/// Copies data both ways between a client and a peer until both are done or
+/// `stop` fires. `buffered` is what the client sent after its ConnectionBind
+/// request. `count` is called with whether data went to the peer and how
+/// much.
+pub(crate) async fn splice<S, C>(
+    client: S,
+    buffered: BytesMut,
+    mut peer: TcpStream,
+    stop: oneshot::Receiver<()>,
+    count: C,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+    C: Fn(bool, usize),
+{
+    let relay = async {
+        if !buffered.is_empty() {
+            peer.write_all(&buffered).await?;
+            count(true, buffered.len());
+        }
+
+        let (mut client_read, mut client_write) = tokio_io::split::split(client);
+        let (mut peer_read, mut peer_write) = peer.split();
+
+        let (to_peer, to_client) = future::join(
+            pipe(&mut client_read, &mut peer_write, |len| count(true, len)),
+            pipe(&mut peer_read, &mut client_write, |len| count(false, len)),
+        )
+        .await;
+        to_peer.and(to_client)
+    };
+
+    // Either way, dropping the streams closes both connections.
+    let _ = future::select(Box::pin(relay), stop).await;
+}
+
+/// Copies everything from `reader` to `writer`, then shuts `writer` down.
+async fn pipe<R, W, C>(reader: &mut R, writer: &mut W, count: C) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+    C: Fn(usize),
+{
+    let mut buf = vec![0; 16384];
+    loop {
+        let len = reader.read(&mut buf).await?;
+        if len == 0 {
+            return writer.shutdown().await;
+        }
+        writer.write_all(&buf[..len]).await?;
+        count(len);
+    }
+}
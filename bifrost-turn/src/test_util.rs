@@ -0,0 +1,123 @@
+use crate::server::{Server, ServerConfig};
+use futures_util::future;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio_io::AsyncReadExt;
+use tokio_net::tcp::{TcpListener, TcpStream};
+use tokio_net::udp::UdpSocket;
+use tokio_sync::oneshot;
+use tokio_timer::Timeout;
+
+pub const TEST_REALM: &str = "bifrost.rs";
+pub const TEST_USERNAME: &str = "alice";
+pub const TEST_PASSWORD: &str = "hunter2";
+
+// A self-signed certificate for `localhost` and 127.0.0.1.
+const TEST_CERT: &[u8] = include_bytes!("../test_data/cert.pem");
+const TEST_IDENTITY: &[u8] = include_bytes!("../test_data/identity.p12");
+const TEST_IDENTITY_PASSWORD: &str = "bifrost";
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub async fn timeout<F: Future>(future: F) -> F::Output {
+    Timeout::new(future, TIMEOUT).await.expect("timed out")
+}
+
+pub fn new_tls_acceptor() -> native_tls::TlsAcceptor {
+    let identity = native_tls::Identity::from_pkcs12(TEST_IDENTITY, TEST_IDENTITY_PASSWORD);
+    native_tls::TlsAcceptor::new(identity.unwrap()).unwrap()
+}
+
+pub fn new_tls_connector() -> native_tls::TlsConnector {
+    let cert = native_tls::Certificate::from_pem(TEST_CERT).unwrap();
+    native_tls::TlsConnector::builder()
+        .add_root_certificate(cert)
+        .build()
+        .unwrap()
+}
+
+pub fn new_test_config() -> ServerConfig {
+    let mut config = ServerConfig::new(TEST_REALM, "127.0.0.1".parse().unwrap());
+    config.add_user(TEST_USERNAME, TEST_PASSWORD);
+    config
+}
+
+/// A server listening on every transport.
+pub struct TestServer {
+    pub server: Server,
+    pub udp_addr: SocketAddr,
+    pub tcp_addr: SocketAddr,
+    pub tls_addr: SocketAddr,
+}
+
+pub async fn start_server(config: ServerConfig) -> TestServer {
+    let server = Server::new(config);
+    let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let tls = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+    let test_server = TestServer {
+        server: server.clone(),
+        udp_addr: udp.local_addr().unwrap(),
+        tcp_addr: tcp.local_addr().unwrap(),
+        tls_addr: tls.local_addr().unwrap(),
+    };
+
+    let s = server.clone();
+    tokio_executor::spawn(async move {
+        s.serve_udp(udp).await.unwrap();
+    });
+    let s = server.clone();
+    tokio_executor::spawn(async move {
+        s.serve_tcp(tcp).await.unwrap();
+    });
+    tokio_executor::spawn(async move {
+        server.serve_tls(tls, new_tls_acceptor()).await.unwrap();
+    });
+
+    test_server
+}
+
+/// A TCP proxy whose connections can be cut to simulate connection loss.
+pub struct Proxy {
+    pub addr: SocketAddr,
+    connections: Arc<Mutex<Vec<oneshot::Sender<()>>>>,
+}
+
+impl Proxy {
+    pub async fn start(upstream: SocketAddr) -> Self {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = Self {
+            addr: listener.local_addr().unwrap(),
+            connections: Arc::default(),
+        };
+
+        let connections = Arc::clone(&proxy.connections);
+        tokio_executor::spawn(async move {
+            while let Ok((mut downstream, _)) = listener.accept().await {
+                let (kill, killed) = oneshot::channel();
+                connections.lock().unwrap().push(kill);
+
+                tokio_executor::spawn(async move {
+                    let mut upstream = TcpStream::connect(upstream).await.unwrap();
+                    let (mut up_read, mut up_write) = upstream.split();
+                    let (mut down_read, mut down_write) = downstream.split();
+
+                    let forward =
+                        future::join(up_read.copy(&mut down_write), down_read.copy(&mut up_write));
+                    // Both connections close when the task ends either way.
+                    let _ = future::select(Box::pin(forward), killed).await;
+                });
+            }
+        });
+
+        proxy
+    }
+
+    /// Closes all connections made through the proxy so far.
+    pub fn cut(&self) {
+        self.connections.lock().unwrap().clear();
+    }
+}
@@ -0,0 +1,861 @@
+use crate::client::{self, Client, Credentials};
+use crate::codec::FrameCodec;
+use crate::frame::Frame;
+use bifrost_stun::message::attribute::ConnectionId;
+use bifrost_stun::uri::{self, Host, IceServer, IceServerUri, Scheme};
+use futures_util::future::{self, Either, FutureExt, Ready};
+use futures_util::{SinkExt, StreamExt};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio_codec::Framed;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_net::tcp::TcpStream;
+use tokio_net::udp::{UdpFramed, UdpSocket};
+use tokio_sync::{mpsc, oneshot};
+use tokio_tls::TlsStream;
+
+/// How long to wait before the first attempt to reconnect.
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_millis(100);
+
+/// The longest to wait between attempts to reconnect.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// The `on_send` hook of a client driven by a `Connection`.
+pub type FrameSender = Box<dyn Fn(Frame, SocketAddr) -> Ready<io::Result<()>> + Send + Sync>;
+
+/// The transport between a TURN client and server.
+#[derive(Clone)]
+pub enum Transport {
+    Udp,
+    Tcp,
+    /// TLS over TCP, with `domain` being the name to validate the server's
+    /// certificate against.
+    Tls {
+        domain: String,
+        connector: native_tls::TlsConnector,
+    },
+}
+
+impl Transport {
+    /// Returns the transport to reach the TURN server at `uri` over. `turns:`
+    /// URIs use TLS, trusting the system's root certificates.
+    pub fn from_uri(uri: &IceServerUri) -> io::Result<Self> {
+        match (uri.scheme, uri.transport()) {
+            (Scheme::Turn, uri::Transport::Udp) => Ok(Transport::Udp),
+            (Scheme::Turn, uri::Transport::Tcp) => Ok(Transport::Tcp),
+            (Scheme::Turns, uri::Transport::Tcp) => Ok(Transport::Tls {
+                domain: match &uri.host {
+                    Host::Ip(ip) => ip.to_string(),
+                    Host::Domain(domain) => domain.clone(),
+                },
+                connector: native_tls::TlsConnector::new().map_err(io::Error::other)?,
+            }),
+            (Scheme::Turns, uri::Transport::Udp) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "DTLS is not supported",
+            )),
+            (Scheme::Stun, _) | (Scheme::Stuns, _) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not a TURN URI",
+            )),
+        }
+    }
+}
+
+/// A TURN client talking to a server over UDP, TCP or TLS.
+///
+/// A background task owns the socket and refreshes the allocation, its
+/// permissions and its channel bindings before they expire. Over TCP and
+/// TLS, it also reconnects when the connection is lost, and since the server
+/// deletes allocations made over a closed connection, it then re-creates the
+/// allocation along with its permissions and channel bindings.
+pub struct Connection {
+    server: SocketAddr,
+    transport: Transport,
+    client: Client<FrameSender>,
+    incoming: mpsc::UnboundedReceiver<(Vec<u8>, SocketAddr)>,
+    attempts: mpsc::UnboundedReceiver<(ConnectionId, SocketAddr)>,
+    // Dropping this stops the background task.
+    _shutdown: oneshot::Receiver<()>,
+}
+
+impl Connection {
+    pub async fn connect(
+        server: SocketAddr,
+        transport: Transport,
+        credentials: Credentials,
+    ) -> io::Result<Self> {
+        let link = Link::connect(server, &transport).await?;
+
+        let (outgoing_tx, outgoing_rx) = mpsc::unbounded_channel();
+        let on_send: FrameSender = Box::new(move |frame, _| {
+            let res = outgoing_tx
+                .clone()
+                .try_send(frame)
+                .map_err(|_| io::Error::from(io::ErrorKind::NotConnected));
+            future::ready(res)
+        });
+        let client = match transport {
+            Transport::Udp => Client::new(server, credentials, on_send),
+            Transport::Tcp | Transport::Tls { .. } => {
+                Client::new_reliable(server, credentials, on_send)
+            }
+        };
+
+        let (incoming_tx, incoming) = mpsc::unbounded_channel();
+        let (attempts_tx, attempts) = mpsc::unbounded_channel();
+        let (shutdown_tx, shutdown) = oneshot::channel();
+        let driver = Driver {
+            server,
+            transport: transport.clone(),
+            client: client.clone(),
+            outgoing: outgoing_rx,
+            incoming: incoming_tx,
+            attempts: attempts_tx,
+            shutdown: shutdown_tx,
+        };
+        tokio_executor::spawn(driver.run(link));
+
+        Ok(Self {
+            server,
+            transport,
+            client,
+            incoming,
+            attempts,
+            _shutdown: shutdown,
+        })
+    }
+
+    /// Connects to the TURN server configured by `server`, trying each of
+    /// its TURN URIs in order.
+    pub async fn connect_ice_server(server: &IceServer) -> io::Result<Self> {
+        let credentials = Credentials::from_ice_server(server)?;
+
+        let mut last_err = io::Error::new(io::ErrorKind::InvalidInput, "no TURN URIs");
+        for uri in server.urls.iter().filter(|uri| uri.scheme.is_turn()) {
+            match Self::connect_uri(uri, credentials.clone()).await {
+                Ok(connection) => return Ok(connection),
+                Err(e) => last_err = e,
+            }
+        }
+
+        Err(last_err)
+    }
+
+    /// Connects to the TURN server at `uri`, trying each of its addresses in
+    /// order.
+    pub async fn connect_uri(uri: &IceServerUri, credentials: Credentials) -> io::Result<Self> {
+        let transport = Transport::from_uri(uri)?;
+        let addrs = uri.socket_addrs().await?;
+        Self::connect_any(&addrs, transport, credentials).await
+    }
+
+    async fn connect_any(
+        addrs: &[SocketAddr],
+        transport: Transport,
+        credentials: Credentials,
+    ) -> io::Result<Self> {
+        let mut last_err = io::Error::from(io::ErrorKind::AddrNotAvailable);
+        for &addr in addrs {
+            match Self::connect(addr, transport.clone(), credentials.clone()).await {
+                Ok(connection) => return Ok(connection),
+                Err(e) => last_err = e,
+            }
+        }
+        Err(last_err)
+    }
+
+    pub fn client(&self) -> &Client<FrameSender> {
+        &self.client
+    }
+
+    /// Receives data relayed from a peer. Returns `None` once the connection
+    /// is closed for good.
+    pub async fn recv_from(&mut self) -> Option<(Vec<u8>, SocketAddr)> {
+        self.incoming.recv().await
+    }
+
+    /// Opens a TCP connection to `peer` through a TCP allocation.
+    pub async fn connect_peer(&self, peer: SocketAddr) -> io::Result<DataConnection> {
+        let id = self.client.connect(peer).await?;
+        self.bind_connection(id, peer).await
+    }
+
+    /// Accepts the next TCP connection from a peer to a TCP allocation.
+    /// Returns `None` once the connection is closed for good.
+    pub async fn accept_peer(&mut self) -> Option<io::Result<DataConnection>> {
+        let (id, peer) = self.attempts.recv().await?;
+        Some(self.bind_connection(id, peer).await)
+    }
+
+    async fn bind_connection(
+        &self,
+        id: ConnectionId,
+        peer: SocketAddr,
+    ) -> io::Result<DataConnection> {
+        let mut stream = Stream::connect(self.server, &self.transport).await?;
+        self.client.bind_connection(&mut stream, id).await?;
+
+        Ok(DataConnection {
+            stream,
+            peer_addr: peer,
+        })
+    }
+}
+
+/// A TCP connection to a peer through a TCP allocation, defined in
+/// [RFC 6062](https://tools.ietf.org/html/rfc6062#section-4.3).
+///
+/// This is a separate connection to the server, over the same transport as
+/// the control connection, which the server splices to the connection with
+/// the peer.
+#[derive(Debug)]
+pub struct DataConnection {
+    stream: Stream,
+    peer_addr: SocketAddr,
+}
+
+impl DataConnection {
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+}
+
+impl AsyncRead for DataConnection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for DataConnection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+/// A TCP or TLS connection to the server.
+#[derive(Debug)]
+enum Stream {
+    Tcp(TcpStream),
+    Tls(TlsStream<TcpStream>),
+}
+
+impl Stream {
+    async fn connect(server: SocketAddr, transport: &Transport) -> io::Result<Self> {
+        match transport {
+            Transport::Udp => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not a stream transport",
+            )),
+            Transport::Tcp => Ok(Stream::Tcp(TcpStream::connect(server).await?)),
+            Transport::Tls { domain, connector } => {
+                let stream = TcpStream::connect(server).await?;
+                let stream = tokio_tls::TlsConnector::from(connector.clone())
+                    .connect(domain, stream)
+                    .await
+                    .map_err(io::Error::other)?;
+                Ok(Stream::Tls(stream))
+            }
+        }
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+/// The connection to the server carrying STUN and ChannelData messages.
+enum Link {
+    Udp(UdpFramed<FrameCodec>),
+    Stream(Framed<Stream, FrameCodec>),
+}
+
+impl Link {
+    async fn connect(server: SocketAddr, transport: &Transport) -> io::Result<Self> {
+        match transport {
+            Transport::Udp => {
+                let ip = match server {
+                    SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                    SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+                };
+                let socket = UdpSocket::bind(SocketAddr::new(ip, 0)).await?;
+                Ok(Link::Udp(UdpFramed::new(socket, FrameCodec::datagram())))
+            }
+            _ => {
+                let stream = Stream::connect(server, transport).await?;
+                Ok(Link::Stream(Framed::new(stream, FrameCodec::stream())))
+            }
+        }
+    }
+
+    fn is_stream(&self) -> bool {
+        match self {
+            Link::Udp(_) => false,
+            Link::Stream(_) => true,
+        }
+    }
+
+    /// Receives the next frame. Returns `None` once the connection is closed,
+    /// and `Some(Ok(None))` for anything that is not a frame from `server`.
+    async fn recv(&mut self, server: SocketAddr) -> Option<io::Result<Option<Frame>>> {
+        match self {
+            Link::Udp(framed) => framed
+                .next()
+                .await
+                .map(|res| res.map(|(frame, addr)| if addr == server { frame } else { None })),
+            Link::Stream(framed) => framed.next().await,
+        }
+    }
+
+    async fn send(&mut self, frame: Frame, server: SocketAddr) -> io::Result<()> {
+        match self {
+            Link::Udp(framed) => framed.send((frame, server)).await,
+            Link::Stream(framed) => framed.send(frame).await,
+        }
+    }
+}
+
+enum Event {
+    Incoming(Option<io::Result<Option<Frame>>>),
+    Outgoing(Option<Frame>),
+    Refresh,
+    Rescheduled(Option<Option<Instant>>),
+}
+
+struct Driver {
+    server: SocketAddr,
+    transport: Transport,
+    client: Client<FrameSender>,
+    outgoing: mpsc::UnboundedReceiver<Frame>,
+    incoming: mpsc::UnboundedSender<(Vec<u8>, SocketAddr)>,
+    attempts: mpsc::UnboundedSender<(ConnectionId, SocketAddr)>,
+    shutdown: oneshot::Sender<()>,
+}
+
+impl Driver {
+    async fn run(mut self, mut link: Link) {
+        let mut rescheduled = self.client.next_refresh();
+        let mut next_refresh = None;
+
+        loop {
+            let event = {
+                let io = future::select(
+                    Box::pin(link.recv(self.server).map(Event::Incoming)),
+                    Box::pin(self.outgoing.recv().map(Event::Outgoing)),
+                )
+                .map(|either| either.factor_first().0);
+                let refresh = future::select(
+                    Box::pin(async {
+                        match next_refresh {
+                            Some(deadline) => tokio_timer::delay(deadline).await,
+                            None => future::pending().await,
+                        }
+                        Event::Refresh
+                    }),
+                    Box::pin(rescheduled.recv().map(Event::Rescheduled)),
+                )
+                .map(|either| either.factor_first().0);
+                let events = future::select(io, refresh).map(|either| either.factor_first().0);
+                let shutdown = Box::pin(self.shutdown.closed());
+                match future::select(events, shutdown).await {
+                    Either::Left((event, _)) => event,
+                    Either::Right(_) => return,
+                }
+            };
+
+            let connected = match event {
+                Event::Incoming(Some(Ok(Some(frame)))) => {
+                    match self.client.on_recv(frame, self.server).await {
+                        Some(client::Event::Data(data, peer)) => {
+                            let _ = self.incoming.try_send((data, peer));
+                        }
+                        Some(client::Event::ConnectionAttempt(id, peer)) => {
+                            let _ = self.attempts.try_send((id, peer));
+                        }
+                        None => (),
+                    }
+                    true
+                }
+                Event::Incoming(Some(Ok(None))) => true,
+                // A datagram socket has no connection to lose.
+                Event::Incoming(Some(Err(_))) => !link.is_stream(),
+                Event::Incoming(None) => false,
+                Event::Outgoing(Some(frame)) => {
+                    link.send(frame, self.server).await.is_ok() || !link.is_stream()
+                }
+                Event::Outgoing(None) => return,
+                Event::Refresh => {
+                    // The responses arrive through this loop, so the requests
+                    // are sent from another task, which reschedules the next
+                    // refresh before sending them.
+                    next_refresh = None;
+                    let client = self.client.clone();
+                    tokio_executor::spawn(async move {
+                        let _ = client.refresh_due(Instant::now()).await;
+                    });
+                    true
+                }
+                Event::Rescheduled(deadline) => {
+                    next_refresh = deadline.unwrap_or(None);
+                    true
+                }
+            };
+
+            if !connected {
+                link = match self.reconnect().await {
+                    Some(link) => link,
+                    None => return,
+                };
+
+                if self.client.allocation().await.is_some() {
+                    let client = self.client.clone();
+                    tokio_executor::spawn(async move {
+                        let _ = client.reallocate().await;
+                    });
+                }
+            }
+        }
+    }
+
+    /// Reconnects with exponential backoff. Returns `None` if the connection
+    /// was dropped in the meantime.
+    async fn reconnect(&mut self) -> Option<Link> {
+        let mut delay = INITIAL_RECONNECT_DELAY;
+
+        loop {
+            if self.shutdown.is_closed() {
+                return None;
+            }
+
+            tokio_timer::delay_for(delay).await;
+            if let Ok(link) = Link::connect(self.server, &self.transport).await {
+                return Some(link);
+            }
+            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{AddressFamily, ErrorResponse};
+    use crate::test_util::{self, timeout, Proxy, TestServer};
+    use bifrost_stun::message::attribute::{ErrorCode, RequestedTransport};
+    use tokio_io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_net::tcp::TcpListener;
+
+    const LOCALHOST_V4: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    const LOCALHOST_V6: IpAddr = IpAddr::V6(Ipv6Addr::LOCALHOST);
+
+    fn new_test_credentials() -> Credentials {
+        Credentials::new(test_util::TEST_USERNAME, test_util::TEST_PASSWORD)
+    }
+
+    fn new_tls_transport() -> Transport {
+        Transport::Tls {
+            domain: "localhost".to_owned(),
+            connector: test_util::new_tls_connector(),
+        }
+    }
+
+    /// Relays data both ways between the client and a peer on `peer_ip`,
+    /// first in Send/Data indications and then over a channel.
+    async fn check_relay(connection: &mut Connection, peer_ip: IpAddr) {
+        let allocation = connection.client().allocation().await.unwrap();
+        let relayed_addr = allocation.relayed_addr_for(peer_ip).unwrap();
+        let mut peer = UdpSocket::bind(SocketAddr::new(peer_ip, 0)).await.unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+        let mut buf = [0; 64];
+
+        let client = connection.client().clone();
+        timeout(client.create_permission(peer_addr.ip()))
+            .await
+            .unwrap();
+
+        for _ in 0..2 {
+            client.send_to(b"hello", peer_addr).await.unwrap();
+            let (len, addr) = timeout(peer.recv_from(&mut buf)).await.unwrap();
+            assert_eq!(&buf[..len], b"hello");
+            assert_eq!(addr, relayed_addr);
+
+            peer.send_to(b"world", &relayed_addr).await.unwrap();
+            let (data, addr) = timeout(connection.recv_from()).await.unwrap();
+            assert_eq!(data, b"world");
+            assert_eq!(addr, peer_addr);
+
+            timeout(client.bind_channel(peer_addr)).await.unwrap();
+        }
+    }
+
+    async fn check_transport(server: SocketAddr, transport: Transport) {
+        let mut connection = Connection::connect(server, transport, new_test_credentials())
+            .await
+            .unwrap();
+
+        let allocation = timeout(connection.client().allocate()).await.unwrap();
+        assert_eq!(allocation.relayed_addr.ip(), server.ip());
+        check_relay(&mut connection, LOCALHOST_V4).await;
+
+        timeout(connection.client().deallocate()).await.unwrap();
+        assert!(connection.client().allocation().await.is_none());
+    }
+
+    #[test]
+    fn udp() {
+        tokio_test::block_on(async {
+            let TestServer { udp_addr, .. } =
+                test_util::start_server(test_util::new_test_config()).await;
+            check_transport(udp_addr, Transport::Udp).await;
+        });
+    }
+
+    #[test]
+    fn tcp() {
+        tokio_test::block_on(async {
+            let TestServer { tcp_addr, .. } =
+                test_util::start_server(test_util::new_test_config()).await;
+            check_transport(tcp_addr, Transport::Tcp).await;
+        });
+    }
+
+    #[test]
+    fn tls() {
+        tokio_test::block_on(async {
+            let TestServer { tls_addr, .. } =
+                test_util::start_server(test_util::new_test_config()).await;
+            check_transport(tls_addr, new_tls_transport()).await;
+        });
+    }
+
+    #[test]
+    fn wrong_password() {
+        tokio_test::block_on(async {
+            let TestServer { tcp_addr, .. } =
+                test_util::start_server(test_util::new_test_config()).await;
+            let credentials = Credentials::new(test_util::TEST_USERNAME, "wrong");
+            let connection = Connection::connect(tcp_addr, Transport::Tcp, credentials)
+                .await
+                .unwrap();
+
+            let err = timeout(connection.client().allocate()).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        });
+    }
+
+    async fn check_reconnect(upstream: SocketAddr, transport: Transport) {
+        let proxy = Proxy::start(upstream).await;
+        let mut connection = Connection::connect(proxy.addr, transport, new_test_credentials())
+            .await
+            .unwrap();
+
+        let client = connection.client().clone();
+        let old = timeout(client.allocate()).await.unwrap();
+        check_relay(&mut connection, LOCALHOST_V4).await;
+
+        proxy.cut();
+
+        // Wait for the client to reconnect and re-allocate.
+        timeout(async {
+            loop {
+                tokio_timer::delay_for(Duration::from_millis(50)).await;
+                match client.allocation().await {
+                    Some(new) if new.relayed_addr != old.relayed_addr => break,
+                    _ => (),
+                }
+            }
+        })
+        .await;
+
+        check_relay(&mut connection, LOCALHOST_V4).await;
+    }
+
+    #[test]
+    fn refresh() {
+        tokio_test::block_on(async {
+            let mut config = test_util::new_test_config();
+            config.max_lifetime = Duration::from_secs(2);
+            let TestServer { udp_addr, .. } = test_util::start_server(config).await;
+            let mut connection =
+                Connection::connect(udp_addr, Transport::Udp, new_test_credentials())
+                    .await
+                    .unwrap();
+
+            let allocation = timeout(connection.client().allocate()).await.unwrap();
+            assert_eq!(allocation.lifetime, Duration::from_secs(2));
+
+            // The allocation outlives its lifetime because it is refreshed.
+            tokio_timer::delay_for(Duration::from_secs(3)).await;
+            check_relay(&mut connection, LOCALHOST_V4).await;
+        });
+    }
+
+    #[test]
+    fn tcp_reconnect() {
+        tokio_test::block_on(async {
+            let TestServer { tcp_addr, .. } =
+                test_util::start_server(test_util::new_test_config()).await;
+            check_reconnect(tcp_addr, Transport::Tcp).await;
+        });
+    }
+
+    #[test]
+    fn tls_reconnect() {
+        tokio_test::block_on(async {
+            let TestServer { tls_addr, .. } =
+                test_util::start_server(test_util::new_test_config()).await;
+            check_reconnect(tls_addr, new_tls_transport()).await;
+        });
+    }
+
+    /// Sends data both ways over a TCP connection through the relay.
+    async fn check_data_connection<S>(conn: &mut DataConnection, peer: &mut S)
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut buf = [0; 5];
+
+        conn.write_all(b"hello").await.unwrap();
+        timeout(peer.read_exact(&mut buf)).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        peer.write_all(b"world").await.unwrap();
+        timeout(conn.read_exact(&mut buf)).await.unwrap();
+        assert_eq!(&buf, b"world");
+    }
+
+    async fn check_tcp_allocation(server: SocketAddr, transport: Transport) {
+        let mut connection = Connection::connect(server, transport, new_test_credentials())
+            .await
+            .unwrap();
+        let client = connection.client().clone();
+
+        let relayed_addr = timeout(client.allocate_tcp()).await.unwrap().relayed_addr;
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let peer_addr = listener.local_addr().unwrap();
+
+        // No connections without a permission.
+        let err = timeout(connection.connect_peer(peer_addr))
+            .await
+            .unwrap_err();
+        assert_eq!(ErrorResponse::code(&err), Some(ErrorCode::FORBIDDEN));
+        timeout(client.create_permission(peer_addr.ip()))
+            .await
+            .unwrap();
+
+        // Connections to the peer come from the relayed transport address.
+        let (conn, accepted) = timeout(future::join(
+            connection.connect_peer(peer_addr),
+            listener.accept(),
+        ))
+        .await;
+        let (mut conn, (mut peer, addr)) = (conn.unwrap(), accepted.unwrap());
+        assert_eq!(addr, relayed_addr);
+        assert_eq!(conn.peer_addr(), peer_addr);
+        check_data_connection(&mut conn, &mut peer).await;
+
+        let err = timeout(connection.connect_peer(peer_addr))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            ErrorResponse::code(&err),
+            Some(ErrorCode::CONNECTION_ALREADY_EXISTS)
+        );
+
+        // Connections from the peer to the relayed transport address.
+        let mut peer = TcpStream::connect(relayed_addr).await.unwrap();
+        let mut conn = timeout(connection.accept_peer()).await.unwrap().unwrap();
+        assert_eq!(conn.peer_addr(), peer.local_addr().unwrap());
+        check_data_connection(&mut conn, &mut peer).await;
+    }
+
+    #[test]
+    fn tcp_allocation() {
+        tokio_test::block_on(async {
+            let TestServer { tcp_addr, .. } =
+                test_util::start_server(test_util::new_test_config()).await;
+            check_tcp_allocation(tcp_addr, Transport::Tcp).await;
+        });
+    }
+
+    #[test]
+    fn tls_tcp_allocation() {
+        tokio_test::block_on(async {
+            let TestServer { tls_addr, .. } =
+                test_util::start_server(test_util::new_test_config()).await;
+            check_tcp_allocation(tls_addr, new_tls_transport()).await;
+        });
+    }
+
+    #[test]
+    fn udp_tcp_allocation() {
+        tokio_test::block_on(async {
+            let TestServer { udp_addr, .. } =
+                test_util::start_server(test_util::new_test_config()).await;
+            let connection = Connection::connect(udp_addr, Transport::Udp, new_test_credentials())
+                .await
+                .unwrap();
+
+            let err = timeout(connection.client().allocate_tcp())
+                .await
+                .unwrap_err();
+            assert_eq!(ErrorResponse::code(&err), Some(ErrorCode::BAD_REQUEST));
+        });
+    }
+
+    #[test]
+    fn dual_stack() {
+        tokio_test::block_on(async {
+            let mut config = test_util::new_test_config();
+            config.relay_ips.push(LOCALHOST_V6);
+            let TestServer { udp_addr, .. } = test_util::start_server(config).await;
+            let mut connection =
+                Connection::connect(udp_addr, Transport::Udp, new_test_credentials())
+                    .await
+                    .unwrap();
+            let client = connection.client().clone();
+
+            let allocation =
+                timeout(client.allocate_with(RequestedTransport::UDP, AddressFamily::DualStack))
+                    .await
+                    .unwrap();
+            assert_eq!(allocation.relayed_addr.ip(), LOCALHOST_V4);
+            assert_eq!(
+                allocation.additional_relayed_addr.map(|addr| addr.ip()),
+                Some(LOCALHOST_V6)
+            );
+            check_relay(&mut connection, LOCALHOST_V4).await;
+            check_relay(&mut connection, LOCALHOST_V6).await;
+
+            timeout(client.deallocate()).await.unwrap();
+            let allocation =
+                timeout(client.allocate_with(RequestedTransport::UDP, AddressFamily::Ipv6))
+                    .await
+                    .unwrap();
+            assert_eq!(allocation.relayed_addr.ip(), LOCALHOST_V6);
+            assert_eq!(allocation.additional_relayed_addr, None);
+            check_relay(&mut connection, LOCALHOST_V6).await;
+
+            let err = timeout(client.create_permission(LOCALHOST_V4))
+                .await
+                .unwrap_err();
+            assert_eq!(
+                ErrorResponse::code(&err),
+                Some(ErrorCode::PEER_ADDRESS_FAMILY_MISMATCH)
+            );
+        });
+    }
+
+    #[test]
+    fn ipv6_not_supported() {
+        tokio_test::block_on(async {
+            let TestServer { udp_addr, .. } =
+                test_util::start_server(test_util::new_test_config()).await;
+            let connection = Connection::connect(udp_addr, Transport::Udp, new_test_credentials())
+                .await
+                .unwrap();
+            let client = connection.client();
+
+            let err = timeout(client.allocate_with(RequestedTransport::UDP, AddressFamily::Ipv6))
+                .await
+                .unwrap_err();
+            assert_eq!(
+                ErrorResponse::code(&err),
+                Some(ErrorCode::ADDRESS_FAMILY_NOT_SUPPORTED)
+            );
+
+            // Dual-stack allocations fall back to IPv4 only.
+            let allocation =
+                timeout(client.allocate_with(RequestedTransport::UDP, AddressFamily::DualStack))
+                    .await
+                    .unwrap();
+            assert_eq!(allocation.relayed_addr.ip(), LOCALHOST_V4);
+            assert_eq!(allocation.additional_relayed_addr, None);
+        });
+    }
+
+    #[test]
+    fn ice_server() {
+        tokio_test::block_on(async {
+            let TestServer {
+                udp_addr, tcp_addr, ..
+            } = test_util::start_server(test_util::new_test_config()).await;
+
+            for uri in &[
+                format!("turn:{}", udp_addr),
+                format!("turn:{}?transport=tcp", tcp_addr),
+            ] {
+                let server = IceServer::new(vec![
+                    "stun:127.0.0.1".parse().unwrap(),
+                    uri.parse().unwrap(),
+                ]);
+                let err = Connection::connect_ice_server(&server)
+                    .await
+                    .map(|_| ())
+                    .unwrap_err();
+                assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+                let server =
+                    server.with_credentials(test_util::TEST_USERNAME, test_util::TEST_PASSWORD);
+                let mut connection = Connection::connect_ice_server(&server).await.unwrap();
+                timeout(connection.client().allocate()).await.unwrap();
+                check_relay(&mut connection, LOCALHOST_V4).await;
+            }
+        });
+    }
+}